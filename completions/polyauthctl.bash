@@ -7,13 +7,16 @@ _polyauthctl() {
     _init_completion || return
 
     # Global options
-    local global_opts="-u --username -c --config-file -p --password --update-as-needed --help"
+    local global_opts="-u --username -c --config-file -p --password --password-fd --password-file --stdin --insecure-argv --update-as-needed --layout --kdf-memory --kdf-iterations --output --help"
     
     # Main commands
-    local commands="info setup reset inspect add set-session set-home-mount set-pre-mount mount"
+    local commands="info setup reset inspect verify-main migrate sign-config export import add set-session set-home-mount set-pre-mount mount sessions doctor provision tui"
     
     # Mount subcommands
-    local mount_cmds="authorize"
+    local mount_cmds="authorize list"
+
+    # Sessions subcommands
+    local sessions_cmds="active list"
     
     # Add subcommands
     local add_methods="password"
@@ -24,12 +27,12 @@ _polyauthctl() {
     local i
     for ((i=1; i < cword; i++)); do
         case "${words[i]}" in
-            -u|--username|-c|--config-file|-p|--password)
+            -u|--username|-c|--config-file|-p|--password|--password-fd|--password-file|--layout|--kdf-memory|--kdf-iterations|--output)
                 ((i++))  # Skip the argument
                 ;;
-            --update-as-needed|--help)
+            --stdin|--insecure-argv|--update-as-needed|--help)
                 ;;
-            info|setup|reset|inspect|add|set-session|set-home-mount|set-pre-mount|mount)
+            info|setup|reset|inspect|verify-main|migrate|sign-config|export|import|add|set-session|set-home-mount|set-pre-mount|mount|sessions|doctor|provision|tui)
                 cmd="${words[i]}"
                 cmd_pos=$i
                 break
@@ -45,15 +48,27 @@ _polyauthctl() {
                 COMPREPLY=($(compgen -u -- "$cur"))
                 return
                 ;;
-            -c|--config-file)
+            -c|--config-file|--password-file)
                 # Complete file paths
                 _filedir
                 return
                 ;;
-            -p|--password)
+            -p|--password|--password-fd)
                 # Don't complete passwords
                 return
                 ;;
+            --kdf-memory|--kdf-iterations)
+                # Numbers
+                return
+                ;;
+            --layout)
+                COMPREPLY=($(compgen -W "detect single-file split" -- "$cur"))
+                return
+                ;;
+            --output)
+                COMPREPLY=($(compgen -W "text json yaml" -- "$cur"))
+                return
+                ;;
             *)
                 COMPREPLY=($(compgen -W "$global_opts $commands" -- "$cur"))
                 return
@@ -70,12 +85,16 @@ _polyauthctl() {
         
         setup)
             case "$prev" in
-                -i|--intermediate)
+                -i|--intermediate|--intermediate-fd)
                     # Don't complete intermediate key
                     return
                     ;;
+                --intermediate-file)
+                    _filedir
+                    return
+                    ;;
                 *)
-                    COMPREPLY=($(compgen -W "-i --intermediate" -- "$cur"))
+                    COMPREPLY=($(compgen -W "-i --intermediate --intermediate-fd --intermediate-file" -- "$cur"))
                     return
                     ;;
             esac
@@ -90,6 +109,55 @@ _polyauthctl() {
             # No specific options (uses global options)
             return
             ;;
+
+        verify-main)
+            # No specific options (uses global options)
+            return
+            ;;
+
+        migrate)
+            COMPREPLY=($(compgen -W "--dry-run" -- "$cur"))
+            return
+            ;;
+
+        sign-config)
+            # No specific options (uses global -u)
+            return
+            ;;
+
+        export)
+            case "$prev" in
+                --out|--passphrase-file)
+                    _filedir
+                    ;;
+                --passphrase|--passphrase-fd)
+                    # Don't complete passphrases
+                    ;;
+                *)
+                    COMPREPLY=($(compgen -W "--out --passphrase --passphrase-fd --passphrase-file" -- "$cur"))
+                    ;;
+            esac
+            return
+            ;;
+
+        import)
+            case "$prev" in
+                --passphrase|--passphrase-fd|-i|--intermediate|--intermediate-fd|--uid|--gid)
+                    # Don't complete secrets and ids
+                    ;;
+                --passphrase-file|--intermediate-file)
+                    _filedir
+                    ;;
+                *)
+                    if [[ "$cur" == -* ]]; then
+                        COMPREPLY=($(compgen -W "--passphrase --passphrase-fd --passphrase-file -i --intermediate --intermediate-fd --intermediate-file --uid --gid --force" -- "$cur"))
+                    else
+                        _filedir
+                    fi
+                    ;;
+            esac
+            return
+            ;;
         
         add)
             # Check if we already have a method
@@ -107,24 +175,28 @@ _polyauthctl() {
                     # User provides name
                     return
                     ;;
-                --intermediate)
+                --intermediate|--intermediate-fd)
                     # Don't complete intermediate key
                     return
                     ;;
-                --secondary-pw)
+                --secondary-pw|--secondary-pw-fd)
                     # Don't complete password
                     return
                     ;;
+                --intermediate-file|--secondary-pw-file)
+                    _filedir
+                    return
+                    ;;
                 add)
-                    COMPREPLY=($(compgen -W "--name --intermediate" -- "$cur"))
+                    COMPREPLY=($(compgen -W "--name --intermediate --intermediate-fd --intermediate-file" -- "$cur"))
                     return
                     ;;
                 *)
                     if [[ $has_method -eq 0 ]]; then
-                        COMPREPLY=($(compgen -W "--name --intermediate $add_methods" -- "$cur"))
+                        COMPREPLY=($(compgen -W "--name --intermediate --intermediate-fd --intermediate-file $add_methods" -- "$cur"))
                     else
                         # After method, show method-specific options
-                        COMPREPLY=($(compgen -W "--secondary-pw" -- "$cur"))
+                        COMPREPLY=($(compgen -W "--secondary-pw --secondary-pw-fd --secondary-pw-file" -- "$cur"))
                     fi
                     return
                     ;;
@@ -143,8 +215,23 @@ _polyauthctl() {
                     _filedir
                     return
                     ;;
+                --desktop)
+                    # Complete the IDs of the installed desktop sessions
+                    local desktops
+                    desktops=$(ls /usr/share/wayland-sessions/*.desktop /usr/share/xsessions/*.desktop 2>/dev/null | xargs -r -n1 basename | sed 's/\.desktop$//')
+                    COMPREPLY=($(compgen -W "$desktops" -- "$cur"))
+                    return
+                    ;;
+                --session-type)
+                    COMPREPLY=($(compgen -W "wayland x11 tty" -- "$cur"))
+                    return
+                    ;;
+                --env|--desktop-names)
+                    # Free-form values
+                    return
+                    ;;
                 *)
-                    COMPREPLY=($(compgen -W "--cmd --args" -- "$cur"))
+                    COMPREPLY=($(compgen -W "--cmd --desktop --args --env --session-type --desktop-names" -- "$cur"))
                     return
                     ;;
             esac
@@ -207,7 +294,7 @@ _polyauthctl() {
             # Check if we have a subcommand
             local mount_subcmd=""
             for ((j=cmd_pos+1; j < cword; j++)); do
-                if [[ "${words[j]}" == "authorize" ]]; then
+                if [[ "${words[j]}" == "authorize" || "${words[j]}" == "list" ]]; then
                     mount_subcmd="${words[j]}"
                     break
                 fi
@@ -217,10 +304,48 @@ _polyauthctl() {
                 # Suggest mount subcommands
                 COMPREPLY=($(compgen -W "$mount_cmds" -- "$cur"))
             else
-                # Mount authorize has no specific options (uses global -u)
+                # Mount subcommands have no specific options (use global -u)
                 return
             fi
             ;;
+
+        sessions)
+            if [[ $cword -eq $((cmd_pos+1)) ]]; then
+                COMPREPLY=($(compgen -W "$sessions_cmds" -- "$cur"))
+            fi
+            return
+            ;;
+
+        provision)
+            if [[ "$cur" == -* ]]; then
+                COMPREPLY=($(compgen -W "--dry-run" -- "$cur"))
+            else
+                _filedir '@(yaml|yml)'
+            fi
+            return
+            ;;
+
+        tui)
+            # No specific options (uses global options)
+            return
+            ;;
+
+        doctor)
+            case "$prev" in
+                --intermediate|--intermediate-fd)
+                    # Don't complete intermediate key
+                    return
+                    ;;
+                --intermediate-file)
+                    _filedir
+                    return
+                    ;;
+                *)
+                    COMPREPLY=($(compgen -W "--intermediate --intermediate-fd --intermediate-file" -- "$cur"))
+                    return
+                    ;;
+            esac
+            ;;
     esac
 }
 
@@ -23,16 +23,11 @@ use std::time::{SystemTime, UNIX_EPOCH};
 
 use bytevec2::*;
 
-use aes_gcm::{
-    aead::{Aead, AeadCore, KeyInit, OsRng},
-    Aes256Gcm, Key, Nonce,
-};
-
-extern crate bcrypt;
-use bcrypt::{hash, verify, DEFAULT_COST};
-
 use crate::{
     error::*,
+    kdf::KdfParams,
+    secret::SecretBytes,
+    storage::legacy::SecondaryPasswordV0,
     user::{AuthDataNonce, AuthDataSalt, UserAuthDataError},
 };
 
@@ -44,68 +39,48 @@ bytevec_decl! {
 
         password_salt: AuthDataSalt,
 
-        password_hash: String // this is used to check the entered password
+        kdf: KdfParams // this is used to derive the key from (password, password_salt)
     }
 }
 
 impl SecondaryPassword {
     // WARNING: it is the user responsibility to check that the intermediate value matches the MainPassword field,
     // therefore the user MUST verify() it beforehand
-    pub fn new(intermediate: &String, password: &String) -> Result<Self, UserOperationError> {
-        let password_salt_arr =
-            <[u8; 32]>::try_from(Aes256Gcm::generate_key(&mut OsRng).to_vec().as_slice()).unwrap();
-
-        let password_hash =
-            hash(password.as_str(), DEFAULT_COST).map_err(UserOperationError::HashingError)?;
-
-        let password_derived_key = crate::derive_key(password.as_str(), &password_salt_arr);
+    pub fn new(
+        intermediate: &[u8],
+        password: &[u8],
+        kdf: &KdfParams,
+    ) -> Result<Self, UserOperationError> {
+        let password_salt_arr = crate::random_salt();
 
-        let key = Key::<Aes256Gcm>::from(password_derived_key);
+        let (enc_intermediate, nonce) =
+            crate::encrypt_with_password(kdf, password, &password_salt_arr, intermediate)?;
 
-        let cipher = Aes256Gcm::new(&key);
-
-        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
-
-        let enc_intermediate = cipher
-            .encrypt(&nonce, crate::password_to_vec(intermediate).as_ref())
-            .map_err(UserOperationError::EncryptionError)?;
-
-        let temp: [u8; 32] = password_salt_arr;
-        let password_salt = AuthDataSalt::from(temp);
-        let temp: [u8; 12] = nonce.into();
-        let enc_intermediate_nonce = AuthDataNonce::from(temp);
         Ok(Self {
-            enc_intermediate_nonce,
+            enc_intermediate_nonce: AuthDataNonce::from(nonce),
             enc_intermediate,
-            password_salt,
-            password_hash,
+            password_salt: AuthDataSalt::from(password_salt_arr),
+            kdf: *kdf,
         })
     }
 
-    // get the intermediate if the password is correct
-    pub fn intermediate(&self, password: &String) -> Result<String, UserOperationError> {
-        if !verify(password.as_str(), self.password_hash.as_str())
-            .map_err(UserOperationError::HashingError)?
-        {
-            return Err(UserOperationError::User(
-                UserAuthDataError::CouldNotAuthenticate,
-            ));
-        }
-
-        let temp: [u8; 32] = self.password_salt.into();
-        let password_derived_key = crate::derive_key(password.as_str(), &temp);
-
-        let key = Key::<Aes256Gcm>::from(password_derived_key);
-        let cipher = Aes256Gcm::new(&key);
-
-        let temp: [u8; 12] = self.enc_intermediate_nonce.into();
-        let nonce = Nonce::from(temp);
-
-        let dec_result = cipher
-            .decrypt(&nonce, self.enc_intermediate.as_ref())
-            .map_err(UserOperationError::EncryptionError)?;
+    pub fn kdf(&self) -> &KdfParams {
+        &self.kdf
+    }
 
-        Ok(crate::vec_to_password(&dec_result))
+    // get the intermediate if the password is correct:
+    // a wrong password fails the authentication of the encrypted intermediate key
+    pub fn intermediate(&self, password: &[u8]) -> Result<SecretBytes, UserOperationError> {
+        let dec_result = crate::decrypt_with_password(
+            &self.kdf,
+            password,
+            &self.password_salt.into(),
+            &self.enc_intermediate_nonce.into(),
+            self.enc_intermediate.as_ref(),
+        )
+        .map_err(|_| UserOperationError::User(UserAuthDataError::CouldNotAuthenticate))?;
+
+        Ok(dec_result)
     }
 }
 
@@ -114,11 +89,15 @@ pub struct SecondaryAuth {
     name: String,
     creation_date: u64,
     method: SecondaryAuthMethod,
+    autologin: Option<bool>,
 }
 
 #[derive(Debug, Eq, PartialEq, Clone)]
 pub enum SecondaryAuthMethod {
     Password(SecondaryPassword),
+
+    /// A password entry still in a bcrypt-verified format: replaced on the next upgrade
+    LegacyPassword(SecondaryPasswordV0),
 }
 
 impl SecondaryAuth {
@@ -127,6 +106,10 @@ impl SecondaryAuth {
         creation_date: Option<u64>,
         password: SecondaryPassword,
     ) -> Self {
+        Self::new(name, creation_date, SecondaryAuthMethod::Password(password))
+    }
+
+    pub(crate) fn new(name: &str, creation_date: Option<u64>, method: SecondaryAuthMethod) -> Self {
         Self {
             name: String::from(name),
             creation_date: match creation_date {
@@ -136,10 +119,22 @@ impl SecondaryAuth {
                     Err(_err) => 0u64,
                 },
             },
-            method: SecondaryAuthMethod::Password(password),
+            method,
+            autologin: None,
         }
     }
 
+    /// Records whether the password of this entry is empty, known whenever it is written
+    pub(crate) fn with_autologin(mut self, autologin: Option<bool>) -> Self {
+        self.autologin = autologin;
+        self
+    }
+
+    /// Whether the password of this entry is empty: None for entries stored before it was recorded
+    pub fn autologin(&self) -> Option<bool> {
+        self.autologin
+    }
+
     pub(crate) fn data(&self) -> &SecondaryAuthMethod {
         &self.method
     }
@@ -148,27 +143,72 @@ impl SecondaryAuth {
         self.name.clone()
     }
 
+    pub(crate) fn set_name(&mut self, name: &str) {
+        self.name = String::from(name);
+    }
+
     pub fn creation_date(&self) -> u64 {
         self.creation_date
     }
 
     pub fn type_name(&self) -> String {
         match self.method {
-            SecondaryAuthMethod::Password(_) => String::from("password"),
+            SecondaryAuthMethod::Password(_) | SecondaryAuthMethod::LegacyPassword(_) => {
+                String::from("password")
+            }
+        }
+    }
+
+    pub fn kdf(&self) -> &KdfParams {
+        match &self.method {
+            SecondaryAuthMethod::Password(pwd) => pwd.kdf(),
+            SecondaryAuthMethod::LegacyPassword(pwd) => pwd.kdf(),
+        }
+    }
+
+    /// Check if this entry is stored in a format that has to be replaced
+    pub(crate) fn is_legacy(&self) -> bool {
+        matches!(self.method, SecondaryAuthMethod::LegacyPassword(_))
+    }
+
+    /// Re-encrypt the intermediate key in the current format with the given KDF parameters:
+    /// the provided secondary password MUST be the one of this authentication method.
+    pub(crate) fn rekey(
+        &mut self,
+        intermediate: &[u8],
+        secondary_password: Option<&[u8]>,
+        kdf: &KdfParams,
+    ) -> Result<(), UserOperationError> {
+        match secondary_password {
+            Some(provided_secondary) => {
+                self.method = SecondaryAuthMethod::Password(SecondaryPassword::new(
+                    intermediate,
+                    provided_secondary,
+                    kdf,
+                )?);
+                self.autologin = Some(provided_secondary.is_empty());
+
+                Ok(())
+            }
+            None => Err(UserOperationError::User(
+                UserAuthDataError::MatchingAuthNotProvided,
+            )),
         }
     }
 
     pub fn intermediate(
         &self,
-        secondary_password: &Option<String>,
-    ) -> Result<String, UserOperationError> {
+        secondary_password: Option<&[u8]>,
+    ) -> Result<SecretBytes, UserOperationError> {
+        let Some(provided_secondary) = secondary_password else {
+            return Err(UserOperationError::User(
+                UserAuthDataError::MatchingAuthNotProvided,
+            ));
+        };
+
         match &self.method {
-            SecondaryAuthMethod::Password(pwd) => match &secondary_password {
-                Some(provided_secondary) => pwd.intermediate(provided_secondary),
-                None => Err(UserOperationError::User(
-                    UserAuthDataError::MatchingAuthNotProvided,
-                )),
-            },
+            SecondaryAuthMethod::Password(pwd) => pwd.intermediate(provided_secondary),
+            SecondaryAuthMethod::LegacyPassword(pwd) => pwd.intermediate(provided_secondary),
         }
     }
 }
@@ -24,10 +24,16 @@ extern crate tokio;
 use pam_polyauth::pam::{
     disk::create_directory,
     mount::{MountAuthDBus, MountAuthOperations},
+    runtime_dir::{RuntimeDirConfig, RuntimeDirSize},
+    service::start_service,
+    service_data_dir,
     session::Sessions,
-    ServiceError,
+    ServiceError, AUTHORIZED_MOUNTS_FILE_NAME, DEFAULT_GREETER_USER, PRIVATE_KEY_FILE_NAME,
+    SESSIONS_SOCKET_PATH, SIGNING_KEY_FILE_NAME, XDG_RUNTIME_DIR_PATH,
 };
+use pam_polyauth::storage::store::{system_store, StoreLayout, UserStore};
 
+use argh::FromArgs;
 use tokio::signal::unix::{signal, SignalKind};
 use tokio::sync::RwLock;
 use users;
@@ -38,56 +44,66 @@ use std::{
     sync::Arc,
 };
 
+#[derive(FromArgs, PartialEq, Debug)]
+/// Service mounting the directories of polyauth users and handling their sessions
+struct Args {
+    #[argh(option)]
+    /// directory where the runtime directory of each user is mounted (default /run/user):
+    /// must match the runtime_dir option of the pam module
+    runtime_dir: Option<PathBuf>,
+
+    #[argh(option)]
+    /// size limit of each runtime directory, i.e. 64m or 10% (default 10%)
+    runtime_dir_size: Option<RuntimeDirSize>,
+
+    #[argh(option, default = "String::from(DEFAULT_GREETER_USER)")]
+    /// user the greeter runs as, allowed to read the session command of every user
+    /// together with root (default greeter)
+    greeter_user: String,
+}
+
 #[tokio::main]
 async fn main() -> Result<(), ServiceError> {
+    let args: Args = argh::from_env();
+
+    let runtime_dir = RuntimeDirConfig::new(
+        args.runtime_dir
+            .unwrap_or_else(|| PathBuf::from(XDG_RUNTIME_DIR_PATH)),
+        args.runtime_dir_size.unwrap_or_default(),
+    )?;
+
     if users::get_current_uid() != 0 {
         eprintln!("🚫 Application started without root privileges: aborting...");
         return Err(ServiceError::MissingPrivilegesError);
     }
 
-    let private_key_file_name_str = "private_key_pkcs1.pem";
-    let authorization_file_name_str = "authorized_mounts.json";
-    let dir_path_str = match std::fs::exists("/usr/lib/polyauth/").unwrap_or(false) {
-        true => "/usr/lib/polyauth/",
-        false => "/etc/polyauth/",
-    };
+    let dir_path = service_data_dir();
 
-    create_directory(PathBuf::from(dir_path_str)).await?;
+    create_directory(dir_path.clone()).await?;
 
     let mounts_auth = Arc::new(RwLock::new(MountAuthOperations::new(
-        Path::new(dir_path_str).join(authorization_file_name_str),
+        dir_path.join(AUTHORIZED_MOUNTS_FILE_NAME),
+        dir_path.join(SIGNING_KEY_FILE_NAME),
     )));
 
-    println!("🔧 Building the dbus object...");
-
-    let dbus_mounts_auth_con = connection::Builder::system()
-        .map_err(ServiceError::ZbusError)?
-        .name("org.neroreflex.polyauth_mount")
-        .map_err(ServiceError::ZbusError)?
-        .serve_at(
-            "/org/neroreflex/polyauth_mount",
-            MountAuthDBus::new(mounts_auth.clone()),
-        )
-        .map_err(ServiceError::ZbusError)?
-        .build()
-        .await
-        .map_err(ServiceError::ZbusError)?;
-
-    let dbus_session_conn = connection::Builder::system()
-        .map_err(ServiceError::ZbusError)?
-        .name("org.neroreflex.polyauth_session")
-        .map_err(ServiceError::ZbusError)?
-        .serve_at(
-            "/org/neroreflex/polyauth_session",
-            Sessions::new(
-                Path::new(dir_path_str).join(private_key_file_name_str),
-                mounts_auth,
-            ),
-        )
-        .map_err(ServiceError::ZbusError)?
-        .build()
-        .await
-        .map_err(ServiceError::ZbusError)?;
+    let store: Arc<dyn UserStore> = Arc::new(system_store(StoreLayout::Detect));
+
+    let sessions = Sessions::new(
+        dir_path.join(PRIVATE_KEY_FILE_NAME),
+        mounts_auth.clone(),
+        store.clone(),
+    )
+    .with_runtime_dir(runtime_dir)
+    .with_greeter_user(args.greeter_user);
+
+    let mount_auth = MountAuthDBus::new(mounts_auth.clone(), store.clone());
+    let service = start_service(
+        Path::new(SESSIONS_SOCKET_PATH),
+        sessions,
+        mount_auth,
+        connection::Builder::system,
+    )
+    .await?;
 
     println!("🔄 Application running");
 
@@ -98,8 +114,7 @@ async fn main() -> Result<(), ServiceError> {
     // Wait for a SIGTERM signal
     sigterm.recv().await;
 
-    drop(dbus_session_conn);
-    drop(dbus_mounts_auth_con);
+    service.stop();
 
     Ok(())
 }
@@ -0,0 +1,245 @@
+/*
+    polyauth A greeter written in rust that also supports autologin with systemd-homed
+    Copyright (C) 2024-2025  Denis Benato
+
+    This program is free software; you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation; either version 2 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License along
+    with this program; if not, write to the Free Software Foundation, Inc.,
+    51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+*/
+
+extern crate tokio;
+
+use std::io::Write;
+use std::process::ExitCode;
+
+use pam_polyauth::command::SessionCommand;
+use pam_polyauth::greetd::{AuthMessageType, Authentication, GreetdClient, GreetdError};
+use pam_polyauth::launcher::session_environment;
+use pam_polyauth::pam::result::ServiceOperationResult;
+use pam_polyauth::pam::session::{SessionsProxy, UserInfo};
+
+use argh::FromArgs;
+use rpassword::prompt_password;
+use zbus::Connection;
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// Text greeter for greetd that logs in polyauth users
+struct Args {
+    #[argh(option, short = 'u')]
+    /// user to log in: if unspecified it is either the only autologin user or prompted for
+    username: Option<String>,
+
+    #[argh(switch)]
+    /// always prompt for the user, even if only one user can log in automatically
+    no_autologin: bool,
+
+    #[argh(option, default = "String::from(\"polyauth-session\")")]
+    /// command started for users without a session command (default polyauth-session)
+    default_cmd: String,
+}
+
+/// What greetd has to start for the user: None if the user must not be logged in
+async fn session_for(
+    proxy: Option<&SessionsProxy<'_>>,
+    default_cmd: &str,
+    user: &UserInfo,
+) -> Option<(Vec<String>, Vec<String>)> {
+    let default_session = || Some((vec![String::from(default_cmd)], vec![]));
+
+    let Some(proxy) = proxy else {
+        return default_session();
+    };
+
+    let command = match proxy.get_session_command(&user.username).await {
+        Ok(info) => SessionCommand::from(info),
+        Err(err) => match err {
+            err if err.result() == ServiceOperationResult::NoSessionCommand => {
+                return default_session()
+            }
+            err if err.result() == ServiceOperationResult::TamperedConfig => {
+                eprintln!("🚫 {}: refusing to start the session", err.message());
+                return None;
+            }
+            err => {
+                eprintln!("⚠️ Error fetching the session command: {}", err.message());
+                return default_session();
+            }
+        },
+    };
+
+    // XDG_RUNTIME_DIR is set by the pam module, in the directory it was configured with
+    let env = session_environment(&command, None)
+        .into_iter()
+        .map(|(key, value)| format!("{key}={value}"))
+        .collect();
+
+    Some((command.argv(), env))
+}
+
+fn prompt_line(message: &str) -> Result<String, GreetdError> {
+    print!("{message}");
+    std::io::stdout().flush()?;
+
+    let mut line = String::new();
+    if std::io::stdin().read_line(&mut line)? == 0 {
+        return Err(GreetdError::IoError(std::io::Error::from(
+            std::io::ErrorKind::UnexpectedEof,
+        )));
+    }
+
+    Ok(line.trim_end_matches(['\r', '\n']).to_string())
+}
+
+fn prompt_user(users: &[UserInfo]) -> Result<UserInfo, GreetdError> {
+    if !users.is_empty() {
+        println!("👥 Users:");
+        for user in users {
+            println!("   👤 {}", user.username);
+        }
+    }
+
+    loop {
+        let username = prompt_line("Username: ")?;
+        if username.is_empty() {
+            continue;
+        }
+
+        return Ok(find_user(users, &username));
+    }
+}
+
+fn find_user(users: &[UserInfo], username: &str) -> UserInfo {
+    users
+        .iter()
+        .find(|user| user.username == username)
+        .cloned()
+        .unwrap_or_else(|| UserInfo {
+            username: String::from(username),
+            uid: users::get_user_by_name(username)
+                .map(|user| user.uid())
+                .unwrap_or_default(),
+            autologin: false,
+        })
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let args: Args = argh::from_env();
+
+    let mut client = match GreetdClient::from_env().await {
+        Ok(client) => client,
+        Err(err) => {
+            eprintln!("❌ Error connecting to greetd: {err}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let connection = match Connection::system().await {
+        Ok(connection) => Some(connection),
+        Err(err) => {
+            eprintln!("⚠️ Error connecting to system bus: {err}");
+            None
+        }
+    };
+
+    let proxy = match &connection {
+        Some(connection) => match SessionsProxy::new(connection).await {
+            Ok(proxy) => Some(proxy),
+            Err(err) => {
+                eprintln!("⚠️ Error creating the sessions proxy: {err}");
+                None
+            }
+        },
+        None => None,
+    };
+
+    let users = match &proxy {
+        Some(proxy) => proxy.list_users().await.unwrap_or_else(|err| {
+            eprintln!("⚠️ Error listing polyauth users: {err}");
+            vec![]
+        }),
+        None => vec![],
+    };
+
+    // only the first attempt can be automatic: a failing autologin must not loop
+    let mut automatic = !args.no_autologin;
+
+    loop {
+        let autologin_users = users
+            .iter()
+            .filter(|user| user.autologin)
+            .collect::<Vec<_>>();
+        let user = match (&args.username, automatic, autologin_users.as_slice()) {
+            (Some(username), _, _) => find_user(&users, username),
+            (None, true, [user]) => {
+                println!("🔓 Logging in {} automatically", user.username);
+                (*user).clone()
+            }
+            (None, _, _) => match prompt_user(&users) {
+                Ok(user) => user,
+                Err(err) => {
+                    eprintln!("❌ Error reading the username: {err}");
+                    return ExitCode::FAILURE;
+                }
+            },
+        };
+        automatic = false;
+
+        let authentication = client
+            .authenticate(&user.username, |kind, message| match kind {
+                AuthMessageType::Visible => prompt_line(message).map(Some),
+                AuthMessageType::Secret => prompt_password(message)
+                    .map(Some)
+                    .map_err(GreetdError::IoError),
+                AuthMessageType::Info => {
+                    println!("ℹ️  {message}");
+                    Ok(None)
+                }
+                AuthMessageType::Error => {
+                    eprintln!("❌ {message}");
+                    Ok(None)
+                }
+            })
+            .await;
+
+        match authentication {
+            Ok(Authentication::Authenticated) => {}
+            Ok(Authentication::Denied(reason)) => {
+                eprintln!("🚫 Login failed: {reason}");
+                continue;
+            }
+            Err(err) => {
+                eprintln!("❌ Error authenticating user {}: {err}", user.username);
+                return ExitCode::FAILURE;
+            }
+        }
+
+        let Some((cmd, env)) = session_for(proxy.as_ref(), &args.default_cmd, &user).await else {
+            if let Err(err) = client.cancel_session().await {
+                eprintln!("❌ Error cancelling the session: {err}");
+                return ExitCode::FAILURE;
+            }
+            continue;
+        };
+
+        println!("🚀 Starting the session {}", cmd.join(" "));
+
+        return match client.start_session(cmd, env).await {
+            Ok(()) => ExitCode::SUCCESS,
+            Err(err) => {
+                eprintln!("❌ Error starting the session: {err}");
+                ExitCode::FAILURE
+            }
+        };
+    }
+}
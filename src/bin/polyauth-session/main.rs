@@ -0,0 +1,189 @@
+/*
+    polyauth A greeter written in rust that also supports autologin with systemd-homed
+    Copyright (C) 2024-2025  Denis Benato
+
+    This program is free software; you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation; either version 2 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License along
+    with this program; if not, write to the Free Software Foundation, Inc.,
+    51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+*/
+
+use std::collections::BTreeMap;
+use std::os::unix::process::CommandExt;
+use std::path::PathBuf;
+use std::process::{Command, ExitCode};
+use std::time::{Duration, Instant};
+
+use pam_polyauth::command::SessionCommand;
+use pam_polyauth::desktop::SessionDirs;
+use pam_polyauth::launcher::{
+    login_shell_name, session_environment, session_failed, user_runtime_dir, user_shell,
+    RestartDecision, RestartPolicy, RestartTracker,
+};
+use pam_polyauth::storage::{
+    load_user_session_command,
+    store::{system_store, JsonStore, StoreLayout, UserStore},
+};
+
+use argh::FromArgs;
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// Start the session command configured for the current user, falling back to the user shell
+struct Args {
+    #[argh(option, short = 'c')]
+    /// force the use of a specific configuration file
+    config_file: Option<PathBuf>,
+
+    #[argh(option)]
+    /// layout of the configuration directory: detect (default), single-file or split
+    layout: Option<StoreLayout>,
+
+    #[argh(option, default = "RestartPolicy::OnFailure")]
+    /// when to restart the session: never, on-failure (default) or always
+    restart: RestartPolicy,
+
+    #[argh(option, default = "3")]
+    /// restarts attempted before falling back to the shell (default 3)
+    max_restarts: u32,
+
+    #[argh(option, default = "1")]
+    /// seconds to wait before restarting the session (default 1)
+    restart_delay: u64,
+
+    #[argh(option, default = "60")]
+    /// seconds after which a running session is considered stable and the restart count is reset (default 60)
+    stable_after: u64,
+
+    #[argh(switch)]
+    /// exit instead of starting the user shell when the session cannot be started
+    no_fallback: bool,
+}
+
+fn main() -> ExitCode {
+    let args: Args = argh::from_env();
+
+    let uid = users::get_current_uid();
+    let Some(username) = users::get_current_username() else {
+        eprintln!("❌ The current user could not be identified");
+        return ExitCode::FAILURE;
+    };
+    let username = username.to_string_lossy().to_string();
+
+    // the pam module already exports the runtime directory: only fill it in when missing
+    let runtime_dir = std::env::var_os("XDG_RUNTIME_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| user_runtime_dir(uid));
+
+    let store: Box<dyn UserStore> = match &args.config_file {
+        Some(path) => Box::new(JsonStore::file(path.clone())),
+        None => Box::new(system_store(args.layout.unwrap_or_default())),
+    };
+
+    let command = match load_user_session_command(store.as_ref(), &username) {
+        Ok(Some(command)) => match SessionDirs::default().resolve(&command) {
+            Ok(command) => Some(command),
+            Err(err) => {
+                eprintln!("❌ Error resolving the desktop session of user {username}: {err}");
+                None
+            }
+        },
+        Ok(None) => {
+            eprintln!("⚠️  No session command configured for user {username}");
+            None
+        }
+        Err(err) => {
+            eprintln!("❌ Error loading the session command of user {username}: {err}");
+            None
+        }
+    };
+
+    if let Some(command) = command {
+        let env = session_environment(&command, Some(&runtime_dir));
+        if let Some(code) = run_session(&args, &command, &env) {
+            return code;
+        }
+    }
+
+    if args.no_fallback {
+        return ExitCode::FAILURE;
+    }
+
+    let shell = user_shell(uid);
+    let mut env = BTreeMap::new();
+    env.insert(
+        String::from("XDG_RUNTIME_DIR"),
+        runtime_dir.to_string_lossy().to_string(),
+    );
+
+    println!("🐚 Starting the shell {}", shell.display());
+
+    // exec only returns on error
+    let err = Command::new(&shell)
+        .arg0(login_shell_name(&shell))
+        .envs(env)
+        .exec();
+
+    eprintln!("❌ Error starting the shell {}: {err}", shell.display());
+
+    ExitCode::FAILURE
+}
+
+/// Runs the session command until the restart policy says otherwise:
+/// returns the exit code of the launcher, or None if it has to fall back to the shell.
+fn run_session(
+    args: &Args,
+    command: &SessionCommand,
+    env: &BTreeMap<String, String>,
+) -> Option<ExitCode> {
+    let mut tracker = RestartTracker::new(
+        args.restart,
+        args.max_restarts,
+        Duration::from_secs(args.stable_after),
+    );
+
+    loop {
+        println!("🚀 Starting the session {}", command.argv().join(" "));
+
+        let started = Instant::now();
+        let status = match Command::new(command.command())
+            .args(command.args())
+            .envs(env)
+            .status()
+        {
+            Ok(status) => status,
+            Err(err) => {
+                eprintln!("❌ Error starting the session {}: {err}", command.command());
+                return None;
+            }
+        };
+
+        let failed = session_failed(&status);
+        match tracker.next(failed, started.elapsed()) {
+            RestartDecision::Finished => {
+                println!("👋 The session ended");
+                return Some(ExitCode::SUCCESS);
+            }
+            RestartDecision::GiveUp => {
+                eprintln!("🚫 The session exited with {status}: giving up");
+                return None;
+            }
+            RestartDecision::Restart => {
+                eprintln!(
+                    "🔄 The session exited with {status}: restarting ({}/{})",
+                    tracker.restarts(),
+                    args.max_restarts
+                );
+                std::thread::sleep(Duration::from_secs(args.restart_delay));
+            }
+        }
+    }
+}
@@ -0,0 +1,214 @@
+/*
+    polyauth A greeter written in rust that also supports autologin with systemd-homed
+    Copyright (C) 2024-2025  Denis Benato
+
+    This program is free software; you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation; either version 2 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License along
+    with this program; if not, write to the Free Software Foundation, Inc.,
+    51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+*/
+
+use std::{
+    fs::File,
+    mem::ManuallyDrop,
+    os::fd::{FromRawFd, RawFd},
+    path::PathBuf,
+};
+
+use pam_polyauth::{
+    constant_time_eq,
+    secret::{
+        is_valid_password, is_valid_secret, read_secret, read_secret_line, SecretBytes,
+        SecretString, MAX_PASSWORD_LEN,
+    },
+    storage::StorageError,
+};
+use rpassword::prompt_password;
+
+use crate::output::{CliError, ErrorClass, Output};
+
+/// What a secret is used for, which decides the bytes it can be made of
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SecretUse {
+    /// Main and secondary passwords: answered to a PAM prompt, so they follow its rules
+    /// wherever they are read from
+    Password,
+
+    /// Intermediate keys and export passphrases: never typed at a PAM prompt,
+    /// any byte is accepted so that key files work whatever they contain
+    Key,
+}
+
+/// Where polyauthctl reads secrets from: options, the terminal or the standard input
+#[derive(Debug, Clone, Copy)]
+pub struct SecretInput {
+    output: Output,
+    stdin: bool,
+    insecure_argv: bool,
+}
+
+impl SecretInput {
+    /// With stdin secrets that would be prompted for are read from the standard input instead,
+    /// with insecure_argv secrets are accepted as command line arguments.
+    pub fn new(output: Output, stdin: bool, insecure_argv: bool) -> Self {
+        Self {
+            output,
+            stdin,
+            insecure_argv,
+        }
+    }
+
+    /// Refuses secrets that are empty or too long and passwords that could never be
+    /// answered to a PAM prompt, whatever they have been read from
+    pub fn validate(&self, what: &str, usage: SecretUse, secret: SecretBytes) -> SecretBytes {
+        let valid = match usage {
+            SecretUse::Password => std::str::from_utf8(secret.expose())
+                .map(is_valid_password)
+                .unwrap_or(false),
+            SecretUse::Key => is_valid_secret(&secret),
+        };
+
+        if !valid {
+            self.output.fail(CliError::new(
+                ErrorClass::Usage,
+                match usage {
+                    SecretUse::Password => format!("The {what} must be 1 to {MAX_PASSWORD_LEN} bytes of text and cannot contain line breaks"),
+                    SecretUse::Key => format!("The {what} must be 1 to {MAX_PASSWORD_LEN} bytes long"),
+                },
+            ))
+        }
+
+        secret
+    }
+
+    /// Refuses passwords typed at the terminal that could never be answered to a PAM prompt
+    fn validate_typed(&self, what: &str, password: SecretString) -> SecretBytes {
+        if !is_valid_password(&password) {
+            self.output.fail(CliError::new(
+                ErrorClass::Usage,
+                format!("The {what} must be 1 to {MAX_PASSWORD_LEN} bytes long and cannot contain line breaks"),
+            ))
+        }
+
+        SecretBytes::from(password)
+    }
+
+    /// Asks for a secret on the terminal, or reads the next line of the standard input
+    pub fn prompt(&self, prompt: &str, usage: SecretUse) -> SecretBytes {
+        let what = prompt.trim_end_matches(':');
+
+        match self.stdin {
+            true => match read_secret_line(&mut std::io::stdin().lock()) {
+                Ok(Some(answer)) => self.validate(what, usage, answer),
+                Ok(None) => self.output.fail(CliError::new(
+                    ErrorClass::Usage,
+                    format!("The standard input ended before the {what}"),
+                )),
+                Err(err) => self.output.fail(CliError::new(
+                    ErrorClass::Usage,
+                    format!("Error reading the {what} from the standard input: {err}"),
+                )),
+            },
+            false => match prompt_password(prompt) {
+                Ok(answer) => self.validate_typed(what, SecretString::from(answer)),
+                Err(err) => self.output.fail(CliError::new(
+                    ErrorClass::Usage,
+                    format!("Error reading from the terminal: {err}"),
+                )),
+            },
+        }
+    }
+
+    /// Prompts twice for a secret, failing if the two answers differ.
+    /// Secrets read from the standard input are not asked for twice.
+    pub fn prompt_confirmed(
+        &self,
+        prompt_text: &str,
+        confirm_text: &str,
+        what: &str,
+        usage: SecretUse,
+    ) -> SecretBytes {
+        let answer = self.prompt(prompt_text, usage);
+        if self.stdin {
+            return answer;
+        }
+
+        let confirmation = self.prompt(confirm_text, usage);
+        if !constant_time_eq(answer.expose(), confirmation.expose()) {
+            self.output.fail(CliError::new(
+                ErrorClass::Usage,
+                format!("{what} and confirmation not matching"),
+            ))
+        }
+
+        answer
+    }
+
+    /// The secret given with `--<name>`, `--<name>-fd` or `--<name>-file`, None if there is none.
+    ///
+    /// `--<name>` leaks the secret to the process list and the shell history:
+    /// it is refused unless --insecure-argv has been given.
+    pub fn option(
+        &self,
+        name: &str,
+        usage: SecretUse,
+        argv: &Option<String>,
+        fd: Option<RawFd>,
+        file: &Option<PathBuf>,
+    ) -> Option<SecretBytes> {
+        let secret = match (argv, fd, file) {
+            (None, None, None) => return None,
+            (Some(secret), None, None) => {
+                if !self.insecure_argv {
+                    self.output.fail(CliError::new(
+                        ErrorClass::Usage,
+                        format!("--{name} exposes the secret to other users: use --{name}-file, --{name}-fd or --stdin, or add --insecure-argv"),
+                    ))
+                }
+
+                SecretBytes::from(secret.as_bytes())
+            }
+            (None, Some(fd), None) => {
+                // SAFETY: fcntl only inspects the descriptor, that is never written nor closed
+                if fd < 0 || unsafe { libc::fcntl(fd, libc::F_GETFD) } == -1 {
+                    self.output.fail(CliError::new(
+                        ErrorClass::Usage,
+                        format!("--{name}-fd {fd} is not an open file descriptor"),
+                    ))
+                }
+
+                // SAFETY: the descriptor is open, and it is left open as it belongs to the caller
+                let file = ManuallyDrop::new(unsafe { File::from_raw_fd(fd) });
+                match read_secret(&*file) {
+                    Ok(secret) => secret,
+                    Err(err) => self.output.fail(CliError::new(
+                        ErrorClass::Usage,
+                        format!("Error reading --{name}-fd {fd}: {err}"),
+                    )),
+                }
+            }
+            (None, None, Some(path)) => match File::open(path).and_then(read_secret) {
+                Ok(secret) => secret,
+                Err(err) => self.output.fail(
+                    CliError::from(StorageError::IoError(err))
+                        .context(&format!("Error reading {}", path.display())),
+                ),
+            },
+            _ => self.output.fail(CliError::new(
+                ErrorClass::Usage,
+                format!("Only one of --{name}, --{name}-fd and --{name}-file can be given"),
+            )),
+        };
+
+        Some(self.validate(&format!("--{name} secret"), usage, secret))
+    }
+}
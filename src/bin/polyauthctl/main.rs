@@ -17,21 +17,43 @@
     51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
 */
 
+use std::collections::BTreeMap;
 use std::fmt::Debug;
-use std::path::PathBuf;
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
 
 use chrono::Local;
 use chrono::TimeZone;
-use pam_polyauth::command::SessionCommand;
-use pam_polyauth::mount::MountParams;
-use pam_polyauth::pam::{mount::MountAuthDBusProxy, result::ServiceOperationResult};
+use pam_polyauth::command::{SessionCommand, SessionType};
+use pam_polyauth::desktop::SessionDirs;
+use pam_polyauth::doctor::{
+    check_config, check_dbus_policies, check_file_permissions, check_main_password,
+    check_mount_devices, check_pam_stack, healthy, Check, CheckStatus, DBUS_SYSTEM_POLICY_DIRS,
+    DISK_BY_DIR, PAM_CONFIG_DIR,
+};
+use pam_polyauth::kdf::{
+    KdfParams, DEFAULT_ARGON2_M_COST, DEFAULT_ARGON2_P_COST, DEFAULT_ARGON2_T_COST,
+    MAX_ARGON2_M_COST, MAX_ARGON2_T_COST, MIN_ARGON2_M_COST, MIN_ARGON2_T_COST,
+};
+use pam_polyauth::mount::{validate_mount_dir, MountParams};
+use pam_polyauth::pam::mount::{MountAuth, MountAuthDBusProxy};
+use pam_polyauth::pam::session::SessionsProxy;
+use pam_polyauth::pam::{
+    service_data_dir, ServiceError, AUTHORIZED_MOUNTS_FILE_NAME, PRIVATE_KEY_FILE_NAME,
+    SIGNING_KEY_FILE_NAME,
+};
+use pam_polyauth::provision::{plan_user, Change, Manifest, SecretKind, SecretSource, UserSecrets};
+use pam_polyauth::secret::{read_secret, read_secret_line, SecretBytes};
 use pam_polyauth::storage::{
-    load_user_auth_data, load_user_mountpoints, load_user_session_command, remove_user_data,
-    store_user_auth_data, store_user_mountpoints, store_user_session_command, StorageSource,
+    export::{decrypt_user_config, export_user_config, import_user_config},
+    load_user_auth_data, load_user_mountpoints, load_user_session_command, migrate_user_config,
+    remove_user_data,
+    store::{system_store, JsonStore, StoreLayout, UserStore},
+    store_user_auth_data, store_user_mountpoints, store_user_session_command, StorageError,
 };
 use pam_polyauth::user::UserAuthData;
 
-use rpassword::prompt_password;
 use users::get_user_by_name;
 #[allow(unused_imports)]
 use users::os::unix::UserExt;
@@ -39,6 +61,19 @@ use users::os::unix::UserExt;
 use argh::FromArgs;
 use zbus::Connection;
 
+mod input;
+mod output;
+mod term;
+mod tui;
+
+use input::{SecretInput, SecretUse};
+use output::{
+    print_mounts, AuthMethodReport, CliError, DesktopSessionReport, DesktopSessionsReport,
+    DoctorReport, ErrorClass, InfoReport, InspectReport, MigrateReport, MigrationStepsReport,
+    MountListReport, MountsReport, Output, OutputFormat, ProvisionReport, SessionReport,
+    SessionsReport, UserProvisionReport,
+};
+
 #[derive(FromArgs, PartialEq, Debug)]
 /// Command line tool for managing polyauth authentication methods
 struct Args {
@@ -51,13 +86,45 @@ struct Args {
     config_file: Option<PathBuf>,
 
     #[argh(option, short = 'p')]
-    /// main password for authentication (the one accepted by PAM)
+    /// main password for authentication (the one accepted by PAM), requires --insecure-argv
     password: Option<String>,
 
+    #[argh(option)]
+    /// read the main password from this file descriptor
+    password_fd: Option<i32>,
+
+    #[argh(option)]
+    /// read the main password from this file
+    password_file: Option<PathBuf>,
+
+    #[argh(switch)]
+    /// read the secrets that would be prompted for from standard input, one per line
+    stdin: bool,
+
+    #[argh(switch)]
+    /// accept secrets given as command line arguments, where other users can read them
+    insecure_argv: bool,
+
+    #[argh(option)]
+    /// layout of the configuration directory: detect (default), single-file or split
+    layout: Option<StoreLayout>,
+
+    #[argh(option)]
+    /// memory cost in KiB of the Argon2id keys of the secrets written (default 65536)
+    kdf_memory: Option<u32>,
+
+    #[argh(option)]
+    /// iterations of the Argon2id keys of the secrets written (default 3)
+    kdf_iterations: Option<u32>,
+
     #[argh(switch)]
     /// force update of the user configuration if required
     update_as_needed: Option<bool>,
 
+    #[argh(option)]
+    /// output format: text (default), json or yaml
+    output: Option<OutputFormat>,
+
     #[argh(subcommand)]
     command: Command,
 }
@@ -70,11 +137,20 @@ enum Command {
     Setup(SetupCommand),
     Reset(ResetCommand),
     Inspect(InspectCommand),
+    VerifyMain(VerifyMainCommand),
+    Migrate(MigrateCommand),
+    SignConfig(SignConfigCommand),
+    Export(ExportCommand),
+    Import(ImportCommand),
     Add(AddAuthCommand),
     SetSession(SetSessionCommand),
     ChangeMainMount(ChangeMainMountCommand),
     ChangeSecondaryMount(ChangeSecondaryMountCommand),
     Mount(MountCommand),
+    Sessions(SessionsCommand),
+    Doctor(DoctorCommand),
+    Provision(ProvisionCommand),
+    Tui(TuiCommand),
 }
 
 #[derive(FromArgs, PartialEq, Debug)]
@@ -125,8 +201,16 @@ struct ChangeMainMountCommand {
 #[argh(subcommand, name = "setup")]
 struct SetupCommand {
     #[argh(option, short = 'i')]
-    /// the intermediate key
+    /// the intermediate key, requires --insecure-argv
     intermediate: Option<String>,
+
+    #[argh(option)]
+    /// read the intermediate key from this file descriptor
+    intermediate_fd: Option<i32>,
+
+    #[argh(option)]
+    /// read the intermediate key from this file
+    intermediate_file: Option<PathBuf>,
 }
 
 #[derive(FromArgs, PartialEq, Debug)]
@@ -135,11 +219,27 @@ struct SetupCommand {
 struct SetSessionCommand {
     #[argh(option)]
     /// command to execute
-    cmd: String,
+    cmd: Option<String>,
+
+    #[argh(option)]
+    /// desktop session to start, see sessions list (alternative to --cmd)
+    desktop: Option<String>,
 
     #[argh(option)]
     /// additional arguments for the command
     args: Vec<String>,
+
+    #[argh(option)]
+    /// environment variable of the session as KEY=VALUE, can be repeated
+    env: Vec<String>,
+
+    #[argh(option)]
+    /// session type: wayland, x11 or tty
+    session_type: Option<SessionType>,
+
+    #[argh(option)]
+    /// desktop name for XDG_CURRENT_DESKTOP, can be repeated or separated by ':'
+    desktop_names: Vec<String>,
 }
 
 #[derive(FromArgs, PartialEq, Debug)]
@@ -152,6 +252,91 @@ struct ResetCommand {}
 #[argh(subcommand, name = "inspect")]
 struct InspectCommand {}
 
+#[derive(FromArgs, PartialEq, Debug)]
+/// Check that the stored main password matches the current system password
+#[argh(subcommand, name = "verify-main")]
+struct VerifyMainCommand {}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// Bring the user configuration file to the current format version
+#[argh(subcommand, name = "migrate")]
+struct MigrateCommand {
+    #[argh(switch)]
+    /// only print the migration steps that would be applied
+    dry_run: bool,
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// Approve the current mounts and session command of a user, so that later changes are refused
+#[argh(subcommand, name = "sign-config")]
+struct SignConfigCommand {}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// Write auth data, mounts and session command of a user to a file encrypted under a passphrase
+#[argh(subcommand, name = "export")]
+struct ExportCommand {
+    #[argh(option)]
+    /// file to write the encrypted configuration to
+    out: PathBuf,
+
+    #[argh(option)]
+    /// passphrase protecting the exported file, prompted for if unspecified, requires --insecure-argv
+    passphrase: Option<String>,
+
+    #[argh(option)]
+    /// read the passphrase from this file descriptor
+    passphrase_fd: Option<i32>,
+
+    #[argh(option)]
+    /// read the passphrase from this file
+    passphrase_file: Option<PathBuf>,
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// Restore a configuration written by export, for the exported user or the one given with -u
+#[argh(subcommand, name = "import")]
+struct ImportCommand {
+    #[argh(positional)]
+    /// exported file to import
+    file: PathBuf,
+
+    #[argh(option)]
+    /// passphrase protecting the exported file, prompted for if unspecified, requires --insecure-argv
+    passphrase: Option<String>,
+
+    #[argh(option)]
+    /// read the passphrase from this file descriptor
+    passphrase_fd: Option<i32>,
+
+    #[argh(option)]
+    /// read the passphrase from this file
+    passphrase_file: Option<PathBuf>,
+
+    #[argh(option, short = 'i')]
+    /// intermediate key of the exported configuration, prompted for if unspecified, requires --insecure-argv
+    intermediate: Option<String>,
+
+    #[argh(option)]
+    /// read the intermediate key from this file descriptor
+    intermediate_fd: Option<i32>,
+
+    #[argh(option)]
+    /// read the intermediate key from this file
+    intermediate_file: Option<PathBuf>,
+
+    #[argh(option)]
+    /// owner of the imported configuration, defaults to the uid of the user
+    uid: Option<u32>,
+
+    #[argh(option)]
+    /// group of the imported configuration, defaults to the primary group of the user
+    gid: Option<u32>,
+
+    #[argh(switch)]
+    /// replace the configuration of the user if there is one already
+    force: bool,
+}
+
 #[derive(FromArgs, PartialEq, Debug)]
 /// Add a new authentication method
 #[argh(subcommand, name = "add")]
@@ -161,9 +346,17 @@ struct AddAuthCommand {
     name: String,
 
     #[argh(option)]
-    /// intermediate key (the key used to unlock the main password)
+    /// intermediate key (the key used to unlock the main password), requires --insecure-argv
     intermediate: Option<String>,
 
+    #[argh(option)]
+    /// read the intermediate key from this file descriptor
+    intermediate_fd: Option<i32>,
+
+    #[argh(option)]
+    /// read the intermediate key from this file
+    intermediate_file: Option<PathBuf>,
+
     #[argh(subcommand)]
     method: AddAuthMethod,
 }
@@ -180,8 +373,16 @@ enum AddAuthMethod {
 #[argh(subcommand, name = "password")]
 struct AddAuthPasswordCommand {
     #[argh(option)]
-    /// secondary password for authentication
+    /// secondary password for authentication, requires --insecure-argv
     secondary_pw: Option<String>,
+
+    #[argh(option)]
+    /// read the secondary password from this file descriptor
+    secondary_pw_fd: Option<i32>,
+
+    #[argh(option)]
+    /// read the secondary password from this file
+    secondary_pw_file: Option<PathBuf>,
 }
 
 #[derive(FromArgs, PartialEq, Debug)]
@@ -197,6 +398,7 @@ struct MountCommand {
 /// Mount action subcommands
 enum MountAction {
     Authorize(MountAuthorizeCommand),
+    List(MountListCommand),
 }
 
 #[derive(FromArgs, PartialEq, Debug)]
@@ -204,475 +406,1372 @@ enum MountAction {
 #[argh(subcommand, name = "authorize")]
 struct MountAuthorizeCommand {}
 
+#[derive(FromArgs, PartialEq, Debug)]
+/// List the mounts configured for a user
+#[argh(subcommand, name = "list")]
+struct MountListCommand {}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// Session management commands
+#[argh(subcommand, name = "sessions")]
+struct SessionsCommand {
+    #[argh(subcommand)]
+    action: SessionsAction,
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand)]
+/// Session action subcommands
+enum SessionsAction {
+    Active(SessionsActiveCommand),
+    List(SessionsListCommand),
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// List the desktop sessions installed in wayland-sessions and xsessions
+#[argh(subcommand, name = "list")]
+struct SessionsListCommand {}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// List the sessions currently opened by pam_polyauth-service
+#[argh(subcommand, name = "active")]
+struct SessionsActiveCommand {}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// Check the configuration of a user and the system setup, reporting what passes, warns or fails
+#[argh(subcommand, name = "doctor")]
+struct DoctorCommand {
+    #[argh(option)]
+    /// intermediate key the stored main password has to decrypt with, requires --insecure-argv
+    intermediate: Option<String>,
+
+    #[argh(option)]
+    /// read the intermediate key from this file descriptor
+    intermediate_fd: Option<i32>,
+
+    #[argh(option)]
+    /// read the intermediate key from this file
+    intermediate_file: Option<PathBuf>,
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// Create or update the configuration of the users described in a YAML manifest
+#[argh(subcommand, name = "provision")]
+struct ProvisionCommand {
+    #[argh(positional)]
+    /// manifest describing the users
+    manifest: PathBuf,
+
+    #[argh(switch)]
+    /// only print the changes that would be made
+    dry_run: bool,
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// Manage passwords and mounts of a user from an interactive terminal interface
+#[argh(subcommand, name = "tui")]
+struct TuiCommand {}
+
+/// User a command that may run on behalf of someone else operates on
+fn target_username(args: &Args, current_username: &str, output: &Output) -> String {
+    match (&args.username, &args.config_file) {
+        (Some(user), _) => user.clone(),
+        (None, None) => String::from(current_username),
+        (None, Some(_)) => output.fail(CliError::new(
+            ErrorClass::Usage,
+            "Username must be specified when using a config file",
+        )),
+    }
+}
+
+async fn system_bus(output: &Output) -> Connection {
+    match Connection::system().await {
+        Ok(connection) => connection,
+        Err(err) => output.fail(CliError::from(err).context("Error connecting to system bus")),
+    }
+}
+
+async fn mount_auth_proxy<'a>(
+    connection: &'a Connection,
+    output: &Output,
+) -> MountAuthDBusProxy<'a> {
+    match MountAuthDBusProxy::new(connection).await {
+        Ok(proxy) => proxy,
+        Err(err) => output.fail(CliError::from(err).context("Error creating mount auth proxy")),
+    }
+}
+
+/// Whether the mounts with the given hash are authorized for the user: the file of
+/// pam_polyauth-service is read directly when possible, the service is asked otherwise.
+async fn is_mount_authorized(username: &str, hash: String) -> zbus::Result<bool> {
+    let path = service_data_dir().join(AUTHORIZED_MOUNTS_FILE_NAME);
+    match MountAuth::load_from_file(&path.to_string_lossy()) {
+        Ok(authorizations) => return Ok(authorizations.authorized(username, hash)),
+        Err(ServiceError::IOError(err)) if err.kind() == std::io::ErrorKind::NotFound => {
+            return Ok(false)
+        }
+        Err(_) => {}
+    }
+
+    let connection = Connection::system().await?;
+    MountAuthDBusProxy::new(&connection)
+        .await?
+        .check(username, hash)
+        .await
+}
+
+async fn check_mount_authorization(username: &str, hash: String) -> Check {
+    const NAME: &str = "mount authorization";
+
+    match is_mount_authorized(username, hash).await {
+        Ok(true) => Check::pass(NAME, "The configured mounts are authorized"),
+        Ok(false) => Check::fail(
+            NAME,
+            "The configured mounts are not authorized: run polyauthctl mount authorize as root",
+        ),
+        Err(err) => Check::warn(
+            NAME,
+            format!("Cannot read the authorizations nor ask the service: {err}"),
+        ),
+    }
+}
+
+/// Reads a secret listed in a provisioning manifest from its source
+fn read_manifest_secret(
+    output: &Output,
+    input: &SecretInput,
+    username: &str,
+    kind: &SecretKind,
+    source: &SecretSource,
+    stdin: &mut std::io::StdinLock,
+) -> SecretBytes {
+    let what = format!("{kind} for user '{username}'");
+    let usage = match kind {
+        SecretKind::IntermediateKey => SecretUse::Key,
+        SecretKind::MainPassword | SecretKind::Secondary(_) => SecretUse::Password,
+    };
+
+    let secret = match source {
+        // a typo in a new intermediate key or secondary password would lock the user out
+        SecretSource::Prompt => match kind {
+            SecretKind::MainPassword => input.prompt(&format!("{what}:"), usage),
+            _ => input.prompt_confirmed(
+                &format!("{what}:"),
+                &format!("{what} (confirm):"),
+                &kind.to_string(),
+                usage,
+            ),
+        },
+        SecretSource::Stdin => match read_secret_line(stdin) {
+            Ok(Some(secret)) => secret,
+            Ok(None) => output.fail(CliError::new(
+                ErrorClass::Usage,
+                format!("The standard input ended before the {what}"),
+            )),
+            Err(err) => output.fail(CliError::new(
+                ErrorClass::Usage,
+                format!("Error reading the {what} from the standard input: {err}"),
+            )),
+        },
+        SecretSource::File { path } => match std::fs::File::open(path).and_then(read_secret) {
+            Ok(secret) => secret,
+            Err(err) => output.fail(
+                CliError::from(StorageError::IoError(err))
+                    .context(&format!("Error reading the {what} from {}", path.display())),
+            ),
+        },
+    };
+
+    input.validate(&what, usage, secret)
+}
+
+async fn provision(
+    output: &Output,
+    input: &SecretInput,
+    store: &dyn UserStore,
+    kdf: &KdfParams,
+    config_file: bool,
+    provision_cmd: &ProvisionCommand,
+) {
+    let manifest = match Manifest::load(&provision_cmd.manifest) {
+        Ok(manifest) => manifest,
+        Err(err) => output.fail(CliError::from(err).context("Error loading the manifest")),
+    };
+
+    if config_file && manifest.users.len() != 1 {
+        output.fail(CliError::new(
+            ErrorClass::Usage,
+            "A config file can only be provisioned from a manifest with a single user",
+        ))
+    }
+
+    // everything is read and checked before the first change is written
+    let session_dirs = SessionDirs::default();
+    let mut stdin = std::io::stdin().lock();
+    let mut users = vec![];
+    for user in manifest.users.iter() {
+        let Some(user_info) = get_user_by_name(&user.username) else {
+            output.fail(CliError::new(
+                ErrorClass::NotFound,
+                format!("Username '{}' does not exist in the system", user.username),
+            ))
+        };
+
+        if let Some(desktop) = user.session.as_ref().and_then(|session| session.desktop()) {
+            if let Err(err) = session_dirs.find(desktop) {
+                output.fail(CliError::from(err).context(&format!("User '{}'", user.username)))
+            }
+        }
+
+        let mut secrets = UserSecrets::default();
+        for (kind, source) in user.secret_sources() {
+            let secret =
+                read_manifest_secret(output, input, &user.username, &kind, source, &mut stdin);
+            secrets.insert(kind, secret);
+        }
+
+        let plan = match plan_user(store, user, &secrets, kdf) {
+            Ok(plan) => plan,
+            Err(err) => output.fail(err),
+        };
+
+        let mut changes = plan.changes().clone();
+        let authorize = match plan.mounts_to_authorize() {
+            Some(hash) => match is_mount_authorized(&user.username, String::from(hash)).await {
+                Ok(true) => None,
+                Ok(false) => {
+                    changes.push(Change::new(
+                        "mounts.authorization",
+                        None,
+                        Some(String::from(hash)),
+                    ));
+                    Some(String::from(hash))
+                }
+                Err(err) => output.fail(CliError::from(err).context(&format!(
+                    "Error checking the mount authorization of '{}'",
+                    user.username
+                ))),
+            },
+            None => None,
+        };
+
+        users.push((plan, user_info, authorize, changes));
+    }
+
+    if !provision_cmd.dry_run {
+        // the service is reached before anything is written, so that it cannot be
+        // missing once the configuration of some users has already been changed
+        let connection = match users.iter().find_map(|(plan, _, authorize, _)| {
+            authorize.as_ref().map(|hash| (plan.username(), hash))
+        }) {
+            Some((username, hash)) => {
+                let connection = system_bus(output).await;
+                let proxy = mount_auth_proxy(&connection, output).await;
+                if let Err(err) = proxy.check(username, hash.clone()).await {
+                    output.fail(
+                        CliError::from(err)
+                            .context("Error reaching the service to authorize the mounts"),
+                    )
+                }
+                Some(connection)
+            }
+            None => None,
+        };
+
+        let mut applied: Vec<&str> = vec![];
+        for (plan, user_info, authorize, _) in users.iter() {
+            let partial_failure = |err: CliError, applied: &[&str]| -> ! {
+                let applied_message = match applied.is_empty() {
+                    true => String::from("no user has been provisioned"),
+                    false => format!("already provisioned: {}", applied.join(", ")),
+                };
+
+                output.fail(
+                    err.context(&format!(
+                        "Error provisioning '{}' ({applied_message})",
+                        plan.username()
+                    ))
+                    .with_detail("applied", applied.join(",")),
+                )
+            };
+
+            if let Err(err) = plan.apply(
+                store,
+                Some(user_info.uid()),
+                Some(user_info.primary_group_id()),
+            ) {
+                partial_failure(
+                    CliError::from(err).context("Error saving the configuration"),
+                    &applied,
+                )
+            }
+
+            if let (Some(hash), Some(connection)) = (authorize, &connection) {
+                let proxy = mount_auth_proxy(connection, output).await;
+                if let Err(err) = proxy.authorize(plan.username(), hash.clone()).await {
+                    partial_failure(
+                        CliError::from(err).context(
+                            "The configuration has been saved, but authorizing the mounts failed",
+                        ),
+                        &applied,
+                    )
+                }
+            }
+
+            applied.push(plan.username());
+        }
+    }
+
+    let report = ProvisionReport {
+        dry_run: provision_cmd.dry_run,
+        users: users
+            .into_iter()
+            .map(|(plan, _, _, changes)| UserProvisionReport {
+                user: String::from(plan.username()),
+                changes,
+            })
+            .collect(),
+    };
+
+    if !output.is_text() {
+        return output.emit(&report);
+    }
+
+    for user in report.users.iter() {
+        if user.changes.is_empty() {
+            println!("👤 {}: up to date", user.user);
+            continue;
+        }
+
+        println!("👤 {}:", user.user);
+        for change in user.changes.iter() {
+            println!("    {change}");
+        }
+    }
+
+    match report.dry_run {
+        true => println!("🔍 Dry run: nothing has been written"),
+        false => println!("✅ {} user(s) provisioned", report.users.len()),
+    }
+}
+
+/// Checks that pam_polyauth-service answers on the system bus
+async fn check_service() -> Check {
+    const NAME: &str = "service";
+
+    let connection = match Connection::system().await {
+        Ok(connection) => connection,
+        Err(err) => return Check::fail(NAME, format!("Cannot connect to the system bus: {err}")),
+    };
+
+    let version = match SessionsProxy::new(&connection).await {
+        Ok(proxy) => proxy.version().await,
+        Err(err) => Err(err),
+    };
+
+    match version {
+        Ok(version) => Check::pass(
+            NAME,
+            format!("pam_polyauth-service {version} is reachable on the system bus"),
+        ),
+        Err(err) => Check::fail(NAME, format!("Not reachable on the system bus: {err}")),
+    }
+}
+
+async fn doctor(
+    store: &dyn UserStore,
+    username: &str,
+    intermediate_key: Option<SecretBytes>,
+    main_password: Option<SecretBytes>,
+) -> Vec<Check> {
+    let config = check_config(store, username);
+    let user_checks = config.status() != CheckStatus::Fail;
+    let mut checks = vec![config];
+
+    // a configuration that cannot be loaded has nothing else to check
+    if user_checks {
+        match load_user_auth_data(store, username) {
+            Ok(auth_data) => checks.extend(check_main_password(
+                &auth_data.unwrap_or_default(),
+                intermediate_key.as_ref().map(|key| key.expose()),
+                main_password.as_ref().map(|password| password.expose()),
+            )),
+            Err(err) => checks.push(Check::fail("main password", err.to_string())),
+        }
+
+        match load_user_mountpoints(store, username) {
+            Ok(Some(mounts)) => {
+                checks.extend(check_mount_devices(&mounts, Path::new(DISK_BY_DIR)));
+                checks.push(check_mount_authorization(username, mounts.hash()).await);
+            }
+            Ok(None) => checks.push(Check::pass("mounts", "No mounts configured")),
+            Err(err) => checks.push(Check::fail("mounts", err.to_string())),
+        }
+    }
+
+    checks.push(check_pam_stack(Path::new(PAM_CONFIG_DIR)));
+    checks.push(check_service().await);
+    checks.extend(check_dbus_policies(&DBUS_SYSTEM_POLICY_DIRS));
+
+    let data_dir = service_data_dir();
+    checks.push(check_file_permissions(
+        "private key",
+        &data_dir.join(PRIVATE_KEY_FILE_NAME),
+        0o077,
+    ));
+    checks.push(check_file_permissions(
+        "configuration signing key",
+        &data_dir.join(SIGNING_KEY_FILE_NAME),
+        0o077,
+    ));
+    checks.push(check_file_permissions(
+        "authorized mounts",
+        &data_dir.join(AUTHORIZED_MOUNTS_FILE_NAME),
+        0o022,
+    ));
+
+    checks
+}
+
 #[tokio::main]
 async fn main() {
     let args: Args = argh::from_env();
+    let output = Output::new(args.output.unwrap_or_default());
+
+    let input = SecretInput::new(output, args.stdin, args.insecure_argv);
+
+    let maybe_main_password = input.option(
+        "password",
+        SecretUse::Password,
+        &args.password,
+        args.password_fd,
+        &args.password_file,
+    );
+    let layout = args.layout.unwrap_or_default();
+    let kdf = KdfParams::argon2id(
+        args.kdf_memory.unwrap_or(DEFAULT_ARGON2_M_COST),
+        args.kdf_iterations.unwrap_or(DEFAULT_ARGON2_T_COST),
+        DEFAULT_ARGON2_P_COST,
+    );
+    if let Err(err) = kdf.check() {
+        output.fail(CliError::new(
+            ErrorClass::Usage,
+            format!("Invalid --kdf-memory or --kdf-iterations: {MIN_ARGON2_M_COST} to {MAX_ARGON2_M_COST} KiB and {MIN_ARGON2_T_COST} to {MAX_ARGON2_T_COST} iterations are accepted ({err})"),
+        ))
+    }
+    let current_username = match users::get_current_username() {
+        Some(username) => username.to_string_lossy().to_string(),
+        None => output.fail(CliError::new(
+            ErrorClass::NotFound,
+            "The current user could not be identified",
+        )),
+    };
+    let store: Box<dyn UserStore> = match &args.config_file {
+        Some(path) => Box::new(JsonStore::file(path.clone())),
+        None => Box::new(system_store(layout)),
+    };
+
+    // provisioning loads the configuration of every user in the manifest by itself
+    if let Command::Provision(provision_cmd) = &args.command {
+        provision(
+            &output,
+            &input,
+            store.as_ref(),
+            &kdf,
+            args.config_file.is_some(),
+            provision_cmd,
+        )
+        .await;
+        return;
+    }
+
+    // the interface keeps running when changes fail, reporting them on screen
+    if let Command::Tui(_) = &args.command {
+        if !output.is_text() {
+            output.fail(CliError::new(
+                ErrorClass::Usage,
+                "The interface has no machine-readable output",
+            ))
+        }
+
+        let username = args.username.clone().unwrap_or(current_username.clone());
+        if let Err(err) = tui::run(store.as_ref(), &username).await {
+            output.fail(err)
+        }
+        return;
+    }
 
-    let (storage_source, maybe_main_password) = match &args.config_file {
-        Some(path) => (StorageSource::File(path.clone()), args.password.clone()),
-        None => (
-            StorageSource::Username(
-                users::get_current_username()
-                    .unwrap()
-                    .to_string_lossy()
-                    .to_string(),
+    // the doctor reports broken configurations instead of failing on them
+    if let Command::Doctor(doctor_cmd) = &args.command {
+        let username = args.username.clone().unwrap_or(current_username.clone());
+        let checks = doctor(
+            store.as_ref(),
+            &username,
+            input.option(
+                "intermediate",
+                SecretUse::Key,
+                &doctor_cmd.intermediate,
+                doctor_cmd.intermediate_fd,
+                &doctor_cmd.intermediate_file,
             ),
-            args.password.clone(),
-        ),
-    };
+            maybe_main_password.clone(),
+        )
+        .await;
+
+        let report = DoctorReport {
+            user: username,
+            healthy: healthy(&checks),
+            checks,
+        };
+
+        if !output.is_text() {
+            output.emit(&report)
+        } else {
+            println!("🩺 Checking the setup of user '{}'", report.user);
+            for check in report.checks.iter() {
+                let icon = match check.status() {
+                    CheckStatus::Pass => "✅",
+                    CheckStatus::Warn => "⚠️ ",
+                    CheckStatus::Fail => "❌",
+                };
+                println!("{icon} {}: {}", check.name(), check.message());
+            }
+        }
 
-    let mut user_cfg = match load_user_auth_data(&storage_source) {
+        match report.healthy {
+            true => std::process::exit(0),
+            false => std::process::exit(ErrorClass::Unhealthy.exit_code()),
+        }
+    }
+
+    let mut user_cfg = match load_user_auth_data(store.as_ref(), &current_username) {
         Ok(load_res) => match load_res {
             Some(auth_data) => auth_data,
             None => UserAuthData::new(),
         },
         Err(err) => {
             if let Command::Setup(_) = &args.command {
-                eprintln!("❌ There is a problem loading your configuration file: {err}");
-                std::process::exit(-1)
+                output.fail(
+                    CliError::from(err)
+                        .context("There is a problem loading your configuration file"),
+                )
             }
 
             UserAuthData::new()
         }
     };
+    user_cfg.set_kdf(kdf);
 
-    let mut user_mounts = match load_user_mountpoints(&storage_source) {
+    let mut user_mounts = match load_user_mountpoints(store.as_ref(), &current_username) {
         Ok(existing_data) => existing_data,
-        Err(err) => {
-            eprintln!("❌ Error in loading user mounts data: {err}");
-            std::process::exit(-1)
-        }
+        Err(err) => output.fail(CliError::from(err).context("Error in loading user mounts data")),
     };
 
+    // reported once the configuration has been written
+    let mut done_message = None;
+
     let mut write_file = args.update_as_needed;
-    match args.command {
-        Command::Mount(mount_cmd) => match mount_cmd.action {
+    match &args.command {
+        Command::Mount(mount_cmd) => match &mount_cmd.action {
             MountAction::Authorize(_) => {
-                let username = match args.username {
-                    Some(ref user) => user.clone(),
-                    None => match &storage_source {
-                        StorageSource::Username(user) => user.clone(),
-                        StorageSource::File(_) => {
-                            eprintln!("❌ Username must be specified when using a config file");
-                            std::process::exit(-1)
-                        }
-                    },
-                };
+                let username = target_username(&args, &current_username, &output);
 
-                let storage_source_for_mount = StorageSource::Username(username.clone());
-
-                let user_mounts = match load_user_mountpoints(&storage_source_for_mount) {
+                let user_mounts = match load_user_mountpoints(&system_store(layout), &username) {
                     Ok(existing_data) => existing_data,
-                    Err(err) => {
-                        eprintln!("❌ Error in loading user mounts data: {err}");
-                        std::process::exit(-1)
-                    }
+                    Err(err) => output
+                        .fail(CliError::from(err).context("Error in loading user mounts data")),
                 };
 
                 let Some(loaded_mounts) = user_mounts else {
-                    eprintln!("⚠️  User does not have mounts configured");
-                    std::process::exit(-1)
+                    output.fail(CliError::new(
+                        ErrorClass::NotFound,
+                        "User does not have mounts configured",
+                    ))
                 };
 
-                let connection = Connection::system()
-                    .await
-                    .map_err(|err| {
-                        eprintln!("❌ Error connecting to system bus: {err}");
-                        std::process::exit(-1)
-                    })
-                    .unwrap();
+                let connection = system_bus(&output).await;
+                let proxy = mount_auth_proxy(&connection, &output).await;
 
-                let proxy = MountAuthDBusProxy::new(&connection)
-                    .await
-                    .map_err(|err| {
-                        eprintln!("❌ Error creating mount auth proxy: {err}");
-                        std::process::exit(-1)
-                    })
-                    .unwrap();
-
-                let reply = proxy
+                if let Err(err) = proxy
                     .authorize(username.as_str(), loaded_mounts.hash())
                     .await
-                    .map_err(|err| {
-                        eprintln!("❌ Error authorizing mount: {err}");
-                        std::process::exit(-1)
-                    })
-                    .unwrap();
+                {
+                    output.fail(CliError::from(err).context("Error in authorizing the user mount"))
+                }
+
+                output.success(format!("Mount authorized for user '{username}'"));
+            }
+            MountAction::List(_) => {
+                let username = args.username.clone().unwrap_or(current_username.clone());
 
-                let result = ServiceOperationResult::from(reply);
+                let mounts = match load_user_mountpoints(store.as_ref(), &username) {
+                    Ok(existing_data) => existing_data,
+                    Err(err) => output
+                        .fail(CliError::from(err).context("Error in loading user mounts data")),
+                };
 
-                if result != ServiceOperationResult::Ok {
-                    eprintln!("❌ Error in authorizing the user mount: {result}");
-                    std::process::exit(-1)
+                let report = MountListReport {
+                    user: username,
+                    mounts: mounts.as_ref().map(MountsReport::from),
+                };
+
+                match (output.is_text(), &report.mounts) {
+                    (false, _) => output.emit(&report),
+                    (true, Some(mounts)) => print_mounts(mounts),
+                    (true, None) => println!("ℹ️  No user-defined mounts"),
                 }
+            }
+        },
+        // exit before the configuration is loaded
+        Command::Doctor(_) | Command::Provision(_) | Command::Tui(_) => unreachable!(),
+        Command::Sessions(sessions_cmd) => match &sessions_cmd.action {
+            SessionsAction::List(_) => {
+                let sessions = SessionDirs::default().list();
+
+                if !output.is_text() {
+                    output.emit(&DesktopSessionsReport {
+                        sessions: sessions.iter().map(DesktopSessionReport::from).collect(),
+                    })
+                } else if sessions.is_empty() {
+                    println!("ℹ️  No desktop sessions installed.")
+                } else {
+                    println!("-----------------------------------------------------------");
+                    for session in sessions {
+                        println!(
+                            "🖥️  {}: {} ({})",
+                            session.id(),
+                            session.name(),
+                            session.session_type()
+                        );
+                        println!("    🚀 exec: {}", session.exec().join(" "));
+                        if !session.desktop_names().is_empty() {
+                            println!("    🏷️  desktop: {}", session.desktop_names().join(":"));
+                        }
+                        println!("-----------------------------------------------------------");
+                    }
+                }
+            }
+            SessionsAction::Active(_) => {
+                let connection = system_bus(&output).await;
+                let proxy = match SessionsProxy::new(&connection).await {
+                    Ok(proxy) => proxy,
+                    Err(err) => output
+                        .fail(CliError::from(err).context("Error creating the sessions proxy")),
+                };
+
+                let mut sessions = match proxy.list_sessions().await {
+                    Ok(sessions) => sessions,
+                    Err(err) => output
+                        .fail(CliError::from(err).context("Error listing the active sessions")),
+                };
+                sessions.sort_by(|a, b| a.username.cmp(&b.username));
 
-                println!("✅ Mount authorized for user '{}'", username);
+                if !output.is_text() {
+                    output.emit(&SessionsReport { sessions })
+                } else if sessions.is_empty() {
+                    println!("ℹ️  No active sessions.")
+                } else {
+                    println!("-----------------------------------------------------------");
+                    for session in sessions {
+                        println!("👤 User: {} (uid {})", session.username, session.uid);
+                        println!("    🔢 sessions: {}", session.count);
+                        match Local.timestamp_opt(session.opened_at as i64, 0).single() {
+                            Some(opened_at) => println!("    📅 opened at: {opened_at}"),
+                            None => println!("    📅 opened at: {}", session.opened_at),
+                        }
+                        for path in session.mounted_paths {
+                            println!("    📁 mounted: {path}");
+                        }
+                        println!("-----------------------------------------------------------");
+                    }
+                }
             }
         },
+        Command::SignConfig(_) => {
+            let username = target_username(&args, &current_username, &output);
+
+            let connection = system_bus(&output).await;
+            let proxy = mount_auth_proxy(&connection, &output).await;
+
+            if let Err(err) = proxy.sign_user_config(username.as_str()).await {
+                output.fail(CliError::from(err).context("Error in signing the user configuration"))
+            }
+
+            output.success(format!("Configuration of user '{username}' signed"));
+        }
+        Command::Export(export_cmd) => {
+            let username = args.username.clone().unwrap_or(current_username.clone());
+
+            let passphrase = match input.option(
+                "passphrase",
+                SecretUse::Key,
+                &export_cmd.passphrase,
+                export_cmd.passphrase_fd,
+                &export_cmd.passphrase_file,
+            ) {
+                Some(passphrase) => passphrase,
+                None => input.prompt_confirmed(
+                    "export passphrase:",
+                    "export passphrase (confirm):",
+                    "Passphrase",
+                    SecretUse::Key,
+                ),
+            };
+
+            let contents =
+                match export_user_config(store.as_ref(), &username, passphrase.expose(), &kdf) {
+                    Ok(contents) => contents,
+                    Err(err) => output.fail(
+                        CliError::from(err).context("Error exporting the user configuration"),
+                    ),
+                };
+
+            let written = std::fs::OpenOptions::new()
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .mode(0o600)
+                .open(&export_cmd.out)
+                .and_then(|mut file| file.write_all(&contents).and_then(|_| file.sync_all()));
+            if let Err(err) = written {
+                output.fail(CliError::new(
+                    ErrorClass::Storage,
+                    format!("Error writing {}: {err}", export_cmd.out.to_string_lossy()),
+                ))
+            }
+
+            output.success(format!(
+                "Configuration of user '{username}' exported to {}",
+                export_cmd.out.to_string_lossy()
+            ));
+            write_file = Some(false);
+        }
+        Command::Import(import_cmd) => {
+            let contents = match std::fs::read(&import_cmd.file) {
+                Ok(contents) => contents,
+                Err(err) => output.fail(CliError::from(StorageError::from(err)).context(&format!(
+                    "Error reading {}",
+                    import_cmd.file.to_string_lossy()
+                ))),
+            };
+
+            let passphrase = match input.option(
+                "passphrase",
+                SecretUse::Key,
+                &import_cmd.passphrase,
+                import_cmd.passphrase_fd,
+                &import_cmd.passphrase_file,
+            ) {
+                Some(passphrase) => passphrase,
+                None => input.prompt("export passphrase:", SecretUse::Key),
+            };
+
+            let export = match decrypt_user_config(&contents, passphrase.expose()) {
+                Ok(export) => export,
+                Err(err) => output.fail(
+                    CliError::from(err).context("Error decrypting the exported configuration"),
+                ),
+            };
+
+            let import_username = args
+                .username
+                .clone()
+                .unwrap_or(export.username().to_string());
+
+            let (uid, gid) = match get_user_by_name(&import_username) {
+                Some(user_info) => (
+                    import_cmd.uid.unwrap_or(user_info.uid()),
+                    import_cmd.gid.unwrap_or(user_info.primary_group_id()),
+                ),
+                None => match (import_cmd.uid, import_cmd.gid) {
+                    (Some(uid), Some(gid)) => (uid, gid),
+                    _ => output.fail(CliError::new(
+                        ErrorClass::NotFound,
+                        format!("Username '{import_username}' does not exist in the system: specify --uid and --gid"),
+                    )),
+                },
+            };
+
+            // the intermediate key is checked before anything is written
+            let auth_data = match export.auth_data() {
+                Ok(auth_data) => auth_data,
+                Err(err) => output.fail(
+                    CliError::from(err).context("Error loading the exported authentication data"),
+                ),
+            };
+
+            if let Some(auth_data) = auth_data.filter(|auth_data| auth_data.has_main()) {
+                let intermediate_key = match input.option(
+                    "intermediate",
+                    SecretUse::Key,
+                    &import_cmd.intermediate,
+                    import_cmd.intermediate_fd,
+                    &import_cmd.intermediate_file,
+                ) {
+                    Some(ik) => ik,
+                    None => input.prompt("intermediate key:", SecretUse::Key),
+                };
+
+                match auth_data.is_intermediate_key(intermediate_key.expose()) {
+                    Ok(true) => {}
+                    Ok(false) => output.fail(CliError::new(
+                        ErrorClass::Authentication,
+                        "The intermediate key does not unlock the exported main password",
+                    )),
+                    Err(err) => output.fail(
+                        CliError::from(err)
+                            .context("Could not verify the correctness of the intermediate key"),
+                    ),
+                }
+            }
+
+            match store.read(&import_username) {
+                Ok(Some(_)) if !import_cmd.force => output.fail(CliError::new(
+                    ErrorClass::Usage,
+                    format!("User '{import_username}' already has a configuration: use --force to replace it"),
+                )),
+                Ok(_) => {}
+                Err(err) => output.fail(
+                    CliError::from(err).context("Error loading the current user configuration"),
+                ),
+            }
+
+            if let Err(err) = import_user_config(
+                &export,
+                store.as_ref(),
+                &import_username,
+                Some(uid),
+                Some(gid),
+            ) {
+                output.fail(CliError::from(err).context("Error importing the user configuration"))
+            }
+
+            let exported_at = match Local.timestamp_opt(export.exported_at() as i64, 0).single() {
+                Some(date) => date.to_string(),
+                None => export.exported_at().to_string(),
+            };
+            output.success(format!(
+                "Configuration of user '{}' exported at {exported_at} imported for user '{import_username}'",
+                export.username(),
+            ));
+
+            if export.mountpoints().is_some() || export.session_command().is_some() {
+                eprintln!("ℹ️  Mounts and session command have to be approved again on this machine: use mount authorize or sign-config");
+            }
+
+            write_file = Some(false);
+        }
         Command::Info(_) => {
             let version = pam_polyauth::LIBRARY_VERSION;
-            println!("pam_polyauth version {version}, Copyright (C) 2024-2025 Denis Benato");
-            println!("pam_polyauth comes with ABSOLUTELY NO WARRANTY;");
-            println!("This is free software, and you are welcome to redistribute it");
-            println!("under certain conditions.");
-            println!("\n");
+            if !output.is_text() {
+                output.emit(&InfoReport {
+                    version: String::from(version),
+                })
+            } else {
+                println!("pam_polyauth version {version}, Copyright (C) 2024-2025 Denis Benato");
+                println!("pam_polyauth comes with ABSOLUTELY NO WARRANTY;");
+                println!("This is free software, and you are welcome to redistribute it");
+                println!("under certain conditions.");
+                println!("\n");
+            }
         }
         Command::ChangeSecondaryMount(mount_data) => {
             let Some(new_data) = user_mounts else {
-                eprintln!("❌ Error in changing user mounts: a main mount has not been defined");
-                std::process::exit(-1)
+                output.fail(CliError::new(
+                    ErrorClass::NotFound,
+                    "Error in changing user mounts: a main mount has not been defined",
+                ))
             };
 
-            user_mounts = Some(new_data.with_premount(
-                &mount_data.dir,
-                &MountParams::new(mount_data.device, mount_data.fstype, mount_data.flags),
-            ));
+            let params = MountParams::new(
+                mount_data.device.clone(),
+                mount_data.fstype.clone(),
+                mount_data.flags.clone(),
+            );
+            if let Err(err) = validate_mount_dir(&mount_data.dir).and(params.validate()) {
+                output.fail(CliError::from(err))
+            }
+
+            user_mounts = Some(new_data.with_premount(&mount_data.dir, &params));
 
+            done_message = Some(format!("Mount of {} set", mount_data.dir));
             write_file = Some(true)
         }
         Command::ChangeMainMount(mount_data) => {
-            user_mounts = Some(
-                user_mounts
-                    .unwrap_or_default()
-                    .with_mount(&MountParams::new(
-                        mount_data.device,
-                        mount_data.fstype,
-                        mount_data.flags,
-                    )),
+            let params = MountParams::new(
+                mount_data.device.clone(),
+                mount_data.fstype.clone(),
+                mount_data.flags.clone(),
             );
+            if let Err(err) = params.validate() {
+                output.fail(CliError::from(err))
+            }
+
+            user_mounts = Some(user_mounts.unwrap_or_default().with_mount(&params));
 
+            done_message = Some(String::from("Home directory mount set"));
             write_file = Some(true)
         }
         Command::SetSession(session_data) => {
-            let command = SessionCommand::new(session_data.cmd);
-
-            match store_user_session_command(&command, &storage_source, None, None) {
-                Ok(_) => {}
-                Err(err) => {
-                    eprintln!("❌ Error changing the user default session: {err}");
-                    std::process::exit(-1)
+            let mut env = BTreeMap::new();
+            for variable in session_data.env.iter() {
+                match variable.split_once('=') {
+                    Some((key, value)) if !key.is_empty() => {
+                        env.insert(key.to_string(), value.to_string());
+                    }
+                    _ => output.fail(CliError::new(
+                        ErrorClass::Usage,
+                        format!("Environment variable '{variable}' is not in the KEY=VALUE form"),
+                    )),
                 }
             }
+
+            let desktop_names = session_data
+                .desktop_names
+                .iter()
+                .flat_map(|names| names.split(':'))
+                .filter(|name| !name.is_empty())
+                .map(String::from)
+                .collect();
+
+            let (command, done_message) = match (&session_data.cmd, &session_data.desktop) {
+                (Some(cmd), None) => (
+                    SessionCommand::new(cmd.clone()),
+                    format!("Default session set to {cmd}"),
+                ),
+                (None, Some(desktop)) => match SessionDirs::default().find(desktop) {
+                    Ok(session) => (
+                        SessionCommand::desktop_session(desktop.clone()),
+                        format!(
+                            "Default session set to the {} desktop session ({})",
+                            session.name(),
+                            session.id()
+                        ),
+                    ),
+                    Err(err) => output.fail(CliError::from(err)),
+                },
+                _ => output.fail(CliError::new(
+                    ErrorClass::Usage,
+                    "Exactly one of --cmd and --desktop must be specified",
+                )),
+            };
+
+            let command = command
+                .with_args(session_data.args.clone())
+                .with_env(env)
+                .with_session_type(session_data.session_type.unwrap_or_default())
+                .with_desktop_names(desktop_names);
+
+            if let Err(err) =
+                store_user_session_command(&command, store.as_ref(), &current_username, None, None)
+            {
+                output.fail(CliError::from(err).context("Error changing the user default session"))
+            }
+
+            output.success(done_message);
         }
         Command::Setup(s) => {
             if user_cfg.has_main() {
-                eprintln!("⚠️  User already has an intermediate key present: use reset if you want to delete the old one");
-                std::process::exit(-1)
+                output.fail(CliError::new(
+                    ErrorClass::Usage,
+                    "User already has an intermediate key present: use reset if you want to delete the old one",
+                ))
             }
 
             // Check if username is specified and exists
             let setup_username = match &args.username {
                 Some(user) => user.clone(),
-                None => {
-                    eprintln!("❌ Username must be specified for setup command");
-                    std::process::exit(-1)
-                }
+                None => output.fail(CliError::new(
+                    ErrorClass::Usage,
+                    "Username must be specified for setup command",
+                )),
             };
 
             let Some(user_info) = get_user_by_name(&setup_username) else {
-                eprintln!("❌ Username '{setup_username}' does not exist in the system");
-                std::process::exit(-1)
+                output.fail(CliError::new(
+                    ErrorClass::NotFound,
+                    format!("Username '{setup_username}' does not exist in the system"),
+                ))
             };
 
             // Collect uid and gid to change ownership to the setup username (user exists)
             let uid = user_info.uid();
             let gid = user_info.primary_group_id();
 
-            // Determine the correct storage source for setup
-            let setup_storage_source = match &args.config_file {
+            // Determine the correct store for setup
+            let setup_store: Box<dyn UserStore> = match &args.config_file {
                 Some(path) => {
                     // If config_file is specified, enforce it
-                    StorageSource::File(path.clone())
+                    Box::new(JsonStore::file(path.clone()))
                 }
                 None => {
                     // If no config_file specified, use the location that will be searched by the service
-                    StorageSource::Username(setup_username.clone())
+                    Box::new(system_store(layout))
                 }
             };
 
-            let intermediate_key = match s.intermediate {
-                Some(ik) => ik.clone(),
-                None => {
-                    let ik = prompt_password("intermediate key:").unwrap();
-                    let ikc = prompt_password("intermediate key (confirm):").unwrap();
-
-                    if ik != ikc {
-                        eprintln!("❌ Intermediate key and confirmation not matching");
-                        std::process::exit(-1)
-                    }
-
-                    ik
-                }
+            let intermediate_key = match input.option(
+                "intermediate",
+                SecretUse::Key,
+                &s.intermediate,
+                s.intermediate_fd,
+                &s.intermediate_file,
+            ) {
+                Some(ik) => ik,
+                None => input.prompt_confirmed(
+                    "intermediate key:",
+                    "intermediate key (confirm):",
+                    "Intermediate key",
+                    SecretUse::Key,
+                ),
             };
 
             let password = match &maybe_main_password {
                 Some(password) => password.clone(),
-                None => prompt_password("main password:").unwrap(),
+                None => input.prompt("main password:", SecretUse::Password),
             };
 
-            match user_cfg.set_main(&password, &intermediate_key) {
-                Ok(_) => {
-                    // Save the setup configuration
-                    if let Err(err) =
-                        store_user_auth_data(&user_cfg, &setup_storage_source, Some(uid), Some(gid))
-                    {
-                        eprintln!("❌ Error saving the user authentication data: {err}");
-                        std::process::exit(-1)
-                    }
+            if let Err(err) = user_cfg.set_main(password.expose(), intermediate_key.expose()) {
+                output.fail(
+                    CliError::from(err).context("Error initializing the user authentication data"),
+                )
+            }
 
-                    // Load mounts for authorization
-                    let setup_user_mounts = match load_user_mountpoints(&setup_storage_source) {
-                        Ok(existing_data) => existing_data,
-                        Err(err) => {
-                            eprintln!("❌ Error loading user mounts data: {err}");
-                            std::process::exit(-1)
-                        }
-                    };
+            // Save the setup configuration
+            if let Err(err) = store_user_auth_data(
+                &user_cfg,
+                setup_store.as_ref(),
+                &setup_username,
+                Some(uid),
+                Some(gid),
+            ) {
+                output
+                    .fail(CliError::from(err).context("Error saving the user authentication data"))
+            }
 
-                    // Save mounts if any
-                    if let Err(err) = store_user_mountpoints(
-                        setup_user_mounts.clone(),
-                        &setup_storage_source,
-                        None,
-                        None,
-                    ) {
-                        eprintln!("❌ Error saving the user mount data: {err}");
-                        std::process::exit(-1)
+            // Load mounts for authorization
+            let setup_user_mounts =
+                match load_user_mountpoints(setup_store.as_ref(), &setup_username) {
+                    Ok(existing_data) => existing_data,
+                    Err(err) => {
+                        output.fail(CliError::from(err).context("Error loading user mounts data"))
                     }
+                };
+
+            // Save mounts if any
+            if let Err(err) = store_user_mountpoints(
+                setup_user_mounts.clone(),
+                setup_store.as_ref(),
+                &setup_username,
+                None,
+                None,
+            ) {
+                output.fail(CliError::from(err).context("Error saving the user mount data"))
+            }
 
-                    // If config_file was not specified, authorize default mount
-                    if args.config_file.is_none() {
-                        // Authorize mount if mounts are configured
-                        if let Some(mounts) = setup_user_mounts {
-                            match Connection::system().await {
-                                Ok(connection) => {
-                                    match MountAuthDBusProxy::new(&connection).await {
-                                        Ok(proxy) => {
-                                            match proxy
-                                                .authorize(setup_username.as_str(), mounts.hash())
-                                                .await
-                                            {
-                                                Ok(reply) => {
-                                                    let result =
-                                                        ServiceOperationResult::from(reply);
-                                                    if result != ServiceOperationResult::Ok {
-                                                        eprintln!("⚠️  Warning: Could not authorize mount: {result}");
-                                                    } else {
-                                                        println!("✅ Default mount authorized for user '{setup_username}'");
-                                                    }
-                                                }
-                                                Err(err) => {
-                                                    eprintln!("⚠️  Warning: Error authorizing mount: {err}");
-                                                }
-                                            }
-                                        }
-                                        Err(err) => {
-                                            eprintln!("⚠️  Warning: Error creating mount auth proxy: {err}");
-                                        }
+            // If config_file was not specified, authorize default mount
+            if args.config_file.is_none() {
+                // Authorize mount if mounts are configured
+                if let Some(mounts) = setup_user_mounts {
+                    match Connection::system().await {
+                        Ok(connection) => match MountAuthDBusProxy::new(&connection).await {
+                            Ok(proxy) => {
+                                match proxy
+                                    .authorize(setup_username.as_str(), mounts.hash())
+                                    .await
+                                {
+                                    Ok(()) => {
+                                        eprintln!("✅ Default mount authorized for user '{setup_username}'");
+                                    }
+                                    Err(err) => {
+                                        eprintln!(
+                                            "⚠️  Warning: Could not authorize mount: {}",
+                                            err.message()
+                                        );
                                     }
-                                }
-                                Err(err) => {
-                                    eprintln!("⚠️  Warning: Error connecting to system bus: {err}");
                                 }
                             }
+                            Err(err) => {
+                                eprintln!("⚠️  Warning: Error creating mount auth proxy: {err}");
+                            }
+                        },
+                        Err(err) => {
+                            eprintln!("⚠️  Warning: Error connecting to system bus: {err}");
                         }
                     }
+                }
+            }
 
-                    println!("✅ Setup completed successfully for user '{setup_username}'",);
+            output.success(format!(
+                "Setup completed successfully for user '{setup_username}'"
+            ));
 
-                    // Prevent the normal file write at the end since we already wrote it
-                    write_file = Some(false);
-                }
-                Err(err) => {
-                    eprintln!("❌ Error initializing the user authentication data: {err}");
-                    std::process::exit(-1)
-                }
-            };
+            // Prevent the normal file write at the end since we already wrote it
+            write_file = Some(false);
         }
         Command::Reset(_) => {
-            match remove_user_data(&storage_source) {
-                Ok(_) => {
-                    // Do NOT rewrite the User structure that was created while authenticating the user
-                    write_file = Some(false)
-                }
-                Err(err) => {
-                    eprintln!("❌ Error resetting user additional authentication methods: {err}");
-                    std::process::exit(-1)
-                }
+            if let Err(err) = remove_user_data(store.as_ref(), &current_username) {
+                output.fail(
+                    CliError::from(err)
+                        .context("Error resetting user additional authentication methods"),
+                )
             }
+
+            // Do NOT rewrite the User structure that was created while authenticating the user
+            write_file = Some(false);
+            output.success(format!(
+                "Configuration of user '{current_username}' removed"
+            ));
         }
         Command::Inspect(_) => {
-            match &storage_source {
-                StorageSource::Username(username) => {
-                    println!("-----------------------------------------------------------");
-                    println!("👤 User: {username}");
-                    println!("-----------------------------------------------------------");
-                }
-                StorageSource::File(path) => {
-                    println!("-----------------------------------------------------------");
-                    println!("📁 Path: {}", path.to_string_lossy());
-                    println!("-----------------------------------------------------------");
-                }
-            }
-
-            match user_mounts {
-                Some(ref mount_info) => {
-                    let hash = mount_info.hash();
-                    println!("🔑 hash: {hash}");
+            let session_command = match load_user_session_command(store.as_ref(), &current_username)
+            {
+                Ok(maybe_data) => maybe_data,
+                Err(err) => output
+                    .fail(CliError::from(err).context("Error in reading the user default session")),
+            };
 
-                    let primary_mount = mount_info.mount();
-                    println!("💾 device: {}", primary_mount.device());
-                    if !primary_mount.fstype().is_empty() {
-                        println!("📂 filesystem: {}", primary_mount.fstype());
-                    }
+            let report = InspectReport {
+                user: current_username.clone(),
+                path: match &args.config_file {
+                    None => store.location(&current_username),
+                    Some(path) => path.to_string_lossy().to_string(),
+                },
+                mounts: user_mounts.as_ref().map(MountsReport::from),
+                session: session_command.as_ref().map(SessionReport::from),
+                has_main_password: user_cfg.has_main(),
+                authentication_methods: user_cfg.secondary().map(AuthMethodReport::from).collect(),
+            };
 
-                    println!("⚙️  args: {}", primary_mount.flags().join(","));
+            if !output.is_text() {
+                output.emit(&report)
+            } else {
+                println!("-----------------------------------------------------------");
+                if args.config_file.is_none() {
+                    println!("👤 User: {}", report.user);
+                }
+                println!("📁 Path: {}", report.path);
+                println!("-----------------------------------------------------------");
 
-                    mount_info.foreach(|a, b| {
-                        println!("***********************************************************");
-                        println!("    📁 directory: {}", a.clone());
-                        println!("    💾 device: {}", b.device().clone());
-                        println!("    📂 filesystem: {}", b.fstype().clone());
-                        println!("    ⚙️  args: {}", b.flags().join(","))
-                    });
+                match &report.mounts {
+                    Some(mounts) => print_mounts(mounts),
+                    None => println!("ℹ️  No user-defined mounts"),
                 }
-                None => println!("ℹ️  No user-defined mounts"),
-            }
 
-            println!("-----------------------------------------------------------");
+                println!("-----------------------------------------------------------");
 
-            match load_user_session_command(&storage_source) {
-                Ok(maybe_data) => match maybe_data {
+                match &session_command {
                     Some(data) => {
-                        let cmd = data.command();
-                        println!("🖥️  Default session command: {cmd}")
+                        match data.desktop() {
+                            Some(desktop) => println!("🖥️  Default desktop session: {desktop}"),
+                            None => println!("🖥️  Default session command: {}", data.command()),
+                        }
+                        if !data.args().is_empty() {
+                            println!("    📝 args: {}", data.args().join(" "));
+                        }
+                        for (key, value) in data.env().iter() {
+                            println!("    🌿 env: {key}={value}");
+                        }
+                        if !data.session_type().is_unspecified() {
+                            println!("    🪟 type: {}", data.session_type());
+                        }
+                        if !data.desktop_names().is_empty() {
+                            println!("    🏷️  desktop: {}", data.desktop_names().join(":"));
+                        }
                     }
                     None => println!("ℹ️  No default session set."),
-                },
-                Err(err) => {
-                    eprintln!("❌ Error in reading the user default session: {err}");
-                    std::process::exit(-1)
                 }
-            };
 
-            println!("-----------------------------------------------------------");
+                println!("-----------------------------------------------------------");
 
-            let methods_count = user_cfg.secondary().len();
-            match methods_count {
-                0 => {
-                    println!("ℹ️  No authentication methods configured.");
-                }
-                1 => {
-                    println!("🔐 There is 1 authentication method: ");
-                    println!("-----------------------------------------------------------");
+                let methods_count = user_cfg.secondary().len();
+                match methods_count {
+                    0 => {
+                        println!("ℹ️  No authentication methods configured.");
+                    }
+                    1 => {
+                        println!("🔐 There is 1 authentication method: ");
+                        println!("-----------------------------------------------------------");
+                    }
+                    _ => {
+                        println!("🔐 There are {methods_count} authentication methods: ");
+                        println!("-----------------------------------------------------------");
+                    }
                 }
-                _ => {
+
+                for s in user_cfg.secondary() {
+                    println!("🏷️  name: {}", s.name());
                     println!(
-                        "🔐 There are {} authentication methods: ",
-                        user_cfg.secondary().len()
+                        "    📅 created at: {:?}",
+                        Local
+                            .timestamp_opt(s.creation_date() as i64, 0)
+                            .unwrap()
+                            .to_string()
                     );
+                    println!("    🔑 type: {}", s.type_name());
                     println!("-----------------------------------------------------------");
                 }
             }
+        }
+        Command::VerifyMain(_) => {
+            if !user_cfg.has_main() {
+                output.fail(CliError::new(
+                    ErrorClass::NotFound,
+                    "No main password has been stored: run setup first",
+                ))
+            }
 
-            for s in user_cfg.secondary() {
-                println!("🏷️  name: {}", s.name());
-                println!(
-                    "    📅 created at: {:?}",
-                    Local
-                        .timestamp_opt(s.creation_date() as i64, 0)
-                        .unwrap()
-                        .to_string()
-                );
-                println!("    🔑 type: {}", s.type_name());
-                println!("-----------------------------------------------------------");
+            let password = match &maybe_main_password {
+                Some(password) => password.clone(),
+                None => input.prompt("current system password:", SecretUse::Password),
+            };
+
+            match user_cfg.check_main(password.expose()) {
+                Ok(true) => output.success("The stored main password matches the system password"),
+                Ok(false) => output.fail(CliError::new(
+                    ErrorClass::Authentication,
+                    "The stored main password does not match the system password: secondary authentication methods will not be able to log in",
+                )),
+                Err(err) => output
+                    .fail(CliError::from(err).context("Error verifying the stored main password")),
             }
         }
-        Command::Add(add_cmd) => {
-            let intermediate_password = match user_cfg.has_main() {
-                false => add_cmd.intermediate.clone().unwrap_or_else(|| {
-                    let intermediate_password = prompt_password("Intermediate key:")
-                        .expect("Failed to read intermediate key");
-
-                    let intermediate_password_repeat =
-                        prompt_password("Intermediate key (repeat):")
-                            .expect("Failed to read intermediate key (repeat)");
+        Command::Migrate(migrate_cmd) => {
+            let report =
+                match migrate_user_config(store.as_ref(), &current_username, migrate_cmd.dry_run) {
+                    Ok(report) => report,
+                    Err(err) => output.fail(
+                        CliError::from(err).context("Error migrating the user configuration"),
+                    ),
+                };
 
-                    if intermediate_password != intermediate_password_repeat {
-                        eprintln!("❌ Intermediate key and Intermediate (repeat) do not match!");
+            if !output.is_text() {
+                output.emit(&MigrateReport {
+                    migration: report
+                        .as_ref()
+                        .map(|report| MigrationStepsReport::new(report, migrate_cmd.dry_run)),
+                })
+            } else {
+                match report {
+                    None => println!("ℹ️  No configuration file to migrate."),
+                    Some(report) if !report.is_needed() => println!(
+                        "✅ Configuration is already at version {}",
+                        report.to_version()
+                    ),
+                    Some(report) => {
+                        match migrate_cmd.dry_run {
+                            true => println!(
+                                "🔍 Configuration would be migrated from version {} to {}:",
+                                report.from_version(),
+                                report.to_version()
+                            ),
+                            false => println!(
+                                "✅ Configuration migrated from version {} to {}:",
+                                report.from_version(),
+                                report.to_version()
+                            ),
+                        }
 
-                        std::process::exit(-1)
+                        for step in report.steps() {
+                            println!("    - {step}");
+                        }
                     }
-
-                    intermediate_password
-                }),
-                true => add_cmd.intermediate.clone().unwrap_or_else(|| {
-                    prompt_password("Intermediate key:").expect("Failed to read intermediate key")
-                }),
+                }
+            }
+        }
+        Command::Add(add_cmd) => {
+            let intermediate = input.option(
+                "intermediate",
+                SecretUse::Key,
+                &add_cmd.intermediate,
+                add_cmd.intermediate_fd,
+                &add_cmd.intermediate_file,
+            );
+            let intermediate_password = match (intermediate, user_cfg.has_main()) {
+                (Some(intermediate), _) => intermediate,
+                (None, false) => input.prompt_confirmed(
+                    "Intermediate key:",
+                    "Intermediate key (repeat):",
+                    "Intermediate key",
+                    SecretUse::Key,
+                ),
+                (None, true) => input.prompt("Intermediate key:", SecretUse::Key),
             };
 
             if user_cfg.has_main() {
-                if let Err(err) = user_cfg.main_by_auth(&Some(intermediate_password.clone())) {
-                    eprintln!("❌ Could not verify the correctness of the intermediate key: {err}");
-                    std::process::exit(-1)
+                if let Err(err) = user_cfg.main_by_auth(Some(intermediate_password.expose())) {
+                    output.fail(
+                        CliError::from(err)
+                            .context("Could not verify the correctness of the intermediate key"),
+                    )
                 }
             }
 
             // if the main password is accepted update the stored one
-            if let Some(main_password) = maybe_main_password {
-                user_cfg
-                    .set_main(&main_password, &intermediate_password)
-                    .expect("Error handling main password");
+            if let Some(main_password) = &maybe_main_password {
+                if let Err(err) =
+                    user_cfg.set_main(main_password.expose(), intermediate_password.expose())
+                {
+                    output.fail(CliError::from(err).context("Error handling main password"))
+                }
             }
 
-            match add_cmd.method {
+            match &add_cmd.method {
                 AddAuthMethod::Password(add_auth_password_command) => {
-                    let secondary_password = match add_auth_password_command.secondary_pw {
+                    let secondary_password = match input.option(
+                        "secondary-pw",
+                        SecretUse::Password,
+                        &add_auth_password_command.secondary_pw,
+                        add_auth_password_command.secondary_pw_fd,
+                        &add_auth_password_command.secondary_pw_file,
+                    ) {
                         Some(secondary_password) => secondary_password,
-                        None => {
-                            let secondary_password = prompt_password("Secondary password:")
-                                .expect("Failed to read secondary password");
-
-                            let repeat = prompt_password("Secondary password (repeat):")
-                                .expect("Failed to read secondary password (repeat)");
-                            if secondary_password != repeat {
-                                eprintln!("❌ Passwords do not match");
-                                std::process::exit(-1)
-                            }
-
-                            secondary_password
-                        }
+                        None => input.prompt_confirmed(
+                            "Secondary password:",
+                            "Secondary password (repeat):",
+                            "Secondary password",
+                            SecretUse::Password,
+                        ),
                     };
 
                     if !user_cfg.has_main() {
-                        eprintln!("❌ Cannot add a secondary password for an account with no main password");
-                        std::process::exit(-1);
+                        output.fail(CliError::new(
+                            ErrorClass::NotFound,
+                            "Cannot add a secondary password for an account with no main password",
+                        ))
                     }
 
-                    match user_cfg.add_secondary_password(
+                    if let Err(err) = user_cfg.add_secondary_password(
                         &add_cmd.name,
-                        &intermediate_password,
-                        &secondary_password,
+                        intermediate_password.expose(),
+                        secondary_password.expose(),
                     ) {
-                        Ok(_) => {
-                            write_file = Some(true);
-                            println!("✅ Secondary password added.");
-                        }
-                        Err(err) => {
-                            eprintln!("❌ Error adding a secondary password: {err}");
-                            std::process::exit(-1);
-                        }
+                        output
+                            .fail(CliError::from(err).context("Error adding a secondary password"))
                     }
+
+                    write_file = Some(true);
+                    done_message = Some(String::from("Secondary password added."));
                 }
             }
         }
     }
 
     if write_file.unwrap_or_default() {
-        store_user_auth_data(&user_cfg, &storage_source, None, None)
-            .expect("❌ Error saving the updated user auth data");
+        if let Err(err) =
+            store_user_auth_data(&user_cfg, store.as_ref(), &current_username, None, None)
+        {
+            output.fail(CliError::from(err).context("Error saving the updated user auth data"))
+        }
+
+        if let Err(err) =
+            store_user_mountpoints(user_mounts, store.as_ref(), &current_username, None, None)
+        {
+            output.fail(CliError::from(err).context("Error saving the updated user mount data"))
+        }
+    }
 
-        store_user_mountpoints(user_mounts, &storage_source, None, None)
-            .expect("❌ Error saving the updated user mount data");
+    if let Some(message) = done_message {
+        output.success(message);
     }
 }
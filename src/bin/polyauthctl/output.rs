@@ -0,0 +1,553 @@
+/*
+    polyauth A greeter written in rust that also supports autologin with systemd-homed
+    Copyright (C) 2024-2025  Denis Benato
+
+    This program is free software; you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation; either version 2 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License along
+    with this program; if not, write to the Free Software Foundation, Inc.,
+    51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+*/
+
+use std::{collections::BTreeMap, fmt, str::FromStr};
+
+use pam_polyauth::{
+    auth::SecondaryAuth,
+    command::SessionCommand,
+    desktop::{DesktopError, DesktopSession},
+    doctor::Check,
+    error::UserOperationError,
+    mount::{MountError, MountPoints},
+    pam::{
+        result::{ServiceOperationError, ServiceOperationResult},
+        session::SessionInfo,
+    },
+    provision::{Change, ProvisionError},
+    storage::{migration::MigrationReport, StorageError},
+    user::UserAuthDataError,
+};
+
+use serde::Serialize;
+
+/// Format of what polyauthctl writes on standard output
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// Human readable messages, errors go to standard error
+    #[default]
+    Text,
+
+    /// A single JSON document, for both results and errors
+    Json,
+
+    /// A single YAML document, for both results and errors
+    Yaml,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            "yaml" => Ok(Self::Yaml),
+            _ => Err(format!(
+                "unknown output format '{s}': expected text, json or yaml"
+            )),
+        }
+    }
+}
+
+/// Class of a failure, which is also the exit code of polyauthctl.
+///
+/// These values are part of the documented interface: never renumber them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+#[repr(i32)]
+pub enum ErrorClass {
+    /// Invalid arguments or confirmations that do not match
+    Usage = 1,
+
+    /// No configuration, main password, mounts or session where one is required
+    NotFound = 2,
+
+    /// The configuration could not be read or written
+    Storage = 3,
+
+    /// The configuration or export file is malformed or written by a newer release
+    Format = 4,
+
+    /// Wrong password, intermediate key or passphrase
+    Authentication = 5,
+
+    /// Encryption or key derivation failed
+    Crypto = 6,
+
+    /// pam_polyauth-service could not be reached
+    ServiceUnavailable = 7,
+
+    /// pam_polyauth-service refused the request
+    ServiceDenied = 8,
+
+    /// pam_polyauth-service failed to carry out the request
+    Service = 9,
+
+    /// doctor found at least one failing check
+    Unhealthy = 10,
+}
+
+impl ErrorClass {
+    pub fn exit_code(&self) -> i32 {
+        *self as i32
+    }
+}
+
+/// A failure of polyauthctl, as reported to the user
+#[derive(Debug, Clone, Serialize)]
+pub struct CliError {
+    class: ErrorClass,
+    exit_code: i32,
+    message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    service_result: Option<u32>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    details: BTreeMap<String, String>,
+}
+
+impl CliError {
+    pub fn new(class: ErrorClass, message: impl fmt::Display) -> Self {
+        Self {
+            class,
+            exit_code: class.exit_code(),
+            message: message.to_string(),
+            service_result: None,
+            details: BTreeMap::new(),
+        }
+    }
+
+    /// Prepends what polyauthctl was doing to the message
+    pub fn context(mut self, context: &str) -> Self {
+        self.message = format!("{context}: {}", self.message);
+        self
+    }
+
+    /// Adds a machine readable detail, reported by the JSON and YAML formats
+    pub fn with_detail(mut self, key: &str, value: impl ToString) -> Self {
+        self.details.insert(String::from(key), value.to_string());
+        self
+    }
+}
+
+fn storage_class(err: &StorageError) -> ErrorClass {
+    match err {
+        StorageError::IoError(io) if io.kind() == std::io::ErrorKind::NotFound => {
+            ErrorClass::NotFound
+        }
+        StorageError::UserDiscoveryError => ErrorClass::NotFound,
+        StorageError::InvalidUsername(_) => ErrorClass::Usage,
+        StorageError::IoError(_) | StorageError::ReadOnly => ErrorClass::Storage,
+        StorageError::UnhandledVersion
+        | StorageError::FutureConfigVersion(_)
+        | StorageError::JsonError(_)
+        | StorageError::SerializationError(_)
+        | StorageError::DeserializationError
+        | StorageError::InvalidExport
+        | StorageError::UnsupportedExportVersion(_) => ErrorClass::Format,
+        StorageError::ExportDecryption => ErrorClass::Authentication,
+        StorageError::Kdf(_) => ErrorClass::Crypto,
+    }
+}
+
+impl From<StorageError> for CliError {
+    fn from(err: StorageError) -> Self {
+        Self::new(storage_class(&err), err)
+    }
+}
+
+fn user_class(err: &UserOperationError) -> ErrorClass {
+    match err {
+        UserOperationError::Io(_) => ErrorClass::Storage,
+        // AES-GCM fails to authenticate the data when the key is wrong
+        UserOperationError::EncryptionError(_) => ErrorClass::Authentication,
+        UserOperationError::HashingError(_) | UserOperationError::Kdf(_) => ErrorClass::Crypto,
+        UserOperationError::User(
+            UserAuthDataError::MainPasswordNotSet | UserAuthDataError::SecondaryNotFound,
+        ) => ErrorClass::NotFound,
+        UserOperationError::User(UserAuthDataError::SecondaryNameTaken) => ErrorClass::Usage,
+        UserOperationError::User(_) => ErrorClass::Authentication,
+    }
+}
+
+impl From<UserOperationError> for CliError {
+    fn from(err: UserOperationError) -> Self {
+        Self::new(user_class(&err), err)
+    }
+}
+
+impl From<ServiceOperationError> for CliError {
+    fn from(err: ServiceOperationError) -> Self {
+        let class = match err.result() {
+            ServiceOperationResult::BusError => ErrorClass::ServiceUnavailable,
+            ServiceOperationResult::NoSuchSession | ServiceOperationResult::NoSessionCommand => {
+                ErrorClass::NotFound
+            }
+            ServiceOperationResult::PubKeyError
+            | ServiceOperationResult::EmptyPubKey
+            | ServiceOperationResult::DataDecryptionFailed
+            | ServiceOperationResult::CannotIdentifyUser
+            | ServiceOperationResult::UnauthorizedMount
+            | ServiceOperationResult::TamperedConfig
+            | ServiceOperationResult::AccessDenied => ErrorClass::ServiceDenied,
+            _ => ErrorClass::Service,
+        };
+
+        Self {
+            service_result: Some(err.result().into()),
+            details: err.details().clone().into_iter().collect(),
+            ..Self::new(class, format!("{} ({})", err.message(), err.result()))
+        }
+    }
+}
+
+impl From<DesktopError> for CliError {
+    fn from(err: DesktopError) -> Self {
+        let class = match &err {
+            DesktopError::NotFound(_) => ErrorClass::NotFound,
+            DesktopError::IoError(_) => ErrorClass::Storage,
+            DesktopError::MissingEntry(_)
+            | DesktopError::MissingExec(_)
+            | DesktopError::InvalidExec(..) => ErrorClass::Format,
+        };
+
+        Self::new(class, err)
+    }
+}
+
+impl From<MountError> for CliError {
+    fn from(err: MountError) -> Self {
+        Self::new(ErrorClass::Usage, err)
+    }
+}
+
+impl From<ProvisionError> for CliError {
+    fn from(err: ProvisionError) -> Self {
+        let class = match &err {
+            ProvisionError::IoError(io) if io.kind() == std::io::ErrorKind::NotFound => {
+                ErrorClass::NotFound
+            }
+            ProvisionError::IoError(_) => ErrorClass::Storage,
+            ProvisionError::InvalidManifest(_)
+            | ProvisionError::DuplicateUser(_)
+            | ProvisionError::InvalidUser(..) => ErrorClass::Format,
+            ProvisionError::MissingIntermediateKey(..) | ProvisionError::MissingMainPassword(_) => {
+                ErrorClass::Usage
+            }
+            ProvisionError::Storage(_, storage) => storage_class(storage),
+            ProvisionError::User(_, user) => user_class(user),
+        };
+
+        Self::new(class, err)
+    }
+}
+
+impl From<zbus::Error> for CliError {
+    fn from(err: zbus::Error) -> Self {
+        Self::new(ErrorClass::ServiceUnavailable, err)
+    }
+}
+
+#[derive(Serialize)]
+struct Document<'a, T: Serialize> {
+    status: &'static str,
+    #[serde(flatten)]
+    body: &'a T,
+}
+
+#[derive(Serialize)]
+struct ErrorBody<'a> {
+    error: &'a CliError,
+}
+
+#[derive(Serialize)]
+struct MessageBody<'a> {
+    message: &'a str,
+}
+
+/// Writes results and errors in the format selected with --output
+#[derive(Debug, Clone, Copy)]
+pub struct Output {
+    format: OutputFormat,
+}
+
+impl Output {
+    pub fn new(format: OutputFormat) -> Self {
+        Self { format }
+    }
+
+    pub fn is_text(&self) -> bool {
+        self.format == OutputFormat::Text
+    }
+
+    fn document<T: Serialize>(&self, status: &'static str, body: &T) {
+        let document = Document { status, body };
+        match self.format {
+            OutputFormat::Text => {}
+            OutputFormat::Json => println!(
+                "{}",
+                serde_json::to_string_pretty(&document).expect("JSON output")
+            ),
+            OutputFormat::Yaml => {
+                print!("{}", serde_yaml::to_string(&document).expect("YAML output"))
+            }
+        }
+    }
+
+    /// Writes the result of a command: text mode is left to the caller
+    pub fn emit<T: Serialize>(&self, body: &T) {
+        self.document("ok", body)
+    }
+
+    /// Reports a command that completed with nothing else to say
+    pub fn success(&self, message: impl fmt::Display) {
+        let message = message.to_string();
+        match self.format {
+            OutputFormat::Text => println!("✅ {message}"),
+            _ => self.document("ok", &MessageBody { message: &message }),
+        }
+    }
+
+    /// Reports the error and exits with the code of its class
+    pub fn fail(&self, err: impl Into<CliError>) -> ! {
+        let err = err.into();
+        match self.format {
+            OutputFormat::Text => eprintln!("❌ {}", err.message),
+            _ => self.document("error", &ErrorBody { error: &err }),
+        }
+
+        std::process::exit(err.exit_code)
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MountReport {
+    device: String,
+    fstype: String,
+    flags: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AdditionalMountReport {
+    directory: String,
+    #[serde(flatten)]
+    mount: MountReport,
+}
+
+/// Mounts of a user as written by inspect and mount list
+#[derive(Debug, Clone, Serialize)]
+pub struct MountsReport {
+    hash: String,
+    home: MountReport,
+    additional: Vec<AdditionalMountReport>,
+}
+
+impl From<&MountPoints> for MountsReport {
+    fn from(mounts: &MountPoints) -> Self {
+        let home = mounts.mount();
+        let mut additional = mounts.foreach(|dir, params| AdditionalMountReport {
+            directory: dir.clone(),
+            mount: MountReport {
+                device: params.device().clone(),
+                fstype: params.fstype().clone(),
+                flags: params.flags().clone(),
+            },
+        });
+        additional.sort_by(|a, b| a.directory.cmp(&b.directory));
+
+        Self {
+            hash: mounts.hash(),
+            home: MountReport {
+                device: home.device().clone(),
+                fstype: home.fstype().clone(),
+                flags: home.flags().clone(),
+            },
+            additional,
+        }
+    }
+}
+
+/// Prints the mounts of a user in text mode
+pub fn print_mounts(mounts: &MountsReport) {
+    println!("🔑 hash: {}", mounts.hash);
+    println!("💾 device: {}", mounts.home.device);
+    if !mounts.home.fstype.is_empty() {
+        println!("📂 filesystem: {}", mounts.home.fstype);
+    }
+
+    println!("⚙️  args: {}", mounts.home.flags.join(","));
+
+    for additional in mounts.additional.iter() {
+        println!("***********************************************************");
+        println!("    📁 directory: {}", additional.directory);
+        println!("    💾 device: {}", additional.mount.device);
+        println!("    📂 filesystem: {}", additional.mount.fstype);
+        println!("    ⚙️  args: {}", additional.mount.flags.join(","))
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AuthMethodReport {
+    name: String,
+    #[serde(rename = "type")]
+    type_name: String,
+    created_at: u64,
+}
+
+impl From<&SecondaryAuth> for AuthMethodReport {
+    fn from(auth: &SecondaryAuth) -> Self {
+        Self {
+            name: auth.name(),
+            type_name: auth.type_name(),
+            created_at: auth.creation_date(),
+        }
+    }
+}
+
+/// Result of inspect
+#[derive(Debug, Clone, Serialize)]
+pub struct InspectReport {
+    pub user: String,
+    pub path: String,
+    pub mounts: Option<MountsReport>,
+    pub session: Option<SessionReport>,
+    pub has_main_password: bool,
+    pub authentication_methods: Vec<AuthMethodReport>,
+}
+
+/// Session command of a user as written by inspect
+#[derive(Debug, Clone, Serialize)]
+pub struct SessionReport {
+    command: String,
+    args: Vec<String>,
+    env: BTreeMap<String, String>,
+    session_type: Option<String>,
+    desktop_names: Vec<String>,
+    desktop: Option<String>,
+}
+
+impl From<&SessionCommand> for SessionReport {
+    fn from(command: &SessionCommand) -> Self {
+        Self {
+            command: command.command(),
+            args: command.args().clone(),
+            env: command.env().clone(),
+            session_type: match command.session_type().is_unspecified() {
+                true => None,
+                false => Some(command.session_type().to_string()),
+            },
+            desktop_names: command.desktop_names().clone(),
+            desktop: command.desktop().map(String::from),
+        }
+    }
+}
+
+/// Result of mount list
+#[derive(Debug, Clone, Serialize)]
+pub struct MountListReport {
+    pub user: String,
+    pub mounts: Option<MountsReport>,
+}
+
+/// Result of sessions active
+#[derive(Debug, Clone, Serialize)]
+pub struct SessionsReport {
+    pub sessions: Vec<SessionInfo>,
+}
+
+/// Result of sessions list
+#[derive(Debug, Clone, Serialize)]
+pub struct DesktopSessionsReport {
+    pub sessions: Vec<DesktopSessionReport>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DesktopSessionReport {
+    id: String,
+    name: String,
+    session_type: String,
+    exec: Vec<String>,
+    desktop_names: Vec<String>,
+    path: String,
+}
+
+impl From<&DesktopSession> for DesktopSessionReport {
+    fn from(session: &DesktopSession) -> Self {
+        Self {
+            id: String::from(session.id()),
+            name: String::from(session.name()),
+            session_type: session.session_type().to_string(),
+            exec: session.exec().clone(),
+            desktop_names: session.desktop_names().clone(),
+            path: session.path().to_string_lossy().to_string(),
+        }
+    }
+}
+
+/// Result of doctor
+#[derive(Debug, Clone, Serialize)]
+pub struct DoctorReport {
+    pub user: String,
+    pub healthy: bool,
+    pub checks: Vec<Check>,
+}
+
+/// Result of provision
+#[derive(Debug, Clone, Serialize)]
+pub struct ProvisionReport {
+    pub dry_run: bool,
+    pub users: Vec<UserProvisionReport>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct UserProvisionReport {
+    pub user: String,
+    pub changes: Vec<Change>,
+}
+
+/// Result of migrate
+#[derive(Debug, Clone, Serialize)]
+pub struct MigrateReport {
+    pub migration: Option<MigrationStepsReport>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MigrationStepsReport {
+    from_version: u32,
+    to_version: u32,
+    steps: Vec<String>,
+    applied: bool,
+}
+
+impl MigrationStepsReport {
+    pub fn new(report: &MigrationReport, dry_run: bool) -> Self {
+        Self {
+            from_version: report.from_version(),
+            to_version: report.to_version(),
+            steps: report.steps().iter().map(|step| step.to_string()).collect(),
+            applied: report.is_needed() && !dry_run,
+        }
+    }
+}
+
+/// Result of info
+#[derive(Debug, Clone, Serialize)]
+pub struct InfoReport {
+    pub version: String,
+}
@@ -0,0 +1,428 @@
+/*
+    polyauth A greeter written in rust that also supports autologin with systemd-homed
+    Copyright (C) 2024-2025  Denis Benato
+
+    This program is free software; you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation; either version 2 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License along
+    with this program; if not, write to the Free Software Foundation, Inc.,
+    51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+*/
+
+use std::io::{self, Write};
+use std::os::fd::RawFd;
+use std::sync::{
+    atomic::{AtomicI32, Ordering},
+    Mutex, Once,
+};
+
+const STDIN: RawFd = libc::STDIN_FILENO;
+const STDOUT: RawFd = libc::STDOUT_FILENO;
+
+/// How long to wait for the rest of an escape sequence before taking ESC as a key
+const ESCAPE_TIMEOUT_MS: i32 = 50;
+
+/// Settings of the terminal before it was taken over, None once restored
+static ORIGINAL: Mutex<Option<libc::termios>> = Mutex::new(None);
+
+/// Write end of the pipe SIGWINCH is reported through, -1 when there is none
+static RESIZE_PIPE: AtomicI32 = AtomicI32::new(-1);
+
+static PANIC_HOOK: Once = Once::new();
+
+/// Leaves the alternate screen and puts back the settings read by Terminal::open, if not done yet
+fn restore() {
+    let Some(original) = ORIGINAL
+        .lock()
+        .unwrap_or_else(|err| err.into_inner())
+        .take()
+    else {
+        return;
+    };
+
+    let mut stdout = io::stdout().lock();
+    let _ = stdout.write_all(b"\x1b[0m\x1b[?25h\x1b[?1049l");
+    let _ = stdout.flush();
+
+    // SAFETY: the settings are the ones read when the terminal was opened
+    unsafe { libc::tcsetattr(STDIN, libc::TCSAFLUSH, &original) };
+}
+
+// only async-signal-safe calls are allowed here: the pipe wakes up read_key
+extern "C" fn on_resize(_signal: libc::c_int) {
+    let fd = RESIZE_PIPE.load(Ordering::Relaxed);
+    if fd >= 0 {
+        let byte = 0u8;
+        // SAFETY: write is async-signal-safe, a full pipe only drops the notification
+        unsafe { libc::write(fd, (&byte as *const u8).cast(), 1) };
+    }
+}
+
+/// A key read from the terminal
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Key {
+    Up,
+    Down,
+    Left,
+    Right,
+    Home,
+    End,
+    Tab,
+    BackTab,
+    Enter,
+    Escape,
+    Backspace,
+    Delete,
+    Interrupt,
+    Char(char),
+    /// The terminal has been resized: the screen has to be drawn again
+    Resize,
+    Unknown,
+}
+
+/// How a line of the screen is drawn
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Style {
+    Normal,
+    Title,
+    Heading,
+    Selected,
+    Dim,
+    Good,
+    Bad,
+}
+
+impl Style {
+    fn sgr(&self) -> &'static str {
+        match self {
+            Style::Normal => "",
+            Style::Title => "\x1b[1;7m",
+            Style::Heading => "\x1b[1m",
+            Style::Selected => "\x1b[7m",
+            Style::Dim => "\x1b[2m",
+            Style::Good => "\x1b[32m",
+            Style::Bad => "\x1b[31m",
+        }
+    }
+
+    /// Whether the style fills the whole width of the screen
+    fn is_bar(&self) -> bool {
+        matches!(self, Style::Title | Style::Selected)
+    }
+}
+
+/// A line of the screen
+#[derive(Debug, Clone)]
+pub struct Line {
+    text: String,
+    style: Style,
+}
+
+impl Line {
+    pub fn new(text: impl Into<String>, style: Style) -> Self {
+        Self {
+            text: text.into(),
+            style,
+        }
+    }
+
+    pub fn blank() -> Self {
+        Self::new("", Style::Normal)
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+}
+
+/// The terminal in raw mode on the alternate screen, restored when dropped or on a panic
+pub struct Terminal {
+    resize_pipe: [RawFd; 2],
+    previous_action: libc::sigaction,
+    clear: bool,
+}
+
+impl Terminal {
+    /// Takes over the terminal: fails if the standard input or output is not one
+    pub fn open() -> io::Result<Self> {
+        // SAFETY: isatty only inspects the descriptors
+        if unsafe { libc::isatty(STDIN) != 1 || libc::isatty(STDOUT) != 1 } {
+            return Err(io::Error::other(
+                "the standard input and output must be a terminal",
+            ));
+        }
+
+        // SAFETY: termios is plain data filled in by tcgetattr
+        let mut original: libc::termios = unsafe { std::mem::zeroed() };
+        if unsafe { libc::tcgetattr(STDIN, &mut original) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        // Ctrl+C is read as a key so that the terminal is always restored
+        let mut raw = original;
+        unsafe { libc::cfmakeraw(&mut raw) };
+        raw.c_cc[libc::VMIN] = 1;
+        raw.c_cc[libc::VTIME] = 0;
+        if unsafe { libc::tcsetattr(STDIN, libc::TCSAFLUSH, &raw) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        *ORIGINAL.lock().unwrap_or_else(|err| err.into_inner()) = Some(original);
+
+        // the message of a panic would be lost on the alternate screen, and the shell
+        // left in raw mode: the terminal is restored before it is printed
+        PANIC_HOOK.call_once(|| {
+            let previous = std::panic::take_hook();
+            std::panic::set_hook(Box::new(move |info| {
+                restore();
+                previous(info);
+            }));
+        });
+
+        let mut resize_pipe: [RawFd; 2] = [-1, -1];
+        // SAFETY: pipe2 fills in the two descriptors of a valid array
+        if unsafe { libc::pipe2(resize_pipe.as_mut_ptr(), libc::O_CLOEXEC | libc::O_NONBLOCK) } != 0
+        {
+            let err = io::Error::last_os_error();
+            restore();
+            return Err(err);
+        }
+        RESIZE_PIPE.store(resize_pipe[1], Ordering::Relaxed);
+
+        // SAFETY: sigaction is plain data, the handler only writes to the pipe
+        let mut previous_action: libc::sigaction = unsafe { std::mem::zeroed() };
+        unsafe {
+            let mut action: libc::sigaction = std::mem::zeroed();
+            action.sa_sigaction = on_resize as extern "C" fn(libc::c_int) as libc::sighandler_t;
+            action.sa_flags = libc::SA_RESTART;
+            libc::sigemptyset(&mut action.sa_mask);
+            libc::sigaction(libc::SIGWINCH, &action, &mut previous_action);
+        }
+
+        // from now on Drop restores everything
+        let terminal = Self {
+            resize_pipe,
+            previous_action,
+            clear: false,
+        };
+
+        // alternate screen, hidden cursor
+        let mut stdout = io::stdout().lock();
+        stdout.write_all(b"\x1b[?1049h\x1b[?25l")?;
+        stdout.flush()?;
+
+        Ok(terminal)
+    }
+
+    /// Columns and rows of the terminal
+    pub fn size(&self) -> (usize, usize) {
+        // SAFETY: winsize is plain data filled in by the ioctl
+        let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+        match unsafe { libc::ioctl(STDOUT, libc::TIOCGWINSZ, &mut size) } {
+            0 if size.ws_col > 0 && size.ws_row > 0 => (size.ws_col as usize, size.ws_row as usize),
+            _ => (80, 24),
+        }
+    }
+
+    /// Redraws the whole screen, cutting what does not fit
+    pub fn draw(&mut self, lines: &[Line]) -> io::Result<()> {
+        let (columns, rows) = self.size();
+
+        // lines wrapped by the terminal while resizing are not overwritten by the redraw
+        let mut screen = match std::mem::take(&mut self.clear) {
+            true => String::from("\x1b[2J\x1b[H"),
+            false => String::from("\x1b[H"),
+        };
+        for (row, line) in lines.iter().take(rows).enumerate() {
+            let mut text: String = line.text.chars().take(columns).collect();
+            if line.style.is_bar() {
+                let width = text.chars().count();
+                text.push_str(&" ".repeat(columns - width));
+            }
+
+            screen.push_str("\x1b[2K");
+            screen.push_str(line.style.sgr());
+            screen.push_str(&text);
+            screen.push_str("\x1b[0m");
+            if row + 1 < rows {
+                screen.push_str("\r\n");
+            }
+        }
+        screen.push_str("\x1b[J");
+
+        let mut stdout = io::stdout().lock();
+        stdout.write_all(screen.as_bytes())?;
+        stdout.flush()
+    }
+
+    /// Waits for the next key, or for the terminal to be resized
+    pub fn read_key(&mut self) -> io::Result<Key> {
+        if self.wait_for_input()? {
+            self.clear = true;
+            return Ok(Key::Resize);
+        }
+
+        let key = match self.read_byte()? {
+            0x1b => match self.read_byte_timeout()? {
+                None => Key::Escape,
+                Some(b'[') | Some(b'O') => self.read_escape_sequence()?,
+                Some(_) => Key::Unknown,
+            },
+            b'\r' | b'\n' => Key::Enter,
+            b'\t' => Key::Tab,
+            0x7f | 0x08 => Key::Backspace,
+            0x03 | 0x04 => Key::Interrupt,
+            byte if byte < 0x20 => Key::Unknown,
+            byte => self.read_char(byte)?,
+        };
+
+        Ok(key)
+    }
+
+    /// Blocks until a key can be read: true if the terminal has been resized meanwhile
+    fn wait_for_input(&mut self) -> io::Result<bool> {
+        let mut poll_fds = [
+            libc::pollfd {
+                fd: STDIN,
+                events: libc::POLLIN,
+                revents: 0,
+            },
+            libc::pollfd {
+                fd: self.resize_pipe[0],
+                events: libc::POLLIN,
+                revents: 0,
+            },
+        ];
+
+        loop {
+            // SAFETY: the array holds two valid pollfd
+            if unsafe { libc::poll(poll_fds.as_mut_ptr(), 2, -1) } == -1 {
+                let err = io::Error::last_os_error();
+                if err.kind() == io::ErrorKind::Interrupted {
+                    continue;
+                }
+                return Err(err);
+            }
+
+            if poll_fds[1].revents & libc::POLLIN == 0 {
+                return Ok(false);
+            }
+
+            // many resizes in a row are drawn once
+            let mut drained = [0u8; 64];
+            // SAFETY: the pipe is non blocking and read into a valid buffer
+            while unsafe { libc::read(self.resize_pipe[0], drained.as_mut_ptr().cast(), 64) } > 0 {}
+            return Ok(true);
+        }
+    }
+
+    // the descriptor is read directly: bytes kept in the buffer of io::Stdin would be missed by poll
+    fn read_byte(&mut self) -> io::Result<u8> {
+        let mut byte = 0u8;
+        loop {
+            // SAFETY: a single byte is read into a valid buffer
+            match unsafe { libc::read(STDIN, (&mut byte as *mut u8).cast(), 1) } {
+                1 => return Ok(byte),
+                0 => return Err(io::ErrorKind::UnexpectedEof.into()),
+                _ => {
+                    let err = io::Error::last_os_error();
+                    if err.kind() != io::ErrorKind::Interrupted {
+                        return Err(err);
+                    }
+                }
+            }
+        }
+    }
+
+    fn read_byte_timeout(&mut self) -> io::Result<Option<u8>> {
+        let mut poll_fd = libc::pollfd {
+            fd: STDIN,
+            events: libc::POLLIN,
+            revents: 0,
+        };
+
+        // SAFETY: a single valid pollfd is passed
+        match unsafe { libc::poll(&mut poll_fd, 1, ESCAPE_TIMEOUT_MS) } {
+            -1 => Err(io::Error::last_os_error()),
+            0 => Ok(None),
+            _ => self.read_byte().map(Some),
+        }
+    }
+
+    fn read_escape_sequence(&mut self) -> io::Result<Key> {
+        // parameters are digits and ';', the sequence ends with any other byte
+        let mut parameters = String::new();
+        let last = loop {
+            match self.read_byte_timeout()? {
+                Some(byte) if byte.is_ascii_digit() || byte == b';' => {
+                    parameters.push(byte as char)
+                }
+                Some(byte) => break byte,
+                None => return Ok(Key::Unknown),
+            }
+        };
+
+        let key = match (last, parameters.as_str()) {
+            (b'A', _) => Key::Up,
+            (b'B', _) => Key::Down,
+            (b'C', _) => Key::Right,
+            (b'D', _) => Key::Left,
+            (b'H', _) => Key::Home,
+            (b'F', _) => Key::End,
+            (b'Z', _) => Key::BackTab,
+            (b'~', "1" | "7") => Key::Home,
+            (b'~', "4" | "8") => Key::End,
+            (b'~', "3") => Key::Delete,
+            _ => Key::Unknown,
+        };
+
+        Ok(key)
+    }
+
+    fn read_char(&mut self, first: u8) -> io::Result<Key> {
+        let length = match first {
+            0xc0..=0xdf => 2,
+            0xe0..=0xef => 3,
+            0xf0..=0xf7 => 4,
+            _ => 1,
+        };
+
+        let mut bytes = vec![first];
+        for _ in 1..length {
+            bytes.push(self.read_byte()?);
+        }
+
+        Ok(
+            match std::str::from_utf8(&bytes)
+                .ok()
+                .and_then(|text| text.chars().next())
+            {
+                Some(c) => Key::Char(c),
+                None => Key::Unknown,
+            },
+        )
+    }
+}
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        // SAFETY: the previous action was filled in by sigaction in open, and
+        // the pipe is closed only once the handler can no longer write to it
+        unsafe {
+            libc::sigaction(libc::SIGWINCH, &self.previous_action, std::ptr::null_mut());
+            RESIZE_PIPE.store(-1, Ordering::Relaxed);
+            libc::close(self.resize_pipe[0]);
+            libc::close(self.resize_pipe[1]);
+        }
+
+        restore();
+    }
+}
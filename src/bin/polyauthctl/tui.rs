@@ -0,0 +1,1079 @@
+/*
+    polyauth A greeter written in rust that also supports autologin with systemd-homed
+    Copyright (C) 2024-2025  Denis Benato
+
+    This program is free software; you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation; either version 2 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License along
+    with this program; if not, write to the Free Software Foundation, Inc.,
+    51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+*/
+
+use chrono::{Local, TimeZone};
+use pam_polyauth::{
+    command::SessionCommand,
+    constant_time_eq,
+    desktop::SessionDirs,
+    error::UserOperationError,
+    mount::{validate_mount_dir, MountParams, MountPoints},
+    pam::mount::MountAuthDBusProxy,
+    secret::{is_valid_password, SecretString, MAX_PASSWORD_LEN},
+    storage::{
+        is_locked_error, load_user_auth_data, load_user_mountpoints, load_user_session_command,
+        store::UserStore, store_user_auth_data, store_user_mountpoints, StorageError,
+    },
+    user::UserAuthData,
+};
+use zbus::Connection;
+use zeroize::Zeroize;
+
+use crate::is_mount_authorized;
+use crate::output::{CliError, ErrorClass};
+use crate::term::{Key, Line, Style, Terminal};
+
+/// Something on the main screen that can be selected
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Item {
+    Secondary(String),
+    AddPassword,
+    HomeMount,
+    PreMount(String),
+    AddPreMount,
+    Authorize,
+}
+
+/// What an entry of a menu does once chosen
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Action {
+    RenameSecondary(String),
+    AskRemoveSecondary(String),
+    RemoveSecondary(String),
+    EditPreMount(String),
+    AskRemovePreMount(String),
+    RemovePreMount(String),
+    Cancel,
+}
+
+/// Work that takes long enough to tell the user about it first
+enum Task {
+    AddPassword {
+        name: String,
+        intermediate: SecretString,
+        password: SecretString,
+    },
+    Authorize,
+    CheckAuthorization,
+}
+
+impl Task {
+    fn description(&self) -> &'static str {
+        match self {
+            Task::AddPassword { .. } => "Adding the password...",
+            Task::Authorize => "Authorizing the mounts...",
+            Task::CheckAuthorization => "Checking the mount authorization...",
+        }
+    }
+}
+
+enum Flow {
+    Continue,
+    Quit,
+    Run(Task),
+}
+
+enum Authorization {
+    NoMounts,
+    Authorized,
+    NotAuthorized,
+    Unknown(String),
+}
+
+struct Status {
+    style: Style,
+    text: String,
+}
+
+impl Status {
+    fn good(text: impl Into<String>) -> Self {
+        Self {
+            style: Style::Good,
+            text: text.into(),
+        }
+    }
+
+    fn bad(text: impl Into<String>) -> Self {
+        Self {
+            style: Style::Bad,
+            text: text.into(),
+        }
+    }
+
+    fn working(text: impl Into<String>) -> Self {
+        Self {
+            style: Style::Dim,
+            text: text.into(),
+        }
+    }
+}
+
+struct Menu {
+    title: String,
+    options: Vec<(&'static str, Action)>,
+    selected: usize,
+}
+
+impl Menu {
+    fn new(title: impl Into<String>, options: Vec<(&'static str, Action)>) -> Self {
+        Self {
+            title: title.into(),
+            options,
+            selected: 0,
+        }
+    }
+
+    /// Asks before doing something that cannot be undone
+    fn confirm(title: impl Into<String>, label: &'static str, action: Action) -> Self {
+        Self::new(title, vec![(label, action), ("Cancel", Action::Cancel)])
+    }
+
+    fn render(&self) -> (Vec<Line>, usize) {
+        let mut lines = vec![
+            Line::blank(),
+            Line::new(format!(" {}", self.title), Style::Heading),
+            Line::blank(),
+        ];
+
+        for (index, (label, _)) in self.options.iter().enumerate() {
+            let style = match index == self.selected {
+                true => Style::Selected,
+                false => Style::Normal,
+            };
+            lines.push(Line::new(format!("   {label}"), style));
+        }
+
+        (lines, 3 + self.selected)
+    }
+
+    fn handle(&mut self, key: Key) -> Option<Action> {
+        match key {
+            Key::Up => self.selected = self.selected.saturating_sub(1),
+            Key::Down | Key::Tab => self.selected = (self.selected + 1).min(self.options.len() - 1),
+            Key::Enter => return Some(self.options[self.selected].1.clone()),
+            Key::Escape | Key::Char('q') => return Some(Action::Cancel),
+            _ => {}
+        }
+
+        None
+    }
+}
+
+struct Field {
+    label: &'static str,
+    value: String,
+    secret: bool,
+}
+
+impl Field {
+    fn text(label: &'static str, value: impl Into<String>) -> Self {
+        Self {
+            label,
+            value: value.into(),
+            secret: false,
+        }
+    }
+
+    fn secret(label: &'static str) -> Self {
+        // never grown past the capacity, so that no copy of the secret is left behind
+        Self {
+            label,
+            value: String::with_capacity(MAX_PASSWORD_LEN),
+            secret: true,
+        }
+    }
+
+    fn take_secret(&mut self) -> SecretString {
+        SecretString::from(std::mem::take(&mut self.value))
+    }
+}
+
+impl Drop for Field {
+    fn drop(&mut self) {
+        self.value.zeroize();
+    }
+}
+
+enum FormKind {
+    AddPassword,
+    Rename(String),
+    HomeMount,
+    PreMount(Option<String>),
+}
+
+enum FormEvent {
+    None,
+    Cancel,
+    Submit,
+}
+
+struct Form {
+    title: String,
+    kind: FormKind,
+    fields: Vec<Field>,
+    focus: usize,
+    error: Option<String>,
+}
+
+impl Form {
+    fn new(title: impl Into<String>, kind: FormKind, fields: Vec<Field>) -> Self {
+        Self {
+            title: title.into(),
+            kind,
+            fields,
+            focus: 0,
+            error: None,
+        }
+    }
+
+    fn add_password(name: &str) -> Self {
+        Self::new(
+            "Add a password",
+            FormKind::AddPassword,
+            vec![
+                Field::text("Name", name),
+                Field::secret("Intermediate key"),
+                Field::secret("New password"),
+                Field::secret("Repeat the password"),
+            ],
+        )
+    }
+
+    fn rename(name: &str) -> Self {
+        Self::new(
+            format!("Rename '{name}'"),
+            FormKind::Rename(String::from(name)),
+            vec![Field::text("New name", name)],
+        )
+    }
+
+    fn home_mount(params: &MountParams) -> Self {
+        Self::new(
+            "Home directory mount",
+            FormKind::HomeMount,
+            vec![
+                Field::text("Device", params.device()),
+                Field::text("Filesystem", params.fstype()),
+                Field::text("Options", params.flags().join(",")),
+            ],
+        )
+    }
+
+    fn pre_mount(dir: Option<&str>, params: &MountParams) -> Self {
+        let title = match dir {
+            Some(_) => "Edit a mount",
+            None => "Add a mount",
+        };
+
+        Self::new(
+            title,
+            FormKind::PreMount(dir.map(String::from)),
+            vec![
+                Field::text("Directory", dir.unwrap_or_default()),
+                Field::text("Device", params.device()),
+                Field::text("Filesystem", params.fstype()),
+                Field::text("Options", params.flags().join(",")),
+            ],
+        )
+    }
+
+    fn value(&self, label: &str) -> &str {
+        self.fields
+            .iter()
+            .find(|field| field.label == label)
+            .map(|field| field.value.trim())
+            .unwrap_or_default()
+    }
+
+    /// Mount parameters as typed: options are separated by commas
+    fn mount_params(&self) -> MountParams {
+        MountParams::new(
+            String::from(self.value("Device")),
+            String::from(self.value("Filesystem")),
+            self.value("Options")
+                .split(',')
+                .map(str::trim)
+                .filter(|flag| !flag.is_empty())
+                .map(String::from)
+                .collect(),
+        )
+    }
+
+    fn render(&self) -> (Vec<Line>, usize) {
+        let mut lines = vec![
+            Line::blank(),
+            Line::new(format!(" {}", self.title), Style::Heading),
+            Line::blank(),
+        ];
+
+        for (index, field) in self.fields.iter().enumerate() {
+            let shown = match field.secret {
+                true => "*".repeat(field.value.chars().count()),
+                false => field.value.clone(),
+            };
+
+            let line = match index == self.focus {
+                true => Line::new(format!("   {:<22}{shown}_", field.label), Style::Selected),
+                false => Line::new(format!("   {:<22}{shown}", field.label), Style::Normal),
+            };
+            lines.push(line);
+        }
+
+        lines.push(Line::blank());
+        if let FormKind::HomeMount | FormKind::PreMount(_) = self.kind {
+            lines.push(Line::new(
+                "   Options are separated by commas, e.g. rw,noatime. An empty filesystem is detected.",
+                Style::Dim,
+            ));
+        }
+        if let Some(error) = &self.error {
+            lines.push(Line::new(format!("   {error}"), Style::Bad));
+        }
+
+        (lines, 3 + self.focus)
+    }
+
+    fn handle(&mut self, key: Key) -> FormEvent {
+        let last = self.fields.len() - 1;
+        let field = &mut self.fields[self.focus];
+
+        match key {
+            Key::Escape => return FormEvent::Cancel,
+            Key::Enter if self.focus == last => return FormEvent::Submit,
+            Key::Enter | Key::Down | Key::Tab => self.focus = (self.focus + 1).min(last),
+            Key::Up | Key::BackTab => self.focus = self.focus.saturating_sub(1),
+            Key::Backspace => {
+                field.value.pop();
+            }
+            Key::Char(c)
+                if !field.secret || field.value.len() + c.len_utf8() <= MAX_PASSWORD_LEN =>
+            {
+                field.value.push(c)
+            }
+            _ => {}
+        }
+
+        FormEvent::None
+    }
+}
+
+enum Dialog {
+    Menu(Menu),
+    Form(Form),
+}
+
+struct App<'a> {
+    store: &'a dyn UserStore,
+    username: String,
+    auth_data: UserAuthData,
+    mounts: Option<MountPoints>,
+    session: Option<SessionCommand>,
+    authorization: Authorization,
+    selected: usize,
+    dialog: Option<Dialog>,
+    status: Option<Status>,
+}
+
+impl<'a> App<'a> {
+    fn load(store: &'a dyn UserStore, username: &str) -> Result<Self, CliError> {
+        let auth_data = load_user_auth_data(store, username)
+            .map_err(|err| CliError::from(err).context("Error loading the authentication data"))?
+            .unwrap_or_default();
+        let mounts = load_user_mountpoints(store, username)
+            .map_err(|err| CliError::from(err).context("Error in loading user mounts data"))?;
+        let session = load_user_session_command(store, username).map_err(|err| {
+            CliError::from(err).context("Error in reading the user default session")
+        })?;
+
+        Ok(Self {
+            store,
+            username: String::from(username),
+            auth_data,
+            mounts,
+            session,
+            authorization: Authorization::NoMounts,
+            selected: 0,
+            dialog: None,
+            status: None,
+        })
+    }
+
+    /// Lines of the main screen, with the item each line selects
+    fn entries(&self) -> Vec<(Line, Option<Item>)> {
+        let mut entries = vec![
+            (Line::blank(), None),
+            (Line::new(" Authentication methods", Style::Heading), None),
+        ];
+
+        if !self.auth_data.has_main() {
+            entries.push((
+                Line::new(
+                    "   No main password stored: run polyauthctl setup first",
+                    Style::Bad,
+                ),
+                None,
+            ));
+        } else if self.auth_data.secondary().next().is_none() {
+            entries.push((Line::new("   No passwords added yet", Style::Dim), None));
+        }
+
+        for auth in self.auth_data.secondary() {
+            let created = Local
+                .timestamp_opt(auth.creation_date() as i64, 0)
+                .single()
+                .map(|date| date.format("%Y-%m-%d %H:%M").to_string())
+                .unwrap_or_default();
+            entries.push((
+                Line::new(
+                    format!(
+                        "   {:<24} {:<10} added {created}",
+                        auth.name(),
+                        auth.type_name()
+                    ),
+                    Style::Normal,
+                ),
+                Some(Item::Secondary(auth.name())),
+            ));
+        }
+
+        entries.push((
+            Line::new("   + Add a password", Style::Normal),
+            Some(Item::AddPassword),
+        ));
+
+        entries.push((Line::blank(), None));
+        entries.push((Line::new(" Mounts", Style::Heading), None));
+
+        match &self.mounts {
+            None => entries.push((
+                Line::new(
+                    format!("   {:<24} not set", "Home directory"),
+                    Style::Normal,
+                ),
+                Some(Item::HomeMount),
+            )),
+            Some(mounts) => {
+                entries.push((
+                    Line::new(mount_line("Home directory", &mounts.mount()), Style::Normal),
+                    Some(Item::HomeMount),
+                ));
+
+                let mut additional = mounts.foreach(|dir, params| (dir.clone(), params.clone()));
+                additional.sort_by(|a, b| a.0.cmp(&b.0));
+                for (dir, params) in additional {
+                    entries.push((
+                        Line::new(mount_line(&dir, &params), Style::Normal),
+                        Some(Item::PreMount(dir)),
+                    ));
+                }
+
+                entries.push((
+                    Line::new("   + Add a mount", Style::Normal),
+                    Some(Item::AddPreMount),
+                ));
+
+                let (text, style) = match &self.authorization {
+                    Authorization::Authorized => (String::from("authorized"), Style::Good),
+                    Authorization::NotAuthorized => (
+                        String::from("not authorized, they will not be mounted"),
+                        Style::Bad,
+                    ),
+                    Authorization::Unknown(err) => (format!("unknown: {err}"), Style::Dim),
+                    Authorization::NoMounts => (String::from("unknown"), Style::Dim),
+                };
+                entries.push((
+                    Line::new(format!("   {:<24} {text}", "Authorize mounts"), style),
+                    Some(Item::Authorize),
+                ));
+            }
+        }
+
+        entries.push((Line::blank(), None));
+        entries.push((Line::new(" Session", Style::Heading), None));
+
+        match &self.session {
+            None => entries.push((
+                Line::new(
+                    "   No default session: polyauthctl set-session sets one",
+                    Style::Dim,
+                ),
+                None,
+            )),
+            Some(session) => {
+                let text = match session.desktop() {
+                    Some(desktop) => match SessionDirs::default().find(desktop) {
+                        Ok(found) => format!("Desktop session {} ({desktop})", found.name()),
+                        Err(_) => format!("Desktop session {desktop} (not installed)"),
+                    },
+                    None => match session.args().is_empty() {
+                        true => format!("Command {}", session.command()),
+                        false => {
+                            format!("Command {} {}", session.command(), session.args().join(" "))
+                        }
+                    },
+                };
+                entries.push((Line::new(format!("   {text}"), Style::Normal), None));
+
+                if !session.session_type().is_unspecified() {
+                    entries.push((
+                        Line::new(format!("   Type {}", session.session_type()), Style::Normal),
+                        None,
+                    ));
+                }
+            }
+        }
+
+        entries
+    }
+
+    fn items(&self) -> Vec<Item> {
+        self.entries()
+            .into_iter()
+            .filter_map(|(_, item)| item)
+            .collect()
+    }
+
+    fn select(&mut self, item: &Item) {
+        if let Some(index) = self.items().iter().position(|candidate| candidate == item) {
+            self.selected = index;
+        }
+    }
+
+    /// Keeps the selection on an item once some have been removed
+    fn clamp_selection(&mut self) {
+        self.selected = self.selected.min(self.items().len().saturating_sub(1));
+    }
+
+    fn render(&self, rows: usize) -> Vec<Line> {
+        let mut lines = vec![Line::new(
+            format!(" polyauth settings of '{}'", self.username),
+            Style::Title,
+        )];
+
+        let (body, selected_row, help) = match &self.dialog {
+            None => {
+                let mut body = vec![];
+                let mut selected_row = 0;
+                let mut item_index = 0;
+                for (line, item) in self.entries() {
+                    match item {
+                        Some(_) if item_index == self.selected => {
+                            selected_row = body.len();
+                            body.push(Line::new(line.text(), Style::Selected));
+                            item_index += 1;
+                        }
+                        Some(_) => {
+                            body.push(line);
+                            item_index += 1;
+                        }
+                        None => body.push(line),
+                    }
+                }
+
+                (
+                    body,
+                    selected_row,
+                    " Up/Down select   Enter open   r rename   d remove   q quit",
+                )
+            }
+            Some(Dialog::Menu(menu)) => {
+                let (body, selected_row) = menu.render();
+                (
+                    body,
+                    selected_row,
+                    " Up/Down select   Enter confirm   Esc back",
+                )
+            }
+            Some(Dialog::Form(form)) => {
+                let (body, selected_row) = form.render();
+                (
+                    body,
+                    selected_row,
+                    " Up/Down/Tab move   Enter next field or save   Esc cancel",
+                )
+            }
+        };
+
+        // title above, status and help below: the body scrolls to keep the selection visible
+        let height = rows.saturating_sub(3).max(1);
+        let first = (selected_row + 1).saturating_sub(height);
+        lines.extend(body.into_iter().skip(first).take(height));
+        while lines.len() < rows.saturating_sub(2) {
+            lines.push(Line::blank());
+        }
+
+        lines.push(match &self.status {
+            Some(status) => Line::new(format!(" {}", status.text), status.style),
+            None => Line::blank(),
+        });
+        lines.push(Line::new(help, Style::Dim));
+
+        lines
+    }
+
+    fn handle(&mut self, key: Key) -> Flow {
+        match key {
+            Key::Interrupt => return Flow::Quit,
+            // the screen is drawn again at the new size by the caller
+            Key::Resize => return Flow::Continue,
+            _ => {}
+        }
+
+        self.status = None;
+
+        match self.dialog.take() {
+            None => self.handle_main(key),
+            Some(Dialog::Menu(mut menu)) => match menu.handle(key) {
+                Some(action) => self.perform(action),
+                None => {
+                    self.dialog = Some(Dialog::Menu(menu));
+                    Flow::Continue
+                }
+            },
+            Some(Dialog::Form(mut form)) => match form.handle(key) {
+                FormEvent::None => {
+                    self.dialog = Some(Dialog::Form(form));
+                    Flow::Continue
+                }
+                FormEvent::Cancel => Flow::Continue,
+                FormEvent::Submit => self.submit(form),
+            },
+        }
+    }
+
+    fn handle_main(&mut self, key: Key) -> Flow {
+        let items = self.items();
+        let selected = items.get(self.selected).cloned();
+
+        match (key, selected) {
+            (Key::Escape | Key::Char('q'), _) => return Flow::Quit,
+            (Key::Up | Key::BackTab, _) => self.selected = self.selected.saturating_sub(1),
+            (Key::Down | Key::Tab, _) => {
+                self.selected = (self.selected + 1).min(items.len().saturating_sub(1))
+            }
+            (Key::Home, _) => self.selected = 0,
+            (Key::End, _) => self.selected = items.len().saturating_sub(1),
+            (Key::Enter, Some(item)) => return self.open(item),
+            (Key::Char('r'), Some(Item::Secondary(name))) => {
+                return self.perform(Action::RenameSecondary(name))
+            }
+            (Key::Char('d') | Key::Delete, Some(Item::Secondary(name))) => {
+                return self.perform(Action::AskRemoveSecondary(name))
+            }
+            (Key::Char('d') | Key::Delete, Some(Item::PreMount(dir))) => {
+                return self.perform(Action::AskRemovePreMount(dir))
+            }
+            _ => {}
+        }
+
+        Flow::Continue
+    }
+
+    fn open(&mut self, item: Item) -> Flow {
+        match item {
+            Item::Secondary(name) => {
+                self.dialog = Some(Dialog::Menu(Menu::new(
+                    format!("Password '{name}'"),
+                    vec![
+                        ("Rename", Action::RenameSecondary(name.clone())),
+                        ("Remove", Action::AskRemoveSecondary(name)),
+                        ("Back", Action::Cancel),
+                    ],
+                )))
+            }
+            Item::AddPassword if !self.auth_data.has_main() => {
+                self.status = Some(Status::bad(
+                    "No main password has been stored: run polyauthctl setup first",
+                ))
+            }
+            Item::AddPassword => self.dialog = Some(Dialog::Form(Form::add_password(""))),
+            Item::HomeMount => {
+                let home = self.mounts.as_ref().map(|mounts| mounts.mount());
+                self.dialog = Some(Dialog::Form(Form::home_mount(&home.unwrap_or_default())))
+            }
+            Item::PreMount(dir) => {
+                self.dialog = Some(Dialog::Menu(Menu::new(
+                    format!("Mount of {dir}"),
+                    vec![
+                        ("Edit", Action::EditPreMount(dir.clone())),
+                        ("Remove", Action::AskRemovePreMount(dir)),
+                        ("Back", Action::Cancel),
+                    ],
+                )))
+            }
+            Item::AddPreMount => {
+                self.dialog = Some(Dialog::Form(Form::pre_mount(None, &MountParams::default())))
+            }
+            Item::Authorize => return Flow::Run(Task::Authorize),
+        }
+
+        Flow::Continue
+    }
+
+    fn perform(&mut self, action: Action) -> Flow {
+        match action {
+            Action::Cancel => {}
+            Action::RenameSecondary(name) => self.dialog = Some(Dialog::Form(Form::rename(&name))),
+            Action::AskRemoveSecondary(name) => {
+                self.dialog = Some(Dialog::Menu(Menu::confirm(
+                    format!("Remove the password '{name}'? It will no longer log in."),
+                    "Remove",
+                    Action::RemoveSecondary(name),
+                )))
+            }
+            Action::RemoveSecondary(name) => {
+                let mut auth_data = self.auth_data.clone();
+                auth_data.remove_secondary(&name);
+                if self.save_auth_data(auth_data) {
+                    self.status = Some(Status::good(format!("Password '{name}' removed")));
+                    self.clamp_selection();
+                }
+            }
+            Action::EditPreMount(dir) => {
+                let params = self
+                    .mounts
+                    .as_ref()
+                    .and_then(|mounts| {
+                        mounts
+                            .foreach(|mount_dir, params| (mount_dir.clone(), params.clone()))
+                            .into_iter()
+                            .find(|(mount_dir, _)| *mount_dir == dir)
+                    })
+                    .map(|(_, params)| params)
+                    .unwrap_or_default();
+                self.dialog = Some(Dialog::Form(Form::pre_mount(Some(&dir), &params)))
+            }
+            Action::AskRemovePreMount(dir) => {
+                self.dialog = Some(Dialog::Menu(Menu::confirm(
+                    format!("Remove the mount of {dir}?"),
+                    "Remove",
+                    Action::RemovePreMount(dir),
+                )))
+            }
+            Action::RemovePreMount(dir) => {
+                let Some(mut mounts) = self.mounts.clone() else {
+                    return Flow::Continue;
+                };
+
+                mounts.remove_premount(&dir);
+                if self.save_mounts(mounts) {
+                    self.status = Some(Status::good(format!("Mount of {dir} removed")));
+                    self.clamp_selection();
+                    return Flow::Run(Task::CheckAuthorization);
+                }
+            }
+        }
+
+        Flow::Continue
+    }
+
+    /// Checks what has been typed: the form stays open with the problem if it is not accepted
+    fn submit(&mut self, mut form: Form) -> Flow {
+        match self.apply(&mut form) {
+            Ok(flow) => flow,
+            Err(error) => {
+                form.error = Some(error);
+                self.dialog = Some(Dialog::Form(form));
+                Flow::Continue
+            }
+        }
+    }
+
+    fn apply(&mut self, form: &mut Form) -> Result<Flow, String> {
+        match &form.kind {
+            FormKind::AddPassword => {
+                let name = String::from(form.value("Name"));
+                self.check_new_name(&name)?;
+
+                // the secrets are typed again whatever happens
+                let intermediate = form.fields[1].take_secret();
+                let password = form.fields[2].take_secret();
+                let confirmation = form.fields[3].take_secret();
+                form.focus = 1;
+
+                if !is_valid_password(&intermediate) {
+                    return Err(String::from("Type the intermediate key chosen at setup"));
+                }
+                if !is_valid_password(&password) {
+                    return Err(format!(
+                        "The password must be 1 to {MAX_PASSWORD_LEN} bytes long"
+                    ));
+                }
+                if !constant_time_eq(password.as_bytes(), confirmation.as_bytes()) {
+                    return Err(String::from("The two passwords do not match"));
+                }
+
+                Ok(Flow::Run(Task::AddPassword {
+                    name,
+                    intermediate,
+                    password,
+                }))
+            }
+            FormKind::Rename(name) => {
+                let new_name = form.value("New name");
+                if new_name != name {
+                    self.check_new_name(new_name)?;
+                }
+
+                let mut auth_data = self.auth_data.clone();
+                auth_data
+                    .rename_secondary(name, new_name)
+                    .map_err(|err| err.to_string())?;
+
+                if self.save_auth_data(auth_data) {
+                    self.status = Some(Status::good(format!(
+                        "Password '{name}' renamed to '{new_name}'"
+                    )));
+                    self.select(&Item::Secondary(String::from(new_name)));
+                }
+
+                Ok(Flow::Continue)
+            }
+            FormKind::HomeMount => {
+                let params = form.mount_params();
+                params.validate().map_err(|err| err.to_string())?;
+
+                let mounts = self.mounts.clone().unwrap_or_default().with_mount(&params);
+                if !self.save_mounts(mounts) {
+                    return Ok(Flow::Continue);
+                }
+
+                self.status = Some(Status::good("Home directory mount saved"));
+                Ok(Flow::Run(Task::CheckAuthorization))
+            }
+            FormKind::PreMount(old_dir) => {
+                let dir = String::from(form.value("Directory"));
+                validate_mount_dir(&dir).map_err(|err| err.to_string())?;
+
+                let params = form.mount_params();
+                params.validate().map_err(|err| err.to_string())?;
+
+                let Some(mut mounts) = self.mounts.clone() else {
+                    return Err(String::from("Set the home directory mount first"));
+                };
+
+                let taken = mounts
+                    .foreach(|mount_dir, _| *mount_dir == dir)
+                    .contains(&true);
+                if taken && old_dir.as_ref() != Some(&dir) {
+                    return Err(format!("A mount of {dir} is already configured"));
+                }
+
+                if let Some(old_dir) = old_dir {
+                    mounts.remove_premount(old_dir);
+                }
+                mounts.add_premount(&dir, &params);
+
+                if !self.save_mounts(mounts) {
+                    return Ok(Flow::Continue);
+                }
+
+                self.status = Some(Status::good(format!("Mount of {dir} saved")));
+                self.select(&Item::PreMount(dir));
+                Ok(Flow::Run(Task::CheckAuthorization))
+            }
+        }
+    }
+
+    fn check_new_name(&self, name: &str) -> Result<(), String> {
+        if name.is_empty() {
+            return Err(String::from("The name cannot be empty"));
+        }
+
+        if self.auth_data.secondary().any(|auth| auth.name() == name) {
+            return Err(format!("A password named '{name}' already exists"));
+        }
+
+        Ok(())
+    }
+
+    fn save_auth_data(&mut self, auth_data: UserAuthData) -> bool {
+        match store_user_auth_data(&auth_data, self.store, &self.username, None, None) {
+            Ok(()) => {
+                self.auth_data = auth_data;
+                true
+            }
+            Err(err) => {
+                self.status = Some(save_error(&err));
+                false
+            }
+        }
+    }
+
+    fn save_mounts(&mut self, mounts: MountPoints) -> bool {
+        match store_user_mountpoints(Some(mounts.clone()), self.store, &self.username, None, None) {
+            Ok(()) => {
+                self.mounts = Some(mounts);
+                true
+            }
+            Err(err) => {
+                self.status = Some(save_error(&err));
+                false
+            }
+        }
+    }
+
+    async fn refresh_authorization(&mut self) {
+        self.authorization = match &self.mounts {
+            None => Authorization::NoMounts,
+            Some(mounts) => match is_mount_authorized(&self.username, mounts.hash()).await {
+                Ok(true) => Authorization::Authorized,
+                Ok(false) => Authorization::NotAuthorized,
+                Err(err) => Authorization::Unknown(err.to_string()),
+            },
+        };
+    }
+
+    async fn run(&mut self, task: Task) {
+        match task {
+            Task::AddPassword {
+                name,
+                intermediate,
+                password,
+            } => {
+                let mut auth_data = self.auth_data.clone();
+                match auth_data.add_secondary_password(
+                    &name,
+                    intermediate.as_bytes(),
+                    password.as_bytes(),
+                ) {
+                    Ok(()) => {
+                        if self.save_auth_data(auth_data) {
+                            self.status = Some(Status::good(format!("Password '{name}' added")));
+                            self.select(&Item::Secondary(name));
+                        }
+                    }
+                    Err(err) => {
+                        // the name is kept, the secrets have to be typed again
+                        let mut form = Form::add_password(&name);
+                        form.focus = 1;
+                        form.error = Some(match err {
+                            UserOperationError::EncryptionError(_)
+                            | UserOperationError::User(_) => String::from("Wrong intermediate key"),
+                            err => format!("Error adding the password: {err}"),
+                        });
+                        self.status = None;
+                        self.dialog = Some(Dialog::Form(form));
+                    }
+                }
+            }
+            Task::Authorize => {
+                let Some(hash) = self.mounts.as_ref().map(|mounts| mounts.hash()) else {
+                    return;
+                };
+
+                let authorized = async {
+                    let connection = Connection::system().await?;
+                    MountAuthDBusProxy::new(&connection)
+                        .await?
+                        .authorize(&self.username, hash)
+                        .await
+                }
+                .await;
+
+                self.status = Some(match authorized {
+                    Ok(()) => Status::good("Mounts authorized"),
+                    Err(err) => Status::bad(format!("Error in authorizing the mounts: {err}")),
+                });
+                self.refresh_authorization().await;
+            }
+            Task::CheckAuthorization => {
+                // the message of what triggered the check stays
+                self.refresh_authorization().await;
+            }
+        }
+
+        self.clamp_selection();
+    }
+}
+
+fn mount_line(label: &str, params: &MountParams) -> String {
+    let fstype = match params.fstype().is_empty() {
+        true => "auto",
+        false => params.fstype(),
+    };
+
+    match params.flags().is_empty() {
+        true => format!("   {label:<24} {} {fstype}", params.device()),
+        false => format!(
+            "   {label:<24} {} {fstype} {}",
+            params.device(),
+            params.flags().join(",")
+        ),
+    }
+}
+
+fn terminal_error(err: std::io::Error) -> CliError {
+    CliError::new(ErrorClass::Usage, format!("Terminal error: {err}"))
+}
+
+/// Shows the configuration of the user and lets them change it until they quit
+/// What to tell when a change could not be written: users can only change the files of
+/// their own configuration they own, the rest (i.e. root-owned mounts) needs root
+fn save_error(err: &StorageError) -> Status {
+    match err {
+        StorageError::IoError(io) if io.kind() == std::io::ErrorKind::PermissionDenied => {
+            Status::bad(format!(
+                "Not allowed to change this configuration ({io}): run polyauthctl tui with sudo"
+            ))
+        }
+        err if is_locked_error(err) => {
+            Status::bad("The configuration is being changed by another program: try again")
+        }
+        err => Status::bad(format!("Error saving the configuration: {err}")),
+    }
+}
+
+pub async fn run(store: &dyn UserStore, username: &str) -> Result<(), CliError> {
+    // nothing of the configuration of another user could be written
+    let current_uid = users::get_current_uid();
+    if current_uid != 0
+        && users::get_user_by_name(username).map(|user| user.uid()) != Some(current_uid)
+    {
+        return Err(CliError::new(
+            ErrorClass::Usage,
+            format!("Only root can manage the configuration of user '{username}': run polyauthctl tui with sudo"),
+        ));
+    }
+
+    let mut app = App::load(store, username)?;
+    app.refresh_authorization().await;
+
+    let mut terminal = Terminal::open().map_err(|err| {
+        CliError::new(
+            ErrorClass::Usage,
+            format!("Cannot start the interface: {err}"),
+        )
+    })?;
+
+    loop {
+        let (_, rows) = terminal.size();
+        terminal.draw(&app.render(rows)).map_err(terminal_error)?;
+
+        match app.handle(terminal.read_key().map_err(terminal_error)?) {
+            Flow::Continue => {}
+            Flow::Quit => return Ok(()),
+            Flow::Run(task) => {
+                app.status = Some(Status::working(task.description()));
+                terminal.draw(&app.render(rows)).map_err(terminal_error)?;
+                app.run(task).await;
+            }
+        }
+    }
+}
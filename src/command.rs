@@ -19,19 +19,152 @@
     51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
 */
 
+use std::{collections::BTreeMap, fmt, str::FromStr};
+
 use serde::{Deserialize, Serialize};
 
+/// Kind of session started by a SessionCommand, as exported in XDG_SESSION_TYPE
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SessionType {
+    /// Left to the greeter to decide
+    #[default]
+    Unspecified,
+    Wayland,
+    X11,
+    Tty,
+}
+
+impl SessionType {
+    pub fn is_unspecified(&self) -> bool {
+        *self == Self::Unspecified
+    }
+}
+
+impl fmt::Display for SessionType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let session_type = match self {
+            Self::Unspecified => "unspecified",
+            Self::Wayland => "wayland",
+            Self::X11 => "x11",
+            Self::Tty => "tty",
+        };
+        write!(f, "{session_type}")
+    }
+}
+
+impl FromStr for SessionType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "unspecified" | "" => Ok(Self::Unspecified),
+            "wayland" => Ok(Self::Wayland),
+            "x11" => Ok(Self::X11),
+            "tty" => Ok(Self::Tty),
+            _ => Err(format!(
+                "unknown session type '{s}': expected wayland, x11 or tty"
+            )),
+        }
+    }
+}
+
+/// What a greeter has to launch when the user logs in.
+///
+/// Empty fields are not serialized: a command stored by an older release reads and
+/// serializes exactly as before, so existing configuration signatures stay valid.
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct SessionCommand {
+    // empty for commands that only refer to a desktop session
+    #[serde(default, skip_serializing_if = "String::is_empty")]
     command: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    args: Vec<String>,
+    // sorted, so that the serialization (and therefore the signature) does not change between loads
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    env: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "SessionType::is_unspecified")]
+    session_type: SessionType,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    desktop_names: Vec<String>,
+    // ID of a session in wayland-sessions or xsessions, resolved by SessionDirs::resolve
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    desktop: Option<String>,
 }
 
 impl SessionCommand {
     pub fn new(command: String) -> Self {
-        Self { command }
+        Self {
+            command,
+            args: vec![],
+            env: BTreeMap::new(),
+            session_type: SessionType::Unspecified,
+            desktop_names: vec![],
+            desktop: None,
+        }
+    }
+
+    /// A command that runs the given desktop session: the command to execute is read from
+    /// its `.desktop` file every time, so that it keeps working when the file changes.
+    pub fn desktop_session(id: String) -> Self {
+        Self::new(String::new()).with_desktop(Some(id))
+    }
+
+    pub fn with_args(mut self, args: Vec<String>) -> Self {
+        self.args = args;
+        self
+    }
+
+    pub fn with_env(mut self, env: BTreeMap<String, String>) -> Self {
+        self.env = env;
+        self
+    }
+
+    pub fn with_session_type(mut self, session_type: SessionType) -> Self {
+        self.session_type = session_type;
+        self
+    }
+
+    /// Desktop names as exported in XDG_CURRENT_DESKTOP, most specific first
+    pub fn with_desktop_names(mut self, desktop_names: Vec<String>) -> Self {
+        self.desktop_names = desktop_names;
+        self
+    }
+
+    pub fn with_desktop(mut self, desktop: Option<String>) -> Self {
+        self.desktop = desktop;
+        self
     }
 
     pub fn command(&self) -> String {
         self.command.clone()
     }
+
+    pub fn args(&self) -> &Vec<String> {
+        &self.args
+    }
+
+    pub fn env(&self) -> &BTreeMap<String, String> {
+        &self.env
+    }
+
+    pub fn session_type(&self) -> SessionType {
+        self.session_type
+    }
+
+    pub fn desktop_names(&self) -> &Vec<String> {
+        &self.desktop_names
+    }
+
+    /// ID of the desktop session, also kept in the commands returned by SessionDirs::resolve
+    pub fn desktop(&self) -> Option<&str> {
+        self.desktop.as_deref()
+    }
+
+    /// The command followed by its arguments, ready to be executed
+    pub fn argv(&self) -> Vec<String> {
+        std::iter::once(self.command.clone())
+            .chain(self.args.iter().cloned())
+            .collect()
+    }
 }
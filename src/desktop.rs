@@ -0,0 +1,354 @@
+/*
+    pam_polyauth: A pam module written in rust that supports multiple
+    authentication modes (including autologin).
+
+    Copyright (C) 2024-2025  Denis Benato
+
+    This program is free software; you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation; either version 2 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License along
+    with this program; if not, write to the Free Software Foundation, Inc.,
+    51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+*/
+
+use std::{
+    collections::HashSet,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use thiserror::Error;
+
+use crate::command::{SessionCommand, SessionType};
+
+/// Sessions installed by wayland compositors
+pub const WAYLAND_SESSIONS_DIR: &str = "/usr/share/wayland-sessions";
+
+/// Sessions installed by X11 desktops and window managers
+pub const X_SESSIONS_DIR: &str = "/usr/share/xsessions";
+
+#[derive(Debug, Error)]
+pub enum DesktopError {
+    #[error("IO Error: {0}")]
+    IoError(#[from] std::io::Error),
+
+    #[error("No desktop session named '{0}' is installed")]
+    NotFound(String),
+
+    #[error("{0} has no [Desktop Entry] group")]
+    MissingEntry(PathBuf),
+
+    #[error("{0} has no Exec key")]
+    MissingExec(PathBuf),
+
+    #[error("Invalid Exec key in {0}: {1}")]
+    InvalidExec(PathBuf, String),
+}
+
+/// A session described by a `.desktop` file, identified by its file name without extension
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DesktopSession {
+    id: String,
+    name: String,
+    exec: Vec<String>,
+    desktop_names: Vec<String>,
+    entry_type: Option<String>,
+    session_type: SessionType,
+    path: PathBuf,
+}
+
+impl DesktopSession {
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Human readable name: the ID when the file has no Name key
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The Exec key split into the command and its arguments
+    pub fn exec(&self) -> &Vec<String> {
+        &self.exec
+    }
+
+    pub fn desktop_names(&self) -> &Vec<String> {
+        &self.desktop_names
+    }
+
+    /// The Type key of the entry (Application or XSession), not to be confused with session_type
+    pub fn entry_type(&self) -> Option<&str> {
+        self.entry_type.as_deref()
+    }
+
+    /// Wayland or X11 depending on the directory the file was found in
+    pub fn session_type(&self) -> SessionType {
+        self.session_type
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Parses the `[Desktop Entry]` group of a session file:
+    /// returns None for entries that are marked as hidden.
+    pub fn parse(
+        id: &str,
+        path: &Path,
+        session_type: SessionType,
+        contents: &str,
+    ) -> Result<Option<Self>, DesktopError> {
+        let mut in_entry = false;
+        let mut found_entry = false;
+        let mut name = None;
+        let mut exec = None;
+        let mut desktop_names = vec![];
+        let mut entry_type = None;
+        let mut hidden = false;
+
+        for line in contents.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            if line.starts_with('[') {
+                in_entry = line == "[Desktop Entry]";
+                found_entry |= in_entry;
+                continue;
+            }
+
+            if !in_entry {
+                continue;
+            }
+
+            // localized keys (i.e. Name[it]) are not of interest
+            let Some((key, value)) = line.split_once('=') else {
+                continue;
+            };
+
+            match key.trim() {
+                "Name" => name = Some(value.trim().to_string()),
+                "Exec" => exec = Some(value.trim().to_string()),
+                "DesktopNames" => {
+                    desktop_names = value
+                        .split(';')
+                        .map(str::trim)
+                        .filter(|name| !name.is_empty())
+                        .map(String::from)
+                        .collect()
+                }
+                "Type" => entry_type = Some(value.trim().to_string()),
+                "Hidden" => hidden = value.trim() == "true",
+                _ => {}
+            }
+        }
+
+        if !found_entry {
+            return Err(DesktopError::MissingEntry(path.to_path_buf()));
+        }
+
+        if hidden {
+            return Ok(None);
+        }
+
+        let exec = exec.ok_or_else(|| DesktopError::MissingExec(path.to_path_buf()))?;
+        let exec =
+            split_exec(&exec).map_err(|err| DesktopError::InvalidExec(path.to_path_buf(), err))?;
+        if exec.is_empty() {
+            return Err(DesktopError::MissingExec(path.to_path_buf()));
+        }
+
+        Ok(Some(Self {
+            id: String::from(id),
+            name: name.unwrap_or_else(|| String::from(id)),
+            exec,
+            desktop_names,
+            entry_type,
+            session_type,
+            path: path.to_path_buf(),
+        }))
+    }
+}
+
+/// Splits an Exec key in its arguments following the desktop entry specification:
+/// double quotes group arguments, field codes such as %f or %U are dropped.
+pub fn split_exec(exec: &str) -> Result<Vec<String>, String> {
+    let mut args = vec![];
+    let mut current = String::new();
+    let mut has_arg = false;
+    let mut quoted = false;
+    let mut chars = exec.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => {
+                quoted = !quoted;
+                has_arg = true;
+            }
+            '\\' if quoted => match chars.next() {
+                Some(escaped) => current.push(escaped),
+                None => return Err(String::from("trailing backslash")),
+            },
+            '%' => match chars.next() {
+                Some('%') => current.push('%'),
+                Some(_) => has_arg |= quoted,
+                None => return Err(String::from("trailing %")),
+            },
+            c if c.is_whitespace() && !quoted => {
+                if has_arg || !current.is_empty() {
+                    args.push(std::mem::take(&mut current));
+                }
+                has_arg = false;
+            }
+            c => current.push(c),
+        }
+    }
+
+    if quoted {
+        return Err(String::from("unterminated quote"));
+    }
+
+    if has_arg || !current.is_empty() {
+        args.push(current);
+    }
+
+    Ok(args)
+}
+
+/// Directories searched for session files, in order of preference
+#[derive(Debug, Clone)]
+pub struct SessionDirs {
+    dirs: Vec<(PathBuf, SessionType)>,
+}
+
+impl Default for SessionDirs {
+    fn default() -> Self {
+        Self::new(vec![
+            (PathBuf::from(WAYLAND_SESSIONS_DIR), SessionType::Wayland),
+            (PathBuf::from(X_SESSIONS_DIR), SessionType::X11),
+        ])
+    }
+}
+
+impl SessionDirs {
+    pub fn new(dirs: Vec<(PathBuf, SessionType)>) -> Self {
+        Self { dirs }
+    }
+
+    /// Every installed session sorted by ID: when the same ID is found in more than
+    /// one directory only the one in the preferred directory is returned.
+    ///
+    /// Files that cannot be parsed are reported and skipped.
+    pub fn list(&self) -> Vec<DesktopSession> {
+        let mut seen = HashSet::new();
+        let mut sessions = vec![];
+
+        for (dir, session_type) in self.dirs.iter() {
+            let Ok(entries) = fs::read_dir(dir) else {
+                continue;
+            };
+
+            let mut paths = entries
+                .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                .filter(|path| path.extension().is_some_and(|ext| ext == "desktop"))
+                .collect::<Vec<_>>();
+            paths.sort();
+
+            for path in paths {
+                let Some(id) = path.file_stem().map(|id| id.to_string_lossy().to_string()) else {
+                    continue;
+                };
+
+                if seen.contains(&id) {
+                    continue;
+                }
+
+                match Self::load(&id, &path, *session_type) {
+                    Ok(Some(session)) => {
+                        seen.insert(id);
+                        sessions.push(session);
+                    }
+                    Ok(None) => {}
+                    Err(err) => eprintln!("⚠️ Ignoring {}: {err}", path.display()),
+                }
+            }
+        }
+
+        sessions.sort_by(|a, b| a.id.cmp(&b.id));
+
+        sessions
+    }
+
+    /// The session with the given ID, from the first directory that has it
+    pub fn find(&self, id: &str) -> Result<DesktopSession, DesktopError> {
+        // an ID is a file name: never let it walk out of the session directories
+        if id.is_empty() || id.contains('/') {
+            return Err(DesktopError::NotFound(String::from(id)));
+        }
+
+        for (dir, session_type) in self.dirs.iter() {
+            let path = dir.join(format!("{id}.desktop"));
+            if !path.exists() {
+                continue;
+            }
+
+            if let Some(session) = Self::load(id, &path, *session_type)? {
+                return Ok(session);
+            }
+        }
+
+        Err(DesktopError::NotFound(String::from(id)))
+    }
+
+    /// Turns a session command that refers to a desktop session into the command to run:
+    /// commands without a desktop session are returned as they are.
+    ///
+    /// Arguments stored in the command follow the ones of the Exec key, while the stored
+    /// session type and desktop names take precedence over the ones of the session file.
+    pub fn resolve(&self, command: &SessionCommand) -> Result<SessionCommand, DesktopError> {
+        let Some(id) = command.desktop() else {
+            return Ok(command.clone());
+        };
+
+        let session = self.find(id)?;
+
+        let args = session.exec[1..]
+            .iter()
+            .chain(command.args().iter())
+            .cloned()
+            .collect();
+
+        let session_type = match command.session_type().is_unspecified() {
+            true => session.session_type,
+            false => command.session_type(),
+        };
+
+        let desktop_names = match command.desktop_names().is_empty() {
+            true => session.desktop_names.clone(),
+            false => command.desktop_names().clone(),
+        };
+
+        Ok(SessionCommand::new(session.exec[0].clone())
+            .with_args(args)
+            .with_env(command.env().clone())
+            .with_session_type(session_type)
+            .with_desktop_names(desktop_names)
+            .with_desktop(Some(String::from(id))))
+    }
+
+    fn load(
+        id: &str,
+        path: &Path,
+        session_type: SessionType,
+    ) -> Result<Option<DesktopSession>, DesktopError> {
+        DesktopSession::parse(id, path, session_type, &fs::read_to_string(path)?)
+    }
+}
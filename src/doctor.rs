@@ -0,0 +1,444 @@
+/*
+    pam_polyauth: A pam module written in rust that supports multiple
+    authentication modes (including autologin).
+
+    Copyright (C) 2024-2025  Denis Benato
+
+    This program is free software; you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation; either version 2 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License along
+    with this program; if not, write to the Free Software Foundation, Inc.,
+    51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+*/
+
+use std::{
+    collections::BTreeMap,
+    fmt, fs,
+    os::unix::fs::{MetadataExt, PermissionsExt},
+    path::{Path, PathBuf},
+};
+
+use serde::Serialize;
+
+use crate::{mount::MountPoints, storage::store::UserStore, user::UserAuthData};
+
+/// Directory udev populates with the by-uuid, by-label, by-partuuid and by-partlabel links
+pub const DISK_BY_DIR: &str = "/dev/disk";
+
+/// Directory holding the PAM configuration of each service
+pub const PAM_CONFIG_DIR: &str = "/etc/pam.d";
+
+/// Directories the system bus reads policies from, vendor first
+pub const DBUS_SYSTEM_POLICY_DIRS: [&str; 2] =
+    ["/usr/share/dbus-1/system.d", "/etc/dbus-1/system.d"];
+
+/// Policies allowing pam_polyauth-service to own its names on the system bus
+pub const DBUS_POLICY_FILES: [&str; 2] = [
+    "org.neroreflex.polyauth_session.conf",
+    "org.neroreflex.polyauth_mount.conf",
+];
+
+/// PAM module types pam_polyauth implements: auth (authenticate and setcred) and session
+pub const PAM_POLYAUTH_PHASES: [&str; 2] = ["auth", "session"];
+
+const PAM_POLYAUTH_MODULE: &str = "pam_polyauth.so";
+
+/// Outcome of a single check
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CheckStatus {
+    Pass,
+    Warn,
+    Fail,
+}
+
+impl fmt::Display for CheckStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CheckStatus::Pass => write!(f, "pass"),
+            CheckStatus::Warn => write!(f, "warn"),
+            CheckStatus::Fail => write!(f, "fail"),
+        }
+    }
+}
+
+/// One line of the report of polyauthctl doctor
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Check {
+    name: String,
+    status: CheckStatus,
+    message: String,
+}
+
+impl Check {
+    pub fn new(name: impl Into<String>, status: CheckStatus, message: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            status,
+            message: message.into(),
+        }
+    }
+
+    pub fn pass(name: impl Into<String>, message: impl Into<String>) -> Self {
+        Self::new(name, CheckStatus::Pass, message)
+    }
+
+    pub fn warn(name: impl Into<String>, message: impl Into<String>) -> Self {
+        Self::new(name, CheckStatus::Warn, message)
+    }
+
+    pub fn fail(name: impl Into<String>, message: impl Into<String>) -> Self {
+        Self::new(name, CheckStatus::Fail, message)
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn status(&self) -> CheckStatus {
+        self.status
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+}
+
+/// Whether none of the checks failed: warnings do not make a system unhealthy
+pub fn healthy(checks: &[Check]) -> bool {
+    checks.iter().all(|check| check.status != CheckStatus::Fail)
+}
+
+/// Checks that the configuration of the user parses and its version is one this release handles
+pub fn check_config(store: &dyn UserStore, username: &str) -> Check {
+    const NAME: &str = "configuration";
+
+    let location = store.location(username);
+    let report = match store.migrate(username, true) {
+        Ok(Some(report)) => report,
+        Ok(None) => {
+            return Check::fail(
+                NAME,
+                format!("No configuration found at {location}: run setup first"),
+            )
+        }
+        Err(err) => return Check::fail(NAME, format!("{location}: {err}")),
+    };
+
+    if let Err(err) = store.read(username) {
+        return Check::fail(NAME, format!("{location}: {err}"));
+    }
+
+    match report.is_needed() {
+        true => Check::warn(
+            NAME,
+            format!(
+                "{location} is at version {} and is migrated to {} on every load: run polyauthctl migrate",
+                report.from_version(),
+                report.to_version()
+            ),
+        ),
+        false => Check::pass(
+            NAME,
+            format!("{location} is at version {}", report.to_version()),
+        ),
+    }
+}
+
+/// Checks the stored main password against the intermediate key and the main password
+/// that were supplied, warning when there is nothing to check it with.
+pub fn check_main_password(
+    auth_data: &UserAuthData,
+    intermediate_key: Option<&[u8]>,
+    main_password: Option<&[u8]>,
+) -> Vec<Check> {
+    const NAME: &str = "main password";
+
+    if !auth_data.has_main() {
+        return vec![Check::fail(
+            NAME,
+            "No main password has been stored: run setup first",
+        )];
+    }
+
+    let mut checks = vec![];
+
+    if let Some(intermediate_key) = intermediate_key {
+        checks.push(match auth_data.main(intermediate_key) {
+            Ok(_) => Check::pass(NAME, "Decrypts with the supplied intermediate key"),
+            Err(err) => Check::fail(
+                NAME,
+                format!("Does not decrypt with the supplied intermediate key: {err}"),
+            ),
+        });
+    }
+
+    if let Some(main_password) = main_password {
+        checks.push(match auth_data.check_main(main_password) {
+            Ok(true) => Check::pass(NAME, "Matches the supplied system password"),
+            Ok(false) => Check::fail(
+                NAME,
+                "Does not match the supplied system password: secondary authentication methods will not be able to log in",
+            ),
+            Err(err) => Check::fail(NAME, format!("Cannot be verified: {err}")),
+        });
+    }
+
+    if checks.is_empty() {
+        checks.push(Check::warn(
+            NAME,
+            "Stored but not verified: supply the intermediate key or the system password",
+        ));
+    }
+
+    checks
+}
+
+/// Escapes a filesystem label or UUID the way udev does when naming the links under /dev/disk
+fn udev_escape(value: &str) -> String {
+    let mut escaped = String::new();
+    for c in value.chars() {
+        match c.is_ascii_alphanumeric() || "#+-.:=@_".contains(c) || !c.is_ascii() {
+            true => escaped.push(c),
+            false => escaped.push_str(&format!("\\x{:02x}", c as u32)),
+        }
+    }
+
+    escaped
+}
+
+/// Path of the device node a mount refers to, resolving the `UUID=`, `LABEL=`, `PARTUUID=`
+/// and `PARTLABEL=` tags through the links in disk_dir.
+///
+/// Returns None for sources that are not local block devices (tmpfs, network shares and such).
+pub fn resolve_device(device: &str, disk_dir: &Path) -> Option<PathBuf> {
+    const TAGS: [(&str, &str); 4] = [
+        ("UUID=", "by-uuid"),
+        ("LABEL=", "by-label"),
+        ("PARTUUID=", "by-partuuid"),
+        ("PARTLABEL=", "by-partlabel"),
+    ];
+
+    for (tag, dir) in TAGS {
+        if let Some(value) = device.strip_prefix(tag) {
+            let value = value.trim_matches('"');
+            return Some(disk_dir.join(dir).join(udev_escape(value)));
+        }
+    }
+
+    // `//server/share` is a CIFS share
+    match device.starts_with('/') && !device.starts_with("//") {
+        true => Some(PathBuf::from(device)),
+        false => None,
+    }
+}
+
+/// Checks that the device of the home directory and of every additional mount exists
+pub fn check_mount_devices(mounts: &MountPoints, disk_dir: &Path) -> Vec<Check> {
+    let mut devices =
+        mounts.foreach(|dir, params| (format!("device of {dir}"), params.device().clone()));
+    devices.sort();
+    devices.insert(
+        0,
+        (String::from("home device"), mounts.mount().device().clone()),
+    );
+
+    devices
+        .into_iter()
+        .map(|(name, device)| match resolve_device(&device, disk_dir) {
+            None => Check::pass(name, format!("{device} is not a local device: not checked")),
+            Some(path) if path.exists() => match path.to_string_lossy() == device {
+                true => Check::pass(name, format!("{device} exists")),
+                false => Check::pass(name, format!("{device} is {}", path.display())),
+            },
+            Some(path) => Check::fail(
+                name,
+                format!("{device} was not found ({} does not exist)", path.display()),
+            ),
+        })
+        .collect()
+}
+
+/// Module types a PAM configuration file loads pam_polyauth.so for.
+///
+/// Lines loading it with a leading `-` count too; `@include` lines are not followed,
+/// as every file of the directory is inspected anyway.
+pub fn pam_phases(contents: &str) -> Vec<String> {
+    let mut phases = vec![];
+
+    for line in contents.lines() {
+        let line = line.split('#').next().unwrap_or_default();
+        let mut tokens = line.split_whitespace();
+
+        let Some(phase) = tokens.next() else {
+            continue;
+        };
+        let phase = phase.trim_start_matches('-').to_lowercase();
+
+        // the control is either a single keyword or [value=action ...]
+        let Some(control) = tokens.next() else {
+            continue;
+        };
+        if control.starts_with('[')
+            && !control.ends_with(']')
+            && !tokens.by_ref().any(|token| token.ends_with(']'))
+        {
+            continue;
+        }
+
+        let Some(module) = tokens.next() else {
+            continue;
+        };
+
+        let module_name = Path::new(module).file_name().unwrap_or_default();
+        if module_name == PAM_POLYAUTH_MODULE && !phases.contains(&phase) {
+            phases.push(phase);
+        }
+    }
+
+    phases
+}
+
+/// Checks that the PAM services in dir load pam_polyauth.so for both auth and session
+pub fn check_pam_stack(dir: &Path) -> Check {
+    const NAME: &str = "PAM stack";
+
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(err) => return Check::fail(NAME, format!("Cannot read {}: {err}", dir.display())),
+    };
+
+    // phase -> services loading the module for it
+    let mut services: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for entry in entries.flatten() {
+        let Ok(contents) = fs::read_to_string(entry.path()) else {
+            continue;
+        };
+
+        for phase in pam_phases(&contents) {
+            services
+                .entry(phase)
+                .or_default()
+                .push(entry.file_name().to_string_lossy().to_string());
+        }
+    }
+
+    if services.is_empty() {
+        return Check::fail(
+            NAME,
+            format!(
+                "No service in {} loads {PAM_POLYAUTH_MODULE}",
+                dir.display()
+            ),
+        );
+    }
+
+    let mut found = vec![];
+    for (phase, names) in services.iter_mut() {
+        names.sort();
+        found.push(format!("{phase}: {}", names.join(", ")));
+    }
+
+    let missing = PAM_POLYAUTH_PHASES
+        .iter()
+        .filter(|phase| !services.contains_key(**phase))
+        .copied()
+        .collect::<Vec<_>>();
+
+    let unsupported = services
+        .keys()
+        .filter(|phase| !PAM_POLYAUTH_PHASES.contains(&phase.as_str()))
+        .cloned()
+        .collect::<Vec<_>>();
+
+    match (missing.is_empty(), unsupported.is_empty()) {
+        (true, true) => Check::pass(NAME, format!("Loaded for {}", found.join("; "))),
+        (false, _) => Check::warn(
+            NAME,
+            format!(
+                "Not loaded for {} ({})",
+                missing.join(", "),
+                found.join("; ")
+            ),
+        ),
+        (true, false) => Check::warn(
+            NAME,
+            format!(
+                "Loaded for {} where it does nothing ({})",
+                unsupported.join(", "),
+                found.join("; ")
+            ),
+        ),
+    }
+}
+
+/// Checks that every policy pam_polyauth-service needs is installed in one of dirs
+pub fn check_dbus_policies<P: AsRef<Path>>(dirs: &[P]) -> Vec<Check> {
+    DBUS_POLICY_FILES
+        .iter()
+        .map(|file| {
+            let name = format!("D-Bus policy {file}");
+            match dirs
+                .iter()
+                .map(|dir| dir.as_ref().join(file))
+                .find(|path| path.exists())
+            {
+                Some(path) => Check::pass(name, format!("Installed at {}", path.display())),
+                None => Check::fail(
+                    name,
+                    "Not installed: pam_polyauth-service cannot own its name on the system bus",
+                ),
+            }
+        })
+        .collect()
+}
+
+/// Checks that a file of pam_polyauth-service belongs to root and none of the
+/// forbidden permission bits is set. Missing files are only a warning, as the service
+/// creates them the first time they are needed.
+pub fn check_file_permissions(name: &str, path: &Path, forbidden_mode: u32) -> Check {
+    let metadata = match fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+            return Check::warn(name, format!("{} does not exist yet", path.display()))
+        }
+        Err(err) => {
+            return Check::warn(
+                name,
+                format!("{} cannot be inspected: {err}", path.display()),
+            )
+        }
+    };
+
+    let mode = metadata.permissions().mode() & 0o7777;
+    if metadata.file_type().is_symlink() {
+        Check::fail(name, format!("{} is a symbolic link", path.display()))
+    } else if metadata.uid() != 0 {
+        Check::fail(
+            name,
+            format!(
+                "{} is owned by uid {} instead of root",
+                path.display(),
+                metadata.uid()
+            ),
+        )
+    } else if mode & forbidden_mode != 0 {
+        Check::fail(
+            name,
+            format!(
+                "{} has mode {mode:04o}: it must not have any of {forbidden_mode:04o}",
+                path.display()
+            ),
+        )
+    } else {
+        Check::pass(name, format!("{} has mode {mode:04o}", path.display()))
+    }
+}
@@ -24,7 +24,7 @@ use std::io::Error as IoError;
 
 use thiserror::Error;
 
-use crate::user::UserAuthDataError;
+use crate::{kdf::KdfError, user::UserAuthDataError};
 
 #[derive(Debug, Error)]
 pub enum UserOperationError {
@@ -39,4 +39,7 @@ pub enum UserOperationError {
 
     #[error("polyauth error: {0}")]
     User(#[from] UserAuthDataError),
+
+    #[error("Key derivation error: {0}")]
+    Kdf(#[from] KdfError),
 }
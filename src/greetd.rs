@@ -0,0 +1,252 @@
+/*
+    pam_polyauth: A pam module written in rust that supports multiple
+    authentication modes (including autologin).
+
+    Copyright (C) 2024-2025  Denis Benato
+
+    This program is free software; you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation; either version 2 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License along
+    with this program; if not, write to the Free Software Foundation, Inc.,
+    51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+*/
+
+// Client side of the greetd IPC protocol: every message is a JSON object
+// preceded by its length as a 32 bits integer in native byte order.
+
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    net::UnixStream,
+};
+
+/// Environment variable greetd uses to tell the greeter where its socket is
+pub const GREETD_SOCK_ENV: &str = "GREETD_SOCK";
+
+// nothing legitimate comes close: refuse to allocate whatever a broken peer asks for
+const MAX_MESSAGE_SIZE: u32 = 1024 * 1024;
+
+#[derive(Debug, Error)]
+pub enum GreetdError {
+    #[error("The {GREETD_SOCK_ENV} environment variable is not set")]
+    MissingSocket,
+
+    #[error("IO Error: {0}")]
+    IoError(#[from] std::io::Error),
+
+    #[error("JSON serialization error: {0}")]
+    JsonError(#[from] serde_json::Error),
+
+    #[error("Message of {0} bytes exceeds the maximum size")]
+    MessageTooLarge(usize),
+
+    #[error("Unexpected response from greetd: {0:?}")]
+    UnexpectedResponse(Response),
+
+    #[error("greetd error: {0}")]
+    Greetd(String),
+}
+
+/// Requests sent from the greeter to greetd
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Request {
+    CreateSession { username: String },
+    PostAuthMessageResponse { response: Option<String> },
+    StartSession { cmd: Vec<String>, env: Vec<String> },
+    CancelSession,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorType {
+    /// The user could not be authenticated
+    AuthError,
+    Error,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuthMessageType {
+    /// The answer can be displayed while typed
+    Visible,
+    /// The answer must not be displayed (e.g. a password)
+    Secret,
+    /// Only to be displayed: the answer is ignored
+    Info,
+    /// Only to be displayed: the answer is ignored
+    Error,
+}
+
+/// Responses sent from greetd to the greeter
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Response {
+    Success,
+    Error {
+        error_type: ErrorType,
+        description: String,
+    },
+    AuthMessage {
+        auth_message_type: AuthMessageType,
+        auth_message: String,
+    },
+}
+
+/// Outcome of authenticating a user with greetd
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Authentication {
+    /// The session can be started with GreetdClient::start_session
+    Authenticated,
+
+    /// The user was not authenticated: the session has already been cancelled
+    Denied(String),
+}
+
+pub async fn write_message<W: AsyncWrite + Unpin, T: Serialize>(
+    writer: &mut W,
+    message: &T,
+) -> Result<(), GreetdError> {
+    let payload = serde_json::to_vec(message)?;
+    let len = u32::try_from(payload.len())
+        .ok()
+        .filter(|len| *len <= MAX_MESSAGE_SIZE)
+        .ok_or(GreetdError::MessageTooLarge(payload.len()))?;
+
+    writer.write_all(&len.to_ne_bytes()).await?;
+    writer.write_all(&payload).await?;
+    writer.flush().await?;
+
+    Ok(())
+}
+
+pub async fn read_message<R: AsyncRead + Unpin, T: for<'de> Deserialize<'de>>(
+    reader: &mut R,
+) -> Result<T, GreetdError> {
+    let mut len = [0u8; 4];
+    reader.read_exact(&mut len).await?;
+
+    let len = u32::from_ne_bytes(len);
+    if len > MAX_MESSAGE_SIZE {
+        return Err(GreetdError::MessageTooLarge(len as usize));
+    }
+
+    let mut payload = vec![0u8; len as usize];
+    reader.read_exact(&mut payload).await?;
+
+    Ok(serde_json::from_slice(&payload)?)
+}
+
+pub struct GreetdClient<S> {
+    stream: S,
+}
+
+impl GreetdClient<UnixStream> {
+    /// Connects to the socket greetd passed in GREETD_SOCK
+    pub async fn from_env() -> Result<Self, GreetdError> {
+        let path = std::env::var_os(GREETD_SOCK_ENV).ok_or(GreetdError::MissingSocket)?;
+
+        Self::connect(Path::new(&path)).await
+    }
+
+    pub async fn connect(path: &Path) -> Result<Self, GreetdError> {
+        Ok(Self::new(UnixStream::connect(path).await?))
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> GreetdClient<S> {
+    pub fn new(stream: S) -> Self {
+        Self { stream }
+    }
+
+    pub async fn request(&mut self, request: &Request) -> Result<Response, GreetdError> {
+        write_message(&mut self.stream, request).await?;
+        read_message(&mut self.stream).await
+    }
+
+    /// Creates a session for the user and answers the authentication messages greetd sends
+    /// with the given prompt until the user is either authenticated or refused.
+    ///
+    /// prompt is called for every message: its answer is only sent back for
+    /// Visible and Secret messages.
+    pub async fn authenticate<F>(
+        &mut self,
+        username: &str,
+        mut prompt: F,
+    ) -> Result<Authentication, GreetdError>
+    where
+        F: FnMut(AuthMessageType, &str) -> Result<Option<String>, GreetdError>,
+    {
+        let mut response = self
+            .request(&Request::CreateSession {
+                username: String::from(username),
+            })
+            .await?;
+
+        loop {
+            match response {
+                Response::Success => return Ok(Authentication::Authenticated),
+                Response::Error {
+                    error_type,
+                    description,
+                } => {
+                    // greetd keeps the failed session around until told otherwise
+                    self.cancel_session().await?;
+
+                    return match error_type {
+                        ErrorType::AuthError => Ok(Authentication::Denied(description)),
+                        ErrorType::Error => Err(GreetdError::Greetd(description)),
+                    };
+                }
+                Response::AuthMessage {
+                    auth_message_type,
+                    auth_message,
+                } => {
+                    let answer = prompt(auth_message_type, &auth_message)?;
+                    let response_to_send = match auth_message_type {
+                        AuthMessageType::Visible | AuthMessageType::Secret => answer,
+                        AuthMessageType::Info | AuthMessageType::Error => None,
+                    };
+
+                    response = self
+                        .request(&Request::PostAuthMessageResponse {
+                            response: response_to_send,
+                        })
+                        .await?;
+                }
+            }
+        }
+    }
+
+    /// Starts the session of the authenticated user: env entries are in the KEY=VALUE form
+    pub async fn start_session(
+        &mut self,
+        cmd: Vec<String>,
+        env: Vec<String>,
+    ) -> Result<(), GreetdError> {
+        match self.request(&Request::StartSession { cmd, env }).await? {
+            Response::Success => Ok(()),
+            Response::Error { description, .. } => Err(GreetdError::Greetd(description)),
+            response => Err(GreetdError::UnexpectedResponse(response)),
+        }
+    }
+
+    pub async fn cancel_session(&mut self) -> Result<(), GreetdError> {
+        match self.request(&Request::CancelSession).await? {
+            Response::Success => Ok(()),
+            Response::Error { description, .. } => Err(GreetdError::Greetd(description)),
+            response => Err(GreetdError::UnexpectedResponse(response)),
+        }
+    }
+}
@@ -0,0 +1,204 @@
+/*
+    pam_polyauth: A pam module written in rust that supports multiple
+    authentication modes (including autologin).
+
+    Copyright (C) 2024-2025  Denis Benato
+
+    This program is free software; you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation; either version 2 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License along
+    with this program; if not, write to the Free Software Foundation, Inc.,
+    51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+*/
+
+use argon2::{Algorithm, Argon2, Params, Version};
+use bytevec2::*;
+
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum KdfError {
+    #[error("Unknown key derivation algorithm {0}")]
+    UnknownAlgorithm(u8),
+
+    #[error("Argon2 error: {0}")]
+    Argon2(argon2::Error),
+
+    #[error("Argon2id costs out of range: m_cost={0} KiB, t_cost={1}, p_cost={2}")]
+    CostsOutOfRange(u32, u32, u32),
+}
+
+/// Key derivation function used to turn a password into an AES-256 key
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum KdfAlgorithm {
+    /// Single HKDF-SHA256 expansion: only used by data written before Argon2id was introduced
+    HkdfSha256 = 0,
+
+    /// Argon2id with the memory/time/parallelism costs stored alongside
+    Argon2id = 1,
+}
+
+impl TryFrom<u8> for KdfAlgorithm {
+    type Error = KdfError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(KdfAlgorithm::HkdfSha256),
+            1 => Ok(KdfAlgorithm::Argon2id),
+            _ => Err(KdfError::UnknownAlgorithm(value)),
+        }
+    }
+}
+
+/// Argon2id memory cost (in KiB) used for newly created entries
+pub const DEFAULT_ARGON2_M_COST: u32 = 65536;
+
+/// Argon2id number of iterations used for newly created entries
+pub const DEFAULT_ARGON2_T_COST: u32 = 3;
+
+/// Argon2id degree of parallelism used for newly created entries
+pub const DEFAULT_ARGON2_P_COST: u32 = 1;
+
+// Costs are read from files users can edit and from import files: anything outside
+// these ranges is refused before deriving, so that a login can never be made to
+// allocate gigabytes of memory or spin for minutes in the PAM stack or the service.
+
+/// Smallest accepted Argon2id memory cost (in KiB), well above the Argon2 minimum of 8 per lane
+pub const MIN_ARGON2_M_COST: u32 = 1024;
+
+/// Largest accepted Argon2id memory cost (in KiB): 1 GiB
+pub const MAX_ARGON2_M_COST: u32 = 1024 * 1024;
+
+/// Smallest accepted Argon2id number of iterations
+pub const MIN_ARGON2_T_COST: u32 = 1;
+
+/// Largest accepted Argon2id number of iterations
+pub const MAX_ARGON2_T_COST: u32 = 16;
+
+/// Smallest accepted Argon2id degree of parallelism
+pub const MIN_ARGON2_P_COST: u32 = 1;
+
+/// Largest accepted Argon2id degree of parallelism
+pub const MAX_ARGON2_P_COST: u32 = 8;
+
+bytevec_decl! {
+    #[derive(PartialEq, Eq, Debug, Copy, Clone)]
+    pub struct KdfParams {
+        algorithm: u8,
+        m_cost: u32,
+        t_cost: u32,
+        p_cost: u32
+    }
+}
+
+impl Default for KdfParams {
+    fn default() -> Self {
+        Self::argon2id(
+            DEFAULT_ARGON2_M_COST,
+            DEFAULT_ARGON2_T_COST,
+            DEFAULT_ARGON2_P_COST,
+        )
+    }
+}
+
+impl KdfParams {
+    /// Parameters of data written before the KDF was configurable
+    pub const fn legacy() -> Self {
+        Self {
+            algorithm: KdfAlgorithm::HkdfSha256 as u8,
+            m_cost: 0,
+            t_cost: 0,
+            p_cost: 0,
+        }
+    }
+
+    pub fn argon2id(m_cost: u32, t_cost: u32, p_cost: u32) -> Self {
+        Self {
+            algorithm: KdfAlgorithm::Argon2id as u8,
+            m_cost,
+            t_cost,
+            p_cost,
+        }
+    }
+
+    pub fn algorithm(&self) -> Result<KdfAlgorithm, KdfError> {
+        KdfAlgorithm::try_from(self.algorithm)
+    }
+
+    /// Check that the algorithm is known and the costs are within the accepted ranges
+    pub fn check(&self) -> Result<(), KdfError> {
+        match self.algorithm()? {
+            KdfAlgorithm::HkdfSha256 => Ok(()),
+            KdfAlgorithm::Argon2id => {
+                if (MIN_ARGON2_M_COST..=MAX_ARGON2_M_COST).contains(&self.m_cost)
+                    && (MIN_ARGON2_T_COST..=MAX_ARGON2_T_COST).contains(&self.t_cost)
+                    && (MIN_ARGON2_P_COST..=MAX_ARGON2_P_COST).contains(&self.p_cost)
+                {
+                    Ok(())
+                } else {
+                    Err(KdfError::CostsOutOfRange(
+                        self.m_cost,
+                        self.t_cost,
+                        self.p_cost,
+                    ))
+                }
+            }
+        }
+    }
+
+    pub fn m_cost(&self) -> u32 {
+        self.m_cost
+    }
+
+    pub fn t_cost(&self) -> u32 {
+        self.t_cost
+    }
+
+    pub fn p_cost(&self) -> u32 {
+        self.p_cost
+    }
+
+    /// Check if data protected with these parameters should be re-encrypted
+    /// with the given ones at the next occasion
+    pub fn is_weaker_than(&self, other: &Self) -> bool {
+        match (self.algorithm(), other.algorithm()) {
+            (Ok(KdfAlgorithm::Argon2id), Ok(KdfAlgorithm::Argon2id)) => {
+                self.m_cost < other.m_cost
+                    || self.t_cost < other.t_cost
+                    || self.p_cost < other.p_cost
+            }
+            (Ok(KdfAlgorithm::Argon2id), _) => false,
+            (_, Ok(KdfAlgorithm::Argon2id)) => true,
+            _ => false,
+        }
+    }
+
+    /// Derive a 256 bits key from the given password and salt
+    pub fn derive(&self, password: &[u8], salt: &[u8; 32]) -> Result<[u8; 32], KdfError> {
+        self.check()?;
+
+        match self.algorithm()? {
+            KdfAlgorithm::HkdfSha256 => Ok(crate::derive_key(password, salt)),
+            KdfAlgorithm::Argon2id => {
+                let params = Params::new(self.m_cost, self.t_cost, self.p_cost, Some(32))
+                    .map_err(KdfError::Argon2)?;
+
+                let mut okm = [0u8; 32];
+                Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+                    .hash_password_into(password, salt, &mut okm)
+                    .map_err(KdfError::Argon2)?;
+
+                Ok(okm)
+            }
+        }
+    }
+}
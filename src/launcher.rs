@@ -0,0 +1,214 @@
+/*
+    pam_polyauth: A pam module written in rust that supports multiple
+    authentication modes (including autologin).
+
+    Copyright (C) 2024-2025  Denis Benato
+
+    This program is free software; you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation; either version 2 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License along
+    with this program; if not, write to the Free Software Foundation, Inc.,
+    51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+*/
+
+use std::{
+    collections::BTreeMap,
+    ffi::OsString,
+    fmt,
+    os::unix::process::ExitStatusExt,
+    path::{Path, PathBuf},
+    process::ExitStatus,
+    str::FromStr,
+    time::Duration,
+};
+
+use users::os::unix::UserExt;
+
+use crate::{command::SessionCommand, pam::XDG_RUNTIME_DIR_PATH};
+
+/// Shell used when the user has none configured or it cannot be found
+pub const FALLBACK_SHELL: &str = "/bin/sh";
+
+/// When polyauth-session starts the session command again after it exited
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RestartPolicy {
+    /// Never restart the session: a failure falls back to the shell
+    Never,
+
+    /// Restart the session when it crashes or exits with a non-zero status
+    #[default]
+    OnFailure,
+
+    /// Restart the session whatever the reason it exited for
+    Always,
+}
+
+impl fmt::Display for RestartPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let policy = match self {
+            Self::Never => "never",
+            Self::OnFailure => "on-failure",
+            Self::Always => "always",
+        };
+        write!(f, "{policy}")
+    }
+}
+
+impl FromStr for RestartPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "never" => Ok(Self::Never),
+            "on-failure" => Ok(Self::OnFailure),
+            "always" => Ok(Self::Always),
+            _ => Err(format!(
+                "unknown restart policy '{s}': expected never, on-failure or always"
+            )),
+        }
+    }
+}
+
+/// What to do once the session command has exited
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RestartDecision {
+    /// Start the session command again
+    Restart,
+
+    /// The session ended as expected: the launcher should exit
+    Finished,
+
+    /// The session keeps failing: the launcher should fall back to the user shell
+    GiveUp,
+}
+
+/// Applies a RestartPolicy to the exits of the session command.
+///
+/// A session that stayed up for at least `stable_after` is considered healthy,
+/// so that a crash after hours of use does not count towards `max_restarts`.
+#[derive(Debug, Clone)]
+pub struct RestartTracker {
+    policy: RestartPolicy,
+    max_restarts: u32,
+    stable_after: Duration,
+    restarts: u32,
+}
+
+impl RestartTracker {
+    pub fn new(policy: RestartPolicy, max_restarts: u32, stable_after: Duration) -> Self {
+        Self {
+            policy,
+            max_restarts,
+            stable_after,
+            restarts: 0,
+        }
+    }
+
+    /// Restarts performed since the session was last stable
+    pub fn restarts(&self) -> u32 {
+        self.restarts
+    }
+
+    pub fn next(&mut self, failed: bool, ran_for: Duration) -> RestartDecision {
+        if ran_for >= self.stable_after {
+            self.restarts = 0;
+        }
+
+        let restart = match self.policy {
+            RestartPolicy::Never => false,
+            RestartPolicy::OnFailure => failed,
+            RestartPolicy::Always => true,
+        };
+
+        if !restart {
+            return match failed {
+                true => RestartDecision::GiveUp,
+                false => RestartDecision::Finished,
+            };
+        }
+
+        if self.restarts >= self.max_restarts {
+            return RestartDecision::GiveUp;
+        }
+
+        self.restarts += 1;
+        RestartDecision::Restart
+    }
+}
+
+/// Whether the session command exited because of an error.
+///
+/// Being terminated by SIGTERM, SIGHUP or SIGINT is how a session is closed on logout,
+/// so that is not considered a failure.
+pub fn session_failed(status: &ExitStatus) -> bool {
+    match status.signal() {
+        Some(libc::SIGTERM) | Some(libc::SIGHUP) | Some(libc::SIGINT) => false,
+        Some(_) => true,
+        None => !status.success(),
+    }
+}
+
+/// The runtime directory the pam module assigns to the given user with the default configuration
+pub fn user_runtime_dir(uid: u32) -> PathBuf {
+    PathBuf::from(XDG_RUNTIME_DIR_PATH).join(format!("{uid}"))
+}
+
+/// The environment a session command has to be started with: XDG_RUNTIME_DIR is only
+/// exported when runtime_dir is given, as the pam module already sets it for the session.
+///
+/// Variables explicitly configured in the SessionCommand take precedence
+/// over the ones derived from the session type and desktop names.
+pub fn session_environment(
+    command: &SessionCommand,
+    runtime_dir: Option<&Path>,
+) -> BTreeMap<String, String> {
+    let mut env = BTreeMap::new();
+
+    if let Some(runtime_dir) = runtime_dir {
+        env.insert(
+            String::from("XDG_RUNTIME_DIR"),
+            runtime_dir.to_string_lossy().to_string(),
+        );
+    }
+
+    if !command.session_type().is_unspecified() {
+        env.insert(
+            String::from("XDG_SESSION_TYPE"),
+            command.session_type().to_string(),
+        );
+    }
+
+    if !command.desktop_names().is_empty() {
+        env.insert(
+            String::from("XDG_CURRENT_DESKTOP"),
+            command.desktop_names().join(":"),
+        );
+    }
+
+    env.extend(command.env().clone());
+
+    env
+}
+
+/// The login shell of the given user, FALLBACK_SHELL if it cannot be found
+pub fn user_shell(uid: u32) -> PathBuf {
+    users::get_user_by_uid(uid)
+        .map(|user| user.shell().to_path_buf())
+        .filter(|shell| !shell.as_os_str().is_empty() && shell.exists())
+        .unwrap_or_else(|| PathBuf::from(FALLBACK_SHELL))
+}
+
+/// The argv[0] that makes a shell behave as a login shell (i.e. `-bash`)
+pub fn login_shell_name(shell: &Path) -> OsString {
+    let mut name = OsString::from("-");
+    name.push(shell.file_name().unwrap_or(shell.as_os_str()));
+    name
+}
@@ -21,24 +21,39 @@
 
 pub mod auth;
 pub mod command;
+pub mod desktop;
+pub mod doctor;
 pub mod error;
+pub mod greetd;
+pub mod kdf;
+pub mod launcher;
 pub mod mount;
+pub mod options;
 pub mod pam;
+pub mod provision;
+pub mod secret;
 pub mod storage;
 pub mod user;
 
 #[cfg(test)]
 pub mod tests;
 
+use aes_gcm::{
+    aead::{Aead, AeadCore, KeyInit, OsRng},
+    Aes256Gcm, Key, Nonce,
+};
 use hkdf::*;
 use sha2::Sha256;
+use subtle::ConstantTimeEq;
+
+use crate::{error::UserOperationError, kdf::KdfParams, secret::SecretBytes};
 //use users::{os::unix::UserExt, User};
 
 pub const LIBRARY_VERSION: &str = env!("CARGO_PKG_VERSION");
 
-pub(crate) fn derive_key(input: &str, salt: &[u8]) -> [u8; 32] {
+pub(crate) fn derive_key(input: &[u8], salt: &[u8]) -> [u8; 32] {
     // Create an HKDF instance with SHA-256 as the hash function
-    let hkdf = Hkdf::<Sha256>::new(Some(salt), input.as_bytes());
+    let hkdf = Hkdf::<Sha256>::new(Some(salt), input);
 
     // Prepare a buffer for the derived key
     let mut okm = [0u8; 32]; // Output key material (32 bytes)
@@ -49,17 +64,51 @@ pub(crate) fn derive_key(input: &str, salt: &[u8]) -> [u8; 32] {
     okm
 }
 
-pub(crate) fn password_to_vec(password: &String) -> Vec<u8> {
-    password.as_str().into()
+// generate a new random salt using the aes-gcm library (it will create a 32 bytes key)
+pub(crate) fn random_salt() -> [u8; 32] {
+    Aes256Gcm::generate_key(&mut OsRng).into()
+}
+
+/// Encrypts plaintext with a key derived from the given password: returns the ciphertext and its nonce
+pub(crate) fn encrypt_with_password(
+    kdf: &KdfParams,
+    password: &[u8],
+    salt: &[u8; 32],
+    plaintext: &[u8],
+) -> Result<(Vec<u8>, [u8; 12]), UserOperationError> {
+    let key = Key::<Aes256Gcm>::from(kdf.derive(password, salt)?);
+    let cipher = Aes256Gcm::new(&key);
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+
+    let ciphertext = cipher
+        .encrypt(&nonce, plaintext)
+        .map_err(UserOperationError::EncryptionError)?;
+
+    Ok((ciphertext, nonce.into()))
 }
 
-pub(crate) fn vec_to_password(vec: &Vec<u8>) -> String {
-    String::from_utf8_lossy(vec.as_slice()).to_string()
+/// Decrypts ciphertext with a key derived from the given password:
+/// a wrong password is detected by the AES-GCM authentication tag.
+pub(crate) fn decrypt_with_password(
+    kdf: &KdfParams,
+    password: &[u8],
+    salt: &[u8; 32],
+    nonce: &[u8; 12],
+    ciphertext: &[u8],
+) -> Result<SecretBytes, UserOperationError> {
+    let key = Key::<Aes256Gcm>::from(kdf.derive(password, salt)?);
+    let cipher = Aes256Gcm::new(&key);
+
+    cipher
+        .decrypt(&Nonce::from(*nonce), ciphertext)
+        .map(SecretBytes::new)
+        .map_err(UserOperationError::EncryptionError)
 }
 
-// this MUST be implemented and used because entering invalid strings can be a security hole (see lossy_utf8)
-pub(crate) fn is_valid_password(password: &String) -> bool {
-    vec_to_password(password_to_vec(password).as_ref()) == password.clone()
+/// Compares two secrets in a time that does not depend on their content:
+/// only their length can be inferred by timing the comparison.
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.ct_eq(b).into()
 }
 
 /*
@@ -86,11 +135,18 @@ pub fn valid_users() -> Vec<User> {
 */
 
 use crate::{
+    options::{ModuleOptions, Transport, UnavailablePolicy},
     pam::{
-        result::ServiceOperationResult, security::SessionPrelude, session::SessionsProxy,
-        XDG_RUNTIME_DIR_PATH,
+        result::{ServiceOperationError, ServiceOperationResult},
+        security::SessionPrelude,
+        session::SessionsProxy,
+    },
+    storage::{
+        load_user_auth_data,
+        store::{system_store, StoreLayout, UserStore},
+        store_user_auth_data,
     },
-    storage::{load_user_auth_data, StorageSource},
+    user::UserAuthData,
 };
 
 pub(crate) extern crate pam as pam_binding;
@@ -103,99 +159,377 @@ use pam_binding::{
     pam_hooks,
 };
 
-use zbus::{Connection, Result as ZResult};
+use zbus::{connection, proxy::CacheProperties, Connection};
 
 use users::{gid_t, uid_t};
 
-use std::{borrow::Cow, ffi::CStr, path::PathBuf, sync::Once};
-use tokio::runtime::Runtime;
-
-static INIT: Once = Once::new();
-static mut RUNTIME: Option<Runtime> = None;
+use std::{borrow::Cow, ffi::CStr, future::Future};
 
 struct PamQuickEmbedded;
 pam_hooks!(PamQuickEmbedded);
 
 impl PamQuickEmbedded {
-    pub(crate) async fn open_session_for_user(
-        user: &String,
-        plain_main_password: String,
-    ) -> ZResult<(ServiceOperationResult, uid_t, gid_t)> {
-        let connection = Connection::session().await?;
+    /// Runs the given future on a single-threaded runtime owned by the calling hook.
+    ///
+    /// The runtime is dropped before the hook returns: no worker threads are left running
+    /// inside the PAM consumer and nothing is inherited by the processes it forks
+    /// (e.g. sshd forks between authentication and session opening).
+    pub(crate) fn block_on<T, F>(future: F) -> PamResult<T>
+    where
+        F: Future<Output = PamResult<T>>,
+    {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .map_err(|_| PamErrorCode::SERVICE_ERR)?;
+
+        runtime.block_on(future)
+    }
 
-        let proxy = SessionsProxy::new(&connection).await?;
+    fn module_options(pamh: &PamHandle, args: &[&CStr], hook: &str) -> PamResult<ModuleOptions> {
+        ModuleOptions::parse(args).map_err(|err| {
+            pamh.log(
+                pam_binding::module::LogLevel::Error,
+                format!("polyauth: {hook}: invalid module options: {err}"),
+            );
 
-        let pk = proxy.initiate_session().await?;
+            PamErrorCode::SERVICE_ERR
+        })
+    }
 
-        // return an unknown error if the service was unable to serialize the RSA public key
-        if pk.is_empty() {
-            return Ok((ServiceOperationResult::EmptyPubKey, 0, 0));
+    async fn connect_bus(options: &ModuleOptions) -> Result<Connection, ServiceOperationError> {
+        match options.bus_address() {
+            Some(address) => Ok(connection::Builder::address(address)?.build().await?),
+            None => Ok(Connection::system().await?),
         }
+    }
 
-        let Ok(session_prelude) = serde_json::from_str::<SessionPrelude>(pk.as_str()) else {
-            return Ok((ServiceOperationResult::SerializationError, 0, 0));
-        };
+    async fn connect_socket(options: &ModuleOptions) -> Result<Connection, ServiceOperationError> {
+        let socket_path = options.socket_path();
+        let stream = tokio::net::UnixStream::connect(socket_path)
+            .await
+            .map_err(|err| {
+                ServiceOperationError::new(
+                    ServiceOperationResult::BusError,
+                    format!("cannot connect to {}: {err}", socket_path.to_string_lossy()),
+                )
+                .with_detail("socket", socket_path.to_string_lossy())
+            })?;
 
-        let Ok(encrypted_password) = session_prelude.encrypt(plain_main_password) else {
-            return Ok((ServiceOperationResult::EncryptionError, 0, 0));
-        };
+        Ok(connection::Builder::unix_stream(stream)
+            .p2p()
+            .build()
+            .await?)
+    }
 
-        let reply = proxy
-            .open_user_session(user.as_str(), encrypted_password)
-            .await?;
+    /// Gives up on an exchange with pam_polyauth-service that does not complete in the configured time.
+    ///
+    /// The deadline covers the whole exchange of a hook (connection included), so that
+    /// an unresponsive service blocks the login for the configured time at most.
+    async fn with_timeout<T, F>(
+        options: &ModuleOptions,
+        operation: &str,
+        call: F,
+    ) -> Result<T, ServiceOperationError>
+    where
+        F: Future<Output = Result<T, ServiceOperationError>>,
+    {
+        let timeout = options.timeout();
+        match tokio::time::timeout(timeout, call).await {
+            Ok(result) => result,
+            Err(_) => Err(ServiceOperationError::new(
+                ServiceOperationResult::BusError,
+                format!(
+                    "{operation} did not complete within {} seconds",
+                    timeout.as_secs()
+                ),
+            )
+            .with_detail("operation", operation)
+            .with_detail("timeout", timeout.as_secs())),
+        }
+    }
 
-        Ok((ServiceOperationResult::from(reply.0), reply.1, reply.2))
+    /// Connects to pam_polyauth-service using the transport selected in module options
+    pub(crate) async fn connect(
+        options: &ModuleOptions,
+    ) -> Result<Connection, ServiceOperationError> {
+        match options.transport() {
+            Transport::Bus => Self::connect_bus(options).await,
+            Transport::Socket => Self::connect_socket(options).await,
+            Transport::Auto => match Self::connect_bus(options).await {
+                Ok(connection) => Ok(connection),
+                // report the bus error if the socket is not there either
+                Err(err) => Self::connect_socket(options).await.map_err(|_| err),
+            },
+        }
+    }
+
+    /// Builds the proxy without caching properties: that needs a bus and
+    /// would not work on direct socket connections.
+    async fn sessions_proxy(
+        connection: &Connection,
+    ) -> Result<SessionsProxy<'_>, ServiceOperationError> {
+        Ok(SessionsProxy::builder(connection)
+            .cache_properties(CacheProperties::No)
+            .build()
+            .await?)
     }
 
-    pub(crate) async fn close_session_for_user(user: &String) -> ZResult<u32> {
-        let connection = Connection::session().await?;
+    pub(crate) async fn open_session_for_user(
+        options: &ModuleOptions,
+        user: &String,
+        plain_main_password: &SecretBytes,
+    ) -> Result<(uid_t, gid_t), ServiceOperationError> {
+        Self::with_timeout(options, "OpenUserSession", async {
+            let connection = Self::connect(options).await?;
 
-        let proxy = SessionsProxy::new(&connection).await?;
-        let reply = proxy.close_user_session(user.as_str()).await?;
+            let proxy = Self::sessions_proxy(&connection).await?;
+
+            let pk = proxy.initiate_session().await?;
+
+            // return an error if the service was unable to serialize the RSA public key
+            if pk.is_empty() {
+                return Err(ServiceOperationError::new(
+                    ServiceOperationResult::EmptyPubKey,
+                    "the service returned an empty public key",
+                ));
+            }
+
+            let session_prelude =
+                serde_json::from_str::<SessionPrelude>(pk.as_str()).map_err(|err| {
+                    ServiceOperationError::new(
+                        ServiceOperationResult::SerializationError,
+                        format!("cannot deserialize the session prelude: {err}"),
+                    )
+                })?;
 
-        Ok(reply)
+            let encrypted_password =
+                session_prelude
+                    .encrypt(plain_main_password)
+                    .map_err(|err| {
+                        ServiceOperationError::new(
+                            ServiceOperationResult::EncryptionError,
+                            format!("cannot encrypt the main password: {err}"),
+                        )
+                    })?;
+
+            proxy
+                .open_user_session(user.as_str(), encrypted_password)
+                .await
+        })
+        .await
     }
 
-    pub(crate) async fn is_user_polyauth_enabled(user: &String) -> ZResult<u32> {
-        let connection = Connection::session().await?;
+    pub(crate) async fn close_session_for_user(
+        options: &ModuleOptions,
+        user: &String,
+    ) -> Result<(), ServiceOperationError> {
+        Self::with_timeout(options, "CloseUserSession", async {
+            let connection = Self::connect(options).await?;
 
-        let proxy = SessionsProxy::new(&connection).await?;
-        let reply = proxy.is_user_polyauth_enabled(user.as_str()).await?;
+            let proxy = Self::sessions_proxy(&connection).await?;
 
-        Ok(reply)
+            proxy.close_user_session(user.as_str()).await
+        })
+        .await
     }
-}
 
-impl PamHooks for PamQuickEmbedded {
-    fn sm_close_session(pamh: &mut PamHandle, _args: Vec<&CStr>, _flags: PamFlag) -> PamResult<()> {
-        pamh.log(
-            pam_binding::module::LogLevel::Debug,
-            "polyauth: sm_close_session: enter".to_string(),
-        );
+    pub(crate) async fn is_user_polyauth_enabled(
+        options: &ModuleOptions,
+        user: &String,
+    ) -> Result<(), ServiceOperationError> {
+        Self::with_timeout(options, "IsUserPolyauthEnabled", async {
+            let connection = Self::connect(options).await?;
 
-        match std::env::var("DBUS_SESSION_BUS_ADDRESS") {
-            Ok(value) => pamh.log(
-                pam_binding::module::LogLevel::Debug,
-                format!("Starting dbus service on socket {value}"),
+            let proxy = Self::sessions_proxy(&connection).await?;
+
+            proxy.is_user_polyauth_enabled(user.as_str()).await
+        })
+        .await
+    }
+
+    /// Re-encrypts the entries unlocked by the given (already verified) password
+    /// if they were stored in a legacy format or protected by an outdated key derivation function.
+    ///
+    /// Failures are logged and never prevent the login.
+    pub(crate) fn upgrade_auth_data(
+        pamh: &PamHandle,
+        user_cfg: &mut UserAuthData,
+        store: &dyn UserStore,
+        username: &str,
+        password: Option<&[u8]>,
+    ) {
+        if !user_cfg.needs_upgrade() {
+            return;
+        }
+
+        // the login never waits for another writer: the upgrade is retried at the next one
+        match user_cfg.upgrade(password) {
+            Ok(true) => match storage::without_waiting_for_locks(|| {
+                store_user_auth_data(user_cfg, store, username, None, None)
+            }) {
+                Ok(()) => pamh.log(
+                    pam_binding::module::LogLevel::Info,
+                    "polyauth: sm_authenticate: upgraded the format of stored secrets".to_string(),
+                ),
+                Err(err) if storage::is_locked_error(&err) => pamh.log(
+                    pam_binding::module::LogLevel::Info,
+                    format!("polyauth: sm_authenticate: upgrade of stored secrets skipped: {err}"),
+                ),
+                Err(err) => pamh.log(
+                    pam_binding::module::LogLevel::Warning,
+                    format!("polyauth: sm_authenticate: could not store upgraded secrets: {err}"),
+                ),
+            },
+            Ok(false) => {}
+            Err(err) => pamh.log(
+                pam_binding::module::LogLevel::Warning,
+                format!("polyauth: sm_authenticate: could not upgrade stored secrets: {err}"),
             ),
-            Err(err) => {
+        }
+    }
+
+    /// Hands the main password over to sm_open_session.
+    ///
+    /// The cleanup callback pam_set_data registers drops the box when the data is replaced
+    /// or pam_end is called: dropping a SecretBytes wipes the main password.
+    fn store_main_password(
+        pamh: &PamHandle,
+        cred_data: &str,
+        main_password: SecretBytes,
+    ) -> PamResult<()> {
+        pamh.set_data(cred_data, Box::new(main_password))
+            .map_err(|err| {
+                pamh.log(
+                    pam_binding::module::LogLevel::Error,
+                    format!("polyauth: sm_authenticate: set_data error {err}"),
+                );
+
+                err
+            })
+    }
+
+    /// Maps an error reported by (or while reaching) pam_polyauth-service to a PAM error code
+    pub(crate) fn pam_error_code(err: &ServiceOperationError) -> PamErrorCode {
+        match err.result() {
+            ServiceOperationResult::CannotIdentifyUser => PamErrorCode::USER_UNKNOWN,
+            ServiceOperationResult::UnauthorizedMount
+            | ServiceOperationResult::TamperedConfig
+            | ServiceOperationResult::AccessDenied => PamErrorCode::PERM_DENIED,
+            ServiceOperationResult::CannotLoadUserMountError
+            | ServiceOperationResult::MountError
+            | ServiceOperationResult::SessionAlreadyOpened
+            | ServiceOperationResult::SessionAlreadyClosed
+            | ServiceOperationResult::NoSuchSession
+            | ServiceOperationResult::NoSessionCommand
+            | ServiceOperationResult::IOError => PamErrorCode::SESSION_ERR,
+            ServiceOperationResult::Ok
+            | ServiceOperationResult::PubKeyError
+            | ServiceOperationResult::DataDecryptionFailed
+            | ServiceOperationResult::EmptyPubKey
+            | ServiceOperationResult::EncryptionError
+            | ServiceOperationResult::SerializationError
+            | ServiceOperationResult::BusError
+            | ServiceOperationResult::Unknown => PamErrorCode::SERVICE_ERR,
+        }
+    }
+
+    /// Applies the policy selected in module options when pam_polyauth-service is unreachable
+    /// and tells the user, whichever hook found out.
+    ///
+    /// Returns `None` when the error is not about the service being unreachable.
+    pub(crate) fn handle_unavailable(
+        pamh: &PamHandle,
+        options: &ModuleOptions,
+        hook: &str,
+        err: &ServiceOperationError,
+    ) -> Option<PamResult<()>> {
+        if err.result() != ServiceOperationResult::BusError {
+            return None;
+        }
+
+        let (result, message) = match options.unavailable() {
+            UnavailablePolicy::FailClosed => {
                 pamh.log(
-                    pam_binding::module::LogLevel::Debug,
-                    format!("Couldn't read dbus socket address: {err} - using default..."),
+                    pam_binding::module::LogLevel::Error,
+                    format!(
+                        "polyauth: {hook}: pam_polyauth-service is unavailable: {}",
+                        err.message()
+                    ),
                 );
-                std::env::set_var(
-                    "DBUS_SESSION_BUS_ADDRESS",
-                    "unix:path=/run/dbus/system_bus_socket",
+
+                (
+                    Err(PamErrorCode::SERVICE_ERR),
+                    "polyauth: the polyauth service is unavailable",
+                )
+            }
+            UnavailablePolicy::Continue => {
+                pamh.log(
+                    pam_binding::module::LogLevel::Warning,
+                    format!(
+                        "polyauth: {hook}: pam_polyauth-service is unavailable, continuing: {}",
+                        err.message()
+                    ),
+                );
+
+                (
+                    Ok(()),
+                    "polyauth: the polyauth service is unavailable, continuing without it",
+                )
+            }
+        };
+
+        if let Ok(Some(conv)) = pamh.get_item::<Conv>() {
+            if let Err(conv_err) = conv.send(PamMessageStyle::PAM_TEXT_INFO, message) {
+                pamh.log(
+                    pam_binding::module::LogLevel::Warning,
+                    format!("polyauth: {hook}: could not display the message: {conv_err}"),
                 );
             }
         }
 
-        INIT.call_once(|| {
-            // Initialize the Tokio runtime
-            unsafe {
-                RUNTIME = Some(Runtime::new().unwrap());
+        Some(result)
+    }
+
+    /// Logs the error and shows a fixed message for its class to the user via the PAM conversation
+    pub(crate) fn report_error(pamh: &PamHandle, hook: &str, err: &ServiceOperationError) {
+        let mut details = err
+            .details()
+            .iter()
+            .map(|(key, value)| format!("{key}={value}"))
+            .collect::<Vec<_>>();
+        details.sort();
+
+        pamh.log(
+            pam_binding::module::LogLevel::Error,
+            format!(
+                "polyauth: {hook}: pam_polyauth-service errored: {} ({}) [{}]",
+                err.message(),
+                err.result(),
+                details.join(", ")
+            ),
+        );
+
+        if let Ok(Some(conv)) = pamh.get_item::<Conv>() {
+            let message = format!("polyauth: {}", err.user_message());
+            if let Err(conv_err) = conv.send(PamMessageStyle::PAM_ERROR_MSG, message.as_str()) {
+                pamh.log(
+                    pam_binding::module::LogLevel::Warning,
+                    format!("polyauth: {hook}: could not display the error: {conv_err}"),
+                );
             }
-        });
+        }
+    }
+}
+
+impl PamHooks for PamQuickEmbedded {
+    fn sm_close_session(pamh: &mut PamHandle, args: Vec<&CStr>, _flags: PamFlag) -> PamResult<()> {
+        pamh.log(
+            pam_binding::module::LogLevel::Debug,
+            "polyauth: sm_close_session: enter".to_string(),
+        );
+
+        let options = PamQuickEmbedded::module_options(pamh, &args, "sm_close_session")?;
 
         let username = match pamh.get_user(None) {
             Ok(Some(res)) => res,
@@ -213,55 +547,39 @@ impl PamHooks for PamQuickEmbedded {
             }
         };
 
-        unsafe {
-            let runtime_ptr = &raw const RUNTIME;
-            match &*runtime_ptr {
-                Some(runtime) => runtime.block_on(async {
-                    let Ok(result) =
-                        PamQuickEmbedded::close_session_for_user(&String::from(username)).await
-                    else {
-                        return Err(PamErrorCode::SERVICE_ERR);
-                    };
-
-                    match ServiceOperationResult::from(result) {
-                        ServiceOperationResult::Ok => Ok(()),
-                        _ => Err(PamErrorCode::SERVICE_ERR),
-                    }
-                }),
-                None => Err(PamErrorCode::SERVICE_ERR),
+        PamQuickEmbedded::block_on(async {
+            let Err(err) =
+                PamQuickEmbedded::close_session_for_user(&options, &String::from(username)).await
+            else {
+                return Ok(());
+            };
+
+            if let Some(result) =
+                PamQuickEmbedded::handle_unavailable(pamh, &options, "sm_close_session", &err)
+            {
+                return result;
             }
-        }
+
+            pamh.log(
+                pam_binding::module::LogLevel::Error,
+                format!(
+                    "polyauth: sm_close_session: pam_polyauth-service errored: {} ({})",
+                    err.message(),
+                    err.result()
+                ),
+            );
+
+            Err(PamQuickEmbedded::pam_error_code(&err))
+        })
     }
 
-    fn sm_open_session(pamh: &mut PamHandle, _args: Vec<&CStr>, _flags: PamFlag) -> PamResult<()> {
+    fn sm_open_session(pamh: &mut PamHandle, args: Vec<&CStr>, _flags: PamFlag) -> PamResult<()> {
         pamh.log(
             pam_binding::module::LogLevel::Debug,
             "polyauth: sm_open_session: enter".to_string(),
         );
 
-        match std::env::var("DBUS_SESSION_BUS_ADDRESS") {
-            Ok(value) => pamh.log(
-                pam_binding::module::LogLevel::Info,
-                format!("Starting dbus service on socket {value}"),
-            ),
-            Err(err) => {
-                pamh.log(
-                    pam_binding::module::LogLevel::Debug,
-                    format!("Couldn't read dbus socket address: {err} - using default..."),
-                );
-                std::env::set_var(
-                    "DBUS_SESSION_BUS_ADDRESS",
-                    "unix:path=/run/dbus/system_bus_socket",
-                );
-            }
-        }
-
-        INIT.call_once(|| {
-            // Initialize the Tokio runtime
-            unsafe {
-                RUNTIME = Some(Runtime::new().unwrap());
-            }
-        });
+        let options = PamQuickEmbedded::module_options(pamh, &args, "sm_open_session")?;
 
         let username = match pamh.get_user(None) {
             Ok(Some(res)) => res,
@@ -298,106 +616,75 @@ impl PamHooks for PamQuickEmbedded {
             format!("polyauth: sm_open_session: user {username}"),
         );
 
-        unsafe {
-            let runtime_ptr = &raw const RUNTIME;
-            let runtime = (&*runtime_ptr).as_ref().ok_or(PamErrorCode::SERVICE_ERR)?;
-            runtime.block_on(async {
-                let cred_data = format!("{}-polyauth", username);
-                let main_password = pamh.get_data::<String>(cred_data.as_str()).map_err(|err| {
-                    pamh.log(
-                        pam_binding::module::LogLevel::Error,
-                        format!(
-                            "polyauth: sm_open_session: get_data error: {err}"
-                        ),
-                    );
-
-                    err
-                })?;
+        let cred_data = format!("{}-polyauth", username);
+        // SAFETY: the data under this key is only ever stored by sm_authenticate, as a SecretBytes
+        let main_password = unsafe { pamh.get_data::<SecretBytes>(cred_data.as_str()) }
+            .map_err(|err| {
+                pamh.log(
+                    pam_binding::module::LogLevel::Error,
+                    format!("polyauth: sm_open_session: get_data error: {err}"),
+                );
 
-                let (result, uid, gid) = PamQuickEmbedded::open_session_for_user(
-                    &String::from(username),
-                    main_password.clone(),
-                )
-                .await
-                .map_err(|err| {
-                    pamh.log(
-                        pam_binding::module::LogLevel::Error,
-                        format!(
-                            "polyauth: sm_open_session: pam_polyauth-service dbus error: {err}"
-                        ),
-                    );
+                err
+            })?
+            .clone();
+
+        PamQuickEmbedded::block_on(async {
+            let (uid, _gid) = match PamQuickEmbedded::open_session_for_user(
+                &options,
+                &String::from(username),
+                &main_password,
+            )
+            .await
+            {
+                Ok(ids) => ids,
+                Err(err) => {
+                    if let Some(result) = PamQuickEmbedded::handle_unavailable(
+                        pamh,
+                        &options,
+                        "sm_open_session",
+                        &err,
+                    ) {
+                        return result;
+                    }
 
-                    PamErrorCode::SERVICE_ERR
-                })?;
+                    PamQuickEmbedded::report_error(pamh, "sm_open_session", &err);
 
-                match result {
-                    ServiceOperationResult::Ok => {
-                        pamh.log(
-                            pam_binding::module::LogLevel::Info,
-                            "polyauth: sm_open_session: pam_polyauth-service was successful".to_string(),
-                        );
-
-                        let uid = uid;
-                        let _gid = gid;
-
-                        let xdg_user_path = PathBuf::from(XDG_RUNTIME_DIR_PATH).join(format!("{uid}"));
-                        match pamh.env_set(Cow::from("XDG_RUNTIME_DIR"), xdg_user_path.to_string_lossy()) {
-                            Ok(_) => pamh.log(
-                                    pam_binding::module::LogLevel::Info,
-                                    "polyauth: sm_open_session: session opened and XDG_RUNTIME_DIR set".to_string(),
-                                ),
-                            Err(err) => pamh.log(
-                                    pam_binding::module::LogLevel::Warning,
-                                    format!("polyauth: sm_open_session: could not set XDG_RUNTIME_DIR: {err}"),
-                                ),
-                        }
-
-                        Ok(())
-                    },
-                    err => {
-                        pamh.log(
-                            pam_binding::module::LogLevel::Error,
-                            format!(
-                                "polyauth: sm_open_session: pam_polyauth-service errored: {err}"
-                            ),
-                        );
-
-                        Err(PamErrorCode::SERVICE_ERR)
-                    },
+                    return Err(PamQuickEmbedded::pam_error_code(&err));
                 }
-            })
-        }
+            };
+
+            pamh.log(
+                pam_binding::module::LogLevel::Info,
+                "polyauth: sm_open_session: pam_polyauth-service was successful".to_string(),
+            );
+
+            let xdg_user_path = options.runtime_dir().join(format!("{uid}"));
+            match pamh.env_set(
+                Cow::from("XDG_RUNTIME_DIR"),
+                xdg_user_path.to_string_lossy(),
+            ) {
+                Ok(_) => pamh.log(
+                    pam_binding::module::LogLevel::Info,
+                    "polyauth: sm_open_session: session opened and XDG_RUNTIME_DIR set".to_string(),
+                ),
+                Err(err) => pamh.log(
+                    pam_binding::module::LogLevel::Warning,
+                    format!("polyauth: sm_open_session: could not set XDG_RUNTIME_DIR: {err}"),
+                ),
+            }
+
+            Ok(())
+        })
     }
 
-    fn sm_setcred(pamh: &mut PamHandle, _args: Vec<&CStr>, _flags: PamFlag) -> PamResult<()> {
+    fn sm_setcred(pamh: &mut PamHandle, args: Vec<&CStr>, _flags: PamFlag) -> PamResult<()> {
         pamh.log(
             pam_binding::module::LogLevel::Debug,
             format!("polyauth: sm_setcred: enter"),
         );
 
-        match std::env::var("DBUS_SESSION_BUS_ADDRESS") {
-            Ok(value) => pamh.log(
-                pam_binding::module::LogLevel::Debug,
-                format!("Using dbus service on socket {value}"),
-            ),
-            Err(err) => {
-                pamh.log(
-                    pam_binding::module::LogLevel::Debug,
-                    format!("Couldn't read dbus socket address: {err} - using default..."),
-                );
-                std::env::set_var(
-                    "DBUS_SESSION_BUS_ADDRESS",
-                    "unix:path=/run/dbus/system_bus_socket",
-                );
-            }
-        }
-
-        INIT.call_once(|| {
-            // Initialize the Tokio runtime
-            unsafe {
-                RUNTIME = Some(Runtime::new().unwrap());
-            }
-        });
+        let options = PamQuickEmbedded::module_options(pamh, &args, "sm_setcred")?;
 
         let username = match pamh.get_user(None)? {
             Some(res) => res,
@@ -416,24 +703,22 @@ impl PamHooks for PamQuickEmbedded {
         };
 
         // Check if the user is polyauth-enabled by asking the service
-        unsafe {
-            let runtime_ptr = &raw const RUNTIME;
-            match &*runtime_ptr {
-                Some(runtime) => runtime.block_on(async {
-                    let Ok(result) =
-                        PamQuickEmbedded::is_user_polyauth_enabled(&String::from(username)).await
-                    else {
-                        return Err(PamErrorCode::SERVICE_ERR);
-                    };
-
-                    match ServiceOperationResult::from(result) {
-                        ServiceOperationResult::Ok => Ok(()),
-                        _ => Err(PamErrorCode::USER_UNKNOWN),
-                    }
-                }),
-                None => Err(PamErrorCode::SERVICE_ERR),
+        PamQuickEmbedded::block_on(async {
+            let Err(err) =
+                PamQuickEmbedded::is_user_polyauth_enabled(&options, &String::from(username)).await
+            else {
+                return Ok(());
+            };
+
+            if let Some(result) =
+                PamQuickEmbedded::handle_unavailable(pamh, &options, "sm_setcred", &err)
+            {
+                return result;
             }
-        }
+
+            // users not managed by polyauth are not an error worth showing
+            Err(PamErrorCode::USER_UNKNOWN)
+        })
     }
 
     /*
@@ -473,7 +758,8 @@ impl PamHooks for PamQuickEmbedded {
         };
 
         // try to load the user and return PAM_USER_UNKNOWN if it cannot be loaded
-        let user_cfg = match load_user_auth_data(&StorageSource::Username(username.to_string())) {
+        let store = system_store(StoreLayout::Detect);
+        let mut user_cfg = match load_user_auth_data(&store, &username) {
             Ok(Some(auth_data)) if auth_data.has_main() => auth_data,
             _ => return Err(PamErrorCode::USER_UNKNOWN),
         };
@@ -483,18 +769,10 @@ impl PamHooks for PamQuickEmbedded {
         // NOTE: if main_by_auth returns a main password the authentication was successful:
         // there is no need to check if the returned main password is the same as the stored one.
         // This will also used below for the user-provided string.
-        if let Ok(main_password) = user_cfg.main_by_auth(&Some(String::new())) {
-            pamh.set_data(cred_data.as_str(), Box::new(main_password))
-                .map_err(|err| {
-                    pamh.log(
-                        pam_binding::module::LogLevel::Error,
-                        format!("polyauth: sm_authenticate: set_data error {err}"),
-                    );
-
-                    err
-                })?;
+        if let Ok(main_password) = user_cfg.main_by_auth(Some(b"")) {
+            PamQuickEmbedded::upgrade_auth_data(pamh, &mut user_cfg, &store, &username, Some(b""));
 
-            return Ok(());
+            return PamQuickEmbedded::store_main_password(pamh, &cred_data, main_password);
         }
 
         // if the empty password was not valid then continue and ask for a password
@@ -519,10 +797,11 @@ impl PamHooks for PamQuickEmbedded {
 
         let password = conv
             .send(PamMessageStyle::PAM_PROMPT_ECHO_OFF, "Password: ")
-            .map(|cstr| cstr.map(|a| a.to_string_lossy()).map(|s| s.to_string()))?
+            .map(|cstr| cstr.map(|a| SecretBytes::from(a.to_bytes())))?
             .ok_or(PamErrorCode::CRED_INSUFFICIENT)?;
 
-        let main_password = user_cfg.main_by_auth(&Some(password)).map_err(|err| {
+        let password = Some(password.expose());
+        let main_password = user_cfg.main_by_auth(password).map_err(|err| {
             pamh.log(
                 pam_binding::module::LogLevel::Error,
                 format!("polyauth: sm_authenticate: authentication error: {err}"),
@@ -530,14 +809,9 @@ impl PamHooks for PamQuickEmbedded {
 
             PamErrorCode::AUTH_ERR
         })?;
-        pamh.set_data(cred_data.as_str(), Box::new(main_password))
-            .map_err(|err| {
-                pamh.log(
-                    pam_binding::module::LogLevel::Error,
-                    format!("polyauth: sm_authenticate: set_data error {err}"),
-                );
 
-                err
-            })
+        PamQuickEmbedded::upgrade_auth_data(pamh, &mut user_cfg, &store, &username, password);
+
+        PamQuickEmbedded::store_main_password(pamh, &cred_data, main_password)
     }
 }
@@ -23,6 +23,44 @@ use rs_sha512::*;
 use std::hash::{BuildHasher, Hasher};
 
 use std::collections::HashMap;
+use std::path::{Component, Path};
+
+use thiserror::Error;
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum MountError {
+    #[error("The device cannot be empty")]
+    EmptyDevice,
+
+    #[error("The device '{0}' cannot contain spaces")]
+    InvalidDevice(String),
+
+    #[error("'{0}' is not a valid filesystem type")]
+    InvalidFsType(String),
+
+    #[error("'{0}' is not a valid mount option: options cannot be empty or contain spaces")]
+    InvalidFlag(String),
+
+    #[error("The directory '{0}' must be an absolute path other than / without . or ..")]
+    InvalidDirectory(String),
+}
+
+/// Checks a directory an additional mount can be mounted on
+pub fn validate_mount_dir(dir: &str) -> Result<(), MountError> {
+    let path = Path::new(dir);
+    let valid = path.is_absolute()
+        && path.components().count() > 1
+        && path
+            .components()
+            .all(|component| matches!(component, Component::RootDir | Component::Normal(_)))
+        // components() hides "." so it is looked for in the string itself
+        && !dir.split('/').any(|part| part == ".");
+
+    match valid {
+        true => Ok(()),
+        false => Err(MountError::InvalidDirectory(String::from(dir))),
+    }
+}
 
 #[derive(Debug, Clone, PartialEq, Default)]
 pub struct MountParams {
@@ -63,6 +101,31 @@ impl MountParams {
     pub fn set_flags(&mut self, flags: Vec<String>) {
         self.flags = flags;
     }
+
+    /// Checks the parameters can be given to mount: an empty filesystem type is detected by mount
+    pub fn validate(&self) -> Result<(), MountError> {
+        if self.device.is_empty() {
+            return Err(MountError::EmptyDevice);
+        }
+
+        if self.device.contains(char::is_whitespace) {
+            return Err(MountError::InvalidDevice(self.device.clone()));
+        }
+
+        let fstype_chars = |c: char| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-');
+        if !self.fstype.chars().all(fstype_chars) {
+            return Err(MountError::InvalidFsType(self.fstype.clone()));
+        }
+
+        match self
+            .flags
+            .iter()
+            .find(|flag| flag.is_empty() || flag.contains(char::is_whitespace))
+        {
+            Some(flag) => Err(MountError::InvalidFlag(flag.clone())),
+            None => Ok(()),
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Default)]
@@ -99,6 +162,11 @@ impl MountPoints {
         n
     }
 
+    /// Removes the mount of the given directory, returning whether there was one
+    pub fn remove_premount(&mut self, dir: &str) -> bool {
+        self.mounts.remove(dir).is_some()
+    }
+
     pub fn mount(&self) -> MountParams {
         self.home.clone()
     }
@@ -0,0 +1,172 @@
+/*
+    pam_polyauth: A pam module written in rust that supports multiple
+    authentication modes (including autologin).
+
+    Copyright (C) 2024-2025  Denis Benato
+
+    This program is free software; you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation; either version 2 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License along
+    with this program; if not, write to the Free Software Foundation, Inc.,
+    51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+*/
+
+use std::{ffi::CStr, path::PathBuf, time::Duration};
+
+use thiserror::Error;
+
+use crate::pam::{SESSIONS_SOCKET_PATH, XDG_RUNTIME_DIR_PATH};
+
+#[derive(Debug, Error, PartialEq)]
+pub enum ModuleOptionsError {
+    #[error("Unknown module option: {0}")]
+    UnknownOption(String),
+
+    #[error("Invalid value '{1}' for module option {0}")]
+    InvalidValue(String, String),
+
+    #[error("Module option is not valid UTF-8")]
+    InvalidEncoding,
+}
+
+/// How the PAM module reaches pam_polyauth-service
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Transport {
+    /// Use the bus, falling back to the direct socket if the bus cannot be reached
+    #[default]
+    Auto,
+
+    /// Only use the bus
+    Bus,
+
+    /// Only use the direct socket
+    Socket,
+}
+
+/// What the PAM module does when pam_polyauth-service cannot be reached (or does not answer in time)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum UnavailablePolicy {
+    /// Fail the PAM hook
+    #[default]
+    FailClosed,
+
+    /// Log a warning and let the login continue without polyauth (e.g. without mounts)
+    Continue,
+}
+
+/// Default time given to each hook to get its answers from pam_polyauth-service, in seconds
+pub const DEFAULT_TIMEOUT_SECS: u64 = 25;
+
+/// Options given to the PAM module in the PAM stack configuration, e.g.
+///
+/// `session optional pam_polyauth.so bus_address=unix:path=/run/dbus/system_bus_socket`
+#[derive(Debug, Clone, PartialEq)]
+pub struct ModuleOptions {
+    bus_address: Option<String>,
+    socket_path: PathBuf,
+    transport: Transport,
+    timeout: Duration,
+    unavailable: UnavailablePolicy,
+    runtime_dir: PathBuf,
+}
+
+impl Default for ModuleOptions {
+    fn default() -> Self {
+        Self {
+            bus_address: None,
+            socket_path: PathBuf::from(SESSIONS_SOCKET_PATH),
+            transport: Transport::default(),
+            timeout: Duration::from_secs(DEFAULT_TIMEOUT_SECS),
+            unavailable: UnavailablePolicy::default(),
+            runtime_dir: PathBuf::from(XDG_RUNTIME_DIR_PATH),
+        }
+    }
+}
+
+impl ModuleOptions {
+    /// Parses the `key=value` arguments of the PAM module
+    pub fn parse(args: &[&CStr]) -> Result<Self, ModuleOptionsError> {
+        let mut options = Self::default();
+
+        for arg in args {
+            let arg = arg
+                .to_str()
+                .map_err(|_| ModuleOptionsError::InvalidEncoding)?;
+
+            let (key, value) = arg.split_once('=').unwrap_or((arg, ""));
+            let invalid = || ModuleOptionsError::InvalidValue(key.to_string(), value.to_string());
+
+            match key {
+                "bus_address" => match value.is_empty() {
+                    true => return Err(invalid()),
+                    false => options.bus_address = Some(value.to_string()),
+                },
+                "socket" => match value.is_empty() {
+                    true => return Err(invalid()),
+                    false => options.socket_path = PathBuf::from(value),
+                },
+                "transport" => {
+                    options.transport = match value {
+                        "auto" => Transport::Auto,
+                        "bus" => Transport::Bus,
+                        "socket" => Transport::Socket,
+                        _ => return Err(invalid()),
+                    }
+                }
+                "timeout" => match value.parse::<u64>() {
+                    Ok(secs) if secs > 0 => options.timeout = Duration::from_secs(secs),
+                    _ => return Err(invalid()),
+                },
+                "unavailable" => {
+                    options.unavailable = match value {
+                        "fail" => UnavailablePolicy::FailClosed,
+                        "continue" => UnavailablePolicy::Continue,
+                        _ => return Err(invalid()),
+                    }
+                }
+                "runtime_dir" => match value.starts_with('/') {
+                    true => options.runtime_dir = PathBuf::from(value),
+                    false => return Err(invalid()),
+                },
+                _ => return Err(ModuleOptionsError::UnknownOption(arg.to_string())),
+            }
+        }
+
+        Ok(options)
+    }
+
+    /// D-Bus address to connect to, the system bus is used when missing
+    pub fn bus_address(&self) -> Option<&str> {
+        self.bus_address.as_deref()
+    }
+
+    pub fn socket_path(&self) -> &PathBuf {
+        &self.socket_path
+    }
+
+    pub fn transport(&self) -> Transport {
+        self.transport
+    }
+
+    /// Time given to each hook to connect to pam_polyauth-service and get its answers
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    pub fn unavailable(&self) -> UnavailablePolicy {
+        self.unavailable
+    }
+
+    /// Base of the runtime directories: must match the one pam_polyauth-service mounts them in
+    pub fn runtime_dir(&self) -> &PathBuf {
+        &self.runtime_dir
+    }
+}
@@ -22,10 +22,40 @@
 pub mod disk;
 pub mod mount;
 pub mod result;
+pub mod runtime_dir;
 pub mod security;
+pub mod service;
 pub mod session;
+pub mod socket;
 
-pub const XDG_RUNTIME_DIR_PATH: &str = "/tmp/xdg/";
+/// Default base of the per-user runtime directories, the one systemd-logind uses
+pub const XDG_RUNTIME_DIR_PATH: &str = "/run/user";
+
+/// Unix socket where pam_polyauth-service accepts direct (bus-less) connections
+pub const SESSIONS_SOCKET_PATH: &str = "/run/polyauth/session.sock";
+
+/// User the greeter of greetd runs as, allowed to read session commands of other users
+pub const DEFAULT_GREETER_USER: &str = "greeter";
+
+/// Private key used by pam_polyauth-service to receive passwords
+pub const PRIVATE_KEY_FILE_NAME: &str = "private_key_pkcs1.pem";
+
+/// Hashes of the mounts each user is authorized to perform
+pub const AUTHORIZED_MOUNTS_FILE_NAME: &str = "authorized_mounts.json";
+
+/// Key signing user configurations
+pub const SIGNING_KEY_FILE_NAME: &str = "config_signing_key";
+
+/// Directory where pam_polyauth-service keeps its files: /usr/lib/polyauth/ on systems
+/// shipping it, /etc/polyauth/ otherwise.
+pub fn service_data_dir() -> PathBuf {
+    match std::fs::exists("/usr/lib/polyauth/").unwrap_or(false) {
+        true => PathBuf::from("/usr/lib/polyauth/"),
+        false => PathBuf::from("/etc/polyauth/"),
+    }
+}
+
+use std::path::PathBuf;
 
 use zbus::Error as ZError;
 
@@ -50,4 +80,10 @@ pub enum ServiceError {
 
     #[error("Join error: {0}")]
     JoinError(#[from] tokio::task::JoinError),
+
+    #[error("Storage error: {0}")]
+    StorageError(#[from] crate::storage::StorageError),
+
+    #[error("Runtime directory configuration error: {0}")]
+    RuntimeDirConfigError(#[from] runtime_dir::RuntimeDirConfigError),
 }
@@ -23,6 +23,8 @@ use sys_mount::{Mount, Unmount, UnmountDrop, UnmountFlags};
 use users;
 
 use crate::mount::MountPoints;
+use crate::secret::SecretBytes;
+use crate::storage::{integrity::ConfigSigningKey, sign_user_config, store::UserStore};
 use tokio::sync::RwLock;
 
 use std::collections::HashMap;
@@ -39,7 +41,8 @@ use serde::{Deserialize, Serialize};
 use serde_json;
 
 use crate::pam::{
-    result::ServiceOperationResult,
+    result::{ServiceOperationError, ServiceOperationResult},
+    runtime_dir::{mount_runtime_dir, RuntimeDirConfig},
     {disk, ServiceError},
 };
 
@@ -95,58 +98,33 @@ where
     }
 }
 
-pub(crate) fn mount_xdg(
-    uid: users::uid_t,
-    gid: users::gid_t,
-    username: &str,
-) -> Option<UnmountDrop<Mount>> {
-    let xdg_path = PathBuf::from(crate::pam::XDG_RUNTIME_DIR_PATH);
-    if !xdg_path.exists() {
-        if let Err(err) = fs::create_dir(xdg_path.clone()) {
-            eprintln!("❌ Error creating the xdg base path: {err}");
-            return None;
-        }
-    } else if !xdg_path.is_dir() {
-        eprintln!("🚫 Failed to use xdg base path: not a directory");
-        return None;
-    }
+/// Builds the error reported to the PAM module when a mount fails,
+/// carrying the device, the target directory and the errno.
+pub(crate) fn mount_error<PATH>(
+    message: &str,
+    device: Option<&str>,
+    directory: PATH,
+    err: &io::Error,
+) -> ServiceOperationError
+where
+    PATH: AsRef<Path>,
+{
+    let directory = directory.as_ref().to_string_lossy();
+    let mut error = ServiceOperationError::new(
+        ServiceOperationResult::MountError,
+        format!("{message} ({directory}): {err}"),
+    )
+    .with_detail("directory", directory);
 
-    let user_xdg_path = xdg_path.join(format!("{uid}"));
-    if !user_xdg_path.exists() {
-        if let Err(err) = fs::create_dir(user_xdg_path.clone()) {
-            eprintln!("❌ Error creating the xdg path for user {username}: {err}");
-            return None;
-        }
-    } else if !xdg_path.is_dir() {
-        eprintln!("🚫 Failed to use xdg path for user {username}: not a directory");
-        return None;
+    if let Some(device) = device {
+        error = error.with_detail("device", device);
     }
 
-    let mount_data = (
-        "tmpfs".to_string(),
-        format!("uid={uid},gid={gid}"),
-        "tmpfs".to_string(),
-        user_xdg_path.as_os_str(),
-    );
-    match mount(mount_data) {
-        Ok(mount) => {
-            let xdg_path = user_xdg_path.as_path().to_string_lossy().to_string();
-            if let Err(err) = set_directory_permissions(xdg_path.as_str(), 0o700) {
-                eprintln!("❌ Error setting permissions of the xdg directory {xdg_path}: {err}");
-            } else {
-                println!("🟢 Changed permissions of {xdg_path} to 0700");
-            }
-
-            Some(mount.into_unmount_drop(UnmountFlags::DETACH))
-        }
-        Err(err) => {
-            eprintln!(
-                "❌ Error mounting the xdg path for user {username} ({}): {err}",
-                user_xdg_path.as_os_str().to_string_lossy()
-            );
-            None
-        }
+    if let Some(errno) = err.raw_os_error() {
+        error = error.with_detail("errno", errno);
     }
+
+    error
 }
 
 fn set_directory_permissions(path: &str, mode: u32) -> std::io::Result<()> {
@@ -166,21 +144,20 @@ fn change_owner(path: &str, uid: users::uid_t, gid: users::gid_t) -> Result<(),
 
 pub(crate) fn mount_all(
     mounts: Option<MountPoints>,
-    password: Vec<u8>,
+    password: SecretBytes,
     uid: users::uid_t,
     gid: users::gid_t,
     username: String,
     homedir: String,
-) -> Vec<UnmountDrop<Mount>> {
-    let Some(xdg_mounted_dir) = mount_xdg(uid, gid, username.as_str()) else {
-        return vec![];
-    };
-
-    // mount xdg folder first
-    let mut mounted_devices = vec![xdg_mounted_dir];
+    runtime_dir: &RuntimeDirConfig,
+) -> Result<Vec<UnmountDrop<Mount>>, ServiceOperationError> {
+    // mount the runtime directory first
+    let mut mounted_devices = mount_runtime_dir(runtime_dir, uid, gid, username.as_str())?
+        .into_iter()
+        .collect::<Vec<_>>();
 
     let Some(mounts) = mounts else {
-        return mounted_devices;
+        return Ok(mounted_devices);
     };
 
     for m in mounts
@@ -206,7 +183,7 @@ pub(crate) fn mount_all(
             Err(err) => {
                 eprintln!("❌ Error mounting device {dev} into {path}: {err}");
 
-                return vec![];
+                return Err(mount_error("cannot mount device", Some(dev), path, &err));
             }
         }
     }
@@ -236,11 +213,16 @@ pub(crate) fn mount_all(
         }
         Err(err) => {
             eprintln!("❌ Error mounting user directory: {err}");
-            return vec![];
+            return Err(mount_error(
+                "cannot mount the home directory",
+                Some(mounts.mount().device().as_str()),
+                homedir.as_str(),
+                &err,
+            ));
         }
     }
 
-    mounted_devices
+    Ok(mounted_devices)
 }
 
 #[derive(Serialize, Deserialize, Default, Clone, PartialEq, Debug)]
@@ -276,11 +258,25 @@ impl MountAuth {
 
 pub struct MountAuthOperations {
     file_path: PathBuf,
+    signing_key_path: PathBuf,
 }
 
 impl MountAuthOperations {
-    pub fn new(file_path: PathBuf) -> Self {
-        Self { file_path }
+    pub fn new(file_path: PathBuf, signing_key_path: PathBuf) -> Self {
+        Self {
+            file_path,
+            signing_key_path,
+        }
+    }
+
+    /// Reads the key used to sign user configurations, generating it on first use
+    pub(crate) async fn read_signing_key(&self) -> Result<ConfigSigningKey, ServiceError> {
+        let encoded = disk::read_file_or_create_default(self.signing_key_path.clone(), || {
+            Ok(ConfigSigningKey::generate().to_base64())
+        })
+        .await?;
+
+        Ok(ConfigSigningKey::from_base64(encoded.as_str())?)
     }
 
     pub(crate) async fn read_auth_file(&self) -> Result<MountAuth, ServiceError> {
@@ -319,11 +315,15 @@ impl MountAuthOperations {
 
 pub struct MountAuthDBus {
     auth_mount_op: Arc<RwLock<MountAuthOperations>>,
+    store: Arc<dyn UserStore>,
 }
 
 impl MountAuthDBus {
-    pub fn new(auth_mount_op: Arc<RwLock<MountAuthOperations>>) -> Self {
-        Self { auth_mount_op }
+    pub fn new(auth_mount_op: Arc<RwLock<MountAuthOperations>>, store: Arc<dyn UserStore>) -> Self {
+        Self {
+            auth_mount_op,
+            store,
+        }
     }
 }
 
@@ -335,7 +335,11 @@ impl MountAuthDBus {
     )
 )]
 impl MountAuthDBus {
-    pub async fn authorize(&mut self, username: &str, hash: String) -> u32 {
+    pub async fn authorize(
+        &mut self,
+        username: &str,
+        hash: String,
+    ) -> Result<(), ServiceOperationError> {
         println!("⚙️ Requested add authorization to mount {hash} for user {username}");
 
         {
@@ -344,7 +348,11 @@ impl MountAuthDBus {
                 Ok(auth_str) => auth_str,
                 Err(err) => {
                     eprintln!("❌ Error opening mount authorizations file: {err}");
-                    return ServiceOperationResult::IOError.into();
+                    return Err(ServiceOperationError::new(
+                        ServiceOperationResult::IOError,
+                        format!("cannot read the mount authorizations file: {err}"),
+                    )
+                    .with_detail("file", lck.file_path.to_string_lossy()));
                 }
             };
 
@@ -352,13 +360,49 @@ impl MountAuthDBus {
 
             if let Err(err) = lck.write_auth_file(&authorizations).await {
                 eprintln!("❌ Error writing the mount authorizations file: {err}");
-                return ServiceOperationResult::IOError.into();
+                return Err(ServiceOperationError::new(
+                    ServiceOperationResult::IOError,
+                    format!("cannot write the mount authorizations file: {err}"),
+                )
+                .with_detail("file", lck.file_path.to_string_lossy()));
             }
         }
 
         println!("✅ New mount authorized to user {username}");
 
-        ServiceOperationResult::Ok.into()
+        Ok(())
+    }
+
+    /// Approves the current mounts and session command of the user:
+    /// any later change to them is detected when a session is opened.
+    pub async fn sign_user_config(&self, username: &str) -> Result<(), ServiceOperationError> {
+        println!("✍️ Requested signature of the configuration of user {username}");
+
+        let lck = self.auth_mount_op.read().await;
+        let key = match lck.read_signing_key().await {
+            Ok(key) => key,
+            Err(err) => {
+                eprintln!("❌ Error reading the configuration signing key: {err}");
+                return Err(ServiceOperationError::new(
+                    ServiceOperationResult::IOError,
+                    format!("cannot read the configuration signing key: {err}"),
+                )
+                .with_detail("file", lck.signing_key_path.to_string_lossy()));
+            }
+        };
+
+        if let Err(err) = sign_user_config(self.store.as_ref(), username, &key) {
+            eprintln!("❌ Error signing the configuration of user {username}: {err}");
+            return Err(ServiceOperationError::new(
+                ServiceOperationResult::IOError,
+                format!("cannot sign the configuration of user '{username}': {err}"),
+            )
+            .with_detail("user", username));
+        }
+
+        println!("✅ Configuration of user {username} signed");
+
+        Ok(())
     }
 
     pub async fn check(&self, username: &str, hash: String) -> bool {
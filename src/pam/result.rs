@@ -19,7 +19,9 @@
     51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
 */
 
-use std::fmt;
+use std::{collections::HashMap, fmt};
+
+use zbus::DBusError;
 
 #[derive(Clone, Copy, PartialEq, Debug)]
 #[repr(C)]
@@ -37,6 +39,11 @@ pub enum ServiceOperationResult {
     UnauthorizedMount = 10,
     SerializationError = 11,
     IOError = 12,
+    NoSuchSession = 13,
+    BusError = 14,
+    TamperedConfig = 15,
+    NoSessionCommand = 16,
+    AccessDenied = 17,
     Unknown,
 }
 
@@ -56,6 +63,11 @@ impl fmt::Display for ServiceOperationResult {
             ServiceOperationResult::UnauthorizedMount => "Unauthorized mount attempted",
             ServiceOperationResult::SerializationError => "(De)Serialization error",
             ServiceOperationResult::IOError => "I/O Error",
+            ServiceOperationResult::NoSuchSession => "No Such Session",
+            ServiceOperationResult::BusError => "DBus Error",
+            ServiceOperationResult::TamperedConfig => "User configuration changed after approval",
+            ServiceOperationResult::NoSessionCommand => "No Session Command Configured",
+            ServiceOperationResult::AccessDenied => "Access Denied",
             ServiceOperationResult::Unknown => "Unknown Error",
         };
         write!(f, "{result_str}")
@@ -84,7 +96,247 @@ impl From<u32> for ServiceOperationResult {
             10 => ServiceOperationResult::UnauthorizedMount,
             11 => ServiceOperationResult::SerializationError,
             12 => ServiceOperationResult::IOError,
+            13 => ServiceOperationResult::NoSuchSession,
+            14 => ServiceOperationResult::BusError,
+            15 => ServiceOperationResult::TamperedConfig,
+            16 => ServiceOperationResult::NoSessionCommand,
+            17 => ServiceOperationResult::AccessDenied,
             _ => ServiceOperationResult::Unknown,
         }
     }
 }
+
+/// Machine-readable details attached to a [`ServiceOperationError`] (e.g. device, directory, errno)
+pub type ErrorDetails = HashMap<String, String>;
+
+/// Error returned by every method of the polyauth dbus interfaces.
+///
+/// Each variant carries a human-readable message and a dictionary of details,
+/// both are sent over the bus as the body of the error reply.
+#[derive(Debug, Clone, PartialEq, DBusError)]
+#[zbus(prefix = "org.neroreflex.polyauth.Error")]
+pub enum ServiceOperationError {
+    PubKey(String, ErrorDetails),
+    DecryptionFailed(String, ErrorDetails),
+    CannotLoadUserMounts(String, ErrorDetails),
+    MountFailed(String, ErrorDetails),
+    SessionAlreadyOpened(String, ErrorDetails),
+    SessionAlreadyClosed(String, ErrorDetails),
+    CannotIdentifyUser(String, ErrorDetails),
+    EmptyPubKey(String, ErrorDetails),
+    EncryptionFailed(String, ErrorDetails),
+    UnauthorizedMount(String, ErrorDetails),
+    Serialization(String, ErrorDetails),
+    IO(String, ErrorDetails),
+    NoSuchSession(String, ErrorDetails),
+    /// The service could not be reached or the reply could not be understood:
+    /// this is never sent by the service itself.
+    Bus(String, ErrorDetails),
+    TamperedConfig(String, ErrorDetails),
+    NoSessionCommand(String, ErrorDetails),
+    AccessDenied(String, ErrorDetails),
+    Unknown(String, ErrorDetails),
+}
+
+impl ServiceOperationError {
+    pub fn new(result: ServiceOperationResult, message: impl Into<String>) -> Self {
+        let message = message.into();
+        let details = ErrorDetails::new();
+
+        match result {
+            ServiceOperationResult::PubKeyError => Self::PubKey(message, details),
+            ServiceOperationResult::DataDecryptionFailed => {
+                Self::DecryptionFailed(message, details)
+            }
+            ServiceOperationResult::CannotLoadUserMountError => {
+                Self::CannotLoadUserMounts(message, details)
+            }
+            ServiceOperationResult::MountError => Self::MountFailed(message, details),
+            ServiceOperationResult::SessionAlreadyOpened => {
+                Self::SessionAlreadyOpened(message, details)
+            }
+            ServiceOperationResult::SessionAlreadyClosed => {
+                Self::SessionAlreadyClosed(message, details)
+            }
+            ServiceOperationResult::CannotIdentifyUser => {
+                Self::CannotIdentifyUser(message, details)
+            }
+            ServiceOperationResult::EmptyPubKey => Self::EmptyPubKey(message, details),
+            ServiceOperationResult::EncryptionError => Self::EncryptionFailed(message, details),
+            ServiceOperationResult::UnauthorizedMount => Self::UnauthorizedMount(message, details),
+            ServiceOperationResult::SerializationError => Self::Serialization(message, details),
+            ServiceOperationResult::IOError => Self::IO(message, details),
+            ServiceOperationResult::NoSuchSession => Self::NoSuchSession(message, details),
+            ServiceOperationResult::BusError => Self::Bus(message, details),
+            ServiceOperationResult::TamperedConfig => Self::TamperedConfig(message, details),
+            ServiceOperationResult::NoSessionCommand => Self::NoSessionCommand(message, details),
+            ServiceOperationResult::AccessDenied => Self::AccessDenied(message, details),
+            ServiceOperationResult::Ok | ServiceOperationResult::Unknown => {
+                Self::Unknown(message, details)
+            }
+        }
+    }
+
+    /// Attach a detail to the error
+    pub fn with_detail(mut self, key: &str, value: impl ToString) -> Self {
+        self.details_mut()
+            .insert(String::from(key), value.to_string());
+        self
+    }
+
+    /// The class of error, as used before structured errors were introduced
+    pub fn result(&self) -> ServiceOperationResult {
+        match self {
+            Self::PubKey(..) => ServiceOperationResult::PubKeyError,
+            Self::DecryptionFailed(..) => ServiceOperationResult::DataDecryptionFailed,
+            Self::CannotLoadUserMounts(..) => ServiceOperationResult::CannotLoadUserMountError,
+            Self::MountFailed(..) => ServiceOperationResult::MountError,
+            Self::SessionAlreadyOpened(..) => ServiceOperationResult::SessionAlreadyOpened,
+            Self::SessionAlreadyClosed(..) => ServiceOperationResult::SessionAlreadyClosed,
+            Self::CannotIdentifyUser(..) => ServiceOperationResult::CannotIdentifyUser,
+            Self::EmptyPubKey(..) => ServiceOperationResult::EmptyPubKey,
+            Self::EncryptionFailed(..) => ServiceOperationResult::EncryptionError,
+            Self::UnauthorizedMount(..) => ServiceOperationResult::UnauthorizedMount,
+            Self::Serialization(..) => ServiceOperationResult::SerializationError,
+            Self::IO(..) => ServiceOperationResult::IOError,
+            Self::NoSuchSession(..) => ServiceOperationResult::NoSuchSession,
+            Self::Bus(..) => ServiceOperationResult::BusError,
+            Self::TamperedConfig(..) => ServiceOperationResult::TamperedConfig,
+            Self::NoSessionCommand(..) => ServiceOperationResult::NoSessionCommand,
+            Self::AccessDenied(..) => ServiceOperationResult::AccessDenied,
+            Self::Unknown(..) => ServiceOperationResult::Unknown,
+        }
+    }
+
+    pub fn message(&self) -> &str {
+        self.parts().0
+    }
+
+    /// Fixed message for the user logging in: the message and details can reveal
+    /// paths, devices and internals of the service, so they are only meant for the logs.
+    pub fn user_message(&self) -> &'static str {
+        match self.result() {
+            ServiceOperationResult::Ok => "the operation completed successfully",
+            ServiceOperationResult::CannotLoadUserMountError => {
+                "your mount configuration could not be loaded"
+            }
+            ServiceOperationResult::MountError => "your directories could not be mounted",
+            ServiceOperationResult::UnauthorizedMount => {
+                "your mounts have not been authorized by the administrator"
+            }
+            ServiceOperationResult::TamperedConfig => {
+                "your configuration changed after being approved by the administrator"
+            }
+            ServiceOperationResult::SessionAlreadyOpened
+            | ServiceOperationResult::SessionAlreadyClosed
+            | ServiceOperationResult::NoSuchSession => "your session is in an unexpected state",
+            ServiceOperationResult::CannotIdentifyUser => "your user account could not be found",
+            ServiceOperationResult::NoSessionCommand => "no session command is configured",
+            ServiceOperationResult::AccessDenied => "you are not allowed to use this service",
+            ServiceOperationResult::PubKeyError
+            | ServiceOperationResult::DataDecryptionFailed
+            | ServiceOperationResult::EmptyPubKey
+            | ServiceOperationResult::EncryptionError
+            | ServiceOperationResult::SerializationError
+            | ServiceOperationResult::IOError
+            | ServiceOperationResult::BusError
+            | ServiceOperationResult::Unknown => {
+                "the session service could not set up your session"
+            }
+        }
+    }
+
+    pub fn details(&self) -> &ErrorDetails {
+        self.parts().1
+    }
+
+    fn parts(&self) -> (&str, &ErrorDetails) {
+        match self {
+            Self::PubKey(message, details)
+            | Self::DecryptionFailed(message, details)
+            | Self::CannotLoadUserMounts(message, details)
+            | Self::MountFailed(message, details)
+            | Self::SessionAlreadyOpened(message, details)
+            | Self::SessionAlreadyClosed(message, details)
+            | Self::CannotIdentifyUser(message, details)
+            | Self::EmptyPubKey(message, details)
+            | Self::EncryptionFailed(message, details)
+            | Self::UnauthorizedMount(message, details)
+            | Self::Serialization(message, details)
+            | Self::IO(message, details)
+            | Self::NoSuchSession(message, details)
+            | Self::Bus(message, details)
+            | Self::TamperedConfig(message, details)
+            | Self::NoSessionCommand(message, details)
+            | Self::AccessDenied(message, details)
+            | Self::Unknown(message, details) => (message.as_str(), details),
+        }
+    }
+
+    fn details_mut(&mut self) -> &mut ErrorDetails {
+        match self {
+            Self::PubKey(_, details)
+            | Self::DecryptionFailed(_, details)
+            | Self::CannotLoadUserMounts(_, details)
+            | Self::MountFailed(_, details)
+            | Self::SessionAlreadyOpened(_, details)
+            | Self::SessionAlreadyClosed(_, details)
+            | Self::CannotIdentifyUser(_, details)
+            | Self::EmptyPubKey(_, details)
+            | Self::EncryptionFailed(_, details)
+            | Self::UnauthorizedMount(_, details)
+            | Self::Serialization(_, details)
+            | Self::IO(_, details)
+            | Self::NoSuchSession(_, details)
+            | Self::Bus(_, details)
+            | Self::TamperedConfig(_, details)
+            | Self::NoSessionCommand(_, details)
+            | Self::AccessDenied(_, details)
+            | Self::Unknown(_, details) => details,
+        }
+    }
+}
+
+impl From<zbus::Error> for ServiceOperationError {
+    fn from(value: zbus::Error) -> Self {
+        let zbus::Error::MethodError(name, description, reply) = &value else {
+            return Self::new(ServiceOperationResult::BusError, value.to_string());
+        };
+
+        let Some(error_name) = name.as_str().strip_prefix("org.neroreflex.polyauth.Error.") else {
+            return Self::new(ServiceOperationResult::BusError, value.to_string());
+        };
+
+        let result = match error_name {
+            "PubKey" => ServiceOperationResult::PubKeyError,
+            "DecryptionFailed" => ServiceOperationResult::DataDecryptionFailed,
+            "CannotLoadUserMounts" => ServiceOperationResult::CannotLoadUserMountError,
+            "MountFailed" => ServiceOperationResult::MountError,
+            "SessionAlreadyOpened" => ServiceOperationResult::SessionAlreadyOpened,
+            "SessionAlreadyClosed" => ServiceOperationResult::SessionAlreadyClosed,
+            "CannotIdentifyUser" => ServiceOperationResult::CannotIdentifyUser,
+            "EmptyPubKey" => ServiceOperationResult::EmptyPubKey,
+            "EncryptionFailed" => ServiceOperationResult::EncryptionError,
+            "UnauthorizedMount" => ServiceOperationResult::UnauthorizedMount,
+            "Serialization" => ServiceOperationResult::SerializationError,
+            "IO" => ServiceOperationResult::IOError,
+            "NoSuchSession" => ServiceOperationResult::NoSuchSession,
+            "Bus" => ServiceOperationResult::BusError,
+            "TamperedConfig" => ServiceOperationResult::TamperedConfig,
+            "NoSessionCommand" => ServiceOperationResult::NoSessionCommand,
+            "AccessDenied" => ServiceOperationResult::AccessDenied,
+            _ => ServiceOperationResult::Unknown,
+        };
+
+        // the body of errors emitted by the service is (message, details)
+        let details = reply
+            .body()
+            .deserialize::<(String, ErrorDetails)>()
+            .map(|(_, details)| details)
+            .unwrap_or_default();
+
+        let mut error = Self::new(result, description.clone().unwrap_or_default());
+        *error.details_mut() = details;
+        error
+    }
+}
@@ -0,0 +1,277 @@
+/*
+    pam_polyauth: A pam module written in rust that supports multiple
+    authentication modes (including autologin).
+
+    Copyright (C) 2024-2025  Denis Benato
+
+    This program is free software; you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation; either version 2 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License along
+    with this program; if not, write to the Free Software Foundation, Inc.,
+    51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+*/
+
+use std::{
+    fs::{self, DirBuilder},
+    io,
+    os::unix::fs::{DirBuilderExt, MetadataExt},
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use sys_mount::{Mount, MountFlags, UnmountDrop, UnmountFlags};
+use thiserror::Error;
+
+use crate::pam::{
+    mount::mount_error,
+    result::{ServiceOperationError, ServiceOperationResult},
+    XDG_RUNTIME_DIR_PATH,
+};
+
+/// Default size limit of each runtime directory, the same systemd-logind uses
+pub const DEFAULT_RUNTIME_DIR_SIZE: &str = "10%";
+
+// base directory managed by systemd-logind, and a directory that only exists while it runs
+const LOGIND_RUNTIME_DIR_BASE: &str = "/run/user";
+const LOGIND_SEATS_DIR: &str = "/run/systemd/seats";
+
+#[derive(Debug, Error, PartialEq)]
+pub enum RuntimeDirConfigError {
+    #[error("The runtime directory base {0} is not an absolute path")]
+    RelativeBase(PathBuf),
+
+    #[error("Invalid runtime directory size '{0}': expected a number optionally followed by k, m, g or %")]
+    InvalidSize(String),
+}
+
+/// Size limit of a runtime directory in the form accepted by tmpfs (i.e. 64m or 10%)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RuntimeDirSize(String);
+
+impl Default for RuntimeDirSize {
+    fn default() -> Self {
+        Self(String::from(DEFAULT_RUNTIME_DIR_SIZE))
+    }
+}
+
+impl FromStr for RuntimeDirSize {
+    type Err = RuntimeDirConfigError;
+
+    // the size ends up in the mount options: anything else than a size must be refused
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let digits = s.trim_end_matches(['k', 'K', 'm', 'M', 'g', 'G', '%']);
+        let suffix_len = s.len() - digits.len();
+
+        match !digits.is_empty() && suffix_len <= 1 && digits.bytes().all(|b| b.is_ascii_digit()) {
+            true => Ok(Self(String::from(s))),
+            false => Err(RuntimeDirConfigError::InvalidSize(String::from(s))),
+        }
+    }
+}
+
+impl std::fmt::Display for RuntimeDirSize {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Where and how pam_polyauth-service creates the XDG_RUNTIME_DIR of users
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RuntimeDirConfig {
+    base: PathBuf,
+    size: RuntimeDirSize,
+}
+
+impl Default for RuntimeDirConfig {
+    fn default() -> Self {
+        Self {
+            base: PathBuf::from(XDG_RUNTIME_DIR_PATH),
+            size: RuntimeDirSize::default(),
+        }
+    }
+}
+
+impl RuntimeDirConfig {
+    pub fn new(base: PathBuf, size: RuntimeDirSize) -> Result<Self, RuntimeDirConfigError> {
+        if !base.is_absolute() {
+            return Err(RuntimeDirConfigError::RelativeBase(base));
+        }
+
+        Ok(Self { base, size })
+    }
+
+    /// The directory containing the runtime directory of every user
+    pub fn base(&self) -> &Path {
+        &self.base
+    }
+
+    pub fn size(&self) -> &RuntimeDirSize {
+        &self.size
+    }
+
+    pub fn user_dir(&self, uid: users::uid_t) -> PathBuf {
+        self.base.join(format!("{uid}"))
+    }
+
+    /// Options of the tmpfs: ownership and permissions are applied by the mount itself,
+    /// so that the directory is never accessible with different ones.
+    pub fn mount_options(&self, uid: users::uid_t, gid: users::gid_t) -> String {
+        format!("mode=0700,size={},uid={uid},gid={gid}", self.size)
+    }
+
+    /// Whether systemd-logind is running and creates the runtime directories in base
+    pub fn managed_by_logind(&self) -> bool {
+        self.base == Path::new(LOGIND_RUNTIME_DIR_BASE) && Path::new(LOGIND_SEATS_DIR).is_dir()
+    }
+}
+
+/// What prepare_runtime_dir found out about the runtime directory of a user
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RuntimeDirState {
+    /// systemd-logind takes care of the directory
+    ManagedByLogind,
+
+    /// A filesystem owned by the user is already mounted on the directory
+    AlreadyMounted(PathBuf),
+
+    /// The directory exists, is owned by root and a tmpfs can be mounted on it
+    Ready(PathBuf),
+}
+
+fn refuse(message: String, path: &Path) -> ServiceOperationError {
+    eprintln!("🚫 {message}");
+    ServiceOperationError::new(ServiceOperationResult::MountError, message)
+        .with_detail("directory", path.to_string_lossy())
+}
+
+fn is_mountpoint(path: &Path, metadata: &fs::Metadata) -> io::Result<bool> {
+    let parent = fs::metadata(path.parent().unwrap_or(Path::new("/")))?;
+    Ok(metadata.dev() != parent.dev() || metadata.ino() == parent.ino())
+}
+
+/// Creates the directory (owned by root) if missing, then checks that it is a directory
+/// and not a symlink, it is owned by root and nobody else can write into it:
+/// checking after the creation also catches a directory created concurrently by someone else.
+fn ensure_root_directory(path: &Path, mode: u32) -> Result<fs::Metadata, ServiceOperationError> {
+    if let Err(err) = DirBuilder::new().mode(mode).create(path) {
+        if err.kind() != io::ErrorKind::AlreadyExists {
+            eprintln!("❌ Error creating the directory {}: {err}", path.display());
+            return Err(mount_error("cannot create the directory", None, path, &err));
+        }
+    }
+
+    let metadata = fs::symlink_metadata(path)
+        .map_err(|err| mount_error("cannot inspect the directory", None, path, &err))?;
+
+    if !metadata.file_type().is_dir() {
+        return Err(refuse(
+            format!("{} is not a directory", path.display()),
+            path,
+        ));
+    }
+
+    if metadata.uid() != 0 {
+        return Err(refuse(
+            format!("{} is not owned by root", path.display()),
+            path,
+        ));
+    }
+
+    if metadata.mode() & 0o022 != 0 {
+        return Err(refuse(
+            format!("{} is writable by users other than root", path.display()),
+            path,
+        ));
+    }
+
+    Ok(metadata)
+}
+
+/// Makes sure the runtime directory of the user can be mounted, creating it (and its base) if needed
+pub fn prepare_runtime_dir(
+    config: &RuntimeDirConfig,
+    uid: users::uid_t,
+) -> Result<RuntimeDirState, ServiceOperationError> {
+    if config.managed_by_logind() {
+        return Ok(RuntimeDirState::ManagedByLogind);
+    }
+
+    ensure_root_directory(config.base(), 0o755)?;
+
+    let user_dir = config.user_dir(uid);
+
+    // a runtime directory that is already mounted (i.e. by logind) is left as it is
+    if let Ok(metadata) = fs::symlink_metadata(&user_dir) {
+        let mounted = metadata.file_type().is_dir()
+            && is_mountpoint(&user_dir, &metadata).map_err(|err| {
+                mount_error("cannot inspect the directory", None, &user_dir, &err)
+            })?;
+
+        if mounted && metadata.uid() == uid {
+            return Ok(RuntimeDirState::AlreadyMounted(user_dir));
+        }
+    }
+
+    ensure_root_directory(&user_dir, 0o700)?;
+
+    Ok(RuntimeDirState::Ready(user_dir))
+}
+
+/// Mounts the runtime directory of the user: None if someone else already takes care of it
+pub(crate) fn mount_runtime_dir(
+    config: &RuntimeDirConfig,
+    uid: users::uid_t,
+    gid: users::gid_t,
+    username: &str,
+) -> Result<Option<UnmountDrop<Mount>>, ServiceOperationError> {
+    let user_dir = match prepare_runtime_dir(config, uid)? {
+        RuntimeDirState::ManagedByLogind => {
+            println!("ℹ️  The runtime directory of user {username} is managed by systemd-logind");
+            return Ok(None);
+        }
+        RuntimeDirState::AlreadyMounted(user_dir) => {
+            println!(
+                "ℹ️  The runtime directory {} of user {username} is already mounted",
+                user_dir.display()
+            );
+            return Ok(None);
+        }
+        RuntimeDirState::Ready(user_dir) => user_dir,
+    };
+
+    let options = config.mount_options(uid, gid);
+    match Mount::builder()
+        .fstype("tmpfs")
+        .flags(MountFlags::NOSUID | MountFlags::NODEV)
+        .data(options.as_str())
+        .mount("tmpfs", &user_dir)
+    {
+        Ok(mount) => {
+            println!(
+                "🟢 Mounted the runtime directory {} for user {username}",
+                user_dir.display()
+            );
+            Ok(Some(mount.into_unmount_drop(UnmountFlags::DETACH)))
+        }
+        Err(err) => {
+            eprintln!(
+                "❌ Error mounting the runtime directory {} for user {username}: {err}",
+                user_dir.display()
+            );
+            Err(mount_error(
+                "cannot mount the runtime directory",
+                Some("tmpfs"),
+                &user_dir,
+                &err,
+            ))
+        }
+    }
+}
@@ -7,6 +7,8 @@ use serde::{Deserialize, Serialize};
 
 use thiserror::Error;
 
+use crate::secret::SecretBytes;
+
 use aes_gcm::{
     aead::{Aead, AeadCore, KeyInit, OsRng},
     Aes256Gcm, Key, Nonce,
@@ -48,34 +50,38 @@ pub struct SessionPrelude {
     one_time_token: Vec<u8>,
 }
 
-fn string_to_vec_u8(input: String) -> Vec<u8> {
-    // Convert the String to Vec<u8>
-    let vec = input.into_bytes();
+/// Size of the block carrying the plaintext: its length (one byte) followed by the zero-padded plaintext
+const PLAINTEXT_BLOCK_LEN: usize = 255;
+
+/// Longest plaintext that fits the block: any byte value (including 0u8) is allowed
+pub const MAX_PLAINTEXT_LEN: usize = PLAINTEXT_BLOCK_LEN - 1;
 
+fn pad_plaintext(vec: &[u8]) -> SecretBytes {
     // Create a new Vec<u8> of length 255, initialized with 0u8
-    let mut result = vec![0u8; 255];
+    let mut result = vec![0u8; PLAINTEXT_BLOCK_LEN];
 
-    // Copy the contents of the original Vec<u8> into the new vector
-    let len = vec.len().min(255); // Ensure we don't exceed the length of 255
-    result[..len].copy_from_slice(&vec[..len]);
+    // Copy the contents of the original Vec<u8> into the new vector after its length
+    let len = vec.len().min(MAX_PLAINTEXT_LEN);
+    result[0] = len as u8;
+    result[1..(len + 1)].copy_from_slice(&vec[..len]);
 
-    result
+    SecretBytes::new(result)
 }
 
-fn combine(otp: Vec<u8>, data: Vec<u8>) -> Vec<u8> {
-    let mut combined = Vec::new();
+fn combine(otp: &[u8], data: &[u8]) -> SecretBytes {
+    let mut combined = Vec::with_capacity(data.len() * 2);
 
     for i in 0..data.len() {
         combined.push(data[i]);
         combined.push(otp[i % otp.len()]);
     }
 
-    combined
+    SecretBytes::new(combined)
 }
 
-fn split(combined: Vec<u8>) -> (Vec<u8>, Vec<u8>) {
+fn split(combined: &[u8]) -> (Vec<u8>, SecretBytes) {
     let mut otp = Vec::new();
-    let mut data = Vec::new();
+    let mut data = Vec::with_capacity(combined.len() / 2 + 1);
 
     for (i, &value) in combined.iter().enumerate() {
         // WARNING: is_multiple_of() is unstable feature!
@@ -86,7 +92,7 @@ fn split(combined: Vec<u8>) -> (Vec<u8>, Vec<u8>) {
         }
     }
 
-    (otp, data)
+    (otp, SecretBytes::new(data))
 }
 
 const NONCE_LEN: usize = 12;
@@ -110,14 +116,14 @@ impl SessionPrelude {
         self.one_time_token.clone()
     }
 
-    pub fn encrypt(&self, plaintext: String) -> Result<Vec<u8>, SessionPreludeError> {
+    pub fn encrypt(&self, plaintext: &SecretBytes) -> Result<Vec<u8>, SessionPreludeError> {
         let key = Aes256Gcm::generate_key(&mut OsRng);
         let serialized_key = <[u8; 32]>::try_from(key.as_ref()).unwrap();
         let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
 
         let cipher = Aes256Gcm::new(&key);
 
-        if plaintext.len() > 255 {
+        if plaintext.len() > MAX_PLAINTEXT_LEN {
             return Err(SessionPreludeError::PlaintextTooLong);
         }
 
@@ -129,16 +135,13 @@ impl SessionPrelude {
             return Err(SessionPreludeError::PubKeyImportError);
         };
 
-        let plain_vec = string_to_vec_u8(plaintext);
-        if plain_vec.len() != 255 {
+        let plain_vec = pad_plaintext(plaintext);
+        if plain_vec.len() != PLAINTEXT_BLOCK_LEN {
             return Err(SessionPreludeError::InternalError);
         }
 
         let encrypted_message = cipher
-            .encrypt(
-                &nonce,
-                combine(self.one_time_token.clone(), plain_vec).as_slice(),
-            )
+            .encrypt(&nonce, combine(&self.one_time_token, &plain_vec).expose())
             .unwrap();
 
         let mut rng = rand::thread_rng();
@@ -172,7 +175,7 @@ impl SessionPrelude {
     pub fn decrypt(
         priv_key: Arc<RsaPrivateKey>,
         ciphertext: Vec<u8>,
-    ) -> Result<(Vec<u8>, Vec<u8>), SessionPreludeError> {
+    ) -> Result<(Vec<u8>, SecretBytes), SessionPreludeError> {
         const HEADER_SIZE: usize = ENCRYPTED_KEY_LEN;
 
         let ciphertext_len = ciphertext.len();
@@ -218,25 +221,25 @@ impl SessionPrelude {
 
         let plaintext_mixed = cipher
             .decrypt(&nonce, encrypted_message)
+            .map(SecretBytes::new)
             .map_err(|_| SessionPreludeError::AESError)?;
 
         if plaintext_mixed.len() != 510 {
             return Err(SessionPreludeError::InvalidCiphertext);
         }
 
-        let (otp, plaintext_long) = split(plaintext_mixed);
+        let (otp, plaintext_long) = split(&plaintext_mixed);
 
         if otp.len() != 255 {
             return Err(SessionPreludeError::InvalidCiphertext);
         }
 
-        let plaintext: Vec<u8> = plaintext_long
-            .iter()
-            .filter_map(|ch| match ch {
-                0u8 => None,
-                ch => Some(*ch),
-            })
-            .collect();
+        let len = plaintext_long[0] as usize;
+        if len > MAX_PLAINTEXT_LEN {
+            return Err(SessionPreludeError::InvalidCiphertext);
+        }
+
+        let plaintext = SecretBytes::from(&plaintext_long[1..(len + 1)]);
 
         Ok((otp, plaintext))
     }
@@ -0,0 +1,141 @@
+/*
+    pam_polyauth: A pam module written in rust that supports multiple
+    authentication modes (including autologin).
+
+    Copyright (C) 2024-2025  Denis Benato
+
+    This program is free software; you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation; either version 2 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License along
+    with this program; if not, write to the Free Software Foundation, Inc.,
+    51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+*/
+
+use std::path::{Path, PathBuf};
+
+use tokio::task::JoinHandle;
+use zbus::{connection, object_server::Interface, Connection};
+
+use crate::pam::{
+    mount::MountAuthDBus,
+    session::Sessions,
+    socket::{
+        bind_sessions_socket, remove_sessions_socket, serve_sessions_socket, SESSIONS_OBJECT_PATH,
+    },
+    ServiceError,
+};
+
+/// Object path the mount authorization object is served at on the bus
+pub const MOUNT_AUTH_OBJECT_PATH: &str = "/org/neroreflex/polyauth_mount";
+
+const MOUNT_AUTH_BUS_NAME: &str = "org.neroreflex.polyauth_mount";
+const SESSIONS_BUS_NAME: &str = "org.neroreflex.polyauth_session";
+
+/// Everything pam_polyauth-service is serving: the direct socket is always there,
+/// each bus connection only when a dbus-daemon could be reached.
+pub struct RunningService {
+    socket_path: PathBuf,
+    socket_task: JoinHandle<()>,
+    mount_auth_conn: Option<Connection>,
+    sessions_conn: Option<Connection>,
+}
+
+impl RunningService {
+    /// Whether the sessions object is also reachable on the bus
+    pub fn sessions_on_bus(&self) -> bool {
+        self.sessions_conn.is_some()
+    }
+
+    /// Whether mount authorizations can be requested on the bus
+    pub fn mount_auth_on_bus(&self) -> bool {
+        self.mount_auth_conn.is_some()
+    }
+
+    /// Stops serving the socket, removes it and leaves the bus
+    pub fn stop(self) {
+        self.socket_task.abort();
+        remove_sessions_socket(self.socket_path);
+
+        drop(self.sessions_conn);
+        drop(self.mount_auth_conn);
+    }
+}
+
+async fn serve_on_bus<I: Interface>(
+    builder: zbus::Result<connection::Builder<'static>>,
+    name: &'static str,
+    path: &'static str,
+    object: I,
+) -> Option<Connection> {
+    let connection = async { builder?.name(name)?.serve_at(path, object)?.build().await };
+
+    match connection.await {
+        Ok(connection) => Some(connection),
+        Err(err) => {
+            eprintln!("⚠️ Cannot serve {name} on the bus: {err}");
+            None
+        }
+    }
+}
+
+/// Starts serving sessions on the direct socket first, so that PAM modules can reach the
+/// service even where there is no dbus-daemon, then on the bus built by connect_bus
+/// (i.e. connection::Builder::system): a bus that cannot be reached is logged and skipped.
+pub async fn start_service<F>(
+    socket_path: &Path,
+    sessions: Sessions,
+    mount_auth: MountAuthDBus,
+    connect_bus: F,
+) -> Result<RunningService, ServiceError>
+where
+    F: Fn() -> zbus::Result<connection::Builder<'static>>,
+{
+    println!(
+        "🔌 Listening for direct connections on {}",
+        socket_path.display()
+    );
+
+    let listener = bind_sessions_socket(socket_path)?;
+    let socket_task = tokio::spawn(serve_sessions_socket(listener, sessions.clone()));
+
+    println!("🔧 Building the dbus objects...");
+
+    let mount_auth_conn = serve_on_bus(
+        connect_bus(),
+        MOUNT_AUTH_BUS_NAME,
+        MOUNT_AUTH_OBJECT_PATH,
+        mount_auth,
+    )
+    .await;
+
+    if mount_auth_conn.is_none() {
+        eprintln!("⚠️ Mount authorizations cannot be requested until the service is restarted with a bus available");
+    }
+
+    let sessions_conn = serve_on_bus(
+        connect_bus(),
+        SESSIONS_BUS_NAME,
+        SESSIONS_OBJECT_PATH,
+        sessions,
+    )
+    .await;
+
+    if sessions_conn.is_none() {
+        eprintln!("⚠️ Sessions are only served on {}", socket_path.display());
+    }
+
+    Ok(RunningService {
+        socket_path: socket_path.to_path_buf(),
+        socket_task,
+        mount_auth_conn,
+        sessions_conn,
+    })
+}
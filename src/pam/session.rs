@@ -19,23 +19,33 @@
     51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
 */
 
+use serde::{Deserialize, Serialize};
 use tokio::{
     sync::{Mutex, RwLock},
     task::spawn,
 };
-use zbus::interface;
+use zbus::{
+    fdo::DBusProxy, interface, message::Header, names::BusName, object_server::SignalEmitter,
+    zvariant::Type, Connection,
+};
 
 use sys_mount::{Mount, UnmountDrop};
 
-use crate::storage::{load_user_auth_data, load_user_mountpoints, StorageSource};
+use crate::command::{SessionCommand, SessionType};
+use crate::desktop::SessionDirs;
+use crate::storage::{
+    integrity::ConfigIntegrity, load_user_auth_data, load_verified_user_mountpoints,
+    load_verified_user_session_command, store::UserStore,
+};
 
 use users::{get_user_by_name, gid_t, os::unix::UserExt, uid_t};
 
 use std::{
-    collections::{hash_map::DefaultHasher, HashMap},
+    collections::{hash_map::DefaultHasher, BTreeMap, HashMap},
     ffi::OsString,
     ops::DerefMut,
     sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
 };
 use std::{
     hash::{Hash, Hasher},
@@ -46,8 +56,9 @@ use crate::pam::{
     disk::read_file_or_create_default,
     mount::{mount_all, MountAuthOperations},
     result::*,
+    runtime_dir::RuntimeDirConfig,
     security::*,
-    ServiceError,
+    ServiceError, DEFAULT_GREETER_USER,
 };
 
 use rsa::{
@@ -58,6 +69,135 @@ use rsa::{
 struct UserSession {
     _mounts: Vec<UnmountDrop<Mount>>,
     count: usize,
+    uid: uid_t,
+    opened_at: u64,
+    mounted_paths: Vec<String>,
+}
+
+/// Read-only view of an active user session as exposed over dbus
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, Type)]
+pub struct SessionInfo {
+    /// name of the user owning the session
+    pub username: String,
+
+    /// uid of the user owning the session
+    pub uid: u32,
+
+    /// number of sessions currently opened for the user
+    pub count: u32,
+
+    /// seconds since the UNIX epoch at which the first session was opened
+    pub opened_at: u64,
+
+    /// every path that has been mounted for the user (runtime directory included, unless managed by logind)
+    pub mounted_paths: Vec<String>,
+}
+
+/// Session command of a user as exposed over dbus
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, Type)]
+pub struct SessionCommandInfo {
+    /// executable to launch
+    pub command: String,
+
+    /// arguments passed to the command
+    pub args: Vec<String>,
+
+    /// variables added to the environment of the session
+    pub env: BTreeMap<String, String>,
+
+    /// one of wayland, x11 or tty: empty if the greeter has to decide
+    pub session_type: String,
+
+    /// desktop names for XDG_CURRENT_DESKTOP, most specific first
+    pub desktop_names: Vec<String>,
+
+    /// ID of the desktop session the command was resolved from: empty if none
+    pub desktop: String,
+}
+
+impl From<&SessionCommand> for SessionCommandInfo {
+    fn from(command: &SessionCommand) -> Self {
+        Self {
+            command: command.command(),
+            args: command.args().clone(),
+            env: command.env().clone(),
+            session_type: match command.session_type() {
+                SessionType::Unspecified => String::new(),
+                session_type => session_type.to_string(),
+            },
+            desktop_names: command.desktop_names().clone(),
+            desktop: command.desktop().map(String::from).unwrap_or_default(),
+        }
+    }
+}
+
+impl From<SessionCommandInfo> for SessionCommand {
+    fn from(info: SessionCommandInfo) -> Self {
+        SessionCommand::new(info.command)
+            .with_args(info.args)
+            .with_env(info.env)
+            .with_session_type(info.session_type.parse().unwrap_or_default())
+            .with_desktop_names(info.desktop_names)
+            .with_desktop(Some(info.desktop).filter(|desktop| !desktop.is_empty()))
+    }
+}
+
+/// A user that can log in through polyauth as exposed over dbus
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, Type)]
+pub struct UserInfo {
+    /// name of the user
+    pub username: String,
+
+    /// uid of the user
+    pub uid: u32,
+
+    /// true if the user is authenticated without being asked for a password
+    pub autologin: bool,
+}
+
+// getpwent() keeps its position in global state: concurrent enumerations would interfere
+static PASSWD_ENUMERATION: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
+/// Filters the given (username, uid) pairs down to the users that have a polyauth
+/// configuration with a main password, sorted by username: root is never included.
+pub fn polyauth_users(
+    store: &dyn UserStore,
+    candidates: impl IntoIterator<Item = (String, uid_t)>,
+) -> Vec<UserInfo> {
+    let mut users = candidates
+        .into_iter()
+        .filter(|(username, uid)| *uid != 0 && username != "root")
+        .filter_map(
+            |(username, uid)| match load_user_auth_data(store, &username) {
+                Ok(Some(auth_data)) if auth_data.has_main() => Some(UserInfo {
+                    autologin: auth_data.autologin(),
+                    username,
+                    uid,
+                }),
+                Ok(_) => None,
+                Err(err) => {
+                    eprintln!("⚠️ Error loading user auth data for '{username}': {err}");
+                    None
+                }
+            },
+        )
+        .collect::<Vec<_>>();
+
+    users.sort_by(|a, b| a.username.cmp(&b.username));
+
+    users
+}
+
+impl UserSession {
+    fn info(&self, username: &str) -> SessionInfo {
+        SessionInfo {
+            username: String::from(username),
+            uid: self.uid,
+            count: self.count as u32,
+            opened_at: self.opened_at,
+            mounted_paths: self.mounted_paths.clone(),
+        }
+    }
 }
 
 enum RsaPrivateKeyFetchOpStatus {
@@ -65,48 +205,137 @@ enum RsaPrivateKeyFetchOpStatus {
     InProgress(tokio::task::JoinHandle<Result<RsaPrivateKey, ServiceError>>),
 }
 
-pub struct Sessions {
-    mounts_auth: Arc<RwLock<MountAuthOperations>>,
-    priv_key: Mutex<RsaPrivateKeyFetchOpStatus>,
+#[derive(Default)]
+struct SessionsState {
     one_time_tokens: HashMap<u64, Vec<u8>>,
     sessions: HashMap<OsString, UserSession>,
 }
 
+/// The sessions dbus object: cloning it shares the same state, so that it can
+/// be served both on the system bus and on every direct socket connection.
+#[derive(Clone)]
+pub struct Sessions {
+    mounts_auth: Arc<RwLock<MountAuthOperations>>,
+    store: Arc<dyn UserStore>,
+    session_dirs: SessionDirs,
+    runtime_dir: RuntimeDirConfig,
+    greeter_user: String,
+    priv_key: Arc<Mutex<RsaPrivateKeyFetchOpStatus>>,
+    state: Arc<Mutex<SessionsState>>,
+}
+
 impl Sessions {
     pub fn new(
         private_key_file_path: PathBuf,
         mounts_auth: Arc<RwLock<MountAuthOperations>>,
+        store: Arc<dyn UserStore>,
     ) -> Self {
         let file_path = private_key_file_path;
 
         let filepath = file_path.clone();
 
-        let priv_key = Mutex::new(RsaPrivateKeyFetchOpStatus::InProgress(spawn(async {
-            let default_key_gen_fn = || {
-                let mut rng = rand::thread_rng();
-                let priv_key =
-                    rsa::RsaPrivateKey::new(&mut rng, 4096).expect("failed to generate a key");
+        let priv_key = Arc::new(Mutex::new(RsaPrivateKeyFetchOpStatus::InProgress(spawn(
+            async {
+                let default_key_gen_fn = || {
+                    let mut rng = rand::thread_rng();
+                    let priv_key =
+                        rsa::RsaPrivateKey::new(&mut rng, 4096).expect("failed to generate a key");
 
-                Ok(priv_key.to_pkcs1_pem(LineEnding::CRLF).unwrap().to_string())
-            };
+                    Ok(priv_key.to_pkcs1_pem(LineEnding::CRLF).unwrap().to_string())
+                };
 
-            let key_as_str = read_file_or_create_default(filepath, default_key_gen_fn).await?;
+                let key_as_str = read_file_or_create_default(filepath, default_key_gen_fn).await?;
 
-            RsaPrivateKey::from_pkcs1_pem(key_as_str.as_str()).map_err(ServiceError::PKCS1Error)
-        })));
+                RsaPrivateKey::from_pkcs1_pem(key_as_str.as_str()).map_err(ServiceError::PKCS1Error)
+            },
+        ))));
 
-        let one_time_tokens = HashMap::new();
-        let sessions = HashMap::new();
+        let state = Arc::new(Mutex::new(SessionsState::default()));
 
         Self {
             mounts_auth,
+            store,
+            session_dirs: SessionDirs::default(),
+            runtime_dir: RuntimeDirConfig::default(),
+            greeter_user: String::from(DEFAULT_GREETER_USER),
             priv_key,
-            one_time_tokens,
-            sessions,
+            state,
+        }
+    }
+
+    /// Where and how the runtime directory of users is mounted when their session opens
+    pub fn with_runtime_dir(mut self, runtime_dir: RuntimeDirConfig) -> Self {
+        self.runtime_dir = runtime_dir;
+        self
+    }
+
+    /// User the greeter runs as: besides root it is the only one reading session commands
+    pub fn with_greeter_user(mut self, greeter_user: String) -> Self {
+        self.greeter_user = greeter_user;
+        self
+    }
+
+    /// Where desktop sessions referred to by session commands are looked up
+    pub fn with_session_dirs(mut self, session_dirs: SessionDirs) -> Self {
+        self.session_dirs = session_dirs;
+        self
+    }
+
+    async fn notify_mount_failure(
+        emitter: &SignalEmitter<'_>,
+        username: &str,
+        reason: &ServiceOperationError,
+    ) {
+        if let Err(err) = Self::mount_failed(emitter, username, reason.message()).await {
+            eprintln!("⚠️ Error emitting the MountFailed signal: {err}");
         }
     }
 
-    async fn fetch_priv_key(&mut self) -> Result<Arc<RsaPrivateKey>, ServiceError> {
+    /// Checks that the sender of a message is root or the greeter: on the bus the uid is
+    /// asked to the bus daemon, on a direct connection it is the one of the socket peer.
+    async fn check_greeter_caller(
+        &self,
+        connection: &Connection,
+        header: &Header<'_>,
+    ) -> Result<(), ServiceOperationError> {
+        let caller = match header.sender() {
+            Some(sender) => match DBusProxy::new(connection).await {
+                Ok(proxy) => proxy
+                    .get_connection_unix_user(BusName::from(sender.to_owned()))
+                    .await
+                    .ok(),
+                Err(_) => None,
+            },
+            None => connection
+                .peer_credentials()
+                .await
+                .ok()
+                .and_then(|credentials| credentials.unix_user_id()),
+        };
+
+        let Some(caller) = caller else {
+            return Err(ServiceOperationError::new(
+                ServiceOperationResult::CannotIdentifyUser,
+                "cannot identify the sender of the request",
+            ));
+        };
+
+        let greeter = get_user_by_name(&self.greeter_user).map(|user| user.uid());
+        if caller == 0 || Some(caller) == greeter {
+            return Ok(());
+        }
+
+        Err(ServiceOperationError::new(
+            ServiceOperationResult::AccessDenied,
+            format!(
+                "only root and the greeter user '{}' are allowed to do this",
+                self.greeter_user
+            ),
+        )
+        .with_detail("uid", caller))
+    }
+
+    async fn fetch_priv_key(&self) -> Result<Arc<RsaPrivateKey>, ServiceError> {
         let mut lck = self.priv_key.lock().await;
         match lck.deref_mut() {
             RsaPrivateKeyFetchOpStatus::Ready(rsa_private_key) => Ok(rsa_private_key.clone()),
@@ -133,14 +362,17 @@ impl Sessions {
     )
 )]
 impl Sessions {
-    async fn initiate_session(&mut self) -> String {
+    async fn initiate_session(&self) -> Result<String, ServiceOperationError> {
         println!("🔓 Requested initialization of a new session");
 
         let priv_key = match self.fetch_priv_key().await {
             Ok(priv_key) => priv_key,
             Err(err) => {
                 println!("❌ Error fetching the private RSA key: {err}");
-                return String::new();
+                return Err(ServiceOperationError::new(
+                    ServiceOperationResult::PubKeyError,
+                    format!("cannot fetch the private RSA key: {err}"),
+                ));
             }
         };
 
@@ -149,7 +381,10 @@ impl Sessions {
                 Ok(key) => key,
                 Err(err) => {
                     println!("❌ Error serializing the RSA key: {err}");
-                    return String::new();
+                    return Err(ServiceOperationError::new(
+                        ServiceOperationResult::SerializationError,
+                        format!("cannot serialize the RSA public key: {err}"),
+                    ));
                 }
             };
 
@@ -165,31 +400,39 @@ impl Sessions {
             Ok(serialized) => serialized,
             Err(err) => {
                 println!("❌ Error serializing the session one time token: {err}");
-                return String::new();
+                return Err(ServiceOperationError::new(
+                    ServiceOperationResult::SerializationError,
+                    format!("cannot serialize the session one time token: {err}"),
+                ));
             }
         };
 
-        self.one_time_tokens.insert(key, otp);
+        self.state.lock().await.one_time_tokens.insert(key, otp);
 
         println!("✅ Created one time token {key}");
 
-        serialized
+        Ok(serialized)
     }
 
     async fn open_user_session(
-        &mut self,
+        &self,
+        #[zbus(signal_emitter)] emitter: SignalEmitter<'_>,
         username: &str,
         password: Vec<u8>,
-    ) -> (u32, uid_t, gid_t) {
+    ) -> Result<(uid_t, gid_t), ServiceOperationError> {
         println!("👤 Requested session for user '{username}' to be opened");
 
-        let source = StorageSource::Username(String::from(username));
-
         let Some(user) = get_user_by_name(username) else {
-            return (ServiceOperationResult::CannotIdentifyUser.into(), 0, 0);
+            return Err(ServiceOperationError::new(
+                ServiceOperationResult::CannotIdentifyUser,
+                format!("user '{username}' does not exist"),
+            )
+            .with_detail("user", username));
         };
 
-        match self.sessions.get_mut(&user.name().to_os_string()) {
+        let mut state = self.state.lock().await;
+
+        match state.sessions.get_mut(&user.name().to_os_string()) {
             Some(session) => {
                 session.count += 1;
 
@@ -200,7 +443,10 @@ impl Sessions {
                     Ok(priv_key) => priv_key,
                     Err(err) => {
                         println!("❌ Error fetching the private RSA key: {err}");
-                        return (ServiceOperationResult::PubKeyError.into(), 0, 0);
+                        return Err(ServiceOperationError::new(
+                            ServiceOperationResult::PubKeyError,
+                            format!("cannot fetch the private RSA key: {err}"),
+                        ));
                     }
                 };
 
@@ -208,43 +454,88 @@ impl Sessions {
                     Ok(result) => result,
                     Err(err) => {
                         eprintln!("❌ Error in decrypting data: {err}");
-                        return (ServiceOperationResult::DataDecryptionFailed.into(), 0, 0);
+                        return Err(ServiceOperationError::new(
+                            ServiceOperationResult::DataDecryptionFailed,
+                            format!("cannot decrypt the provided credentials: {err}"),
+                        )
+                        .with_detail("reason", err));
                     }
                 };
 
                 // check the OTP to be available to defeat replay attacks
                 let mut hasher = DefaultHasher::new();
                 otp.hash(&mut hasher);
-                match self.one_time_tokens.remove(&hasher.finish()) {
+                match state.one_time_tokens.remove(&hasher.finish()) {
                     Some(stored) => {
-                        if stored != otp {
+                        if !crate::constant_time_eq(&stored, &otp) {
                             eprintln!("🚫 The provided temporary OTP key couldn't be verified");
-                            return (ServiceOperationResult::EncryptionError.into(), 0, 0);
+                            return Err(ServiceOperationError::new(
+                                ServiceOperationResult::EncryptionError,
+                                "the provided one time token does not match the issued one",
+                            )
+                            .with_detail("check", "mismatch"));
                         }
                     }
                     None => {
                         println!("❌ Error in finding the provided temporary OTP key");
-                        return (ServiceOperationResult::EncryptionError.into(), 0, 0);
+                        return Err(ServiceOperationError::new(
+                            ServiceOperationResult::EncryptionError,
+                            "the provided one time token was never issued or was already used",
+                        )
+                        .with_detail("check", "missing"));
                     }
                 }
 
-                let user_mounts = match load_user_mountpoints(&source) {
-                    Ok(user_cfg) => user_cfg,
+                let signing_key = match self.mounts_auth.read().await.read_signing_key().await {
+                    Ok(key) => key,
+                    Err(err) => {
+                        eprintln!("❌ Error reading the configuration signing key: {err}");
+                        return Err(ServiceOperationError::new(
+                            ServiceOperationResult::IOError,
+                            format!("cannot read the configuration signing key: {err}"),
+                        ));
+                    }
+                };
+
+                let (integrity, user_mounts) = match load_verified_user_mountpoints(
+                    self.store.as_ref(),
+                    username,
+                    &signing_key,
+                ) {
+                    Ok(loaded) => loaded,
                     Err(err) => {
                         eprintln!("❌ Error loading user mount data: {err}");
-                        return (
-                            ServiceOperationResult::CannotLoadUserMountError.into(),
-                            0,
-                            0,
-                        );
+                        return Err(ServiceOperationError::new(
+                            ServiceOperationResult::CannotLoadUserMountError,
+                            format!("cannot load mounts of user '{username}': {err}"),
+                        )
+                        .with_detail("user", username));
                     }
                 };
 
+                // A configuration signed by root and changed afterwards is refused as a whole
+                if integrity == ConfigIntegrity::Tampered {
+                    eprintln!(
+                        "🚫 The configuration of user {username} changed after being signed."
+                    );
+                    let err = ServiceOperationError::new(
+                        ServiceOperationResult::TamperedConfig,
+                        format!(
+                            "the configuration of user '{username}' changed after being signed"
+                        ),
+                    )
+                    .with_detail("user", username);
+                    Self::notify_mount_failure(&emitter, username, &err).await;
+                    return Err(err);
+                }
+
                 // Check for the mount to be approved by root
                 // otherwise the user might mount everything he wants to
                 // with every dmask, potentially compromising the
                 // security and integrity of the whole system.
-                if let Some(mounts) = user_mounts.clone() {
+                // A valid signature already covers the mounts.
+                if let (ConfigIntegrity::Unsigned, Some(mounts)) = (integrity, user_mounts.clone())
+                {
                     let hash_to_check = mounts.hash();
                     match self.mounts_auth.read().await.read_auth_file().await {
                         Ok(mounts_auth) => {
@@ -252,118 +543,353 @@ impl Sessions {
                                 eprintln!(
                                     "🚫 User {username} attempted an unauthorized mount {hash_to_check}."
                                 );
-                                return (ServiceOperationResult::UnauthorizedMount.into(), 0, 0);
+                                let err = ServiceOperationError::new(
+                                    ServiceOperationResult::UnauthorizedMount,
+                                    format!("mounts of user '{username}' have not been authorized"),
+                                )
+                                .with_detail("user", username)
+                                .with_detail("hash", hash_to_check);
+                                Self::notify_mount_failure(&emitter, username, &err).await;
+                                return Err(err);
                             }
                         }
                         Err(err) => {
                             eprintln!("❌ Error reading mount authorizations file: {err}");
-                            return (ServiceOperationResult::UnauthorizedMount.into(), 0, 0);
+                            let err = ServiceOperationError::new(
+                                ServiceOperationResult::UnauthorizedMount,
+                                format!("cannot read mount authorizations: {err}"),
+                            )
+                            .with_detail("user", username);
+                            Self::notify_mount_failure(&emitter, username, &err).await;
+                            return Err(err);
                         }
                     };
                 };
 
-                let mounted_devices = mount_all(
+                let mounted_devices = match mount_all(
                     user_mounts,
                     password,
                     user.uid(),
                     user.primary_group_id(),
                     user.name().to_string_lossy().to_string(),
                     user.home_dir().as_os_str().to_string_lossy().to_string(),
-                );
+                    &self.runtime_dir,
+                ) {
+                    Ok(mounted_devices) => mounted_devices,
+                    Err(err) => {
+                        eprintln!("❌ Error mounting one or more devices for user {username}");
+                        let err = err.with_detail("user", username);
+                        Self::notify_mount_failure(&emitter, username, &err).await;
+                        return Err(err);
+                    }
+                };
 
-                if mounted_devices.is_empty() {
-                    eprintln!("❌ Error mounting one or more devices for user {username}");
-                    return (ServiceOperationResult::MountError.into(), 0, 0);
-                }
+                let mounted_paths = mounted_devices
+                    .iter()
+                    .map(|m| m.target_path().to_string_lossy().to_string())
+                    .collect();
 
                 let user_session = UserSession {
                     _mounts: mounted_devices,
                     count: 1,
+                    uid: user.uid(),
+                    opened_at: SystemTime::now()
+                        .duration_since(UNIX_EPOCH)
+                        .map(|from_epoch| from_epoch.as_secs())
+                        .unwrap_or(0u64),
+                    mounted_paths,
                 };
 
-                self.sessions
+                state
+                    .sessions
                     .insert(user.name().to_os_string(), user_session);
 
                 println!("✅ Successfully opened session for user {username}");
+
+                if let Err(err) = Self::session_opened(&emitter, username, user.uid()).await {
+                    eprintln!("⚠️ Error emitting the SessionOpened signal: {err}");
+                }
             }
         }
 
-        (
-            ServiceOperationResult::Ok.into(),
-            user.uid(),
-            user.primary_group_id(),
-        )
+        Ok((user.uid(), user.primary_group_id()))
     }
 
-    async fn close_user_session(&mut self, user: &str) -> u32 {
+    async fn close_user_session(
+        &self,
+        #[zbus(signal_emitter)] emitter: SignalEmitter<'_>,
+        user: &str,
+    ) -> Result<(), ServiceOperationError> {
         println!("👤 Requested session for user '{user}' to be closed");
 
         let Some(user) = get_user_by_name(user) else {
-            return ServiceOperationResult::CannotIdentifyUser.into();
+            return Err(ServiceOperationError::new(
+                ServiceOperationResult::CannotIdentifyUser,
+                format!("user '{user}' does not exist"),
+            )
+            .with_detail("user", user));
         };
 
         let username = user.name().to_string_lossy();
 
-        match self.sessions.get_mut(user.name()) {
+        let mut state = self.state.lock().await;
+
+        match state.sessions.get_mut(user.name()) {
             Some(session) => {
                 session.count -= 1;
                 if session.count == 0 {
                     // due to how directories are mounted discarding the session also umounts all mount points:
                     // either remove the user session from the collection and destroy the session or
                     // report to the caller that the requested session is already closed
-                    match self.sessions.remove(user.name()) {
+                    match state.sessions.remove(user.name()) {
                         Some(user_session) => drop(user_session),
-                        None => return ServiceOperationResult::SessionAlreadyClosed.into(),
+                        None => {
+                            return Err(ServiceOperationError::new(
+                                ServiceOperationResult::SessionAlreadyClosed,
+                                format!("session of user '{username}' is already closed"),
+                            )
+                            .with_detail("user", &username))
+                        }
                     };
+
+                    if let Err(err) =
+                        Self::session_closed(&emitter, username.as_ref(), user.uid()).await
+                    {
+                        eprintln!("⚠️ Error emitting the SessionClosed signal: {err}");
+                    }
                 }
 
                 println!("✅ Successfully closed session for user '{username}'");
 
-                ServiceOperationResult::Ok.into()
+                Ok(())
             }
             None => {
                 eprintln!("❌ Error closing session for user {username}: already closed");
 
-                ServiceOperationResult::SessionAlreadyClosed.into()
+                Err(ServiceOperationError::new(
+                    ServiceOperationResult::SessionAlreadyClosed,
+                    format!("session of user '{username}' is already closed"),
+                )
+                .with_detail("user", &username))
             }
         }
     }
 
-    async fn is_user_polyauth_enabled(&self, username: &str) -> u32 {
+    async fn is_user_polyauth_enabled(&self, username: &str) -> Result<(), ServiceOperationError> {
         println!("🔍 Checking if user '{username}' is polyauth-enabled");
 
         // Check for empty username or root
         if username.is_empty() {
             eprintln!("❌ Empty username provided");
-            return ServiceOperationResult::CannotIdentifyUser.into();
+            return Err(ServiceOperationError::new(
+                ServiceOperationResult::CannotIdentifyUser,
+                "empty username provided",
+            ));
         }
 
         if username == "root" {
             eprintln!("❌ Root user is not allowed for polyauth");
-            return ServiceOperationResult::CannotIdentifyUser.into();
+            return Err(ServiceOperationError::new(
+                ServiceOperationResult::CannotIdentifyUser,
+                "the root user is not allowed for polyauth",
+            )
+            .with_detail("user", username));
         }
 
         // Load polyauth data and check if the user has it configured
-        match load_user_auth_data(&StorageSource::Username(username.to_string())) {
+        match load_user_auth_data(self.store.as_ref(), username) {
             Ok(load_res) => match load_res {
                 Some(auth_data) => {
                     if auth_data.has_main() {
                         println!("✅ User '{username}' is polyauth-enabled");
-                        ServiceOperationResult::Ok.into()
+                        Ok(())
                     } else {
                         eprintln!("❌ User '{username}' has no main password configured");
-                        ServiceOperationResult::CannotIdentifyUser.into()
+                        Err(ServiceOperationError::new(
+                            ServiceOperationResult::CannotIdentifyUser,
+                            format!("user '{username}' has no main password configured"),
+                        )
+                        .with_detail("user", username))
                     }
                 }
                 None => {
                     eprintln!("❌ User '{username}' has no polyauth configuration");
-                    ServiceOperationResult::CannotIdentifyUser.into()
+                    Err(ServiceOperationError::new(
+                        ServiceOperationResult::CannotIdentifyUser,
+                        format!("user '{username}' has no polyauth configuration"),
+                    )
+                    .with_detail("user", username))
                 }
             },
             Err(err) => {
                 eprintln!("❌ Error loading user auth data for '{username}': {err}");
-                ServiceOperationResult::CannotIdentifyUser.into()
+                Err(ServiceOperationError::new(
+                    ServiceOperationResult::CannotIdentifyUser,
+                    format!("cannot load polyauth configuration of user '{username}': {err}"),
+                )
+                .with_detail("user", username))
+            }
+        }
+    }
+
+    async fn list_users(&self) -> Vec<UserInfo> {
+        println!("📋 Requested the list of polyauth-enabled users");
+
+        // reading the passwd database and every configuration blocks: keep it off the executor
+        let store = self.store.clone();
+        let listed = tokio::task::spawn_blocking(move || {
+            let candidates = {
+                let _guard = PASSWD_ENUMERATION
+                    .lock()
+                    .unwrap_or_else(|err| err.into_inner());
+
+                // SAFETY: the passwd database is enumerated only while holding PASSWD_ENUMERATION
+                unsafe { users::all_users() }
+                    .filter(|user| user.uid() >= 1000 && user.uid() != uid_t::MAX)
+                    .map(|user| (user.name().to_string_lossy().to_string(), user.uid()))
+                    .collect::<Vec<_>>()
+            };
+
+            polyauth_users(store.as_ref(), candidates)
+        })
+        .await;
+
+        match listed {
+            Ok(users) => users,
+            Err(err) => {
+                eprintln!("❌ Error listing the polyauth-enabled users: {err}");
+                vec![]
             }
         }
     }
+
+    async fn list_sessions(&self) -> Vec<SessionInfo> {
+        println!("📋 Requested the list of active sessions");
+
+        self.state
+            .lock()
+            .await
+            .sessions
+            .iter()
+            .map(|(username, session)| session.info(username.to_string_lossy().as_ref()))
+            .collect()
+    }
+
+    async fn get_session(&self, username: &str) -> Result<SessionInfo, ServiceOperationError> {
+        println!("🔍 Requested session details for user '{username}'");
+
+        match self
+            .state
+            .lock()
+            .await
+            .sessions
+            .get(&OsString::from(username))
+        {
+            Some(session) => Ok(session.info(username)),
+            None => Err(ServiceOperationError::new(
+                ServiceOperationResult::NoSuchSession,
+                format!("user '{username}' has no active session"),
+            )
+            .with_detail("user", username)),
+        }
+    }
+
+    /// Session command configured by the user, for a greeter to launch.
+    ///
+    /// A signed configuration that changed after being signed is refused as on login.
+    async fn get_session_command(
+        &self,
+        #[zbus(connection)] connection: &Connection,
+        #[zbus(header)] header: Header<'_>,
+        username: &str,
+    ) -> Result<SessionCommandInfo, ServiceOperationError> {
+        println!("🖥️  Requested the session command of user '{username}'");
+
+        if let Err(err) = self.check_greeter_caller(connection, &header).await {
+            eprintln!(
+                "🚫 Refused the session command of user {username}: {}",
+                err.message()
+            );
+            return Err(err.with_detail("user", username));
+        }
+
+        let signing_key = match self.mounts_auth.read().await.read_signing_key().await {
+            Ok(key) => key,
+            Err(err) => {
+                eprintln!("❌ Error reading the configuration signing key: {err}");
+                return Err(ServiceOperationError::new(
+                    ServiceOperationResult::IOError,
+                    format!("cannot read the configuration signing key: {err}"),
+                ));
+            }
+        };
+
+        let (integrity, command) =
+            match load_verified_user_session_command(self.store.as_ref(), username, &signing_key) {
+                Ok(loaded) => loaded,
+                Err(err) => {
+                    eprintln!("❌ Error loading the session command of user {username}: {err}");
+                    return Err(ServiceOperationError::new(
+                        ServiceOperationResult::IOError,
+                        format!("cannot load the session command of user '{username}': {err}"),
+                    )
+                    .with_detail("user", username));
+                }
+            };
+
+        if integrity == ConfigIntegrity::Tampered {
+            eprintln!("🚫 The configuration of user {username} changed after being signed.");
+            return Err(ServiceOperationError::new(
+                ServiceOperationResult::TamperedConfig,
+                format!("the configuration of user '{username}' changed after being signed"),
+            )
+            .with_detail("user", username));
+        }
+
+        match command.map(|command| self.session_dirs.resolve(&command)) {
+            Some(Ok(command)) => Ok(SessionCommandInfo::from(&command)),
+            Some(Err(err)) => {
+                eprintln!("❌ Error resolving the desktop session of user {username}: {err}");
+                Err(ServiceOperationError::new(
+                    ServiceOperationResult::NoSessionCommand,
+                    format!("cannot resolve the desktop session of user '{username}': {err}"),
+                )
+                .with_detail("user", username))
+            }
+            None => Err(ServiceOperationError::new(
+                ServiceOperationResult::NoSessionCommand,
+                format!("user '{username}' has no session command configured"),
+            )
+            .with_detail("user", username)),
+        }
+    }
+
+    #[zbus(property)]
+    async fn version(&self) -> String {
+        String::from(crate::LIBRARY_VERSION)
+    }
+
+    /// Emitted when the first session of a user is opened and its mounts are in place
+    #[zbus(signal)]
+    async fn session_opened(
+        emitter: &SignalEmitter<'_>,
+        username: &str,
+        uid: u32,
+    ) -> zbus::Result<()>;
+
+    /// Emitted when the last session of a user is closed and its mounts are released
+    #[zbus(signal)]
+    async fn session_closed(
+        emitter: &SignalEmitter<'_>,
+        username: &str,
+        uid: u32,
+    ) -> zbus::Result<()>;
+
+    /// Emitted when opening a session failed because user mounts could not be performed
+    #[zbus(signal)]
+    async fn mount_failed(
+        emitter: &SignalEmitter<'_>,
+        username: &str,
+        reason: &str,
+    ) -> zbus::Result<()>;
 }
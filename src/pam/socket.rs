@@ -0,0 +1,111 @@
+/*
+    pam_polyauth: A pam module written in rust that supports multiple
+    authentication modes (including autologin).
+
+    Copyright (C) 2024-2025  Denis Benato
+
+    This program is free software; you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation; either version 2 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License along
+    with this program; if not, write to the Free Software Foundation, Inc.,
+    51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+*/
+
+use std::{
+    fs,
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+};
+
+use futures_util::StreamExt;
+use tokio::net::{UnixListener, UnixStream};
+use zbus::{connection, Guid, MessageStream};
+
+use crate::pam::{session::Sessions, ServiceError};
+
+/// Object path the sessions object is served at, both on the bus and on the direct socket
+pub const SESSIONS_OBJECT_PATH: &str = "/org/neroreflex/polyauth_session";
+
+/// Binds the direct socket used by PAM modules running where no dbus-daemon is available.
+///
+/// A stale socket left behind by a previous instance is removed, and the new one
+/// is only accessible by root, as is the sessions interface on the system bus.
+pub fn bind_sessions_socket(socket_path: &Path) -> Result<UnixListener, ServiceError> {
+    if let Some(parent) = socket_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    if socket_path.exists() {
+        fs::remove_file(socket_path)?;
+    }
+
+    let listener = UnixListener::bind(socket_path)?;
+    fs::set_permissions(socket_path, fs::Permissions::from_mode(0o600))?;
+
+    Ok(listener)
+}
+
+/// Serves the sessions object to every peer connecting to the direct socket
+pub async fn serve_sessions_socket(listener: UnixListener, sessions: Sessions) {
+    loop {
+        let stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(err) => {
+                eprintln!("❌ Error accepting a connection on the sessions socket: {err}");
+                continue;
+            }
+        };
+
+        let sessions = sessions.clone();
+        tokio::spawn(async move {
+            if let Err(err) = serve_peer(stream, sessions).await {
+                eprintln!("❌ Error serving a peer on the sessions socket: {err}");
+            }
+        });
+    }
+}
+
+async fn serve_peer(stream: UnixStream, sessions: Sessions) -> Result<(), ServiceError> {
+    let peer_uid = stream.peer_cred()?.uid();
+    if peer_uid != 0 {
+        eprintln!("🚫 Refused a connection on the sessions socket from uid {peer_uid}");
+        return Ok(());
+    }
+
+    let connection = connection::Builder::unix_stream(stream)
+        .server(Guid::generate())?
+        .p2p()
+        .serve_at(SESSIONS_OBJECT_PATH, sessions)?
+        .build()
+        .await?;
+
+    println!("🔌 Accepted a direct connection on the sessions socket");
+
+    // keep the connection alive until the peer hangs up
+    let mut stream = MessageStream::from(&connection);
+    while let Some(msg) = stream.next().await {
+        if msg.is_err() {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+/// Removes the direct socket when the service shuts down
+pub fn remove_sessions_socket(socket_path: PathBuf) {
+    if let Err(err) = fs::remove_file(&socket_path) {
+        eprintln!(
+            "⚠️ Error removing the sessions socket {}: {err}",
+            socket_path.to_string_lossy()
+        );
+    }
+}
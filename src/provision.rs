@@ -0,0 +1,667 @@
+/*
+    pam_polyauth: A pam module written in rust that supports multiple
+    authentication modes (including autologin).
+
+    Copyright (C) 2024-2025  Denis Benato
+
+    This program is free software; you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation; either version 2 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License along
+    with this program; if not, write to the Free Software Foundation, Inc.,
+    51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+*/
+
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{
+    command::SessionCommand,
+    error::UserOperationError,
+    kdf::KdfParams,
+    mount::{MountParams, MountPoints},
+    secret::SecretBytes,
+    storage::{
+        load_user_auth_data, load_user_mountpoints, load_user_session_command, store::UserStore,
+        store_user_auth_data, store_user_mountpoints, store_user_session_command, StorageError,
+    },
+    user::{UserAuthData, UserAuthDataError},
+};
+
+#[derive(Debug, Error)]
+pub enum ProvisionError {
+    #[error("IO Error: {0}")]
+    IoError(#[from] std::io::Error),
+
+    #[error("Invalid manifest: {0}")]
+    InvalidManifest(#[from] serde_yaml::Error),
+
+    #[error("User '{0}' is listed more than once")]
+    DuplicateUser(String),
+
+    #[error("User '{0}': {1}")]
+    InvalidUser(String, String),
+
+    #[error("User '{0}': the intermediate key is required to {1}")]
+    MissingIntermediateKey(String, String),
+
+    #[error("User '{0}': the main password is required to set up authentication")]
+    MissingMainPassword(String),
+
+    #[error("User '{0}': {1}")]
+    Storage(String, StorageError),
+
+    #[error("User '{0}': {1}")]
+    User(String, UserOperationError),
+}
+
+/// Where a secret listed in the manifest is read from: never the manifest itself
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(tag = "source", rename_all = "lowercase", deny_unknown_fields)]
+pub enum SecretSource {
+    /// Asked on the terminal
+    Prompt,
+
+    /// One line of the standard input, in the order secrets appear in the manifest
+    Stdin,
+
+    /// The whole file, without the trailing newline
+    File { path: PathBuf },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SecondaryManifest {
+    pub name: String,
+    pub password: SecretSource,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MountManifest {
+    pub device: String,
+    #[serde(default)]
+    pub fstype: String,
+    #[serde(default)]
+    pub flags: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PreMountManifest {
+    pub dir: String,
+    pub device: String,
+    #[serde(default)]
+    pub fstype: String,
+    #[serde(default)]
+    pub flags: Vec<String>,
+}
+
+/// The mounts of a user, replacing the configured ones as a whole
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MountsManifest {
+    pub home: MountManifest,
+    #[serde(default)]
+    pub pre: Vec<PreMountManifest>,
+}
+
+impl MountsManifest {
+    pub fn mount_points(&self) -> MountPoints {
+        let pre = self
+            .pre
+            .iter()
+            .map(|mount| {
+                (
+                    mount.dir.clone(),
+                    MountParams::new(
+                        mount.device.clone(),
+                        mount.fstype.clone(),
+                        mount.flags.clone(),
+                    ),
+                )
+            })
+            .collect::<HashMap<_, _>>();
+
+        MountPoints::new(
+            MountParams::new(
+                self.home.device.clone(),
+                self.home.fstype.clone(),
+                self.home.flags.clone(),
+            ),
+            pre,
+        )
+    }
+}
+
+fn default_authorize_mounts() -> bool {
+    true
+}
+
+/// What a user has to look like once provisioned: sections that are left out are not touched
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UserManifest {
+    pub username: String,
+    #[serde(default)]
+    pub main_password: Option<SecretSource>,
+    #[serde(default)]
+    pub intermediate_key: Option<SecretSource>,
+    // methods that are not listed are kept
+    #[serde(default)]
+    pub secondary: Vec<SecondaryManifest>,
+    #[serde(default)]
+    pub mounts: Option<MountsManifest>,
+    #[serde(default)]
+    pub session: Option<SessionCommand>,
+    #[serde(default = "default_authorize_mounts")]
+    pub authorize_mounts: bool,
+}
+
+impl UserManifest {
+    fn validate(&self) -> Result<(), ProvisionError> {
+        let invalid = |message: String| ProvisionError::InvalidUser(self.username.clone(), message);
+
+        if self.username.is_empty() {
+            return Err(ProvisionError::InvalidUser(
+                self.username.clone(),
+                String::from("empty username"),
+            ));
+        }
+
+        let mut names = HashSet::new();
+        for secondary in self.secondary.iter() {
+            if secondary.name.is_empty() {
+                return Err(invalid(String::from("secondary method with an empty name")));
+            }
+
+            if !names.insert(secondary.name.as_str()) {
+                return Err(invalid(format!(
+                    "secondary method '{}' is listed more than once",
+                    secondary.name
+                )));
+            }
+        }
+
+        if let Some(mounts) = &self.mounts {
+            let mut dirs = HashSet::new();
+            for mount in mounts.pre.iter() {
+                if !mount.dir.starts_with('/') {
+                    return Err(invalid(format!(
+                        "pre-mount directory '{}' is not an absolute path",
+                        mount.dir
+                    )));
+                }
+
+                if !dirs.insert(mount.dir.as_str()) {
+                    return Err(invalid(format!(
+                        "pre-mount directory '{}' is listed more than once",
+                        mount.dir
+                    )));
+                }
+            }
+        }
+
+        if let Some(session) = &self.session {
+            if session.command().is_empty() == session.desktop().is_none() {
+                return Err(invalid(String::from(
+                    "the session needs exactly one of command and desktop",
+                )));
+            }
+
+            if let Some(key) = session
+                .env()
+                .keys()
+                .find(|key| key.is_empty() || key.contains('='))
+            {
+                return Err(invalid(format!(
+                    "invalid session environment variable '{key}'"
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Secrets in the order they have to be read: main password, intermediate key, secondary methods
+    pub fn secret_sources(&self) -> Vec<(SecretKind, &SecretSource)> {
+        let mut sources = vec![];
+
+        if let Some(source) = &self.main_password {
+            sources.push((SecretKind::MainPassword, source));
+        }
+
+        if let Some(source) = &self.intermediate_key {
+            sources.push((SecretKind::IntermediateKey, source));
+        }
+
+        for secondary in self.secondary.iter() {
+            sources.push((
+                SecretKind::Secondary(secondary.name.clone()),
+                &secondary.password,
+            ));
+        }
+
+        sources
+    }
+}
+
+/// Which secret of a user a source is for
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SecretKind {
+    MainPassword,
+    IntermediateKey,
+    Secondary(String),
+}
+
+impl fmt::Display for SecretKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SecretKind::MainPassword => write!(f, "main password"),
+            SecretKind::IntermediateKey => write!(f, "intermediate key"),
+            SecretKind::Secondary(name) => write!(f, "password of '{name}'"),
+        }
+    }
+}
+
+/// The secrets of a user, once read from their sources
+#[derive(Debug, Clone, Default)]
+pub struct UserSecrets {
+    pub main_password: Option<SecretBytes>,
+    pub intermediate_key: Option<SecretBytes>,
+    pub secondary: BTreeMap<String, SecretBytes>,
+}
+
+impl UserSecrets {
+    pub fn insert(&mut self, kind: SecretKind, secret: SecretBytes) {
+        match kind {
+            SecretKind::MainPassword => self.main_password = Some(secret),
+            SecretKind::IntermediateKey => self.intermediate_key = Some(secret),
+            SecretKind::Secondary(name) => {
+                self.secondary.insert(name, secret);
+            }
+        }
+    }
+}
+
+/// A declarative description of the polyauth configuration of many users
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Manifest {
+    pub users: Vec<UserManifest>,
+}
+
+impl Manifest {
+    pub fn parse(contents: &str) -> Result<Self, ProvisionError> {
+        let manifest: Manifest = serde_yaml::from_str(contents)?;
+
+        let mut usernames = HashSet::new();
+        for user in manifest.users.iter() {
+            user.validate()?;
+
+            if !usernames.insert(user.username.as_str()) {
+                return Err(ProvisionError::DuplicateUser(user.username.clone()));
+            }
+        }
+
+        Ok(manifest)
+    }
+
+    pub fn load(path: &Path) -> Result<Self, ProvisionError> {
+        Self::parse(&std::fs::read_to_string(path)?)
+    }
+}
+
+/// A value of the configuration that provisioning adds, removes or changes
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Change {
+    key: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    old: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    new: Option<String>,
+}
+
+impl Change {
+    pub fn new(key: impl Into<String>, old: Option<String>, new: Option<String>) -> Self {
+        Self {
+            key: key.into(),
+            old,
+            new,
+        }
+    }
+
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    pub fn old(&self) -> Option<&str> {
+        self.old.as_deref()
+    }
+
+    pub fn new_value(&self) -> Option<&str> {
+        self.new.as_deref()
+    }
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.old, &self.new) {
+            (None, Some(new)) => write!(f, "+ {}: {new}", self.key),
+            (Some(old), None) => write!(f, "- {}: {old}", self.key),
+            (Some(old), Some(new)) => write!(f, "~ {}: {old} -> {new}", self.key),
+            (None, None) => write!(f, "  {}", self.key),
+        }
+    }
+}
+
+/// Changes turning the old entries into the new ones, sorted by key
+pub fn diff(old: &BTreeMap<String, String>, new: &BTreeMap<String, String>) -> Vec<Change> {
+    let keys = old
+        .keys()
+        .chain(new.keys())
+        .collect::<std::collections::BTreeSet<_>>();
+
+    keys.into_iter()
+        .filter_map(|key| match (old.get(key), new.get(key)) {
+            (Some(old), Some(new)) if old == new => None,
+            (old, new) => Some(Change::new(key.clone(), old.cloned(), new.cloned())),
+        })
+        .collect()
+}
+
+/// The mounts flattened into the entries compared by diff
+pub fn mount_entries(mounts: &MountPoints) -> BTreeMap<String, String> {
+    let mut entries = BTreeMap::new();
+
+    let mut add = |prefix: String, params: &MountParams| {
+        entries.insert(format!("{prefix}.device"), params.device().clone());
+        if !params.fstype().is_empty() {
+            entries.insert(format!("{prefix}.fstype"), params.fstype().clone());
+        }
+        if !params.flags().is_empty() {
+            entries.insert(format!("{prefix}.flags"), params.flags().join(","));
+        }
+    };
+
+    add(String::from("mounts.home"), &mounts.mount());
+    for (dir, params) in mounts.foreach(|dir, params| (dir.clone(), params.clone())) {
+        add(format!("mounts.pre.{dir}"), &params);
+    }
+
+    entries
+}
+
+/// The session command flattened into the entries compared by diff
+pub fn session_entries(command: &SessionCommand) -> BTreeMap<String, String> {
+    let mut entries = BTreeMap::new();
+
+    if !command.command().is_empty() {
+        entries.insert(String::from("session.command"), command.command());
+    }
+    if let Some(desktop) = command.desktop() {
+        entries.insert(String::from("session.desktop"), String::from(desktop));
+    }
+    if !command.args().is_empty() {
+        entries.insert(String::from("session.args"), command.args().join(" "));
+    }
+    for (key, value) in command.env() {
+        entries.insert(format!("session.env.{key}"), value.clone());
+    }
+    if !command.session_type().is_unspecified() {
+        entries.insert(
+            String::from("session.session_type"),
+            command.session_type().to_string(),
+        );
+    }
+    if !command.desktop_names().is_empty() {
+        entries.insert(
+            String::from("session.desktop_names"),
+            command.desktop_names().join(":"),
+        );
+    }
+
+    entries
+}
+
+/// What provisioning does to the configuration of a user: nothing at all when it already matches
+#[derive(Debug, Clone)]
+pub struct UserPlan {
+    username: String,
+    changes: Vec<Change>,
+    auth_data: Option<UserAuthData>,
+    mounts: Option<MountPoints>,
+    session_command: Option<SessionCommand>,
+    authorize: Option<String>,
+}
+
+impl UserPlan {
+    pub fn username(&self) -> &str {
+        &self.username
+    }
+
+    /// Changes to the configuration, mount authorization excluded
+    pub fn changes(&self) -> &Vec<Change> {
+        &self.changes
+    }
+
+    pub fn is_unchanged(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    /// Hash of the mounts the user ends up with, if they have to be authorized
+    pub fn mounts_to_authorize(&self) -> Option<&str> {
+        self.authorize.as_deref()
+    }
+
+    /// Writes the changed sections: new files are given to uid/gid, if specified
+    pub fn apply(
+        &self,
+        store: &dyn UserStore,
+        uid: Option<u32>,
+        gid: Option<u32>,
+    ) -> Result<(), StorageError> {
+        if let Some(auth_data) = &self.auth_data {
+            store_user_auth_data(auth_data, store, &self.username, uid, gid)?;
+        }
+
+        if let Some(mounts) = &self.mounts {
+            store_user_mountpoints(Some(mounts.clone()), store, &self.username, uid, gid)?;
+        }
+
+        if let Some(command) = &self.session_command {
+            store_user_session_command(command, store, &self.username, uid, gid)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Brings the authentication data to what the manifest describes, returning the changes made
+fn plan_auth_data(
+    user: &UserManifest,
+    secrets: &UserSecrets,
+    auth_data: &mut UserAuthData,
+) -> Result<Vec<Change>, ProvisionError> {
+    let username = &user.username;
+    let user_error = |err| ProvisionError::User(username.clone(), err);
+    let require_intermediate = |what: String| {
+        secrets
+            .intermediate_key
+            .as_ref()
+            .ok_or(ProvisionError::MissingIntermediateKey(
+                username.clone(),
+                what,
+            ))
+    };
+
+    let mut changes = vec![];
+
+    // a wrong intermediate key is refused even when nothing has to be changed
+    // (the main password itself is accepted by main() but cannot stand for the intermediate key)
+    if let (true, Some(intermediate_key)) = (auth_data.has_main(), &secrets.intermediate_key) {
+        if !auth_data
+            .is_intermediate_key(intermediate_key.expose())
+            .map_err(user_error)?
+        {
+            return Err(user_error(UserOperationError::User(
+                UserAuthDataError::WrongIntermediateKey,
+            )));
+        }
+    }
+
+    if let Some(main_password) = &secrets.main_password {
+        if !auth_data.has_main() {
+            let intermediate_key = require_intermediate(String::from("set the main password"))?;
+            auth_data
+                .set_main(main_password.expose(), intermediate_key.expose())
+                .map_err(user_error)?;
+            changes.push(Change::new(
+                "auth.main_password",
+                None,
+                Some(String::from("set")),
+            ));
+        } else if !auth_data
+            .check_main(main_password.expose())
+            .map_err(user_error)?
+        {
+            let intermediate_key = require_intermediate(String::from("change the main password"))?;
+            auth_data
+                .set_main(main_password.expose(), intermediate_key.expose())
+                .map_err(user_error)?;
+            changes.push(Change::new(
+                "auth.main_password",
+                Some(String::from("set")),
+                Some(String::from("changed")),
+            ));
+        }
+    } else if !auth_data.has_main() && !secrets.secondary.is_empty() {
+        return Err(ProvisionError::MissingMainPassword(username.clone()));
+    }
+
+    for secondary in user.secondary.iter() {
+        let Some(password) = secrets.secondary.get(&secondary.name) else {
+            continue;
+        };
+
+        let existing = auth_data
+            .secondary()
+            .filter(|auth| auth.name() == secondary.name)
+            .collect::<Vec<_>>();
+
+        // only a single method with that name, unlocked by the password, is left as it is
+        let unchanged = existing.len() == 1
+            && existing[0]
+                .intermediate(Some(password.expose()))
+                .and_then(|intermediate| auth_data.main(&intermediate))
+                .is_ok();
+        if unchanged {
+            continue;
+        }
+
+        let key = format!("auth.secondary.{}", secondary.name);
+        let change = match existing.is_empty() {
+            true => Change::new(key, None, Some(String::from("password"))),
+            false => Change::new(
+                key,
+                Some(String::from("password")),
+                Some(String::from("password (changed)")),
+            ),
+        };
+
+        let intermediate_key =
+            require_intermediate(format!("set the secondary method '{}'", secondary.name))?;
+        auth_data.remove_secondary(&secondary.name);
+        auth_data
+            .add_secondary_password(
+                &secondary.name,
+                intermediate_key.expose(),
+                password.expose(),
+            )
+            .map_err(user_error)?;
+        changes.push(change);
+    }
+
+    Ok(changes)
+}
+
+/// Compares the configuration of the user with the manifest, without writing anything.
+///
+/// Entries that have to be encrypted again are protected with the given KDF parameters.
+pub fn plan_user(
+    store: &dyn UserStore,
+    user: &UserManifest,
+    secrets: &UserSecrets,
+    kdf: &KdfParams,
+) -> Result<UserPlan, ProvisionError> {
+    let username = &user.username;
+    let storage_error = |err| ProvisionError::Storage(username.clone(), err);
+
+    let mut plan = UserPlan {
+        username: username.clone(),
+        changes: vec![],
+        auth_data: None,
+        mounts: None,
+        session_command: None,
+        authorize: None,
+    };
+
+    let mut auth_data = load_user_auth_data(store, username)
+        .map_err(storage_error)?
+        .unwrap_or_default();
+    auth_data.set_kdf(*kdf);
+    let auth_changes = plan_auth_data(user, secrets, &mut auth_data)?;
+    if !auth_changes.is_empty() {
+        plan.changes.extend(auth_changes);
+        plan.auth_data = Some(auth_data);
+    }
+
+    let existing_mounts = load_user_mountpoints(store, username).map_err(storage_error)?;
+    if let Some(mounts) = &user.mounts {
+        let mounts = mounts.mount_points();
+        if existing_mounts.as_ref() != Some(&mounts) {
+            plan.changes.extend(diff(
+                &existing_mounts
+                    .as_ref()
+                    .map(mount_entries)
+                    .unwrap_or_default(),
+                &mount_entries(&mounts),
+            ));
+            plan.mounts = Some(mounts);
+        }
+    }
+
+    if let Some(command) = &user.session {
+        let existing = load_user_session_command(store, username).map_err(storage_error)?;
+        if existing.as_ref() != Some(command) {
+            plan.changes.extend(diff(
+                &existing.as_ref().map(session_entries).unwrap_or_default(),
+                &session_entries(command),
+            ));
+            plan.session_command = Some(command.clone());
+        }
+    }
+
+    if user.authorize_mounts {
+        plan.authorize = plan
+            .mounts
+            .as_ref()
+            .or(existing_mounts.as_ref())
+            .map(MountPoints::hash);
+    }
+
+    Ok(plan)
+}
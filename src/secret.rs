@@ -0,0 +1,305 @@
+/*
+    pam_polyauth: A pam module written in rust that supports multiple
+    authentication modes (including autologin).
+
+    Copyright (C) 2024-2025  Denis Benato
+
+    This program is free software; you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation; either version 2 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License along
+    with this program; if not, write to the Free Software Foundation, Inc.,
+    51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+*/
+
+use std::{
+    fmt,
+    io::{BufRead, Read},
+    ops::Deref,
+};
+
+use zeroize::Zeroize;
+
+/// Locks the pages holding the buffer in RAM so that they are never swapped out.
+///
+/// This is best effort: failures (i.e. RLIMIT_MEMLOCK being exhausted) are ignored
+/// and the secret is only wiped when dropped. Locks are per page and do not stack:
+/// unlocking a secret also unlocks any other secret sharing a page with it.
+fn lock(ptr: *const u8, len: usize) -> bool {
+    if len == 0 {
+        return false;
+    }
+
+    // SAFETY: the range is a live heap allocation owned by the caller
+    unsafe { libc::mlock(ptr as *const libc::c_void, len) == 0 }
+}
+
+fn unlock(ptr: *const u8, len: usize) {
+    // SAFETY: the range has been locked by lock() and is still allocated
+    unsafe {
+        libc::munlock(ptr as *const libc::c_void, len);
+    }
+}
+
+/// Bytes of a secret (passwords, keys): wiped from memory when dropped
+pub struct SecretBytes {
+    bytes: Vec<u8>,
+    locked: bool,
+}
+
+impl SecretBytes {
+    pub fn new(bytes: Vec<u8>) -> Self {
+        let locked = lock(bytes.as_ptr(), bytes.capacity());
+
+        Self { bytes, locked }
+    }
+
+    pub fn expose(&self) -> &[u8] {
+        self.bytes.as_slice()
+    }
+}
+
+impl Drop for SecretBytes {
+    fn drop(&mut self) {
+        self.bytes.zeroize();
+
+        if self.locked {
+            unlock(self.bytes.as_ptr(), self.bytes.capacity());
+        }
+    }
+}
+
+impl Deref for SecretBytes {
+    type Target = [u8];
+
+    fn deref(&self) -> &Self::Target {
+        self.expose()
+    }
+}
+
+impl AsRef<[u8]> for SecretBytes {
+    fn as_ref(&self) -> &[u8] {
+        self.expose()
+    }
+}
+
+impl Clone for SecretBytes {
+    fn clone(&self) -> Self {
+        Self::new(self.bytes.clone())
+    }
+}
+
+impl From<Vec<u8>> for SecretBytes {
+    fn from(value: Vec<u8>) -> Self {
+        Self::new(value)
+    }
+}
+
+impl From<&[u8]> for SecretBytes {
+    fn from(value: &[u8]) -> Self {
+        Self::new(value.to_vec())
+    }
+}
+
+impl PartialEq for SecretBytes {
+    fn eq(&self, other: &Self) -> bool {
+        crate::constant_time_eq(self.expose(), other.expose())
+    }
+}
+
+impl Eq for SecretBytes {}
+
+impl PartialEq<[u8]> for SecretBytes {
+    fn eq(&self, other: &[u8]) -> bool {
+        crate::constant_time_eq(self.expose(), other)
+    }
+}
+
+impl PartialEq<Vec<u8>> for SecretBytes {
+    fn eq(&self, other: &Vec<u8>) -> bool {
+        crate::constant_time_eq(self.expose(), other)
+    }
+}
+
+impl PartialEq<&[u8]> for SecretBytes {
+    fn eq(&self, other: &&[u8]) -> bool {
+        crate::constant_time_eq(self.expose(), other)
+    }
+}
+
+impl fmt::Debug for SecretBytes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("SecretBytes([REDACTED])")
+    }
+}
+
+/// A secret known to be valid UTF-8 (i.e. a password typed by the user): wiped from memory when dropped
+pub struct SecretString {
+    string: String,
+    locked: bool,
+}
+
+impl SecretString {
+    pub fn new(string: String) -> Self {
+        let locked = lock(string.as_ptr(), string.capacity());
+
+        Self { string, locked }
+    }
+
+    pub fn expose(&self) -> &str {
+        self.string.as_str()
+    }
+}
+
+impl Drop for SecretString {
+    fn drop(&mut self) {
+        self.string.zeroize();
+
+        if self.locked {
+            unlock(self.string.as_ptr(), self.string.capacity());
+        }
+    }
+}
+
+impl Deref for SecretString {
+    type Target = str;
+
+    fn deref(&self) -> &Self::Target {
+        self.expose()
+    }
+}
+
+impl AsRef<str> for SecretString {
+    fn as_ref(&self) -> &str {
+        self.expose()
+    }
+}
+
+impl Clone for SecretString {
+    fn clone(&self) -> Self {
+        Self::new(self.string.clone())
+    }
+}
+
+impl From<String> for SecretString {
+    fn from(value: String) -> Self {
+        Self::new(value)
+    }
+}
+
+impl From<&str> for SecretString {
+    fn from(value: &str) -> Self {
+        Self::new(String::from(value))
+    }
+}
+
+impl From<SecretString> for SecretBytes {
+    fn from(mut value: SecretString) -> Self {
+        // the heap buffer is moved, not copied: the lock (if any) moves along with it
+        let string = std::mem::take(&mut value.string);
+        let locked = std::mem::replace(&mut value.locked, false);
+
+        Self {
+            bytes: string.into_bytes(),
+            locked,
+        }
+    }
+}
+
+impl PartialEq for SecretString {
+    fn eq(&self, other: &Self) -> bool {
+        crate::constant_time_eq(self.as_bytes(), other.as_bytes())
+    }
+}
+
+impl Eq for SecretString {}
+
+impl PartialEq<String> for SecretString {
+    fn eq(&self, other: &String) -> bool {
+        crate::constant_time_eq(self.as_bytes(), other.as_bytes())
+    }
+}
+
+impl PartialEq<&str> for SecretString {
+    fn eq(&self, other: &&str) -> bool {
+        crate::constant_time_eq(self.as_bytes(), other.as_bytes())
+    }
+}
+
+impl fmt::Debug for SecretString {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("SecretString([REDACTED])")
+    }
+}
+
+/// Longest secret accepted: the size of a response to a PAM prompt (PAM_MAX_RESP_SIZE)
+pub const MAX_PASSWORD_LEN: usize = 512;
+
+/// Whether a secret can be used as a password (or key): it must not be empty nor be longer
+/// than MAX_PASSWORD_LEN bytes. Any other byte is accepted, as in key files.
+pub fn is_valid_secret(secret: &[u8]) -> bool {
+    !secret.is_empty() && secret.len() <= MAX_PASSWORD_LEN
+}
+
+/// Whether a password typed at a prompt can be used: on top of is_valid_secret, it must not
+/// contain NUL characters and line breaks, none of which can be answered to a PAM prompt.
+pub fn is_valid_password(password: &str) -> bool {
+    is_valid_secret(password.as_bytes()) && !password.contains(['\0', '\n', '\r'])
+}
+
+fn strip_line_break(secret: &mut Vec<u8>) {
+    if secret.ends_with(b"\n") {
+        secret.pop();
+        if secret.ends_with(b"\r") {
+            secret.pop();
+        }
+    }
+}
+
+/// Reads a secret until the end of the input, dropping the line break that terminates it.
+/// The secret is taken as it is, without requiring it to be UTF-8.
+///
+/// Reading stops a little past MAX_PASSWORD_LEN, so that the buffer is never moved
+/// (leaving copies of the secret behind) and oversized secrets are still refused.
+pub fn read_secret<R: Read>(reader: R) -> std::io::Result<SecretBytes> {
+    let limit = MAX_PASSWORD_LEN + 2;
+    let mut secret = Vec::with_capacity(limit + 1);
+
+    if let Err(err) = reader.take(limit as u64 + 1).read_to_end(&mut secret) {
+        secret.zeroize();
+        return Err(err);
+    }
+
+    strip_line_break(&mut secret);
+    Ok(SecretBytes::from(secret))
+}
+
+/// Reads a line holding a secret, without its line break: None at the end of the input.
+/// As with read_secret, the line is neither required to be UTF-8 nor read past MAX_PASSWORD_LEN.
+pub fn read_secret_line<R: BufRead>(reader: &mut R) -> std::io::Result<Option<SecretBytes>> {
+    let limit = MAX_PASSWORD_LEN + 2;
+    let mut secret = Vec::with_capacity(limit + 1);
+
+    match reader
+        .by_ref()
+        .take(limit as u64 + 1)
+        .read_until(b'\n', &mut secret)
+    {
+        Ok(0) => Ok(None),
+        Ok(_) => {
+            strip_line_break(&mut secret);
+            Ok(Some(SecretBytes::from(secret)))
+        }
+        Err(err) => {
+            secret.zeroize();
+            Err(err)
+        }
+    }
+}
@@ -0,0 +1,242 @@
+/*
+    pam_polyauth: A pam module written in rust that supports multiple
+    authentication modes (including autologin).
+
+    Copyright (C) 2024-2025  Denis Benato
+
+    This program is free software; you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation; either version 2 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License along
+    with this program; if not, write to the Free Software Foundation, Inc.,
+    51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+*/
+
+use aes_gcm::{
+    aead::{Aead, AeadCore, KeyInit, OsRng, Payload},
+    Aes256Gcm, Key, Nonce,
+};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    command::SessionCommand, kdf::KdfParams, mount::MountPoints, secret::SecretBytes,
+    user::UserAuthData,
+};
+
+use super::{
+    auth_data_from_config, migration, mountpoints_from_config, store::UserStore, StorageError,
+    UserConfig,
+};
+
+/// Value of the magic field that identifies an export file
+const EXPORT_MAGIC: &str = "polyauth-export";
+
+/// Version of the export file layout written by this release
+pub const EXPORT_FORMAT_VERSION: u32 = 1;
+
+/// Cleartext part of an export file: everything needed to derive the key.
+///
+/// It is also passed to AES-GCM as associated data, so changing any field
+/// (for example to weaken the KDF) makes the decryption fail.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ExportHeader {
+    magic: String,
+    version: u32,
+    m_cost: u32,
+    t_cost: u32,
+    p_cost: u32,
+    salt: String,  // base64-encoded
+    nonce: String, // base64-encoded
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ExportFile {
+    header: ExportHeader,
+    ciphertext: String, // base64-encoded
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ExportPayload {
+    username: String,
+    exported_at: u64,
+    config: UserConfig,
+}
+
+/// The configuration of a user as read back from an export file
+#[derive(Debug, Clone)]
+pub struct ConfigExport {
+    username: String,
+    exported_at: u64,
+    config: UserConfig,
+}
+
+impl ConfigExport {
+    /// Name of the user the configuration was exported from
+    pub fn username(&self) -> &str {
+        &self.username
+    }
+
+    /// Unix timestamp of the export
+    pub fn exported_at(&self) -> u64 {
+        self.exported_at
+    }
+
+    pub fn auth_data(&self) -> Result<Option<UserAuthData>, StorageError> {
+        auth_data_from_config(Some(self.config.clone()))
+    }
+
+    pub fn mountpoints(&self) -> Option<MountPoints> {
+        mountpoints_from_config(Some(self.config.clone()))
+    }
+
+    pub fn session_command(&self) -> Option<SessionCommand> {
+        self.config.session_command.clone()
+    }
+}
+
+fn export_key(header: &ExportHeader, passphrase: &[u8]) -> Result<Key<Aes256Gcm>, StorageError> {
+    let salt: [u8; 32] = BASE64
+        .decode(&header.salt)
+        .ok()
+        .and_then(|salt| salt.try_into().ok())
+        .ok_or(StorageError::InvalidExport)?;
+
+    // the header comes from the file being imported: costs out of bounds are never derived with
+    let kdf = KdfParams::argon2id(header.m_cost, header.t_cost, header.p_cost);
+    kdf.check().map_err(|_| StorageError::InvalidExport)?;
+
+    Ok(Key::<Aes256Gcm>::from(kdf.derive(passphrase, &salt)?))
+}
+
+/// Bundles auth data, mounts and session command of the user into a file encrypted under passphrase.
+///
+/// Secondary authentication methods are exported as stored: they are still protected
+/// by their own passwords once the export is decrypted.
+pub fn export_user_config(
+    store: &dyn UserStore,
+    username: &str,
+    passphrase: &[u8],
+    kdf: &KdfParams,
+) -> Result<Vec<u8>, StorageError> {
+    let Some(config) = store.read(username)? else {
+        return Err(StorageError::IoError(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            "there is no configuration to export",
+        )));
+    };
+
+    let payload = ExportPayload {
+        username: username.to_string(),
+        exported_at: std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default(),
+        config,
+    };
+    let plaintext = SecretBytes::from(serde_json::to_vec(&payload)?);
+
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    let header = ExportHeader {
+        magic: EXPORT_MAGIC.to_string(),
+        version: EXPORT_FORMAT_VERSION,
+        m_cost: kdf.m_cost(),
+        t_cost: kdf.t_cost(),
+        p_cost: kdf.p_cost(),
+        salt: BASE64.encode(crate::random_salt()),
+        nonce: BASE64.encode(nonce),
+    };
+
+    let aad = serde_json::to_vec(&header)?;
+    let ciphertext = Aes256Gcm::new(&export_key(&header, passphrase)?)
+        .encrypt(
+            &nonce,
+            Payload {
+                msg: plaintext.expose(),
+                aad: &aad,
+            },
+        )
+        .map_err(|_| StorageError::InvalidExport)?;
+
+    let file = ExportFile {
+        header,
+        ciphertext: BASE64.encode(ciphertext),
+    };
+
+    Ok(serde_json::to_vec_pretty(&file)?)
+}
+
+/// Decrypts a file written by export_user_config, migrating the configuration
+/// inside if it was exported by an older release.
+pub fn decrypt_user_config(
+    contents: &[u8],
+    passphrase: &[u8],
+) -> Result<ConfigExport, StorageError> {
+    let file: ExportFile =
+        serde_json::from_slice(contents).map_err(|_| StorageError::InvalidExport)?;
+
+    if file.header.magic != EXPORT_MAGIC {
+        return Err(StorageError::InvalidExport);
+    }
+
+    if file.header.version != EXPORT_FORMAT_VERSION {
+        return Err(StorageError::UnsupportedExportVersion(file.header.version));
+    }
+
+    let nonce: [u8; 12] = BASE64
+        .decode(&file.header.nonce)
+        .ok()
+        .and_then(|nonce| nonce.try_into().ok())
+        .ok_or(StorageError::InvalidExport)?;
+    let ciphertext = BASE64
+        .decode(&file.ciphertext)
+        .map_err(|_| StorageError::InvalidExport)?;
+
+    let aad = serde_json::to_vec(&file.header)?;
+    let plaintext = Aes256Gcm::new(&export_key(&file.header, passphrase)?)
+        .decrypt(
+            &Nonce::from(nonce),
+            Payload {
+                msg: &ciphertext,
+                aad: &aad,
+            },
+        )
+        .map(SecretBytes::new)
+        .map_err(|_| StorageError::ExportDecryption)?;
+
+    let mut payload: serde_json::Value = serde_json::from_slice(plaintext.expose())?;
+    if let Some(config) = payload.get_mut("config") {
+        migration::migrate(config)?;
+    }
+    let payload: ExportPayload = serde_json::from_value(payload)?;
+
+    Ok(ConfigExport {
+        username: payload.username,
+        exported_at: payload.exported_at,
+        config: payload.config,
+    })
+}
+
+/// Replaces the whole configuration of username with the exported one.
+///
+/// username does not have to be the one the configuration was exported from:
+/// files that are created are given to uid/gid, if specified.
+pub fn import_user_config(
+    export: &ConfigExport,
+    store: &dyn UserStore,
+    username: &str,
+    uid: Option<u32>,
+    gid: Option<u32>,
+) -> Result<(), StorageError> {
+    store.update(username, uid, gid, &mut |config| {
+        *config = export.config.clone();
+        Ok(())
+    })
+}
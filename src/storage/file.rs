@@ -0,0 +1,332 @@
+/*
+    pam_polyauth: A pam module written in rust that supports multiple
+    authentication modes (including autologin).
+
+    Copyright (C) 2024-2025  Denis Benato
+
+    This program is free software; you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation; either version 2 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License along
+    with this program; if not, write to the Free Software Foundation, Inc.,
+    51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+*/
+
+use std::{
+    cell::Cell,
+    ffi::OsString,
+    fs::{self, File, OpenOptions},
+    io::{self, ErrorKind, Write},
+    os::{
+        fd::AsRawFd,
+        unix::fs::{fchown, MetadataExt, OpenOptionsExt, PermissionsExt},
+    },
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+/// Mode given to configuration files that do not exist yet
+pub(crate) const CONFIG_FILE_MODE: u32 = 0o600;
+
+/// Mode given to new files that users may read but not write
+pub(crate) const PUBLIC_FILE_MODE: u32 = 0o644;
+
+/// Lock files are empty, but anyone able to open them can take the lock:
+/// only the owner of the configuration (and root) may
+const LOCK_FILE_MODE: u32 = 0o600;
+
+/// How long a writer waits for the lock held by another one by default
+pub(crate) const DEFAULT_LOCK_TIMEOUT: Duration = Duration::from_secs(5);
+
+const LOCK_RETRY_INTERVAL: Duration = Duration::from_millis(50);
+
+thread_local! {
+    static LOCK_TIMEOUT: Cell<Duration> = const { Cell::new(DEFAULT_LOCK_TIMEOUT) };
+}
+
+/// Runs f with every ConfigLock taken by this thread waiting at most timeout
+/// (Duration::ZERO gives up at once when the lock is busy)
+pub(crate) fn with_lock_timeout<T>(timeout: Duration, f: impl FnOnce() -> T) -> T {
+    let previous = LOCK_TIMEOUT.with(|cell| cell.replace(timeout));
+    let result = f();
+    LOCK_TIMEOUT.with(|cell| cell.set(previous));
+    result
+}
+
+fn sidecar_path(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = OsString::from(path.file_name().unwrap_or_default());
+    name.push(suffix);
+    path.with_file_name(name)
+}
+
+pub(crate) fn lock_path(path: &Path) -> PathBuf {
+    sidecar_path(path, ".lock")
+}
+
+pub(crate) fn backup_path(path: &Path) -> PathBuf {
+    sidecar_path(path, ".bak")
+}
+
+pub(crate) fn signature_path(path: &Path) -> PathBuf {
+    sidecar_path(path, ".sig")
+}
+
+/// Advisory lock held for a whole read-modify-write cycle on a configuration file.
+///
+/// The lock is taken on `<config>.lock` rather than on the configuration itself,
+/// because the configuration is replaced by rename on every write.
+/// The lock is released when the guard is dropped.
+pub(crate) struct ConfigLock {
+    file: File,
+}
+
+impl ConfigLock {
+    /// Locks path, waiting for other writers as long as allowed by with_lock_timeout:
+    /// fails with ErrorKind::WouldBlock when they do not release it in time.
+    ///
+    /// The lock file is given to uid/gid if specified, or else to the owner of the configuration.
+    pub(crate) fn acquire(path: &Path, uid: Option<u32>, gid: Option<u32>) -> io::Result<Self> {
+        if let Some(parent) = path.parent() {
+            if !parent.as_os_str().is_empty() && !parent.exists() {
+                fs::create_dir_all(parent)?;
+            }
+        }
+
+        let (uid, gid) = match (uid, fs::metadata(path)) {
+            (Some(uid), _) => (Some(uid), gid),
+            (None, Ok(metadata)) => (Some(metadata.uid()), Some(metadata.gid())),
+            (None, Err(_)) => (None, None),
+        };
+
+        // a user can open the lock file of their own configuration even when they
+        // cannot create files in its directory: root creates it along with the configuration
+        let lock_path = lock_path(path);
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .mode(LOCK_FILE_MODE)
+            .open(&lock_path)?;
+
+        // lock files written by older releases were readable, and therefore lockable, by anyone
+        let metadata = file.metadata()?;
+        if metadata.permissions().mode() & 0o777 != LOCK_FILE_MODE {
+            file.set_permissions(fs::Permissions::from_mode(LOCK_FILE_MODE))?;
+        }
+        if uid.is_some_and(|uid| uid != metadata.uid()) {
+            fchown(&file, uid, gid)?;
+        }
+
+        let deadline = Instant::now() + LOCK_TIMEOUT.with(Cell::get);
+        loop {
+            match unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } {
+                0 => break,
+                _ => {
+                    let err = io::Error::last_os_error();
+                    match err.kind() {
+                        ErrorKind::Interrupted => continue,
+                        ErrorKind::WouldBlock if Instant::now() < deadline => {
+                            std::thread::sleep(LOCK_RETRY_INTERVAL)
+                        }
+                        ErrorKind::WouldBlock => {
+                            return Err(io::Error::new(
+                                ErrorKind::WouldBlock,
+                                format!("{} is locked by another writer", path.to_string_lossy()),
+                            ))
+                        }
+                        _ => return Err(err),
+                    }
+                }
+            }
+        }
+
+        Ok(Self { file })
+    }
+}
+
+impl Drop for ConfigLock {
+    fn drop(&mut self) {
+        unsafe { libc::flock(self.file.as_raw_fd(), libc::LOCK_UN) };
+    }
+}
+
+fn sync_dir(dir: &Path) -> io::Result<()> {
+    File::open(dir)?.sync_all()
+}
+
+/// Replaces the file at path with contents so that readers either see the old or the new version.
+///
+/// The previous version is kept as `<config>.bak`; the new contents are written to a
+/// temporary file in the same directory, synced to disk and renamed over the old file.
+/// Ownership is set to uid/gid if given, otherwise the owner and mode of the replaced file are kept;
+/// mode is only used for files that do not exist yet.
+///
+/// When the directory is not writable by the caller (a user editing its own file in a
+/// root-owned directory) the file is rewritten through its backup instead, see write_through_backup.
+pub(crate) fn write_atomic(
+    path: &Path,
+    contents: &[u8],
+    uid: Option<u32>,
+    gid: Option<u32>,
+    mode: u32,
+) -> io::Result<()> {
+    let previous = match fs::metadata(path) {
+        Ok(metadata) => Some(metadata),
+        Err(err) if err.kind() == ErrorKind::NotFound => None,
+        Err(err) => return Err(err),
+    };
+
+    let (uid, gid) = match (uid, &previous) {
+        (Some(uid), _) => (Some(uid), gid),
+        (None, Some(metadata)) => (Some(metadata.uid()), Some(metadata.gid())),
+        (None, None) => (None, None),
+    };
+    let mode = previous
+        .as_ref()
+        .map(|metadata| metadata.permissions().mode() & 0o7777)
+        .unwrap_or(mode);
+
+    let dir = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+
+    let (tmp_path, mut tmp) = match create_temporary(path, mode) {
+        Ok(tmp) => tmp,
+        Err(err) if err.kind() == ErrorKind::PermissionDenied && previous.is_some() => {
+            return write_through_backup(path, contents);
+        }
+        Err(err) if err.kind() == ErrorKind::PermissionDenied => {
+            return Err(io::Error::new(
+                err.kind(),
+                format!(
+                    "cannot create a temporary file in {}: {err}",
+                    dir.to_string_lossy()
+                ),
+            ))
+        }
+        Err(err) => return Err(err),
+    };
+
+    let written = (|| {
+        tmp.write_all(contents)?;
+        tmp.set_permissions(fs::Permissions::from_mode(mode))?;
+        if uid.is_some() {
+            fchown(&tmp, uid, gid)?;
+        }
+        tmp.sync_all()?;
+
+        // the backup always exists, so that the owner can later write through it
+        let backup = backup_path(path);
+        fs::copy(
+            match previous {
+                Some(_) => path,
+                None => tmp_path.as_path(),
+            },
+            &backup,
+        )?;
+        if uid.is_some() {
+            std::os::unix::fs::chown(&backup, uid, gid)?;
+        }
+
+        fs::rename(&tmp_path, path)
+    })();
+
+    if let Err(err) = written {
+        let _ = fs::remove_file(&tmp_path);
+        return Err(err);
+    }
+
+    sync_dir(&dir)
+}
+
+/// Creates `<config>.tmp.<random>`: a name left behind by a crash is never reused
+fn create_temporary(path: &Path, mode: u32) -> io::Result<(PathBuf, File)> {
+    loop {
+        let tmp_path = sidecar_path(path, &format!(".tmp.{:016x}", rand::random::<u64>()));
+        match OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(mode)
+            .open(&tmp_path)
+        {
+            Ok(tmp) => return Ok((tmp_path, tmp)),
+            Err(err) if err.kind() == ErrorKind::AlreadyExists => continue,
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+/// Rewrites the file in place for callers that own it but cannot create files next to it.
+///
+/// The current version is first copied (and synced) into `<config>.bak`, which write_atomic
+/// creates along with the configuration: should the rewrite be interrupted, the configuration
+/// is read back from there, see read_with_backup.
+fn write_through_backup(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let backup = backup_path(path);
+    let mut backup_file = OpenOptions::new()
+        .write(true)
+        .open(&backup)
+        .map_err(|err| {
+            io::Error::new(
+                err.kind(),
+                format!(
+                    "cannot create a temporary file next to {} nor write its backup: {err}",
+                    path.to_string_lossy()
+                ),
+            )
+        })?;
+
+    let current = fs::read(path)?;
+    backup_file.set_len(0)?;
+    backup_file.write_all(&current)?;
+    backup_file.sync_all()?;
+
+    let mut file = OpenOptions::new().write(true).open(path)?;
+    file.set_len(0)?;
+    file.write_all(contents)?;
+    file.sync_all()
+}
+
+/// Reads a file written by write_atomic, parsing it with parse.
+///
+/// If it cannot be parsed (an interrupted write_through_backup) the backup is returned instead,
+/// when that can be parsed: otherwise the error of the file itself is.
+pub(crate) fn read_with_backup<T, E>(
+    path: &Path,
+    parse: impl Fn(&str) -> Result<T, E>,
+) -> Result<T, E>
+where
+    E: From<io::Error>,
+{
+    let contents = fs::read_to_string(path)?;
+    match parse(&contents) {
+        Ok(parsed) => Ok(parsed),
+        Err(err) => match fs::read_to_string(backup_path(path)).map(|backup| parse(&backup)) {
+            Ok(Ok(parsed)) => Ok(parsed),
+            _ => Err(err),
+        },
+    }
+}
+
+/// Removes the file at path, leaving the last version behind as `<config>.bak`.
+pub(crate) fn remove(path: &Path) -> io::Result<()> {
+    match fs::rename(path, backup_path(path)) {
+        Ok(()) => (),
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(()),
+        Err(err) => return Err(err),
+    }
+
+    match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => sync_dir(parent),
+        _ => sync_dir(Path::new(".")),
+    }
+}
@@ -0,0 +1,105 @@
+/*
+    pam_polyauth: A pam module written in rust that supports multiple
+    authentication modes (including autologin).
+
+    Copyright (C) 2024-2025  Denis Benato
+
+    This program is free software; you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation; either version 2 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License along
+    with this program; if not, write to the Free Software Foundation, Inc.,
+    51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+*/
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use sha2::Sha256;
+
+use crate::secret::SecretBytes;
+
+use super::StorageError;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Length in bytes of the key used to sign user configurations
+pub const SIGNING_KEY_LEN: usize = 32;
+
+/// Key held by pam_polyauth-service to sign the security-relevant sections of user configurations.
+///
+/// Users can edit their own configuration file, but cannot produce a valid tag for it:
+/// only configurations approved by an administrator carry one.
+pub struct ConfigSigningKey {
+    key: SecretBytes,
+}
+
+/// Result of checking a user configuration against its signature
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigIntegrity {
+    /// The configuration has been signed and is unchanged since
+    Signed,
+
+    /// The configuration has never been signed
+    Unsigned,
+
+    /// The configuration has been changed after being signed
+    Tampered,
+}
+
+impl ConfigSigningKey {
+    pub fn generate() -> Self {
+        let mut key = vec![0u8; SIGNING_KEY_LEN];
+        rand::thread_rng().fill_bytes(&mut key);
+
+        Self {
+            key: SecretBytes::from(key),
+        }
+    }
+
+    pub fn from_base64(encoded: &str) -> Result<Self, StorageError> {
+        let key = SecretBytes::from(
+            BASE64
+                .decode(encoded.trim())
+                .map_err(|_| StorageError::DeserializationError)?,
+        );
+
+        if key.len() != SIGNING_KEY_LEN {
+            return Err(StorageError::DeserializationError);
+        }
+
+        Ok(Self { key })
+    }
+
+    pub fn to_base64(&self) -> String {
+        BASE64.encode(self.key.expose())
+    }
+
+    // the username is part of the tag so that a signed configuration cannot be reused by another user
+    fn mac(&self, username: &str, sections: &[u8]) -> HmacSha256 {
+        let mut mac =
+            HmacSha256::new_from_slice(self.key.expose()).expect("HMAC accepts keys of any size");
+        mac.update(&(username.len() as u64).to_le_bytes());
+        mac.update(username.as_bytes());
+        mac.update(sections);
+        mac
+    }
+
+    pub(crate) fn sign(&self, username: &str, sections: &[u8]) -> String {
+        BASE64.encode(self.mac(username, sections).finalize().into_bytes())
+    }
+
+    pub(crate) fn verify(&self, username: &str, sections: &[u8], tag: &str) -> bool {
+        match BASE64.decode(tag.trim()) {
+            Ok(tag) => self.mac(username, sections).verify_slice(&tag).is_ok(),
+            Err(_) => false,
+        }
+    }
+}
@@ -0,0 +1,219 @@
+/*
+    pam_polyauth: A pam module written in rust that supports multiple
+    authentication modes (including autologin).
+
+    Copyright (C) 2024-2025  Denis Benato
+
+    This program is free software; you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation; either version 2 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License along
+    with this program; if not, write to the Free Software Foundation, Inc.,
+    51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+*/
+
+// Authentication data format written by releases before the current (AEAD only) format.
+//
+// Those blobs store bcrypt hashes of the protected keys next to the ciphertext, and their
+// keys are derived with HKDF-SHA256: they are still read and used for authentication,
+// and re-written in the current format as soon as a login provides the password that unlocks them.
+
+use bytevec2::*;
+
+use aes_gcm::{
+    aead::{Aead, KeyInit},
+    Aes256Gcm, Key, Nonce,
+};
+
+extern crate bcrypt;
+use bcrypt::verify;
+
+use crate::{
+    error::*,
+    kdf::KdfParams,
+    secret::SecretBytes,
+    user::{AuthDataNonce, AuthDataSalt, UserAuthDataError},
+};
+
+use super::StorageError;
+
+/// Format of blobs storing bcrypt hashes, without KDF parameters (HKDF-SHA256 implied)
+pub(crate) const AUTH_BLOB_FORMAT_V0: u32 = 0;
+
+// every entry of this format has been encrypted with the same KDF
+static LEGACY_KDF: KdfParams = KdfParams::legacy();
+
+bytevec_decl! {
+    #[derive(PartialEq, Eq, Debug, Clone)]
+    pub struct MainPasswordV0 {
+        main_hash: String,
+        enc_main: Vec<u8>,
+        enc_main_nonce: AuthDataNonce,
+        intermediate_key_salt: AuthDataSalt,
+        intermediate_key_hash: String
+    }
+}
+
+impl MainPasswordV0 {
+    /// Only used to produce entries in this format for tests
+    #[cfg(test)]
+    pub(crate) fn new(main: &[u8], intermediate_key: &[u8]) -> Result<Self, UserOperationError> {
+        let intermediate_key_salt = crate::random_salt();
+        let (enc_main, enc_main_nonce) = crate::encrypt_with_password(
+            &LEGACY_KDF,
+            intermediate_key,
+            &intermediate_key_salt,
+            main,
+        )?;
+
+        Ok(Self {
+            main_hash: bcrypt::hash(main, bcrypt::DEFAULT_COST)
+                .map_err(UserOperationError::HashingError)?,
+            enc_main,
+            enc_main_nonce: AuthDataNonce::from(enc_main_nonce),
+            intermediate_key_salt: AuthDataSalt::from(intermediate_key_salt),
+            intermediate_key_hash: bcrypt::hash(intermediate_key, bcrypt::DEFAULT_COST)
+                .map_err(UserOperationError::HashingError)?,
+        })
+    }
+
+    pub(crate) fn kdf(&self) -> &KdfParams {
+        &LEGACY_KDF
+    }
+
+    pub(crate) fn intermediate_key_salt(&self) -> [u8; 32] {
+        self.intermediate_key_salt.into()
+    }
+
+    pub(crate) fn check(&self, main_password: &[u8]) -> Result<bool, UserOperationError> {
+        verify(main_password, self.main_hash.as_str()).map_err(UserOperationError::HashingError)
+    }
+
+    pub(crate) fn is_intermediate_key(
+        &self,
+        intermediate_key: &[u8],
+    ) -> Result<bool, UserOperationError> {
+        verify(intermediate_key, self.intermediate_key_hash.as_str())
+            .map_err(UserOperationError::HashingError)
+    }
+
+    pub(crate) fn by_intermediate_key(
+        &self,
+        intermediate_key: &[u8],
+    ) -> Result<SecretBytes, UserOperationError> {
+        if !self.is_intermediate_key(intermediate_key)? {
+            return Err(UserOperationError::User(
+                UserAuthDataError::WrongIntermediateKey,
+            ));
+        }
+
+        let temp: [u8; 32] = self.intermediate_key_salt.into();
+        let intermediate_derived_key = LEGACY_KDF.derive(intermediate_key, &temp)?;
+
+        let key = Key::<Aes256Gcm>::from(intermediate_derived_key);
+
+        let main_cipher = Aes256Gcm::new(&key);
+        let temp: [u8; 12] = self.enc_main_nonce.into();
+        let main_nonce = Nonce::from(temp);
+
+        let decrypted_main = main_cipher
+            .decrypt(&main_nonce, self.enc_main.as_ref())
+            .map(SecretBytes::new)
+            .map_err(UserOperationError::EncryptionError)?;
+
+        if !verify(decrypted_main.expose(), &self.main_hash)
+            .map_err(UserOperationError::HashingError)?
+        {
+            return Err(UserOperationError::User(
+                UserAuthDataError::WrongIntermediateKey,
+            ));
+        }
+
+        Ok(decrypted_main)
+    }
+}
+
+bytevec_decl! {
+    #[derive(Debug, Eq, PartialEq, Clone)]
+    pub struct SecondaryPasswordV0 {
+        enc_intermediate_nonce: AuthDataNonce,
+        enc_intermediate: Vec<u8>,
+        password_salt: AuthDataSalt,
+        password_hash: String
+    }
+}
+
+impl SecondaryPasswordV0 {
+    /// Only used to produce entries in this format for tests
+    #[cfg(test)]
+    pub(crate) fn new(intermediate: &[u8], password: &[u8]) -> Result<Self, UserOperationError> {
+        let password_salt = crate::random_salt();
+        let (enc_intermediate, nonce) =
+            crate::encrypt_with_password(&LEGACY_KDF, password, &password_salt, intermediate)?;
+
+        Ok(Self {
+            enc_intermediate_nonce: AuthDataNonce::from(nonce),
+            enc_intermediate,
+            password_salt: AuthDataSalt::from(password_salt),
+            password_hash: bcrypt::hash(password, bcrypt::DEFAULT_COST)
+                .map_err(UserOperationError::HashingError)?,
+        })
+    }
+
+    pub(crate) fn kdf(&self) -> &KdfParams {
+        &LEGACY_KDF
+    }
+
+    pub(crate) fn intermediate(&self, password: &[u8]) -> Result<SecretBytes, UserOperationError> {
+        if !verify(password, self.password_hash.as_str())
+            .map_err(UserOperationError::HashingError)?
+        {
+            return Err(UserOperationError::User(
+                UserAuthDataError::CouldNotAuthenticate,
+            ));
+        }
+
+        let temp: [u8; 32] = self.password_salt.into();
+        let password_derived_key = LEGACY_KDF.derive(password, &temp)?;
+
+        let key = Key::<Aes256Gcm>::from(password_derived_key);
+        let cipher = Aes256Gcm::new(&key);
+
+        let temp: [u8; 12] = self.enc_intermediate_nonce.into();
+        let nonce = Nonce::from(temp);
+
+        let dec_result = cipher
+            .decrypt(&nonce, self.enc_intermediate.as_ref())
+            .map(SecretBytes::new)
+            .map_err(UserOperationError::EncryptionError)?;
+
+        Ok(dec_result)
+    }
+}
+
+pub(crate) fn decode_main_password(
+    bytes: &[u8],
+    format: u32,
+) -> Result<MainPasswordV0, StorageError> {
+    match format {
+        AUTH_BLOB_FORMAT_V0 => Ok(MainPasswordV0::decode::<u16>(bytes)?),
+        _ => Err(StorageError::UnhandledVersion),
+    }
+}
+
+pub(crate) fn decode_secondary_password(
+    bytes: &[u8],
+    format: u32,
+) -> Result<SecondaryPasswordV0, StorageError> {
+    match format {
+        AUTH_BLOB_FORMAT_V0 => Ok(SecondaryPasswordV0::decode::<u16>(bytes)?),
+        _ => Err(StorageError::UnhandledVersion),
+    }
+}
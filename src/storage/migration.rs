@@ -0,0 +1,145 @@
+/*
+    pam_polyauth: A pam module written in rust that supports multiple
+    authentication modes (including autologin).
+
+    Copyright (C) 2024-2025  Denis Benato
+
+    This program is free software; you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation; either version 2 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License along
+    with this program; if not, write to the Free Software Foundation, Inc.,
+    51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+*/
+
+use serde_json::Value;
+
+use super::StorageError;
+
+/// Version written in the `version` field of every user configuration file.
+///
+/// Bump this and register a step in MIGRATIONS whenever the layout of UserConfig changes
+/// in a way that serde defaults cannot cover: fields added since the first release
+/// (i.e. blob formats, session arguments) default to what older files meant.
+pub const CURRENT_CONFIG_VERSION: u32 = 0;
+
+/// A step that brings a configuration from version `from` to version `from + 1`
+pub(crate) struct Migration {
+    pub(crate) from: u32,
+    pub(crate) description: &'static str,
+    pub(crate) apply: fn(&mut Value) -> Result<(), StorageError>,
+}
+
+/// Registered migration steps, ordered by the version they start from.
+///
+/// Steps work on the raw JSON so that they keep working after UserConfig has changed.
+const MIGRATIONS: &[Migration] = &[];
+
+/// Outcome of migrating a configuration file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MigrationReport {
+    from: u32,
+    to: u32,
+    steps: Vec<&'static str>,
+}
+
+impl MigrationReport {
+    /// Report for a configuration already at the current version
+    pub(crate) fn current() -> Self {
+        Self {
+            from: CURRENT_CONFIG_VERSION,
+            to: CURRENT_CONFIG_VERSION,
+            steps: vec![],
+        }
+    }
+
+    /// Combines the reports of configurations stored in more than one file
+    pub(crate) fn merge(mut self, other: Self) -> Self {
+        self.from = self.from.min(other.from);
+        self.to = self.to.max(other.to);
+        for step in other.steps {
+            if !self.steps.contains(&step) {
+                self.steps.push(step);
+            }
+        }
+
+        self
+    }
+
+    pub fn from_version(&self) -> u32 {
+        self.from
+    }
+
+    pub fn to_version(&self) -> u32 {
+        self.to
+    }
+
+    /// Descriptions of the steps applied, in order
+    pub fn steps(&self) -> &[&'static str] {
+        &self.steps
+    }
+
+    pub fn is_needed(&self) -> bool {
+        self.from != self.to
+    }
+}
+
+/// Reads the version of a configuration; files that predate versioning have none and are version 0
+pub(crate) fn config_version(config: &Value) -> Result<u32, StorageError> {
+    match config.get("version") {
+        None => Ok(0),
+        Some(version) => version
+            .as_u64()
+            .and_then(|v| u32::try_from(v).ok())
+            .ok_or(StorageError::DeserializationError),
+    }
+}
+
+/// Brings a configuration to CURRENT_CONFIG_VERSION, refusing files written by a newer release.
+pub(crate) fn migrate(config: &mut Value) -> Result<MigrationReport, StorageError> {
+    migrate_value(config, MIGRATIONS, CURRENT_CONFIG_VERSION)
+}
+
+/// Brings a configuration to version `to` applying the given steps in order.
+///
+/// A version with no step starting from it is an error: the configuration is left as it is
+/// written on disk rather than guessed.
+pub(crate) fn migrate_value(
+    config: &mut Value,
+    steps: &[Migration],
+    to: u32,
+) -> Result<MigrationReport, StorageError> {
+    let from = config_version(config)?;
+    if from > to {
+        return Err(StorageError::FutureConfigVersion(from));
+    }
+
+    let mut applied = vec![];
+    for version in from..to {
+        let step = steps
+            .iter()
+            .find(|m| m.from == version)
+            .ok_or(StorageError::DeserializationError)?;
+
+        (step.apply)(config)?;
+        applied.push(step.description);
+    }
+
+    config
+        .as_object_mut()
+        .ok_or(StorageError::DeserializationError)?
+        .insert(String::from("version"), Value::from(to));
+
+    Ok(MigrationReport {
+        from,
+        to,
+        steps: applied,
+    })
+}
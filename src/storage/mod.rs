@@ -0,0 +1,607 @@
+/*
+    pam_polyauth: A pam module written in rust that supports multiple
+    authentication modes (including autologin).
+
+    Copyright (C) 2024-2025  Denis Benato
+
+    This program is free software; you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation; either version 2 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License along
+    with this program; if not, write to the Free Software Foundation, Inc.,
+    51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+*/
+
+pub mod export;
+mod file;
+pub mod integrity;
+pub mod legacy;
+pub mod migration;
+pub mod store;
+
+use std::{collections::HashMap, fs, path::Path};
+
+use crate::{
+    auth::{SecondaryAuth, SecondaryAuthMethod, SecondaryPassword},
+    command::SessionCommand,
+    kdf::KdfError,
+    mount::{MountParams, MountPoints},
+    storage::integrity::{ConfigIntegrity, ConfigSigningKey},
+    storage::migration::{MigrationReport, CURRENT_CONFIG_VERSION},
+    storage::store::UserStore,
+    user::{MainPassword, StoredMainPassword, UserAuthData},
+};
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use bytevec2::{ByteDecodable, ByteEncodable};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum StorageError {
+    #[error("Uhandled data version")]
+    UnhandledVersion,
+
+    #[error("Configuration version {0} was written by a newer release")]
+    FutureConfigVersion(u32),
+
+    #[error("Username not recognised")]
+    UserDiscoveryError,
+
+    #[error("Invalid username '{0}'")]
+    InvalidUsername(String),
+
+    #[error("IO Error: {0}")]
+    IoError(#[from] std::io::Error),
+
+    #[error("JSON serialization error: {0}")]
+    JsonError(#[from] serde_json::Error),
+
+    #[error("Bytevec serialization error: {0}")]
+    SerializationError(#[from] bytevec2::errors::ByteVecError),
+
+    #[error("Deserialization error")]
+    DeserializationError,
+
+    #[error("The storage is read-only")]
+    ReadOnly,
+
+    #[error("Not a polyauth export file")]
+    InvalidExport,
+
+    #[error("Export file version {0} was written by a newer release")]
+    UnsupportedExportVersion(u32),
+
+    #[error("Wrong passphrase or corrupted export file")]
+    ExportDecryption,
+
+    #[error("Key derivation error: {0}")]
+    Kdf(#[from] KdfError),
+}
+
+/// Format of the base64-encoded MainPassword and SecondaryPassword blobs:
+/// entries are authenticated by AES-GCM alone, with no stored hash to check guesses against.
+///
+/// Format 0 (see legacy.rs) is still loaded: those entries are written back
+/// unchanged until a login re-encrypts them in this format.
+const AUTH_BLOB_FORMAT: u32 = 1;
+
+/// Everything stored about a user, as read and written by a UserStore
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UserConfig {
+    version: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    session_command: Option<SessionCommand>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    auth_data: Option<AuthDataSerialized>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    mountpoints: Option<MountPointsConfig>,
+}
+
+impl UserConfig {
+    fn new() -> Self {
+        Self {
+            version: CURRENT_CONFIG_VERSION,
+            session_command: None,
+            auth_data: None,
+            mountpoints: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct MountPointsConfig {
+    home: MountPointSerialized,
+    #[serde(default)]
+    additional: Vec<MountPointSerialized>,
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize)]
+struct MountPointSerialized {
+    fstype: String,
+    device: String,
+    directory: String,
+    args: Vec<String>,
+}
+
+impl From<(&String, &MountParams)> for MountPointSerialized {
+    fn from(mount_param: (&String, &MountParams)) -> Self {
+        Self {
+            directory: mount_param.0.clone(),
+            fstype: mount_param.1.fstype().clone(),
+            device: mount_param.1.device().clone(),
+            args: mount_param.1.flags().clone(),
+        }
+    }
+}
+
+impl From<&MountPointSerialized> for (String, MountParams) {
+    fn from(serialized: &MountPointSerialized) -> Self {
+        (
+            serialized.directory.clone(),
+            MountParams::new(
+                serialized.device.clone(),
+                serialized.fstype.clone(),
+                serialized.args.clone(),
+            ),
+        )
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct AuthDataSerialized {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    main: Option<String>, // base64-encoded MainPassword
+    #[serde(default)]
+    main_format: u32,
+    #[serde(default)]
+    secondary: Vec<SecondaryAuthItem>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct SecondaryAuthItem {
+    name: String,
+    creation_date: u64,
+    auth_type: u32,
+    password: String, // base64-encoded SecondaryPassword
+    #[serde(default)]
+    format: u32,
+    // whether the password is empty: absent in entries written before it was recorded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    autologin: Option<bool>,
+}
+
+// costs out of range are refused as a corrupt entry, before anything is derived from them
+fn decode_main_password(bytes: &[u8], format: u32) -> Result<StoredMainPassword, StorageError> {
+    match format {
+        AUTH_BLOB_FORMAT => {
+            let main = MainPassword::decode::<u16>(bytes)?;
+            main.kdf()
+                .check()
+                .map_err(|_| StorageError::DeserializationError)?;
+            Ok(StoredMainPassword::Current(main))
+        }
+        _ => Ok(StoredMainPassword::Legacy(legacy::decode_main_password(
+            bytes, format,
+        )?)),
+    }
+}
+
+fn decode_secondary_password(
+    bytes: &[u8],
+    format: u32,
+) -> Result<SecondaryAuthMethod, StorageError> {
+    match format {
+        AUTH_BLOB_FORMAT => {
+            let password = SecondaryPassword::decode::<u16>(bytes)?;
+            password
+                .kdf()
+                .check()
+                .map_err(|_| StorageError::DeserializationError)?;
+            Ok(SecondaryAuthMethod::Password(password))
+        }
+        _ => Ok(SecondaryAuthMethod::LegacyPassword(
+            legacy::decode_secondary_password(bytes, format)?,
+        )),
+    }
+}
+
+fn encode_main_password(main: &StoredMainPassword) -> Result<(String, u32), StorageError> {
+    let (main_bytes, format) = match main {
+        StoredMainPassword::Current(m) => (m.encode::<u16>()?, AUTH_BLOB_FORMAT),
+        StoredMainPassword::Legacy(m) => (m.encode::<u16>()?, legacy::AUTH_BLOB_FORMAT_V0),
+    };
+
+    Ok((BASE64.encode(&main_bytes), format))
+}
+
+/// Runs f giving up at once on configurations another writer has locked,
+/// instead of waiting for it: the store fails with an ErrorKind::WouldBlock IoError.
+pub fn without_waiting_for_locks<T>(f: impl FnOnce() -> T) -> T {
+    file::with_lock_timeout(std::time::Duration::ZERO, f)
+}
+
+/// Whether the store failed because another writer holds the lock, see without_waiting_for_locks
+pub fn is_locked_error(err: &StorageError) -> bool {
+    matches!(err, StorageError::IoError(io) if io.kind() == std::io::ErrorKind::WouldBlock)
+}
+
+/// Loads a configuration file, migrating it in memory if it was written by an older release
+pub(crate) fn load_config_from_path(path: &Path) -> Result<Option<UserConfig>, StorageError> {
+    if !path.exists() {
+        return Ok(None);
+    }
+
+    let mut raw = file::read_with_backup(path, |contents| {
+        serde_json::from_str::<serde_json::Value>(contents).map_err(StorageError::from)
+    })?;
+    migration::migrate(&mut raw)?;
+    let config: UserConfig = serde_json::from_value(raw)?;
+    Ok(Some(config))
+}
+
+/// Atomically replaces a configuration file, see file::write_atomic.
+///
+/// mode is only used when the file does not exist yet.
+pub(crate) fn save_config_to_path(
+    path: &Path,
+    config: &UserConfig,
+    uid: Option<u32>,
+    gid: Option<u32>,
+    mode: u32,
+) -> Result<(), StorageError> {
+    // Create parent directory if it doesn't exist
+    if let Some(parent) = path.parent() {
+        if !parent.as_os_str().is_empty() && !parent.exists() {
+            fs::create_dir_all(parent)?;
+        }
+    }
+
+    let contents = serde_json::to_string_pretty(config)?;
+    file::write_atomic(path, contents.as_bytes(), uid, gid, mode)?;
+
+    Ok(())
+}
+
+/// Brings a configuration file to the current version, see migrate_user_config.
+///
+/// The caller must hold the lock of the file unless dry_run is set.
+pub(crate) fn migrate_config_at_path(
+    path: &Path,
+    dry_run: bool,
+) -> Result<Option<MigrationReport>, StorageError> {
+    migrate_config_at_path_with(path, dry_run, migration::migrate)
+}
+
+/// Same as migrate_config_at_path, with the migration applied to the raw configuration given by the caller
+pub(crate) fn migrate_config_at_path_with(
+    path: &Path,
+    dry_run: bool,
+    migrate: impl FnOnce(&mut serde_json::Value) -> Result<MigrationReport, StorageError>,
+) -> Result<Option<MigrationReport>, StorageError> {
+    if !path.exists() {
+        return Ok(None);
+    }
+
+    let mut raw = file::read_with_backup(path, |contents| {
+        serde_json::from_str::<serde_json::Value>(contents).map_err(StorageError::from)
+    })?;
+    let report = migrate(&mut raw)?;
+
+    // make sure the migrated configuration is one this release can load
+    let config: UserConfig = serde_json::from_value(raw)?;
+
+    if report.is_needed() && !dry_run {
+        save_config_to_path(path, &config, None, None, file::CONFIG_FILE_MODE)?;
+    }
+
+    Ok(Some(report))
+}
+
+/// Serializes the sections that decide what happens on login (mounts and session command):
+/// these are covered by the configuration signature, authentication data is not.
+///
+/// Additional mounts are sorted so that storing the same mounts again keeps the signature valid.
+fn signed_sections(config: &UserConfig) -> Result<Vec<u8>, StorageError> {
+    let mut mountpoints = config.mountpoints.clone();
+    if let Some(mountpoints) = mountpoints.as_mut() {
+        mountpoints
+            .additional
+            .sort_by(|a, b| a.directory.cmp(&b.directory));
+    }
+
+    let sections = serde_json::json!({
+        "mountpoints": mountpoints,
+        "session_command": config.session_command,
+    });
+
+    Ok(serde_json::to_vec(&sections)?)
+}
+
+fn config_integrity(
+    config: Option<&UserConfig>,
+    store: &dyn UserStore,
+    username: &str,
+    key: &ConfigSigningKey,
+) -> Result<ConfigIntegrity, StorageError> {
+    let Some(tag) = store.read_signature(username)? else {
+        return Ok(ConfigIntegrity::Unsigned);
+    };
+
+    let empty = UserConfig::new();
+    let sections = signed_sections(config.unwrap_or(&empty))?;
+    match key.verify(username, &sections, &tag) {
+        true => Ok(ConfigIntegrity::Signed),
+        false => Ok(ConfigIntegrity::Tampered),
+    }
+}
+
+/// Signs the mounts and session command of the configuration as they are now,
+/// storing the tag where the user cannot remove it (`<config>.sig` for JSON stores).
+pub fn sign_user_config(
+    store: &dyn UserStore,
+    username: &str,
+    key: &ConfigSigningKey,
+) -> Result<(), StorageError> {
+    let Some(config) = store.read(username)? else {
+        return Err(StorageError::IoError(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            "there is no configuration to sign",
+        )));
+    };
+
+    let tag = key.sign(username, &signed_sections(&config)?);
+    store.write_signature(username, &tag)
+}
+
+/// Checks the configuration against the signature produced by sign_user_config
+pub fn verify_user_config(
+    store: &dyn UserStore,
+    username: &str,
+    key: &ConfigSigningKey,
+) -> Result<ConfigIntegrity, StorageError> {
+    let config = store.read(username)?;
+    config_integrity(config.as_ref(), store, username, key)
+}
+
+/// Brings the configuration of the user to the current version, returning what was (or would be) done.
+///
+/// Older files are migrated in memory on every load anyway: this persists the result.
+/// With dry_run nothing is written. Returns None if there is no configuration.
+pub fn migrate_user_config(
+    store: &dyn UserStore,
+    username: &str,
+    dry_run: bool,
+) -> Result<Option<MigrationReport>, StorageError> {
+    store.migrate(username, dry_run)
+}
+
+pub fn load_user_session_command(
+    store: &dyn UserStore,
+    username: &str,
+) -> Result<Option<SessionCommand>, StorageError> {
+    let config = store.read(username)?;
+    Ok(config.and_then(|c| c.session_command))
+}
+
+/// Loads the session command together with the result of checking the configuration signature,
+/// see load_verified_user_mountpoints.
+pub fn load_verified_user_session_command(
+    store: &dyn UserStore,
+    username: &str,
+    key: &ConfigSigningKey,
+) -> Result<(ConfigIntegrity, Option<SessionCommand>), StorageError> {
+    let config = store.read(username)?;
+    let integrity = config_integrity(config.as_ref(), store, username, key)?;
+    Ok((integrity, config.and_then(|c| c.session_command)))
+}
+
+pub fn store_user_session_command(
+    settings: &SessionCommand,
+    store: &dyn UserStore,
+    username: &str,
+    uid: Option<u32>,
+    gid: Option<u32>,
+) -> Result<(), StorageError> {
+    store.update(username, uid, gid, &mut |config| {
+        config.session_command = Some(settings.clone());
+        Ok(())
+    })
+}
+
+pub fn load_user_auth_data(
+    store: &dyn UserStore,
+    username: &str,
+) -> Result<Option<UserAuthData>, StorageError> {
+    auth_data_from_config(store.read(username)?)
+}
+
+fn auth_data_from_config(config: Option<UserConfig>) -> Result<Option<UserAuthData>, StorageError> {
+    let Some(config) = config else {
+        return Ok(None);
+    };
+
+    let Some(auth_data_ser) = config.auth_data else {
+        return Ok(None);
+    };
+
+    let mut auth_data = UserAuthData::new();
+
+    // Deserialize main password from base64
+    if let Some(main_b64) = auth_data_ser.main {
+        let main_bytes = BASE64
+            .decode(&main_b64)
+            .map_err(|_| StorageError::DeserializationError)?;
+        let main = decode_main_password(&main_bytes, auth_data_ser.main_format)?;
+        auth_data.push_main(main);
+    } else {
+        return Ok(None);
+    }
+
+    // Deserialize secondary auth
+    for item in auth_data_ser.secondary {
+        let password_bytes = BASE64
+            .decode(&item.password)
+            .map_err(|_| StorageError::DeserializationError)?;
+        let method = decode_secondary_password(&password_bytes, item.format)?;
+
+        match item.auth_type {
+            0 => {
+                let secondary_auth =
+                    SecondaryAuth::new(&item.name, Some(item.creation_date), method)
+                        .with_autologin(item.autologin);
+                auth_data.push_secondary(secondary_auth);
+            }
+            _ => return Err(StorageError::DeserializationError),
+        }
+    }
+
+    Ok(Some(auth_data))
+}
+
+pub fn remove_user_data(store: &dyn UserStore, username: &str) -> Result<(), StorageError> {
+    store.remove(username)
+}
+
+pub fn store_user_auth_data(
+    auth_data: &UserAuthData,
+    store: &dyn UserStore,
+    username: &str,
+    uid: Option<u32>,
+    gid: Option<u32>,
+) -> Result<(), StorageError> {
+    // Serialize main password to base64
+    let (main_b64, main_format) = match auth_data.main_password() {
+        Some(m) => {
+            let (main_b64, main_format) = encode_main_password(m)?;
+            (Some(main_b64), main_format)
+        }
+        None => (None, AUTH_BLOB_FORMAT),
+    };
+
+    // Serialize secondary auth
+    let mut secondary = Vec::new();
+    for val in auth_data.secondary() {
+        let name = val.name();
+        let creation_date = val.creation_date();
+
+        let (auth_type, password_b64, format) = match val.data() {
+            SecondaryAuthMethod::Password(secondary_password) => {
+                let password_bytes = secondary_password.encode::<u16>()?;
+                (0, BASE64.encode(&password_bytes), AUTH_BLOB_FORMAT)
+            }
+            SecondaryAuthMethod::LegacyPassword(secondary_password) => {
+                let password_bytes = secondary_password.encode::<u16>()?;
+                (
+                    0,
+                    BASE64.encode(&password_bytes),
+                    legacy::AUTH_BLOB_FORMAT_V0,
+                )
+            }
+        };
+
+        secondary.push(SecondaryAuthItem {
+            name,
+            creation_date,
+            auth_type,
+            password: password_b64,
+            format,
+            autologin: val.autologin(),
+        });
+    }
+
+    let auth_data = AuthDataSerialized {
+        main: main_b64,
+        main_format,
+        secondary,
+    };
+
+    store.update(username, uid, gid, &mut |config| {
+        config.auth_data = Some(auth_data.clone());
+        Ok(())
+    })
+}
+
+fn mountpoints_from_config(config: Option<UserConfig>) -> Option<MountPoints> {
+    let mountpoints_cfg = config?.mountpoints?;
+
+    // Convert serialized home mount to MountParams
+    let home_mount = MountParams::new(
+        mountpoints_cfg.home.device,
+        mountpoints_cfg.home.fstype,
+        mountpoints_cfg.home.args,
+    );
+
+    // Convert additional mounts
+    let mut mounts = HashMap::new();
+    for mount_ser in mountpoints_cfg.additional {
+        let mount_params = MountParams::new(mount_ser.device, mount_ser.fstype, mount_ser.args);
+        mounts.insert(mount_ser.directory, mount_params);
+    }
+
+    Some(MountPoints::new(home_mount, mounts))
+}
+
+pub fn load_user_mountpoints(
+    store: &dyn UserStore,
+    username: &str,
+) -> Result<Option<MountPoints>, StorageError> {
+    let config = store.read(username)?;
+    Ok(mountpoints_from_config(config))
+}
+
+/// Loads user mounts together with the result of checking the configuration signature:
+/// both come from the same read of the file, so the mounts are exactly the ones checked.
+pub fn load_verified_user_mountpoints(
+    store: &dyn UserStore,
+    username: &str,
+    key: &ConfigSigningKey,
+) -> Result<(ConfigIntegrity, Option<MountPoints>), StorageError> {
+    let config = store.read(username)?;
+    let integrity = config_integrity(config.as_ref(), store, username, key)?;
+    Ok((integrity, mountpoints_from_config(config)))
+}
+
+pub fn store_user_mountpoints(
+    mountpoints_data: Option<MountPoints>,
+    store: &dyn UserStore,
+    username: &str,
+    uid: Option<u32>,
+    gid: Option<u32>,
+) -> Result<(), StorageError> {
+    let Some(mountpoints) = mountpoints_data else {
+        return store.update(username, uid, gid, &mut |config| {
+            config.mountpoints = None;
+            Ok(())
+        });
+    };
+
+    // Serialize home mount
+    let home = MountPointSerialized {
+        fstype: mountpoints.mount().fstype().clone(),
+        device: mountpoints.mount().device().clone(),
+        directory: String::new(),
+        args: mountpoints.mount().flags().clone(),
+    };
+
+    // Serialize additional mounts
+    let additional = mountpoints.foreach(|dir, params| MountPointSerialized {
+        fstype: params.fstype().clone(),
+        device: params.device().clone(),
+        directory: dir.clone(),
+        args: params.flags().clone(),
+    });
+
+    let mountpoints = MountPointsConfig { home, additional };
+    store.update(username, uid, gid, &mut |config| {
+        config.mountpoints = Some(mountpoints.clone());
+        Ok(())
+    })
+}
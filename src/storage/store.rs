@@ -0,0 +1,773 @@
+/*
+    pam_polyauth: A pam module written in rust that supports multiple
+    authentication modes (including autologin).
+
+    Copyright (C) 2024-2025  Denis Benato
+
+    This program is free software; you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation; either version 2 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License along
+    with this program; if not, write to the Free Software Foundation, Inc.,
+    51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+*/
+
+use std::{
+    collections::HashMap,
+    fs,
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::Mutex,
+};
+
+use super::{
+    file, load_config_from_path, migrate_config_at_path, migration::MigrationReport,
+    save_config_to_path, StorageError, UserConfig,
+};
+
+/// Directory holding the configuration of every user
+pub const POLYAUTH_CONFIG_DIR: &str = "/etc/polyauth";
+
+/// Read-only defaults shipped by the vendor,
+/// used for every section a configured user has not set
+pub const POLYAUTH_VENDOR_DEFAULTS: &str = "/usr/lib/polyauth/defaults.json";
+
+/// Where and how user configurations are kept.
+///
+/// Every load_*/store_* function of this module goes through a UserStore,
+/// so that the PAM module, the service and polyauthctl work on any layout.
+pub trait UserStore: Send + Sync {
+    /// Reads the configuration of the user, None if there is none
+    fn read(&self, username: &str) -> Result<Option<UserConfig>, StorageError>;
+
+    /// Reads the configuration of the user (an empty one if there is none),
+    /// lets update modify it and writes it back as a single locked operation.
+    ///
+    /// Files that are created are given to uid/gid, if specified.
+    fn update(
+        &self,
+        username: &str,
+        uid: Option<u32>,
+        gid: Option<u32>,
+        update: &mut dyn FnMut(&mut UserConfig) -> Result<(), StorageError>,
+    ) -> Result<(), StorageError>;
+
+    /// Removes the configuration of the user
+    fn remove(&self, username: &str) -> Result<(), StorageError>;
+
+    /// Brings the stored configuration to the current version, see migrate_user_config
+    fn migrate(
+        &self,
+        username: &str,
+        dry_run: bool,
+    ) -> Result<Option<MigrationReport>, StorageError>;
+
+    /// Reads the signature of the configuration of the user, None if it was never signed
+    fn read_signature(&self, username: &str) -> Result<Option<String>, StorageError>;
+
+    /// Stores the signature of the configuration of the user
+    fn write_signature(&self, username: &str, tag: &str) -> Result<(), StorageError>;
+
+    /// Human readable location of the configuration of the user
+    fn location(&self, username: &str) -> String;
+}
+
+/// Usernames end up in paths: refuse the ones that could name anything but their own configuration
+pub fn check_username(username: &str) -> Result<(), StorageError> {
+    if username.is_empty()
+        || username.starts_with('.')
+        || username.contains('/')
+        || username.contains('\0')
+    {
+        return Err(StorageError::InvalidUsername(String::from(username)));
+    }
+
+    Ok(())
+}
+
+fn read_signature_file(path: &Path) -> Result<Option<String>, StorageError> {
+    match fs::read_to_string(path) {
+        Ok(tag) => Ok(Some(tag)),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(StorageError::IoError(err)),
+    }
+}
+
+fn write_signature_file(path: &Path, tag: &str) -> Result<(), StorageError> {
+    file::write_atomic(
+        path,
+        format!("{tag}\n").as_bytes(),
+        None,
+        None,
+        file::CONFIG_FILE_MODE,
+    )?;
+
+    Ok(())
+}
+
+/// The whole configuration of a user in a single JSON file:
+/// either `<dir>/<username>.json` or a fixed file whatever the username.
+pub struct JsonStore {
+    location: JsonLocation,
+}
+
+enum JsonLocation {
+    Directory(PathBuf),
+    File(PathBuf),
+}
+
+impl JsonStore {
+    /// Configurations are kept in `<dir>/<username>.json`
+    pub fn directory(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            location: JsonLocation::Directory(dir.into()),
+        }
+    }
+
+    /// The configuration is kept in the given file, whatever the username
+    pub fn file(path: impl Into<PathBuf>) -> Self {
+        Self {
+            location: JsonLocation::File(path.into()),
+        }
+    }
+
+    pub fn path(&self, username: &str) -> Result<PathBuf, StorageError> {
+        check_username(username)?;
+
+        Ok(match &self.location {
+            JsonLocation::Directory(dir) => dir.join(format!("{username}.json")),
+            JsonLocation::File(path) => path.clone(),
+        })
+    }
+}
+
+impl UserStore for JsonStore {
+    fn read(&self, username: &str) -> Result<Option<UserConfig>, StorageError> {
+        load_config_from_path(&self.path(username)?)
+    }
+
+    fn update(
+        &self,
+        username: &str,
+        uid: Option<u32>,
+        gid: Option<u32>,
+        update: &mut dyn FnMut(&mut UserConfig) -> Result<(), StorageError>,
+    ) -> Result<(), StorageError> {
+        let path = self.path(username)?;
+        let _lock = file::ConfigLock::acquire(&path, uid, gid)?;
+
+        let mut config = load_config_from_path(&path)?.unwrap_or_else(UserConfig::new);
+        update(&mut config)?;
+        save_config_to_path(&path, &config, uid, gid, file::CONFIG_FILE_MODE)
+    }
+
+    fn remove(&self, username: &str) -> Result<(), StorageError> {
+        let path = self.path(username)?;
+        let _lock = file::ConfigLock::acquire(&path, None, None)?;
+
+        file::remove(&path)?;
+
+        Ok(())
+    }
+
+    fn migrate(
+        &self,
+        username: &str,
+        dry_run: bool,
+    ) -> Result<Option<MigrationReport>, StorageError> {
+        let path = self.path(username)?;
+        let _lock = match dry_run {
+            true => None,
+            false => Some(file::ConfigLock::acquire(&path, None, None)?),
+        };
+
+        migrate_config_at_path(&path, dry_run)
+    }
+
+    fn read_signature(&self, username: &str) -> Result<Option<String>, StorageError> {
+        read_signature_file(&file::signature_path(&self.path(username)?))
+    }
+
+    fn write_signature(&self, username: &str, tag: &str) -> Result<(), StorageError> {
+        write_signature_file(&file::signature_path(&self.path(username)?), tag)
+    }
+
+    fn location(&self, username: &str) -> String {
+        match self.path(username) {
+            Ok(path) => path.to_string_lossy().to_string(),
+            Err(err) => err.to_string(),
+        }
+    }
+}
+
+/// A directory per user, `<dir>/<username>/`, with each section in its own file:
+/// `auth.json`, `mounts.json` and `session.json`.
+///
+/// Only `auth.json` is given to the user: mounts and session command are created
+/// world-readable but owned by whoever writes them first (root, when set up by an administrator),
+/// so users can change their passwords but not what happens on login.
+pub struct SplitStore {
+    dir: PathBuf,
+}
+
+#[derive(Clone, Copy)]
+enum Section {
+    Auth,
+    Mounts,
+    Session,
+}
+
+const SECTIONS: [Section; 3] = [Section::Auth, Section::Mounts, Section::Session];
+
+impl Section {
+    fn file_name(&self) -> &'static str {
+        match self {
+            Section::Auth => "auth.json",
+            Section::Mounts => "mounts.json",
+            Section::Session => "session.json",
+        }
+    }
+
+    fn is_set(&self, config: &UserConfig) -> bool {
+        match self {
+            Section::Auth => config.auth_data.is_some(),
+            Section::Mounts => config.mountpoints.is_some(),
+            Section::Session => config.session_command.is_some(),
+        }
+    }
+
+    /// Moves this section of from into into
+    fn merge(&self, into: &mut UserConfig, from: UserConfig) {
+        match self {
+            Section::Auth => into.auth_data = from.auth_data,
+            Section::Mounts => into.mountpoints = from.mountpoints,
+            Section::Session => into.session_command = from.session_command,
+        }
+    }
+
+    /// A configuration holding only this section of config
+    fn extract(&self, config: &UserConfig) -> UserConfig {
+        let mut extracted = UserConfig::new();
+        self.merge(&mut extracted, config.clone());
+        extracted
+    }
+}
+
+impl SplitStore {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    pub fn user_dir(&self, username: &str) -> Result<PathBuf, StorageError> {
+        check_username(username)?;
+
+        Ok(self.dir.join(username))
+    }
+
+    fn section_path(&self, username: &str, section: Section) -> Result<PathBuf, StorageError> {
+        Ok(self.user_dir(username)?.join(section.file_name()))
+    }
+
+    // lock and signature are shared by all the sections
+    fn config_path(&self, username: &str) -> Result<PathBuf, StorageError> {
+        Ok(self.user_dir(username)?.join("config"))
+    }
+
+    // the lock belongs to the user, like auth.json, so that they can take it
+    // in a directory they cannot create files in
+    fn lock(
+        &self,
+        username: &str,
+        uid: Option<u32>,
+        gid: Option<u32>,
+    ) -> Result<file::ConfigLock, StorageError> {
+        let (uid, gid) = match (
+            uid,
+            fs::metadata(self.section_path(username, Section::Auth)?),
+        ) {
+            (Some(uid), _) => (Some(uid), gid),
+            (None, Ok(metadata)) => (Some(metadata.uid()), Some(metadata.gid())),
+            (None, Err(_)) => (None, None),
+        };
+
+        Ok(file::ConfigLock::acquire(
+            &self.config_path(username)?,
+            uid,
+            gid,
+        )?)
+    }
+}
+
+impl UserStore for SplitStore {
+    fn read(&self, username: &str) -> Result<Option<UserConfig>, StorageError> {
+        let mut config: Option<UserConfig> = None;
+        for section in SECTIONS {
+            if let Some(stored) = load_config_from_path(&self.section_path(username, section)?)? {
+                section.merge(config.get_or_insert_with(UserConfig::new), stored);
+            }
+        }
+
+        Ok(config)
+    }
+
+    fn update(
+        &self,
+        username: &str,
+        uid: Option<u32>,
+        gid: Option<u32>,
+        update: &mut dyn FnMut(&mut UserConfig) -> Result<(), StorageError>,
+    ) -> Result<(), StorageError> {
+        let _lock = self.lock(username, uid, gid)?;
+
+        let previous = self.read(username)?.unwrap_or_else(UserConfig::new);
+        let mut config = previous.clone();
+        update(&mut config)?;
+
+        // only sections that changed are written: the others may not be writable by the caller
+        for section in SECTIONS {
+            let before = serde_json::to_value(section.extract(&previous))?;
+            let after = serde_json::to_value(section.extract(&config))?;
+            if before == after {
+                continue;
+            }
+
+            let path = self.section_path(username, section)?;
+            if !section.is_set(&config) {
+                file::remove(&path)?;
+                continue;
+            }
+
+            match section {
+                Section::Auth => save_config_to_path(
+                    &path,
+                    &section.extract(&config),
+                    uid,
+                    gid,
+                    file::CONFIG_FILE_MODE,
+                )?,
+                Section::Mounts | Section::Session => save_config_to_path(
+                    &path,
+                    &section.extract(&config),
+                    None,
+                    None,
+                    file::PUBLIC_FILE_MODE,
+                )?,
+            }
+        }
+
+        Ok(())
+    }
+
+    fn remove(&self, username: &str) -> Result<(), StorageError> {
+        let _lock = self.lock(username, None, None)?;
+
+        for section in SECTIONS {
+            file::remove(&self.section_path(username, section)?)?;
+        }
+
+        Ok(())
+    }
+
+    fn migrate(
+        &self,
+        username: &str,
+        dry_run: bool,
+    ) -> Result<Option<MigrationReport>, StorageError> {
+        let _lock = match dry_run {
+            true => None,
+            false => Some(self.lock(username, None, None)?),
+        };
+
+        let mut report: Option<MigrationReport> = None;
+        for section in SECTIONS {
+            let path = self.section_path(username, section)?;
+            if let Some(migrated) = migrate_config_at_path(&path, dry_run)? {
+                report = Some(match report {
+                    Some(report) => report.merge(migrated),
+                    None => migrated,
+                });
+            }
+        }
+
+        Ok(report)
+    }
+
+    fn read_signature(&self, username: &str) -> Result<Option<String>, StorageError> {
+        read_signature_file(&file::signature_path(&self.config_path(username)?))
+    }
+
+    fn write_signature(&self, username: &str, tag: &str) -> Result<(), StorageError> {
+        write_signature_file(&file::signature_path(&self.config_path(username)?), tag)
+    }
+
+    fn location(&self, username: &str) -> String {
+        match self.user_dir(username) {
+            Ok(dir) => dir.to_string_lossy().to_string(),
+            Err(err) => err.to_string(),
+        }
+    }
+}
+
+/// Configurations kept in memory only, mostly useful for tests
+#[derive(Default)]
+pub struct MemoryStore {
+    configs: Mutex<HashMap<String, UserConfig>>,
+    signatures: Mutex<HashMap<String, String>>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl UserStore for MemoryStore {
+    fn read(&self, username: &str) -> Result<Option<UserConfig>, StorageError> {
+        Ok(self.configs.lock().unwrap().get(username).cloned())
+    }
+
+    fn update(
+        &self,
+        username: &str,
+        _uid: Option<u32>,
+        _gid: Option<u32>,
+        update: &mut dyn FnMut(&mut UserConfig) -> Result<(), StorageError>,
+    ) -> Result<(), StorageError> {
+        let mut configs = self.configs.lock().unwrap();
+
+        let mut config = configs
+            .get(username)
+            .cloned()
+            .unwrap_or_else(UserConfig::new);
+        update(&mut config)?;
+        configs.insert(String::from(username), config);
+
+        Ok(())
+    }
+
+    fn remove(&self, username: &str) -> Result<(), StorageError> {
+        self.configs.lock().unwrap().remove(username);
+        Ok(())
+    }
+
+    fn migrate(
+        &self,
+        username: &str,
+        _dry_run: bool,
+    ) -> Result<Option<MigrationReport>, StorageError> {
+        // nothing older than the current version can be held in memory
+        Ok(self
+            .configs
+            .lock()
+            .unwrap()
+            .get(username)
+            .map(|_| MigrationReport::current()))
+    }
+
+    fn read_signature(&self, username: &str) -> Result<Option<String>, StorageError> {
+        Ok(self.signatures.lock().unwrap().get(username).cloned())
+    }
+
+    fn write_signature(&self, username: &str, tag: &str) -> Result<(), StorageError> {
+        self.signatures
+            .lock()
+            .unwrap()
+            .insert(String::from(username), String::from(tag));
+        Ok(())
+    }
+
+    fn location(&self, username: &str) -> String {
+        format!("memory:{username}")
+    }
+}
+
+/// Wraps a store refusing every change to it
+pub struct ReadOnlyStore<S: UserStore> {
+    inner: S,
+}
+
+impl<S: UserStore> ReadOnlyStore<S> {
+    pub fn new(inner: S) -> Self {
+        Self { inner }
+    }
+}
+
+impl<S: UserStore> UserStore for ReadOnlyStore<S> {
+    fn read(&self, username: &str) -> Result<Option<UserConfig>, StorageError> {
+        self.inner.read(username)
+    }
+
+    fn update(
+        &self,
+        _username: &str,
+        _uid: Option<u32>,
+        _gid: Option<u32>,
+        _update: &mut dyn FnMut(&mut UserConfig) -> Result<(), StorageError>,
+    ) -> Result<(), StorageError> {
+        Err(StorageError::ReadOnly)
+    }
+
+    fn remove(&self, _username: &str) -> Result<(), StorageError> {
+        Err(StorageError::ReadOnly)
+    }
+
+    fn migrate(
+        &self,
+        username: &str,
+        _dry_run: bool,
+    ) -> Result<Option<MigrationReport>, StorageError> {
+        self.inner.migrate(username, true)
+    }
+
+    fn read_signature(&self, username: &str) -> Result<Option<String>, StorageError> {
+        self.inner.read_signature(username)
+    }
+
+    fn write_signature(&self, _username: &str, _tag: &str) -> Result<(), StorageError> {
+        Err(StorageError::ReadOnly)
+    }
+
+    fn location(&self, username: &str) -> String {
+        self.inner.location(username)
+    }
+}
+
+/// A single read-only configuration used as the defaults of every user.
+///
+/// Authentication data is never shared between users, so it is ignored.
+pub struct DefaultsStore {
+    path: PathBuf,
+}
+
+impl DefaultsStore {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+}
+
+impl UserStore for DefaultsStore {
+    fn read(&self, _username: &str) -> Result<Option<UserConfig>, StorageError> {
+        Ok(load_config_from_path(&self.path)?.map(|mut config| {
+            config.auth_data = None;
+            config
+        }))
+    }
+
+    fn update(
+        &self,
+        _username: &str,
+        _uid: Option<u32>,
+        _gid: Option<u32>,
+        _update: &mut dyn FnMut(&mut UserConfig) -> Result<(), StorageError>,
+    ) -> Result<(), StorageError> {
+        Err(StorageError::ReadOnly)
+    }
+
+    fn remove(&self, _username: &str) -> Result<(), StorageError> {
+        Err(StorageError::ReadOnly)
+    }
+
+    fn migrate(
+        &self,
+        _username: &str,
+        _dry_run: bool,
+    ) -> Result<Option<MigrationReport>, StorageError> {
+        migrate_config_at_path(&self.path, true)
+    }
+
+    fn read_signature(&self, _username: &str) -> Result<Option<String>, StorageError> {
+        Ok(None)
+    }
+
+    fn write_signature(&self, _username: &str, _tag: &str) -> Result<(), StorageError> {
+        Err(StorageError::ReadOnly)
+    }
+
+    fn location(&self, _username: &str) -> String {
+        self.path.to_string_lossy().to_string()
+    }
+}
+
+/// Reads each section from upper, falling back to lower for sections upper does not have:
+/// every change goes to upper, lower is never written.
+///
+/// Users without a configuration in upper have none: lower only fills in the gaps.
+pub struct OverlayStore<U: UserStore, L: UserStore> {
+    upper: U,
+    lower: L,
+}
+
+impl<U: UserStore, L: UserStore> OverlayStore<U, L> {
+    pub fn new(upper: U, lower: L) -> Self {
+        Self { upper, lower }
+    }
+}
+
+impl<U: UserStore, L: UserStore> UserStore for OverlayStore<U, L> {
+    fn read(&self, username: &str) -> Result<Option<UserConfig>, StorageError> {
+        let Some(mut upper) = self.upper.read(username)? else {
+            return Ok(None);
+        };
+
+        if let Some(lower) = self.lower.read(username)? {
+            for section in SECTIONS {
+                if !section.is_set(&upper) {
+                    section.merge(&mut upper, lower.clone());
+                }
+            }
+        }
+
+        Ok(Some(upper))
+    }
+
+    fn update(
+        &self,
+        username: &str,
+        uid: Option<u32>,
+        gid: Option<u32>,
+        update: &mut dyn FnMut(&mut UserConfig) -> Result<(), StorageError>,
+    ) -> Result<(), StorageError> {
+        self.upper.update(username, uid, gid, update)
+    }
+
+    fn remove(&self, username: &str) -> Result<(), StorageError> {
+        self.upper.remove(username)
+    }
+
+    fn migrate(
+        &self,
+        username: &str,
+        dry_run: bool,
+    ) -> Result<Option<MigrationReport>, StorageError> {
+        self.upper.migrate(username, dry_run)
+    }
+
+    fn read_signature(&self, username: &str) -> Result<Option<String>, StorageError> {
+        self.upper.read_signature(username)
+    }
+
+    fn write_signature(&self, username: &str, tag: &str) -> Result<(), StorageError> {
+        self.upper.write_signature(username, tag)
+    }
+
+    fn location(&self, username: &str) -> String {
+        self.upper.location(username)
+    }
+}
+
+/// Layout of the configurations in POLYAUTH_CONFIG_DIR
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum StoreLayout {
+    /// Split layout for users that have a directory, single file otherwise
+    #[default]
+    Detect,
+
+    /// `<username>.json`, see JsonStore
+    SingleFile,
+
+    /// `<username>/*.json`, see SplitStore
+    Split,
+}
+
+impl FromStr for StoreLayout {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "detect" => Ok(Self::Detect),
+            "single-file" => Ok(Self::SingleFile),
+            "split" => Ok(Self::Split),
+            _ => Err(format!(
+                "unknown layout '{s}': expected detect, single-file or split"
+            )),
+        }
+    }
+}
+
+/// Picks the layout of each user in a directory
+pub struct LayoutStore {
+    layout: StoreLayout,
+    json: JsonStore,
+    split: SplitStore,
+}
+
+impl LayoutStore {
+    pub fn new(dir: impl Into<PathBuf>, layout: StoreLayout) -> Self {
+        let dir = dir.into();
+
+        Self {
+            layout,
+            json: JsonStore::directory(dir.clone()),
+            split: SplitStore::new(dir),
+        }
+    }
+
+    fn store(&self, username: &str) -> &dyn UserStore {
+        match self.layout {
+            StoreLayout::SingleFile => &self.json,
+            StoreLayout::Split => &self.split,
+            // an invalid username is refused by either store
+            StoreLayout::Detect => match self.split.user_dir(username) {
+                Ok(dir) if dir.is_dir() => &self.split,
+                _ => &self.json,
+            },
+        }
+    }
+}
+
+impl UserStore for LayoutStore {
+    fn read(&self, username: &str) -> Result<Option<UserConfig>, StorageError> {
+        self.store(username).read(username)
+    }
+
+    fn update(
+        &self,
+        username: &str,
+        uid: Option<u32>,
+        gid: Option<u32>,
+        update: &mut dyn FnMut(&mut UserConfig) -> Result<(), StorageError>,
+    ) -> Result<(), StorageError> {
+        self.store(username).update(username, uid, gid, update)
+    }
+
+    fn remove(&self, username: &str) -> Result<(), StorageError> {
+        self.store(username).remove(username)
+    }
+
+    fn migrate(
+        &self,
+        username: &str,
+        dry_run: bool,
+    ) -> Result<Option<MigrationReport>, StorageError> {
+        self.store(username).migrate(username, dry_run)
+    }
+
+    fn read_signature(&self, username: &str) -> Result<Option<String>, StorageError> {
+        self.store(username).read_signature(username)
+    }
+
+    fn write_signature(&self, username: &str, tag: &str) -> Result<(), StorageError> {
+        self.store(username).write_signature(username, tag)
+    }
+
+    fn location(&self, username: &str) -> String {
+        self.store(username).location(username)
+    }
+}
+
+/// The store used by the PAM module, the service and polyauthctl:
+/// POLYAUTH_CONFIG_DIR over the vendor defaults in POLYAUTH_VENDOR_DEFAULTS.
+pub fn system_store(layout: StoreLayout) -> OverlayStore<LayoutStore, DefaultsStore> {
+    OverlayStore::new(
+        LayoutStore::new(POLYAUTH_CONFIG_DIR, layout),
+        DefaultsStore::new(POLYAUTH_VENDOR_DEFAULTS),
+    )
+}
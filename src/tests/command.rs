@@ -0,0 +1,76 @@
+/*
+    pam_polyauth: A pam module written in rust that supports multiple
+    authentication modes (including autologin).
+
+    Copyright (C) 2024-2025  Denis Benato
+
+    This program is free software; you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation; either version 2 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License along
+    with this program; if not, write to the Free Software Foundation, Inc.,
+    51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+*/
+
+use crate::command::{SessionCommand, SessionType};
+
+#[test]
+fn test_session_command_compatibility() {
+    // commands stored by older releases have no arguments, environment or type
+    let stored = r#"{"command":"sway"}"#;
+    let command: SessionCommand = serde_json::from_str(stored).unwrap();
+
+    assert_eq!(command.command(), "sway");
+    assert!(command.args().is_empty());
+    assert!(command.env().is_empty());
+    assert_eq!(command.session_type(), SessionType::Unspecified);
+    assert!(command.desktop_names().is_empty());
+
+    // serializing it again must not change what the configuration signature covers
+    assert_eq!(serde_json::to_string(&command).unwrap(), stored);
+}
+
+#[test]
+fn test_session_command_fields() {
+    let env = [
+        (String::from("XDG_SESSION_DESKTOP"), String::from("sway")),
+        (String::from("MOZ_ENABLE_WAYLAND"), String::from("1")),
+    ]
+    .into_iter()
+    .collect();
+
+    let command = SessionCommand::new(String::from("/usr/bin/sway"))
+        .with_args(vec![String::from("--unsupported-gpu")])
+        .with_env(env)
+        .with_session_type(SessionType::Wayland)
+        .with_desktop_names(vec![String::from("sway"), String::from("wlroots")]);
+
+    let serialized = serde_json::to_string(&command).unwrap();
+    let reloaded: SessionCommand = serde_json::from_str(&serialized).unwrap();
+
+    assert_eq!(reloaded, command);
+    assert!(serialized.contains(r#""session_type":"wayland""#));
+    // environment variables are always serialized in the same order
+    assert!(serialized.find("MOZ_ENABLE_WAYLAND") < serialized.find("XDG_SESSION_DESKTOP"));
+    assert_eq!(command.argv(), vec!["/usr/bin/sway", "--unsupported-gpu"]);
+}
+
+#[test]
+fn test_session_type_parse() {
+    assert_eq!("wayland".parse(), Ok(SessionType::Wayland));
+    assert_eq!("x11".parse(), Ok(SessionType::X11));
+    assert_eq!("tty".parse(), Ok(SessionType::Tty));
+    assert_eq!("".parse(), Ok(SessionType::Unspecified));
+    assert!("mir".parse::<SessionType>().is_err());
+
+    for session_type in [SessionType::Wayland, SessionType::X11, SessionType::Tty] {
+        assert_eq!(session_type.to_string().parse(), Ok(session_type));
+    }
+}
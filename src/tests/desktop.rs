@@ -0,0 +1,197 @@
+/*
+    pam_polyauth: A pam module written in rust that supports multiple
+    authentication modes (including autologin).
+
+    Copyright (C) 2024-2025  Denis Benato
+
+    This program is free software; you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation; either version 2 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License along
+    with this program; if not, write to the Free Software Foundation, Inc.,
+    51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+*/
+
+use std::path::{Path, PathBuf};
+
+use crate::{
+    command::{SessionCommand, SessionType},
+    desktop::{split_exec, DesktopError, DesktopSession, SessionDirs},
+};
+
+const PLASMA: &str = r#"[Desktop Entry]
+Exec=/usr/lib/plasma-dbus-run-session-if-needed /usr/bin/startplasma-wayland
+TryExec=/usr/bin/startplasma-wayland
+DesktopNames=KDE
+Name=Plasma (Wayland)
+Name[it]=Plasma (Wayland)
+Comment=Plasma by KDE
+X-KDE-PluginInfo-Version=6.0.0
+"#;
+
+fn session_dirs(name: &str) -> (PathBuf, SessionDirs) {
+    let root = std::env::temp_dir().join(name);
+    let _ = std::fs::remove_dir_all(&root);
+
+    let wayland = root.join("wayland-sessions");
+    let x11 = root.join("xsessions");
+    std::fs::create_dir_all(&wayland).unwrap();
+    std::fs::create_dir_all(&x11).unwrap();
+
+    let dirs = SessionDirs::new(vec![
+        (wayland, SessionType::Wayland),
+        (x11, SessionType::X11),
+    ]);
+
+    (root, dirs)
+}
+
+#[test]
+fn test_split_exec() {
+    assert_eq!(
+        split_exec("sway --unsupported-gpu").unwrap(),
+        vec!["sway", "--unsupported-gpu"]
+    );
+    assert_eq!(
+        split_exec(r#"sh -c "exec \"$HOME/session\"" %U"#).unwrap(),
+        vec!["sh", "-c", r#"exec "$HOME/session""#]
+    );
+    assert_eq!(
+        split_exec(r#"run "" 100%%"#).unwrap(),
+        vec!["run", "", "100%"]
+    );
+    assert!(split_exec(r#"run "unterminated"#).is_err());
+}
+
+#[test]
+fn test_parse_desktop_file() {
+    let path = Path::new("/usr/share/wayland-sessions/plasma.desktop");
+    let session = DesktopSession::parse("plasma", path, SessionType::Wayland, PLASMA)
+        .unwrap()
+        .unwrap();
+
+    assert_eq!(session.id(), "plasma");
+    assert_eq!(session.name(), "Plasma (Wayland)");
+    assert_eq!(
+        session.exec(),
+        &vec![
+            "/usr/lib/plasma-dbus-run-session-if-needed",
+            "/usr/bin/startplasma-wayland"
+        ]
+    );
+    assert_eq!(session.desktop_names(), &vec!["KDE"]);
+    assert_eq!(session.session_type(), SessionType::Wayland);
+
+    let hidden = format!("{PLASMA}Hidden=true\n");
+    assert!(
+        DesktopSession::parse("plasma", path, SessionType::Wayland, &hidden)
+            .unwrap()
+            .is_none()
+    );
+
+    assert!(matches!(
+        DesktopSession::parse(
+            "broken",
+            path,
+            SessionType::X11,
+            "[Desktop Entry]\nName=x\n"
+        ),
+        Err(DesktopError::MissingExec(_))
+    ));
+}
+
+#[test]
+fn test_session_dirs() {
+    let (root, dirs) = session_dirs("test_session_dirs");
+
+    std::fs::write(root.join("wayland-sessions/plasma.desktop"), PLASMA).unwrap();
+    std::fs::write(
+        root.join("xsessions/plasma.desktop"),
+        "[Desktop Entry]\nType=XSession\nExec=startplasma-x11\nName=Plasma (X11)\n",
+    )
+    .unwrap();
+    std::fs::write(
+        root.join("xsessions/i3.desktop"),
+        "[Desktop Entry]\nType=XSession\nExec=i3\nName=i3\n",
+    )
+    .unwrap();
+    std::fs::write(root.join("xsessions/broken.desktop"), "garbage").unwrap();
+
+    // the wayland session shadows the X11 one with the same ID, broken files are skipped
+    let sessions = dirs.list();
+    let ids = sessions.iter().map(|s| s.id()).collect::<Vec<_>>();
+    assert_eq!(ids, vec!["i3", "plasma"]);
+    assert_eq!(sessions[1].session_type(), SessionType::Wayland);
+    assert_eq!(sessions[0].entry_type(), Some("XSession"));
+
+    assert_eq!(dirs.find("i3").unwrap().session_type(), SessionType::X11);
+    assert!(matches!(dirs.find("gnome"), Err(DesktopError::NotFound(_))));
+    assert!(matches!(
+        dirs.find("../xsessions/i3"),
+        Err(DesktopError::NotFound(_))
+    ));
+
+    let _ = std::fs::remove_dir_all(&root);
+}
+
+#[test]
+fn test_resolve_session_command() {
+    let (root, dirs) = session_dirs("test_resolve_session_command");
+    std::fs::write(root.join("wayland-sessions/plasma.desktop"), PLASMA).unwrap();
+
+    let stored = SessionCommand::desktop_session(String::from("plasma"))
+        .with_args(vec![String::from("--debug")])
+        .with_env(
+            [(String::from("QT_QPA_PLATFORM"), String::from("wayland"))]
+                .into_iter()
+                .collect(),
+        );
+
+    // only the ID is stored, so that a changed Exec line is picked up
+    assert_eq!(
+        serde_json::to_string(&stored).unwrap(),
+        r#"{"args":["--debug"],"env":{"QT_QPA_PLATFORM":"wayland"},"desktop":"plasma"}"#
+    );
+
+    let resolved = dirs.resolve(&stored).unwrap();
+    assert_eq!(
+        resolved.argv(),
+        vec![
+            "/usr/lib/plasma-dbus-run-session-if-needed",
+            "/usr/bin/startplasma-wayland",
+            "--debug"
+        ]
+    );
+    assert_eq!(resolved.env(), stored.env());
+    assert_eq!(resolved.session_type(), SessionType::Wayland);
+    assert_eq!(resolved.desktop_names(), &vec!["KDE"]);
+    assert_eq!(resolved.desktop(), Some("plasma"));
+
+    // stored settings take precedence over the session file
+    let overridden = stored
+        .clone()
+        .with_desktop_names(vec![String::from("plasma")])
+        .with_session_type(SessionType::X11);
+    let resolved = dirs.resolve(&overridden).unwrap();
+    assert_eq!(resolved.desktop_names(), &vec!["plasma"]);
+    assert_eq!(resolved.session_type(), SessionType::X11);
+
+    // plain commands are left untouched
+    let plain = SessionCommand::new(String::from("sway"));
+    assert_eq!(dirs.resolve(&plain).unwrap(), plain);
+
+    std::fs::remove_file(root.join("wayland-sessions/plasma.desktop")).unwrap();
+    assert!(matches!(
+        dirs.resolve(&stored),
+        Err(DesktopError::NotFound(_))
+    ));
+
+    let _ = std::fs::remove_dir_all(&root);
+}
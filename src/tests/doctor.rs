@@ -0,0 +1,291 @@
+/*
+    pam_polyauth: A pam module written in rust that supports multiple
+    authentication modes (including autologin).
+
+    Copyright (C) 2024-2025  Denis Benato
+
+    This program is free software; you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation; either version 2 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License along
+    with this program; if not, write to the Free Software Foundation, Inc.,
+    51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+*/
+
+use std::{
+    collections::HashMap,
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    doctor::{
+        check_config, check_dbus_policies, check_file_permissions, check_main_password,
+        check_mount_devices, check_pam_stack, healthy, pam_phases, resolve_device, Check,
+        CheckStatus, DBUS_POLICY_FILES,
+    },
+    mount::{MountParams, MountPoints},
+    storage::store::{JsonStore, MemoryStore, UserStore},
+    user::UserAuthData,
+};
+
+fn test_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(name);
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[test]
+fn test_pam_phases() {
+    let contents = r#"#%PAM-1.0
+# auth sufficient pam_polyauth.so
+auth       sufficient   pam_polyauth.so
+-auth      optional     /usr/lib/security/pam_polyauth.so
+account    include      system-local-login
+session    [success=ok default=ignore]   pam_polyauth.so transport=socket
+session    optional     pam_polyauth_other.so
+password   include      system-local-login
+@include common-session
+"#;
+
+    assert_eq!(pam_phases(contents), vec!["auth", "session"]);
+    assert!(pam_phases("auth sufficient pam_unix.so\n").is_empty());
+    assert!(pam_phases("session [success=ok\n").is_empty());
+}
+
+#[test]
+fn test_check_pam_stack() {
+    let dir = test_dir("polyauth-doctor-pam");
+
+    let missing = check_pam_stack(&dir);
+
+    std::fs::write(dir.join("login"), "auth sufficient pam_unix.so\n").unwrap();
+    std::fs::write(dir.join("greetd"), "auth sufficient pam_polyauth.so\n").unwrap();
+    let auth_only = check_pam_stack(&dir);
+
+    std::fs::write(
+        dir.join("common-session"),
+        "session optional pam_polyauth.so\n",
+    )
+    .unwrap();
+    let complete = check_pam_stack(&dir);
+
+    std::fs::write(dir.join("passwd"), "password required pam_polyauth.so\n").unwrap();
+    let unsupported = check_pam_stack(&dir);
+
+    let unreadable = check_pam_stack(&dir.join("nonexistent"));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(missing.status(), CheckStatus::Fail);
+    assert_eq!(auth_only.status(), CheckStatus::Warn);
+    assert!(auth_only.message().contains("session"));
+    assert_eq!(complete.status(), CheckStatus::Pass);
+    assert!(complete.message().contains("greetd"));
+    assert!(complete.message().contains("common-session"));
+    assert_eq!(unsupported.status(), CheckStatus::Warn);
+    assert!(unsupported.message().contains("password"));
+    assert_eq!(unreadable.status(), CheckStatus::Fail);
+}
+
+#[test]
+fn test_resolve_device() {
+    let disk = Path::new("/dev/disk");
+
+    assert_eq!(
+        resolve_device("UUID=1234-ABCD", disk),
+        Some(PathBuf::from("/dev/disk/by-uuid/1234-ABCD"))
+    );
+    assert_eq!(
+        resolve_device("LABEL=\"My Home\"", disk),
+        Some(PathBuf::from("/dev/disk/by-label/My\\x20Home"))
+    );
+    assert_eq!(
+        resolve_device("PARTUUID=0fc63daf", disk),
+        Some(PathBuf::from("/dev/disk/by-partuuid/0fc63daf"))
+    );
+    assert_eq!(
+        resolve_device("PARTLABEL=home", disk),
+        Some(PathBuf::from("/dev/disk/by-partlabel/home"))
+    );
+    assert_eq!(
+        resolve_device("/dev/sda2", disk),
+        Some(PathBuf::from("/dev/sda2"))
+    );
+    assert_eq!(resolve_device("tmpfs", disk), None);
+    assert_eq!(resolve_device("//server/share", disk), None);
+}
+
+#[test]
+fn test_check_mount_devices() {
+    let dir = test_dir("polyauth-doctor-disk");
+    std::fs::create_dir_all(dir.join("by-uuid")).unwrap();
+    std::fs::write(dir.join("by-uuid").join("1234-ABCD"), "").unwrap();
+
+    let mut mounts = HashMap::new();
+    mounts.insert(
+        String::from("/mnt/cache"),
+        MountParams::new(String::from("LABEL=cache"), String::from("ext4"), vec![]),
+    );
+    mounts.insert(
+        String::from("/tmp/scratch"),
+        MountParams::new(String::from("tmpfs"), String::from("tmpfs"), vec![]),
+    );
+    let mounts = MountPoints::new(
+        MountParams::new(String::from("UUID=1234-ABCD"), String::from("ext4"), vec![]),
+        mounts,
+    );
+
+    let checks = check_mount_devices(&mounts, &dir);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    let statuses = checks
+        .iter()
+        .map(|check| (check.name(), check.status()))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        statuses,
+        vec![
+            ("home device", CheckStatus::Pass),
+            ("device of /mnt/cache", CheckStatus::Fail),
+            ("device of /tmp/scratch", CheckStatus::Pass),
+        ]
+    );
+    assert!(!healthy(&checks));
+}
+
+#[test]
+fn test_check_config() {
+    let store = MemoryStore::new();
+
+    let missing = check_config(&store, "user");
+    store.update("user", None, None, &mut |_| Ok(())).unwrap();
+    let present = check_config(&store, "user");
+
+    let dir = test_dir("polyauth-doctor-config");
+    let file_path = dir.join("config.json");
+    let file_store = JsonStore::file(file_path.clone());
+
+    std::fs::write(
+        &file_path,
+        r#"{ "session_command": { "command": "sway" } }"#,
+    )
+    .unwrap();
+    let old = check_config(&file_store, "user");
+
+    std::fs::write(
+        &file_path,
+        format!(
+            r#"{{ "version": {} }}"#,
+            crate::storage::migration::CURRENT_CONFIG_VERSION + 1
+        ),
+    )
+    .unwrap();
+    let future = check_config(&file_store, "user");
+
+    std::fs::write(&file_path, "{ not json").unwrap();
+    let malformed = check_config(&file_store, "user");
+
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(missing.status(), CheckStatus::Fail);
+    assert_eq!(present.status(), CheckStatus::Pass);
+    // files of the first release are still current
+    assert_eq!(old.status(), CheckStatus::Pass);
+    assert_eq!(future.status(), CheckStatus::Fail);
+    assert_eq!(malformed.status(), CheckStatus::Fail);
+}
+
+#[test]
+fn test_check_main_password() {
+    let mut auth_data = UserAuthData::new();
+
+    let not_set = check_main_password(&auth_data, Some(b"intermediate"), None);
+
+    auth_data.set_main(b"password", b"intermediate").unwrap();
+
+    let unverified = check_main_password(&auth_data, None, None);
+    let verified = check_main_password(&auth_data, Some(b"intermediate"), Some(b"password"));
+    let wrong = check_main_password(&auth_data, Some(b"wrong"), Some(b"wrong"));
+
+    assert_eq!(not_set.len(), 1);
+    assert_eq!(not_set[0].status(), CheckStatus::Fail);
+    assert_eq!(unverified.len(), 1);
+    assert_eq!(unverified[0].status(), CheckStatus::Warn);
+    assert!(verified
+        .iter()
+        .all(|check| check.status() == CheckStatus::Pass));
+    assert_eq!(verified.len(), 2);
+    assert!(wrong
+        .iter()
+        .all(|check| check.status() == CheckStatus::Fail));
+    assert_eq!(wrong.len(), 2);
+}
+
+#[test]
+fn test_check_dbus_policies() {
+    let vendor = test_dir("polyauth-doctor-dbus-vendor");
+    let admin = test_dir("polyauth-doctor-dbus-admin");
+
+    std::fs::write(vendor.join(DBUS_POLICY_FILES[0]), "").unwrap();
+    std::fs::write(admin.join(DBUS_POLICY_FILES[1]), "").unwrap();
+    let installed = check_dbus_policies(&[&vendor, &admin]);
+    let partial = check_dbus_policies(&[&vendor]);
+
+    std::fs::remove_dir_all(&vendor).unwrap();
+    std::fs::remove_dir_all(&admin).unwrap();
+
+    assert!(healthy(&installed));
+    assert_eq!(installed.len(), DBUS_POLICY_FILES.len());
+    assert_eq!(
+        partial.iter().map(Check::status).collect::<Vec<_>>(),
+        vec![CheckStatus::Pass, CheckStatus::Fail]
+    );
+}
+
+#[test]
+fn test_check_file_permissions() {
+    let dir = test_dir("polyauth-doctor-permissions");
+    let key = dir.join("private_key_pkcs1.pem");
+
+    let missing = check_file_permissions("private key", &key, 0o077);
+
+    std::fs::write(&key, "").unwrap();
+    std::fs::set_permissions(&key, std::fs::Permissions::from_mode(0o600)).unwrap();
+    let private = check_file_permissions("private key", &key, 0o077);
+
+    std::fs::set_permissions(&key, std::fs::Permissions::from_mode(0o644)).unwrap();
+    let readable = check_file_permissions("private key", &key, 0o077);
+    let not_writable = check_file_permissions("authorized mounts", &key, 0o022);
+
+    let link = dir.join("link");
+    std::os::unix::fs::symlink(&key, &link).unwrap();
+    let symlink = check_file_permissions("private key", &link, 0o077);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(missing.status(), CheckStatus::Warn);
+    assert_eq!(readable.status(), CheckStatus::Fail);
+    assert_eq!(symlink.status(), CheckStatus::Fail);
+
+    // files created by anyone but root are refused whatever their mode
+    match users::get_current_uid() == 0 {
+        true => {
+            assert_eq!(private.status(), CheckStatus::Pass);
+            assert_eq!(not_writable.status(), CheckStatus::Pass);
+        }
+        false => {
+            assert_eq!(private.status(), CheckStatus::Fail);
+            assert_eq!(not_writable.status(), CheckStatus::Fail);
+        }
+    }
+}
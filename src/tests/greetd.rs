@@ -0,0 +1,177 @@
+/*
+    pam_polyauth: A pam module written in rust that supports multiple
+    authentication modes (including autologin).
+
+    Copyright (C) 2024-2025  Denis Benato
+
+    This program is free software; you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation; either version 2 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License along
+    with this program; if not, write to the Free Software Foundation, Inc.,
+    51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+*/
+
+use tokio::net::UnixListener;
+
+use crate::greetd::{
+    read_message, write_message, AuthMessageType, Authentication, ErrorType, GreetdClient,
+    GreetdError, Request, Response,
+};
+
+/// Serves a single greeter, checking every request against the script and answering as scripted
+async fn fake_greetd(listener: UnixListener, script: Vec<(Request, Response)>) {
+    let (mut stream, _) = listener.accept().await.unwrap();
+
+    for (expected, response) in script {
+        let request: Request = read_message(&mut stream).await.unwrap();
+        assert_eq!(request, expected);
+        write_message(&mut stream, &response).await.unwrap();
+    }
+}
+
+#[test]
+fn test_protocol_messages() {
+    let request = Request::StartSession {
+        cmd: vec![String::from("sway")],
+        env: vec![String::from("XDG_SESSION_TYPE=wayland")],
+    };
+    assert_eq!(
+        serde_json::to_string(&request).unwrap(),
+        r#"{"type":"start_session","cmd":["sway"],"env":["XDG_SESSION_TYPE=wayland"]}"#
+    );
+
+    let response: Response = serde_json::from_str(
+        r#"{"type":"auth_message","auth_message_type":"secret","auth_message":"Password: "}"#,
+    )
+    .unwrap();
+    assert_eq!(
+        response,
+        Response::AuthMessage {
+            auth_message_type: AuthMessageType::Secret,
+            auth_message: String::from("Password: "),
+        }
+    );
+}
+
+#[tokio::test]
+async fn test_fake_greetd_login() {
+    let socket_path = std::env::temp_dir().join("test_fake_greetd_login.sock");
+    let _ = std::fs::remove_file(&socket_path);
+    let listener = UnixListener::bind(&socket_path).unwrap();
+
+    let create_session = Request::CreateSession {
+        username: String::from("johndoe"),
+    };
+    let password_prompt = Response::AuthMessage {
+        auth_message_type: AuthMessageType::Secret,
+        auth_message: String::from("Password: "),
+    };
+
+    let server = tokio::spawn(fake_greetd(
+        listener,
+        vec![
+            // wrong password: the session is cancelled
+            (create_session.clone(), password_prompt.clone()),
+            (
+                Request::PostAuthMessageResponse {
+                    response: Some(String::from("wrong")),
+                },
+                Response::Error {
+                    error_type: ErrorType::AuthError,
+                    description: String::from("pam_authenticate: AUTH_ERR"),
+                },
+            ),
+            (Request::CancelSession, Response::Success),
+            // an info message is shown, its answer is not sent
+            (
+                create_session,
+                Response::AuthMessage {
+                    auth_message_type: AuthMessageType::Info,
+                    auth_message: String::from("Welcome"),
+                },
+            ),
+            (
+                Request::PostAuthMessageResponse { response: None },
+                password_prompt,
+            ),
+            (
+                Request::PostAuthMessageResponse {
+                    response: Some(String::from("correct")),
+                },
+                Response::Success,
+            ),
+            (
+                Request::StartSession {
+                    cmd: vec![String::from("sway")],
+                    env: vec![],
+                },
+                Response::Success,
+            ),
+        ],
+    ));
+
+    let mut client = GreetdClient::connect(&socket_path).await.unwrap();
+
+    let denied = client
+        .authenticate("johndoe", |_, _| Ok(Some(String::from("wrong"))))
+        .await
+        .unwrap();
+    assert!(matches!(denied, Authentication::Denied(_)));
+
+    let mut prompts = vec![];
+    let authenticated = client
+        .authenticate("johndoe", |kind, message| {
+            prompts.push((kind, String::from(message)));
+            Ok(Some(String::from("correct")))
+        })
+        .await
+        .unwrap();
+    assert_eq!(authenticated, Authentication::Authenticated);
+    assert_eq!(prompts.len(), 2);
+
+    client
+        .start_session(vec![String::from("sway")], vec![])
+        .await
+        .unwrap();
+
+    server.await.unwrap();
+    let _ = std::fs::remove_file(&socket_path);
+}
+
+#[tokio::test]
+async fn test_fake_greetd_error() {
+    let socket_path = std::env::temp_dir().join("test_fake_greetd_error.sock");
+    let _ = std::fs::remove_file(&socket_path);
+    let listener = UnixListener::bind(&socket_path).unwrap();
+
+    let server = tokio::spawn(fake_greetd(
+        listener,
+        vec![
+            (
+                Request::CreateSession {
+                    username: String::from("johndoe"),
+                },
+                Response::Error {
+                    error_type: ErrorType::Error,
+                    description: String::from("a session is already being configured"),
+                },
+            ),
+            (Request::CancelSession, Response::Success),
+        ],
+    ));
+
+    let mut client = GreetdClient::connect(&socket_path).await.unwrap();
+    let result = client.authenticate("johndoe", |_, _| Ok(None)).await;
+    assert!(matches!(result, Err(GreetdError::Greetd(_))));
+
+    server.await.unwrap();
+    let _ = std::fs::remove_file(&socket_path);
+}
@@ -0,0 +1,93 @@
+/*
+    pam_polyauth: A pam module written in rust that supports multiple
+    authentication modes (including autologin).
+
+    Copyright (C) 2024-2025  Denis Benato
+
+    This program is free software; you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation; either version 2 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License along
+    with this program; if not, write to the Free Software Foundation, Inc.,
+    51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+*/
+
+use crate::kdf::{KdfAlgorithm, KdfError, KdfParams, MAX_ARGON2_M_COST, MAX_ARGON2_T_COST};
+
+#[test]
+fn test_derive() {
+    let kdf = KdfParams::argon2id(1024, 1, 1);
+    let salt = [0x42u8; 32];
+
+    let key = kdf.derive(b"password", &salt).unwrap();
+    assert_eq!(key, kdf.derive(b"password", &salt).unwrap());
+    assert_ne!(key, kdf.derive(b"passw0rd", &salt).unwrap());
+    assert_ne!(key, kdf.derive(b"password", &[0x24u8; 32]).unwrap());
+
+    // costs are part of the derivation
+    assert_ne!(
+        key,
+        KdfParams::argon2id(1024, 2, 1)
+            .derive(b"password", &salt)
+            .unwrap()
+    );
+}
+
+#[test]
+fn test_legacy() {
+    let salt = [0x42u8; 32];
+
+    assert_eq!(
+        KdfParams::legacy().algorithm().unwrap(),
+        KdfAlgorithm::HkdfSha256
+    );
+    assert_eq!(
+        KdfParams::legacy().derive(b"password", &salt).unwrap(),
+        crate::derive_key(b"password", &salt)
+    );
+}
+
+#[test]
+fn test_is_weaker_than() {
+    let default = KdfParams::default();
+
+    assert_eq!(default.algorithm().unwrap(), KdfAlgorithm::Argon2id);
+    assert!(KdfParams::legacy().is_weaker_than(&default));
+    assert!(!default.is_weaker_than(&KdfParams::legacy()));
+    assert!(!default.is_weaker_than(&default));
+    assert!(KdfParams::argon2id(1024, 1, 1).is_weaker_than(&default));
+    assert!(!default.is_weaker_than(&KdfParams::argon2id(1024, 1, 1)));
+}
+
+#[test]
+fn test_costs_out_of_range() {
+    let salt = [0x42u8; 32];
+
+    assert!(KdfParams::default().check().is_ok());
+    assert!(KdfParams::legacy().check().is_ok());
+    assert!(KdfParams::argon2id(MAX_ARGON2_M_COST, MAX_ARGON2_T_COST, 8)
+        .check()
+        .is_ok());
+
+    for kdf in [
+        KdfParams::argon2id(u32::MAX, 1, 1),
+        KdfParams::argon2id(8, 1, 1),
+        KdfParams::argon2id(1024, u32::MAX, 1),
+        KdfParams::argon2id(1024, 0, 1),
+        KdfParams::argon2id(1024, 1, 64),
+        KdfParams::argon2id(1024, 1, 0),
+    ] {
+        assert!(matches!(kdf.check(), Err(KdfError::CostsOutOfRange(..))));
+        assert!(matches!(
+            kdf.derive(b"password", &salt),
+            Err(KdfError::CostsOutOfRange(..))
+        ));
+    }
+}
@@ -0,0 +1,91 @@
+/*
+    pam_polyauth: A pam module written in rust that supports multiple
+    authentication modes (including autologin).
+
+    Copyright (C) 2024-2025  Denis Benato
+
+    This program is free software; you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation; either version 2 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License along
+    with this program; if not, write to the Free Software Foundation, Inc.,
+    51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+*/
+
+use std::{os::unix::process::ExitStatusExt, path::Path, process::ExitStatus, time::Duration};
+
+use crate::{
+    command::{SessionCommand, SessionType},
+    launcher::{
+        login_shell_name, session_environment, session_failed, RestartDecision, RestartPolicy,
+        RestartTracker,
+    },
+};
+
+#[test]
+fn test_session_environment() {
+    let command = SessionCommand::new(String::from("sway"))
+        .with_session_type(SessionType::Wayland)
+        .with_desktop_names(vec![String::from("sway"), String::from("wlroots")])
+        .with_env(
+            [(String::from("XDG_SESSION_TYPE"), String::from("x11"))]
+                .into_iter()
+                .collect(),
+        );
+
+    let env = session_environment(&command, Some(Path::new("/run/user/1000")));
+    assert_eq!(env["XDG_RUNTIME_DIR"], "/run/user/1000");
+    assert_eq!(env["XDG_CURRENT_DESKTOP"], "sway:wlroots");
+    // explicitly configured variables win over derived ones
+    assert_eq!(env["XDG_SESSION_TYPE"], "x11");
+
+    let env = session_environment(&SessionCommand::new(String::from("bash")), None);
+    assert!(env.is_empty());
+}
+
+#[test]
+fn test_restart_policy() {
+    let stable = Duration::from_secs(60);
+    let quick = Duration::from_secs(1);
+
+    let mut tracker = RestartTracker::new(RestartPolicy::OnFailure, 2, stable);
+    assert_eq!(tracker.next(false, quick), RestartDecision::Finished);
+    assert_eq!(tracker.next(true, quick), RestartDecision::Restart);
+    assert_eq!(tracker.next(true, quick), RestartDecision::Restart);
+    assert_eq!(tracker.next(true, quick), RestartDecision::GiveUp);
+    // a crash after a long run does not count towards the limit
+    assert_eq!(tracker.next(true, stable), RestartDecision::Restart);
+    assert_eq!(tracker.restarts(), 1);
+
+    let mut tracker = RestartTracker::new(RestartPolicy::Never, 2, stable);
+    assert_eq!(tracker.next(false, quick), RestartDecision::Finished);
+    assert_eq!(tracker.next(true, quick), RestartDecision::GiveUp);
+
+    let mut tracker = RestartTracker::new(RestartPolicy::Always, 1, stable);
+    assert_eq!(tracker.next(false, quick), RestartDecision::Restart);
+    assert_eq!(tracker.next(false, quick), RestartDecision::GiveUp);
+
+    assert_eq!("on-failure".parse(), Ok(RestartPolicy::OnFailure));
+    assert!("sometimes".parse::<RestartPolicy>().is_err());
+}
+
+#[test]
+fn test_session_failed() {
+    assert!(!session_failed(&ExitStatus::from_raw(0)));
+    // exit code 1
+    assert!(session_failed(&ExitStatus::from_raw(1 << 8)));
+    assert!(!session_failed(&ExitStatus::from_raw(libc::SIGTERM)));
+    assert!(session_failed(&ExitStatus::from_raw(libc::SIGSEGV)));
+}
+
+#[test]
+fn test_login_shell_name() {
+    assert_eq!(login_shell_name(Path::new("/usr/bin/zsh")), "-zsh");
+}
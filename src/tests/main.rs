@@ -21,8 +21,8 @@
 
 #[test]
 fn test_main_password_auth() {
-    let first_main = "main password <3".to_string();
-    let intermediate = "intermediate_key".to_string();
+    let first_main = b"main password <3".to_vec();
+    let intermediate = b"intermediate_key".to_vec();
 
     let mut user_cfg = crate::user::UserAuthData::new();
 
@@ -30,11 +30,11 @@ fn test_main_password_auth() {
     user_cfg.set_main(&first_main, &intermediate).unwrap();
 
     assert_eq!(
-        user_cfg.main_by_auth(&Some(first_main.clone())).unwrap(),
+        user_cfg.main_by_auth(Some(&first_main)).unwrap(),
         first_main
     );
 
-    let login_attempt = user_cfg.main_by_auth(&Some(String::from("this is a wrong password")));
+    let login_attempt = user_cfg.main_by_auth(Some(b"this is a wrong password".as_slice()));
 
     assert!(login_attempt.is_err());
 }
@@ -19,8 +19,19 @@
     51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
 */
 
+pub mod command;
+pub mod desktop;
+pub mod doctor;
+pub mod greetd;
+pub mod kdf;
+pub mod launcher;
 pub mod main;
+pub mod module;
+pub mod mount;
+pub mod options;
 pub mod pam;
+pub mod provision;
 pub mod secondary;
+pub mod secret;
 pub mod storage;
 pub mod user;
@@ -0,0 +1,173 @@
+/*
+    pam_polyauth: A pam module written in rust that supports multiple
+    authentication modes (including autologin).
+
+    Copyright (C) 2024-2025  Denis Benato
+
+    This program is free software; you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation; either version 2 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License along
+    with this program; if not, write to the Free Software Foundation, Inc.,
+    51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+*/
+
+use crate::options::ModuleOptions;
+use crate::pam::mount::MountAuthOperations;
+use crate::pam::session::Sessions;
+use crate::pam::socket::{bind_sessions_socket, remove_sessions_socket, serve_sessions_socket};
+use crate::pam_binding::error::PamErrorCode;
+use crate::storage::store::MemoryStore;
+use crate::PamQuickEmbedded;
+
+use std::{ffi::CString, path::Path, process::Command, sync::Arc, time::Duration};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::sync::RwLock;
+
+async fn round_trip() -> Result<[u8; 8], PamErrorCode> {
+    let (mut tx, mut rx) = tokio::net::UnixStream::pair().map_err(|_| PamErrorCode::SERVICE_ERR)?;
+
+    tokio::time::sleep(Duration::from_millis(10)).await;
+
+    tx.write_all(b"polyauth")
+        .await
+        .map_err(|_| PamErrorCode::SERVICE_ERR)?;
+
+    let mut buf = [0u8; 8];
+    tokio::time::timeout(Duration::from_secs(5), rx.read_exact(&mut buf))
+        .await
+        .map_err(|_| PamErrorCode::SERVICE_ERR)?
+        .map_err(|_| PamErrorCode::SERVICE_ERR)?;
+
+    Ok(buf)
+}
+
+#[test]
+fn test_block_on() {
+    assert!(matches!(PamQuickEmbedded::block_on(round_trip()), Ok(buf) if &buf == b"polyauth"));
+
+    // every hook gets its own runtime
+    assert!(matches!(PamQuickEmbedded::block_on(round_trip()), Ok(buf) if &buf == b"polyauth"));
+}
+
+// environment variable telling fork_child where the sessions socket is
+const FORK_CHILD_SOCKET_ENV: &str = "POLYAUTH_TEST_FORK_CHILD_SOCKET";
+
+fn service_version(options: &ModuleOptions) -> Result<String, PamErrorCode> {
+    PamQuickEmbedded::block_on(async {
+        let connection = PamQuickEmbedded::connect(options)
+            .await
+            .map_err(|_| PamErrorCode::SERVICE_ERR)?;
+
+        let proxy = PamQuickEmbedded::sessions_proxy(&connection)
+            .await
+            .map_err(|_| PamErrorCode::SERVICE_ERR)?;
+
+        proxy.version().await.map_err(|_| PamErrorCode::SERVICE_ERR)
+    })
+}
+
+fn thread_count() -> usize {
+    std::fs::read_to_string("/proc/self/status")
+        .unwrap()
+        .lines()
+        .find_map(|line| line.strip_prefix("Threads:"))
+        .unwrap()
+        .trim()
+        .parse()
+        .unwrap()
+}
+
+/// Runs in a process of its own started by test_block_on_across_fork with a single test thread:
+/// forking is only safe while no other thread can be holding a lock.
+#[test]
+#[ignore = "started by test_block_on_across_fork"]
+fn fork_child() {
+    let Ok(socket_path) = std::env::var(FORK_CHILD_SOCKET_ENV) else {
+        return;
+    };
+
+    let socket_arg = CString::new(format!("socket={socket_path}")).unwrap();
+    let options = ModuleOptions::parse(&[c"transport=socket", socket_arg.as_c_str()]).unwrap();
+
+    // besides this one, only the harness thread waiting for the test to finish
+    let threads = thread_count();
+
+    // use the module in the parent first, as PAM consumers do before forking
+    assert_eq!(service_version(&options).unwrap(), crate::LIBRARY_VERSION);
+
+    // nothing is left running once the hook returns
+    assert_eq!(thread_count(), threads);
+
+    let pid = unsafe { libc::fork() };
+    assert!(pid >= 0);
+
+    if pid == 0 {
+        let exit_code = match service_version(&options) {
+            Ok(version) if version == crate::LIBRARY_VERSION => 0,
+            _ => 1,
+        };
+
+        unsafe { libc::_exit(exit_code) };
+    }
+
+    let mut status = 0;
+    assert_eq!(unsafe { libc::waitpid(pid, &mut status, 0) }, pid);
+    assert!(libc::WIFEXITED(status));
+    assert_eq!(libc::WEXITSTATUS(status), 0);
+
+    // and the parent is still able to use it
+    assert_eq!(service_version(&options).unwrap(), crate::LIBRARY_VERSION);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_block_on_across_fork() {
+    // peers other than root are refused by the service
+    if users::get_current_uid() != 0 {
+        return;
+    }
+
+    let socket_path = Path::new("./").join("test_block_on_across_fork.sock");
+    let listener = bind_sessions_socket(socket_path.as_path()).unwrap();
+
+    let sessions = Sessions::new(
+        std::env::temp_dir().join("test_block_on_across_fork.pem"),
+        Arc::new(RwLock::new(MountAuthOperations::new(
+            std::env::temp_dir().join("test_block_on_across_fork.json"),
+            std::env::temp_dir().join("test_block_on_across_fork.key"),
+        ))),
+        Arc::new(MemoryStore::new()),
+    );
+    let server = tokio::spawn(serve_sessions_socket(listener, sessions));
+
+    // the test harness runs several threads: fork from a process running a single one
+    let child_socket_path = socket_path.clone();
+    let output = tokio::task::spawn_blocking(move || {
+        Command::new(std::env::current_exe().unwrap())
+            .args([
+                "--exact",
+                "tests::module::fork_child",
+                "--ignored",
+                "--test-threads=1",
+            ])
+            .env(FORK_CHILD_SOCKET_ENV, child_socket_path)
+            .output()
+            .unwrap()
+    })
+    .await
+    .unwrap();
+
+    server.abort();
+    remove_sessions_socket(socket_path);
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{stdout}");
+    assert!(stdout.contains("1 passed"), "{stdout}");
+}
@@ -0,0 +1,96 @@
+/*
+    pam_polyauth: A pam module written in rust that supports multiple
+    authentication modes (including autologin).
+
+    Copyright (C) 2024-2025  Denis Benato
+
+    This program is free software; you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation; either version 2 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License along
+    with this program; if not, write to the Free Software Foundation, Inc.,
+    51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+*/
+
+use crate::mount::{validate_mount_dir, MountError, MountParams, MountPoints};
+
+fn params(device: &str, fstype: &str, flags: &[&str]) -> MountParams {
+    MountParams::new(
+        String::from(device),
+        String::from(fstype),
+        flags.iter().map(|flag| String::from(*flag)).collect(),
+    )
+}
+
+#[test]
+fn test_validate_mount_params() {
+    assert_eq!(params("/dev/sda1", "ext4", &["rw"]).validate(), Ok(()));
+    assert_eq!(params("UUID=1234-ABCD", "", &[]).validate(), Ok(()));
+    assert_eq!(
+        params("server:/export", "fuse.sshfs", &["rw,noatime", "uid=1000"]).validate(),
+        Ok(())
+    );
+
+    assert_eq!(
+        params("", "ext4", &[]).validate(),
+        Err(MountError::EmptyDevice)
+    );
+    assert_eq!(
+        params("/dev/sda 1", "ext4", &[]).validate(),
+        Err(MountError::InvalidDevice(String::from("/dev/sda 1")))
+    );
+    assert_eq!(
+        params("/dev/sda1", "ext4 -o", &[]).validate(),
+        Err(MountError::InvalidFsType(String::from("ext4 -o")))
+    );
+    assert_eq!(
+        params("/dev/sda1", "ext4", &["rw", ""]).validate(),
+        Err(MountError::InvalidFlag(String::new()))
+    );
+    assert_eq!(
+        params("/dev/sda1", "ext4", &["rw noatime"]).validate(),
+        Err(MountError::InvalidFlag(String::from("rw noatime")))
+    );
+}
+
+#[test]
+fn test_validate_mount_dir() {
+    assert!(validate_mount_dir("/home/user/Games").is_ok());
+    assert!(validate_mount_dir("/mnt/sd card").is_ok());
+
+    for dir in [
+        "",
+        "/",
+        "mnt/data",
+        "/mnt/../etc",
+        "/mnt/./data",
+        "/mnt/data/.",
+    ] {
+        assert_eq!(
+            validate_mount_dir(dir),
+            Err(MountError::InvalidDirectory(String::from(dir)))
+        );
+    }
+}
+
+#[test]
+fn test_remove_premount() {
+    let mut mounts = MountPoints::default()
+        .with_mount(&params("/dev/sda1", "ext4", &[]))
+        .with_premount(&String::from("/mnt/data"), &params("/dev/sdb1", "xfs", &[]));
+    let hash = mounts.hash();
+
+    assert!(!mounts.remove_premount("/mnt/other"));
+    assert_eq!(mounts.hash(), hash);
+
+    assert!(mounts.remove_premount("/mnt/data"));
+    assert!(mounts.foreach(|dir, _| dir.clone()).is_empty());
+    assert_ne!(mounts.hash(), hash);
+}
@@ -0,0 +1,106 @@
+/*
+    pam_polyauth: A pam module written in rust that supports multiple
+    authentication modes (including autologin).
+
+    Copyright (C) 2024-2025  Denis Benato
+
+    This program is free software; you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation; either version 2 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License along
+    with this program; if not, write to the Free Software Foundation, Inc.,
+    51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+*/
+
+use std::{ffi::CStr, path::PathBuf, time::Duration};
+
+use crate::options::{
+    ModuleOptions, ModuleOptionsError, Transport, UnavailablePolicy, DEFAULT_TIMEOUT_SECS,
+};
+
+#[test]
+fn test_default() {
+    let options = ModuleOptions::parse(&[]).unwrap();
+
+    assert_eq!(options, ModuleOptions::default());
+    assert_eq!(options.bus_address(), None);
+    assert_eq!(options.transport(), Transport::Auto);
+    assert_eq!(options.timeout(), Duration::from_secs(DEFAULT_TIMEOUT_SECS));
+    assert_eq!(options.unavailable(), UnavailablePolicy::FailClosed);
+    assert_eq!(
+        options.socket_path(),
+        &PathBuf::from(crate::pam::SESSIONS_SOCKET_PATH)
+    );
+    assert_eq!(options.runtime_dir(), &PathBuf::from("/run/user"));
+}
+
+#[test]
+fn test_parse() {
+    let args: [&CStr; 6] = [
+        c"bus_address=unix:path=/run/dbus/system_bus_socket",
+        c"socket=/tmp/polyauth.sock",
+        c"transport=socket",
+        c"timeout=5",
+        c"unavailable=continue",
+        c"runtime_dir=/run/polyauth/user",
+    ];
+
+    let options = ModuleOptions::parse(&args).unwrap();
+
+    assert_eq!(
+        options.bus_address(),
+        Some("unix:path=/run/dbus/system_bus_socket")
+    );
+    assert_eq!(options.socket_path(), &PathBuf::from("/tmp/polyauth.sock"));
+    assert_eq!(options.transport(), Transport::Socket);
+    assert_eq!(options.timeout(), Duration::from_secs(5));
+    assert_eq!(options.unavailable(), UnavailablePolicy::Continue);
+    assert_eq!(options.runtime_dir(), &PathBuf::from("/run/polyauth/user"));
+}
+
+#[test]
+fn test_parse_invalid() {
+    assert_eq!(
+        ModuleOptions::parse(&[c"transport=carrier-pigeon"]),
+        Err(ModuleOptionsError::InvalidValue(
+            String::from("transport"),
+            String::from("carrier-pigeon")
+        ))
+    );
+
+    assert_eq!(
+        ModuleOptions::parse(&[c"bus_address="]),
+        Err(ModuleOptionsError::InvalidValue(
+            String::from("bus_address"),
+            String::new()
+        ))
+    );
+
+    assert_eq!(
+        ModuleOptions::parse(&[c"timeout=0"]),
+        Err(ModuleOptionsError::InvalidValue(
+            String::from("timeout"),
+            String::from("0")
+        ))
+    );
+
+    assert_eq!(
+        ModuleOptions::parse(&[c"runtime_dir=tmp/xdg"]),
+        Err(ModuleOptionsError::InvalidValue(
+            String::from("runtime_dir"),
+            String::from("tmp/xdg")
+        ))
+    );
+
+    assert_eq!(
+        ModuleOptions::parse(&[c"debug"]),
+        Err(ModuleOptionsError::UnknownOption(String::from("debug")))
+    );
+}
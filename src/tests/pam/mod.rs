@@ -20,4 +20,9 @@
 */
 
 pub mod mount;
+pub mod result;
+pub mod runtime_dir;
 pub mod security;
+pub mod service;
+pub mod session;
+pub mod socket;
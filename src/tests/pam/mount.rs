@@ -33,9 +33,15 @@ async fn test_new() {
         std::fs::remove_file(filepath.clone()).unwrap();
     }
 
-    let mounts_auth_op = Arc::new(RwLock::new(MountAuthOperations::new(filepath.clone())));
-
-    let mounts_auth = MountAuthDBus::new(mounts_auth_op.clone());
+    let mounts_auth_op = Arc::new(RwLock::new(MountAuthOperations::new(
+        filepath.clone(),
+        std::env::temp_dir().join("test_new.key"),
+    )));
+
+    let mounts_auth = MountAuthDBus::new(
+        mounts_auth_op.clone(),
+        Arc::new(crate::storage::store::MemoryStore::new()),
+    );
 
     assert!(
         !(mounts_auth
@@ -55,19 +61,23 @@ async fn test_authorize() {
         std::fs::remove_file(filepath.clone()).unwrap();
     }
 
-    let mounts_auth_op = Arc::new(RwLock::new(MountAuthOperations::new(filepath.clone())));
+    let mounts_auth_op = Arc::new(RwLock::new(MountAuthOperations::new(
+        filepath.clone(),
+        std::env::temp_dir().join("test_authorize.key"),
+    )));
 
-    let mut mounts_auth = MountAuthDBus::new(mounts_auth_op.clone());
+    let mut mounts_auth = MountAuthDBus::new(
+        mounts_auth_op.clone(),
+        Arc::new(crate::storage::store::MemoryStore::new()),
+    );
 
     const NUM: u64 = 0x4E421u64;
 
     assert!(!(mounts_auth.check("username", format!("{:X}", NUM)).await));
-    assert_eq!(
-        mounts_auth
-            .authorize("username", format!("{:X}", NUM))
-            .await,
-        0u32
-    );
+    assert!(mounts_auth
+        .authorize("username", format!("{:X}", NUM))
+        .await
+        .is_ok());
     assert!(mounts_auth.check("username", format!("{:X}", NUM)).await);
 
     std::fs::remove_file(filepath.clone()).unwrap();
@@ -82,25 +92,29 @@ async fn test_authorize_different_users() {
         std::fs::remove_file(filepath.clone()).unwrap();
     }
 
-    let mounts_auth_op = Arc::new(RwLock::new(MountAuthOperations::new(filepath.clone())));
+    let mounts_auth_op = Arc::new(RwLock::new(MountAuthOperations::new(
+        filepath.clone(),
+        std::env::temp_dir().join("test_authorize_different_users.key"),
+    )));
 
-    let mut mounts_auth = MountAuthDBus::new(mounts_auth_op.clone());
+    let mut mounts_auth = MountAuthDBus::new(
+        mounts_auth_op.clone(),
+        Arc::new(crate::storage::store::MemoryStore::new()),
+    );
 
     const NUM1: u64 = 0x2913787u64;
     const NUM2: u64 = 0x4E42142u64;
 
     assert!(!(mounts_auth.check("username", format!("{:X}", NUM1)).await));
     assert!(!(mounts_auth.check("test", format!("{:X}", NUM2)).await));
-    assert_eq!(
-        mounts_auth.authorize("test", format!("{:X}", NUM2)).await,
-        0u32
-    );
-    assert_eq!(
-        mounts_auth
-            .authorize("username", format!("{:X}", NUM1))
-            .await,
-        0u32
-    );
+    assert!(mounts_auth
+        .authorize("test", format!("{:X}", NUM2))
+        .await
+        .is_ok());
+    assert!(mounts_auth
+        .authorize("username", format!("{:X}", NUM1))
+        .await
+        .is_ok());
     assert!(mounts_auth.check("username", format!("{:X}", NUM1)).await);
     assert!(mounts_auth.check("test", format!("{:X}", NUM2)).await);
     assert!(!(mounts_auth.check("test", format!("{:X}", NUM1)).await));
@@ -130,9 +144,15 @@ async fn test_authorization_file() {
 
     std::fs::write(filepath.clone(), content).unwrap();
 
-    let mounts_auth_op = Arc::new(RwLock::new(MountAuthOperations::new(filepath.clone())));
+    let mounts_auth_op = Arc::new(RwLock::new(MountAuthOperations::new(
+        filepath.clone(),
+        std::env::temp_dir().join("test_authorization_file.key"),
+    )));
 
-    let mounts_auth = MountAuthDBus::new(mounts_auth_op.clone());
+    let mounts_auth = MountAuthDBus::new(
+        mounts_auth_op.clone(),
+        Arc::new(crate::storage::store::MemoryStore::new()),
+    );
 
     const AUTH_TO_TEST: u64 = 0x3ED66D06576D7F05;
 
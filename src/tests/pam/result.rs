@@ -0,0 +1,89 @@
+/*
+    pam_polyauth: A pam module written in rust that supports multiple
+    authentication modes (including autologin).
+
+    Copyright (C) 2024-2025  Denis Benato
+
+    This program is free software; you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation; either version 2 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License along
+    with this program; if not, write to the Free Software Foundation, Inc.,
+    51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+*/
+
+use crate::pam::result::{ServiceOperationError, ServiceOperationResult};
+use zbus::DBusError;
+
+#[test]
+fn test_new() {
+    for code in 1u32..=16u32 {
+        let result = ServiceOperationResult::from(code);
+        let err = ServiceOperationError::new(result, "message");
+
+        assert_eq!(err.result(), result);
+        assert_eq!(err.message(), "message");
+        assert!(err.details().is_empty());
+    }
+
+    assert_eq!(
+        ServiceOperationError::new(ServiceOperationResult::Ok, "message").result(),
+        ServiceOperationResult::Unknown
+    );
+}
+
+#[test]
+fn test_details() {
+    let err = ServiceOperationError::new(ServiceOperationResult::MountError, "mount failed")
+        .with_detail("device", "/dev/sda1")
+        .with_detail("errno", 13);
+
+    assert_eq!(err.details().get("device").unwrap(), "/dev/sda1");
+    assert_eq!(err.details().get("errno").unwrap(), "13");
+    assert_eq!(err.details().len(), 2);
+}
+
+#[test]
+fn test_name() {
+    let err = ServiceOperationError::new(ServiceOperationResult::MountError, "mount failed");
+    assert_eq!(err.name(), "org.neroreflex.polyauth.Error.MountFailed");
+
+    let err = ServiceOperationError::new(ServiceOperationResult::NoSuchSession, "no session");
+    assert_eq!(err.name(), "org.neroreflex.polyauth.Error.NoSuchSession");
+}
+
+#[test]
+fn test_from_bus_error() {
+    let err = ServiceOperationError::from(zbus::Error::InvalidReply);
+
+    assert_eq!(err.result(), ServiceOperationResult::BusError);
+    assert!(err.details().is_empty());
+}
+
+#[test]
+fn test_user_message() {
+    let err = ServiceOperationError::new(
+        ServiceOperationResult::MountError,
+        "cannot mount device /dev/sda1 into /home/user: Permission denied",
+    )
+    .with_detail("device", "/dev/sda1");
+    assert_eq!(err.user_message(), "your directories could not be mounted");
+
+    // every internal failure of the service ends up with the same message
+    let err = ServiceOperationError::new(
+        ServiceOperationResult::IOError,
+        "cannot read the configuration signing key: No such file or directory",
+    );
+    assert_eq!(
+        err.user_message(),
+        ServiceOperationError::from(zbus::Error::InvalidReply).user_message()
+    );
+    assert!(!err.user_message().contains("signing key"));
+}
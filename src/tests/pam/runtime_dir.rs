@@ -0,0 +1,99 @@
+/*
+    pam_polyauth: A pam module written in rust that supports multiple
+    authentication modes (including autologin).
+
+    Copyright (C) 2024-2025  Denis Benato
+
+    This program is free software; you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation; either version 2 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License along
+    with this program; if not, write to the Free Software Foundation, Inc.,
+    51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+*/
+
+use std::{
+    os::unix::fs::{symlink, MetadataExt, PermissionsExt},
+    path::PathBuf,
+};
+
+use crate::pam::runtime_dir::{
+    prepare_runtime_dir, RuntimeDirConfig, RuntimeDirConfigError, RuntimeDirSize, RuntimeDirState,
+};
+
+#[test]
+fn test_runtime_dir_config() {
+    let config = RuntimeDirConfig::default();
+    assert_eq!(config.base(), PathBuf::from("/run/user"));
+    assert_eq!(config.user_dir(1000), PathBuf::from("/run/user/1000"));
+    assert_eq!(
+        config.mount_options(1000, 100),
+        "mode=0700,size=10%,uid=1000,gid=100"
+    );
+
+    for size in ["10%", "64m", "1G", "1048576"] {
+        assert!(size.parse::<RuntimeDirSize>().is_ok(), "{size}");
+    }
+
+    // the size ends up in the mount options: nothing else can be smuggled in
+    for size in ["", "m", "10%%", "64m,exec", "-1", "1 g"] {
+        assert_eq!(
+            size.parse::<RuntimeDirSize>(),
+            Err(RuntimeDirConfigError::InvalidSize(String::from(size)))
+        );
+    }
+
+    assert_eq!(
+        RuntimeDirConfig::new(PathBuf::from("run/user"), RuntimeDirSize::default()),
+        Err(RuntimeDirConfigError::RelativeBase(PathBuf::from(
+            "run/user"
+        )))
+    );
+}
+
+#[test]
+fn test_prepare_runtime_dir() {
+    // directories must be owned by root
+    if users::get_current_uid() != 0 {
+        return;
+    }
+
+    let base = std::env::temp_dir().join("test_prepare_runtime_dir");
+    let _ = std::fs::remove_dir_all(&base);
+
+    let config = RuntimeDirConfig::new(base.clone(), "16m".parse().unwrap()).unwrap();
+    assert!(!config.managed_by_logind());
+
+    // missing directories are created with restrictive permissions
+    assert_eq!(
+        prepare_runtime_dir(&config, 1000).unwrap(),
+        RuntimeDirState::Ready(base.join("1000"))
+    );
+    let metadata = std::fs::metadata(base.join("1000")).unwrap();
+    assert_eq!(metadata.uid(), 0);
+    assert_eq!(metadata.mode() & 0o777, 0o700);
+
+    // a symlink planted in place of the directory is refused
+    std::fs::remove_dir(base.join("1000")).unwrap();
+    symlink("/etc", base.join("1000")).unwrap();
+    assert!(prepare_runtime_dir(&config, 1000).is_err());
+    std::fs::remove_file(base.join("1000")).unwrap();
+
+    // as is a directory owned by someone else
+    std::fs::create_dir(base.join("1001")).unwrap();
+    std::os::unix::fs::chown(base.join("1001"), Some(1001), Some(1001)).unwrap();
+    assert!(prepare_runtime_dir(&config, 1001).is_err());
+
+    // or a base that everybody can write into
+    std::fs::set_permissions(&base, std::fs::Permissions::from_mode(0o1777)).unwrap();
+    assert!(prepare_runtime_dir(&config, 1000).is_err());
+
+    let _ = std::fs::remove_dir_all(&base);
+}
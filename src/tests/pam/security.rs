@@ -20,6 +20,7 @@
 */
 
 use crate::pam::security::{SessionPrelude, SessionPreludeError};
+use crate::secret::SecretBytes;
 use rand::rngs::OsRng;
 use rsa::pkcs1::EncodeRsaPublicKey;
 use rsa::{pkcs1::DecodeRsaPrivateKey, pkcs8::LineEnding, RsaPrivateKey, RsaPublicKey};
@@ -98,7 +99,7 @@ fn test_encrypt_decrypt_success() {
     let plaintext = "Hello, World!";
 
     let encrypted = session
-        .encrypt(plaintext.to_string())
+        .encrypt(&SecretBytes::from(plaintext.as_bytes()))
         .expect("Encryption failed");
 
     let (otp, decrypted_plaintext) =
@@ -108,6 +109,33 @@ fn test_encrypt_decrypt_success() {
     assert_eq!(decrypted_plaintext, plaintext.as_bytes());
 }
 
+#[test]
+fn test_encrypt_decrypt_binary() {
+    let priv_key = Arc::new(RsaPrivateKey::from_pkcs1_pem(RSA_PRIVATE_KEY).unwrap());
+    let pub_key = RsaPublicKey::from(priv_key.as_ref());
+
+    let pub_key_pem = pub_key.to_pkcs1_pem(LineEnding::CRLF).unwrap();
+
+    let session = SessionPrelude::new(pub_key_pem.to_string());
+
+    // NUL bytes (even trailing ones) and invalid UTF-8 must survive untouched
+    for plaintext in [
+        b"\0key\0file\0\0".to_vec(),
+        vec![0xff, 0xfe, 0x00, 0xc3],
+        vec![0u8; 254],
+        vec![],
+    ] {
+        let encrypted = session
+            .encrypt(&SecretBytes::from(plaintext.as_slice()))
+            .expect("Encryption failed");
+
+        let (_, decrypted_plaintext) =
+            SessionPrelude::decrypt(priv_key.clone(), encrypted).expect("Decryption failed");
+
+        assert_eq!(decrypted_plaintext, plaintext);
+    }
+}
+
 #[test]
 fn test_encrypt_too_long_plaintext() {
     let priv_key = RsaPrivateKey::from_pkcs1_pem(RSA_PRIVATE_KEY).unwrap();
@@ -117,7 +145,7 @@ fn test_encrypt_too_long_plaintext() {
     let session = SessionPrelude::new(pub_key_pem.to_string());
     let long_plaintext = "A".repeat(256); // 256 characters long
 
-    let result = session.encrypt(long_plaintext);
+    let result = session.encrypt(&SecretBytes::from(long_plaintext.as_bytes()));
     assert!(result.is_err());
     assert_eq!(result.err(), Some(SessionPreludeError::PlaintextTooLong));
 }
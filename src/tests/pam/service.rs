@@ -0,0 +1,84 @@
+/*
+    pam_polyauth: A pam module written in rust that supports multiple
+    authentication modes (including autologin).
+
+    Copyright (C) 2024-2025  Denis Benato
+
+    This program is free software; you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation; either version 2 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License along
+    with this program; if not, write to the Free Software Foundation, Inc.,
+    51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+*/
+
+use crate::pam::mount::{MountAuthDBus, MountAuthOperations};
+use crate::pam::service::start_service;
+use crate::pam::session::{Sessions, SessionsProxy};
+use crate::storage::store::{MemoryStore, UserStore};
+use std::path::Path;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+use zbus::{connection, proxy::CacheProperties};
+
+#[tokio::test]
+async fn test_start_without_bus() {
+    // peers other than root are refused by the service
+    if users::get_current_uid() != 0 {
+        return;
+    }
+
+    let socket_path = Path::new("./").join("test_start_without_bus.sock");
+
+    let store: Arc<dyn UserStore> = Arc::new(MemoryStore::new());
+    let mounts_auth = Arc::new(RwLock::new(MountAuthOperations::new(
+        std::env::temp_dir().join("test_start_without_bus.json"),
+        std::env::temp_dir().join("test_start_without_bus.key"),
+    )));
+    let sessions = Sessions::new(
+        std::env::temp_dir().join("test_start_without_bus.pem"),
+        mounts_auth.clone(),
+        store.clone(),
+    );
+
+    // a bus address nobody listens on, as when no dbus-daemon is running
+    let service = start_service(
+        socket_path.as_path(),
+        sessions,
+        MountAuthDBus::new(mounts_auth, store),
+        || connection::Builder::address("unix:path=/nonexistent/polyauth_test_bus_socket"),
+    )
+    .await
+    .unwrap();
+
+    assert!(!service.sessions_on_bus());
+    assert!(!service.mount_auth_on_bus());
+
+    let stream = tokio::net::UnixStream::connect(socket_path.clone())
+        .await
+        .unwrap();
+    let connection = connection::Builder::unix_stream(stream)
+        .p2p()
+        .build()
+        .await
+        .unwrap();
+
+    let proxy = SessionsProxy::builder(&connection)
+        .cache_properties(CacheProperties::No)
+        .build()
+        .await
+        .unwrap();
+
+    assert_eq!(proxy.version().await.unwrap(), crate::LIBRARY_VERSION);
+    assert!(proxy.list_sessions().await.unwrap().is_empty());
+
+    service.stop();
+    assert!(!socket_path.exists());
+}
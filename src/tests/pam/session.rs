@@ -0,0 +1,212 @@
+/*
+    pam_polyauth: A pam module written in rust that supports multiple
+    authentication modes (including autologin).
+
+    Copyright (C) 2024-2025  Denis Benato
+
+    This program is free software; you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation; either version 2 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License along
+    with this program; if not, write to the Free Software Foundation, Inc.,
+    51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+*/
+
+use crate::{
+    mount::{MountParams, MountPoints},
+    pam::{
+        mount::MountAuthOperations,
+        result::ServiceOperationResult,
+        runtime_dir::RuntimeDirConfig,
+        security::SessionPrelude,
+        session::{polyauth_users, Sessions, SessionsProxy, UserInfo},
+        socket::{bind_sessions_socket, remove_sessions_socket, serve_sessions_socket},
+    },
+    secret::SecretBytes,
+    storage::{store::MemoryStore, store_user_auth_data, store_user_mountpoints},
+    user::UserAuthData,
+};
+use futures_util::StreamExt;
+use std::{collections::HashMap, path::Path, sync::Arc};
+use tokio::sync::RwLock;
+use zbus::{connection, proxy::CacheProperties};
+
+#[test]
+fn test_polyauth_users() {
+    let intermediate = b"intermediate_key".to_vec();
+    let store = MemoryStore::new();
+
+    let mut autologin = UserAuthData::new();
+    autologin.set_main(b"main", &intermediate).unwrap();
+    autologin
+        .add_secondary_password("autologin", &intermediate, b"")
+        .unwrap();
+    store_user_auth_data(&autologin, &store, "bob", None, None).unwrap();
+    store_user_auth_data(&autologin, &store, "root", None, None).unwrap();
+
+    let mut password = UserAuthData::new();
+    password.set_main(b"main", &intermediate).unwrap();
+    store_user_auth_data(&password, &store, "alice", None, None).unwrap();
+
+    // configured, but without a main password
+    store_user_auth_data(&UserAuthData::new(), &store, "carol", None, None).unwrap();
+
+    let candidates = [
+        (String::from("root"), 0),
+        (String::from("bob"), 1001),
+        (String::from("carol"), 1002),
+        (String::from("dave"), 1003),
+        (String::from("alice"), 1000),
+    ];
+
+    assert_eq!(
+        polyauth_users(&store, candidates),
+        vec![
+            UserInfo {
+                username: String::from("alice"),
+                uid: 1000,
+                autologin: false,
+            },
+            UserInfo {
+                username: String::from("bob"),
+                uid: 1001,
+                autologin: true,
+            },
+        ]
+    );
+}
+
+async fn encrypted_password(proxy: &SessionsProxy<'_>, password: &[u8]) -> Vec<u8> {
+    let prelude: SessionPrelude =
+        serde_json::from_str(proxy.initiate_session().await.unwrap().as_str()).unwrap();
+
+    prelude
+        .encrypt(&SecretBytes::new(password.to_vec()))
+        .unwrap()
+}
+
+#[tokio::test]
+async fn test_session_lifecycle() {
+    // peers other than root are refused by the service, and opening a session mounts a tmpfs
+    if users::get_current_uid() != 0 {
+        return;
+    }
+
+    let socket_path = Path::new("./").join("test_session_lifecycle.sock");
+    let listener = bind_sessions_socket(socket_path.as_path()).unwrap();
+
+    let runtime_base = std::env::temp_dir().join("test_session_lifecycle_run");
+    let store = Arc::new(MemoryStore::new());
+    let sessions = Sessions::new(
+        std::env::temp_dir().join("test_session_lifecycle.pem"),
+        Arc::new(RwLock::new(MountAuthOperations::new(
+            std::env::temp_dir().join("test_session_lifecycle.json"),
+            std::env::temp_dir().join("test_session_lifecycle.key"),
+        ))),
+        store.clone(),
+    )
+    .with_runtime_dir(RuntimeDirConfig::new(runtime_base.clone(), "1m".parse().unwrap()).unwrap());
+    let server = tokio::spawn(serve_sessions_socket(listener, sessions));
+
+    let stream = tokio::net::UnixStream::connect(socket_path.clone())
+        .await
+        .unwrap();
+    let connection = connection::Builder::unix_stream(stream)
+        .p2p()
+        .build()
+        .await
+        .unwrap();
+
+    let proxy = SessionsProxy::builder(&connection)
+        .cache_properties(CacheProperties::No)
+        .build()
+        .await
+        .unwrap();
+
+    let mut opened = proxy.receive_session_opened().await.unwrap();
+    let mut closed = proxy.receive_session_closed().await.unwrap();
+    let mut failed = proxy.receive_mount_failed().await.unwrap();
+
+    // the first session mounts the runtime directory and is announced
+    let password = encrypted_password(&proxy, b"password").await;
+    assert_eq!(
+        proxy.open_user_session("root", password).await.unwrap(),
+        (0, 0)
+    );
+
+    let signal = opened.next().await.unwrap();
+    let args = signal.args().unwrap();
+    assert_eq!(args.username, "root");
+    assert_eq!(args.uid, 0);
+
+    let runtime_dir = runtime_base.join("0").to_string_lossy().to_string();
+    let session = proxy.get_session("root").await.unwrap();
+    assert_eq!(session.username, "root");
+    assert_eq!(session.uid, 0);
+    assert_eq!(session.count, 1);
+    assert!(session.opened_at > 0);
+    assert_eq!(session.mounted_paths, vec![runtime_dir.clone()]);
+    assert_eq!(proxy.list_sessions().await.unwrap(), vec![session.clone()]);
+
+    // a second session of the same user only increments the count
+    proxy.open_user_session("root", vec![]).await.unwrap();
+    let session = proxy.get_session("root").await.unwrap();
+    assert_eq!(session.count, 2);
+    assert_eq!(session.mounted_paths, vec![runtime_dir]);
+
+    // the session is gone (and announced) only once the last one is closed
+    proxy.close_user_session("root").await.unwrap();
+    assert_eq!(proxy.get_session("root").await.unwrap().count, 1);
+
+    proxy.close_user_session("root").await.unwrap();
+    let signal = closed.next().await.unwrap();
+    let args = signal.args().unwrap();
+    assert_eq!(args.username, "root");
+    assert_eq!(args.uid, 0);
+
+    assert!(proxy.list_sessions().await.unwrap().is_empty());
+    assert_eq!(
+        proxy.get_session("root").await.unwrap_err().result(),
+        ServiceOperationResult::NoSuchSession
+    );
+    assert_eq!(
+        proxy.close_user_session("root").await.unwrap_err().result(),
+        ServiceOperationResult::SessionAlreadyClosed
+    );
+
+    // mounts nobody authorized are refused and reported
+    let mounts = MountPoints::new(
+        MountParams::new(String::from("tmpfs"), String::from("tmpfs"), vec![]),
+        HashMap::new(),
+    );
+    store_user_mountpoints(Some(mounts), store.as_ref(), "root", None, None).unwrap();
+
+    let password = encrypted_password(&proxy, b"password").await;
+    assert_eq!(
+        proxy
+            .open_user_session("root", password)
+            .await
+            .unwrap_err()
+            .result(),
+        ServiceOperationResult::UnauthorizedMount
+    );
+
+    let signal = failed.next().await.unwrap();
+    let args = signal.args().unwrap();
+    assert_eq!(args.username, "root");
+    assert_eq!(
+        args.reason,
+        "mounts of user 'root' have not been authorized"
+    );
+    assert!(proxy.list_sessions().await.unwrap().is_empty());
+
+    server.abort();
+    remove_sessions_socket(socket_path);
+}
@@ -0,0 +1,102 @@
+/*
+    pam_polyauth: A pam module written in rust that supports multiple
+    authentication modes (including autologin).
+
+    Copyright (C) 2024-2025  Denis Benato
+
+    This program is free software; you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation; either version 2 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License along
+    with this program; if not, write to the Free Software Foundation, Inc.,
+    51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+*/
+
+use crate::pam::mount::MountAuthOperations;
+use crate::pam::result::ServiceOperationResult;
+use crate::pam::session::{Sessions, SessionsProxy};
+use crate::pam::socket::{bind_sessions_socket, remove_sessions_socket, serve_sessions_socket};
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+use zbus::{connection, proxy::CacheProperties};
+
+#[tokio::test]
+async fn test_direct_connection() {
+    // peers other than root are refused by the service
+    if users::get_current_uid() != 0 {
+        return;
+    }
+
+    let socket_path = Path::new("./").join("test_direct_connection.sock");
+    let listener = bind_sessions_socket(socket_path.as_path()).unwrap();
+
+    let mode = std::fs::metadata(socket_path.clone())
+        .unwrap()
+        .permissions()
+        .mode();
+    assert_eq!(mode & 0o777, 0o600);
+
+    let store = Arc::new(crate::storage::store::MemoryStore::new());
+    let command = crate::command::SessionCommand::new(String::from("sway"))
+        .with_args(vec![String::from("--unsupported-gpu")])
+        .with_session_type(crate::command::SessionType::Wayland);
+    crate::storage::store_user_session_command(&command, store.as_ref(), "username", None, None)
+        .unwrap();
+
+    let sessions = Sessions::new(
+        std::env::temp_dir().join("test_direct_connection.pem"),
+        Arc::new(RwLock::new(MountAuthOperations::new(
+            Path::new("./").join("test_direct_connection.json"),
+            std::env::temp_dir().join("test_direct_connection.key"),
+        ))),
+        store,
+    );
+    let server = tokio::spawn(serve_sessions_socket(listener, sessions));
+
+    let stream = tokio::net::UnixStream::connect(socket_path.clone())
+        .await
+        .unwrap();
+    let connection = connection::Builder::unix_stream(stream)
+        .p2p()
+        .build()
+        .await
+        .unwrap();
+
+    let proxy = SessionsProxy::builder(&connection)
+        .cache_properties(CacheProperties::No)
+        .build()
+        .await
+        .unwrap();
+
+    assert!(proxy.list_sessions().await.unwrap().is_empty());
+    assert_eq!(
+        proxy.get_session("username").await.unwrap_err().result(),
+        ServiceOperationResult::NoSuchSession
+    );
+
+    let info = proxy.get_session_command("username").await.unwrap();
+    assert_eq!(info.command, "sway");
+    assert_eq!(info.args, vec!["--unsupported-gpu"]);
+    assert_eq!(info.session_type, "wayland");
+    assert_eq!(crate::command::SessionCommand::from(info), command);
+    assert_eq!(
+        proxy
+            .get_session_command("other")
+            .await
+            .unwrap_err()
+            .result(),
+        ServiceOperationResult::NoSessionCommand
+    );
+
+    server.abort();
+    remove_sessions_socket(socket_path);
+}
@@ -0,0 +1,344 @@
+/*
+    pam_polyauth: A pam module written in rust that supports multiple
+    authentication modes (including autologin).
+
+    Copyright (C) 2024-2025  Denis Benato
+
+    This program is free software; you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation; either version 2 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License along
+    with this program; if not, write to the Free Software Foundation, Inc.,
+    51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+*/
+
+use std::collections::BTreeMap;
+
+use crate::{
+    command::SessionType,
+    kdf::KdfParams,
+    provision::{
+        diff, plan_user, Change, Manifest, ProvisionError, SecretKind, SecretSource, UserSecrets,
+    },
+    secret::SecretBytes,
+    storage::{load_user_auth_data, load_user_session_command, store::MemoryStore},
+};
+
+const MANIFEST: &str = r#"
+users:
+  - username: alice
+    main_password:
+      source: stdin
+    intermediate_key:
+      source: file
+      path: /root/keys/alice
+    secondary:
+      - name: pin
+        password:
+          source: prompt
+    mounts:
+      home:
+        device: UUID=1234-ABCD
+        fstype: ext4
+        flags: [rw, noatime]
+      pre:
+        - dir: /mnt/cache
+          device: LABEL=cache
+          fstype: btrfs
+    session:
+      command: sway
+      args: [--unsupported-gpu]
+      env:
+        XDG_CURRENT_DESKTOP: sway
+      session_type: wayland
+  - username: bob
+    session:
+      desktop: plasma
+    authorize_mounts: false
+"#;
+
+fn secrets(main: &str, intermediate: &str, secondary: &[(&str, &str)]) -> UserSecrets {
+    let mut secrets = UserSecrets::default();
+    secrets.insert(SecretKind::MainPassword, SecretBytes::from(main.as_bytes()));
+    secrets.insert(
+        SecretKind::IntermediateKey,
+        SecretBytes::from(intermediate.as_bytes()),
+    );
+    for (name, password) in secondary {
+        secrets.insert(
+            SecretKind::Secondary(String::from(*name)),
+            SecretBytes::from(password.as_bytes()),
+        );
+    }
+
+    secrets
+}
+
+fn keys(changes: &[Change]) -> Vec<&str> {
+    changes.iter().map(Change::key).collect()
+}
+
+#[test]
+fn test_manifest_parse() {
+    let manifest = Manifest::parse(MANIFEST).unwrap();
+
+    assert_eq!(manifest.users.len(), 2);
+
+    let alice = &manifest.users[0];
+    assert_eq!(
+        alice
+            .secret_sources()
+            .into_iter()
+            .map(|(kind, source)| (kind, source.clone()))
+            .collect::<Vec<_>>(),
+        vec![
+            (SecretKind::MainPassword, SecretSource::Stdin),
+            (
+                SecretKind::IntermediateKey,
+                SecretSource::File {
+                    path: "/root/keys/alice".into()
+                }
+            ),
+            (
+                SecretKind::Secondary(String::from("pin")),
+                SecretSource::Prompt
+            ),
+        ]
+    );
+    assert!(alice.authorize_mounts);
+
+    let mounts = alice.mounts.as_ref().unwrap().mount_points();
+    assert_eq!(mounts.mount().device(), "UUID=1234-ABCD");
+    assert_eq!(mounts.mount().flags(), &vec!["rw", "noatime"]);
+    assert_eq!(mounts.foreach(|dir, _| dir.clone()), vec!["/mnt/cache"]);
+
+    let session = alice.session.as_ref().unwrap();
+    assert_eq!(session.argv(), vec!["sway", "--unsupported-gpu"]);
+    assert_eq!(session.session_type(), SessionType::Wayland);
+    assert_eq!(session.env().get("XDG_CURRENT_DESKTOP").unwrap(), "sway");
+
+    let bob = &manifest.users[1];
+    assert!(bob.secret_sources().is_empty());
+    assert!(!bob.authorize_mounts);
+    assert_eq!(bob.session.as_ref().unwrap().desktop(), Some("plasma"));
+}
+
+#[test]
+fn test_invalid_manifests() {
+    let invalid = [
+        "users:\n  - username: alice\n  - username: alice\n",
+        "users:\n  - username: ''\n",
+        "users:\n  - username: alice\n    shell: /bin/sh\n",
+        "users:\n  - username: alice\n    main_password:\n      source: argv\n",
+        "users:\n  - username: alice\n    secondary:\n      - { name: pin, password: { source: prompt } }\n      - { name: pin, password: { source: stdin } }\n",
+        "users:\n  - username: alice\n    mounts:\n      home: { device: /dev/sda2 }\n      pre:\n        - { dir: mnt, device: /dev/sdb1 }\n",
+        "users:\n  - username: alice\n    session: { command: sway, desktop: plasma }\n",
+        "users:\n  - username: alice\n    session: { args: [--help] }\n",
+        "users:\n  - username: alice\n    session: { command: sway, env: { 'A=B': c } }\n",
+    ];
+
+    for manifest in invalid {
+        assert!(Manifest::parse(manifest).is_err(), "{manifest}");
+    }
+
+    assert!(matches!(
+        Manifest::parse(invalid[0]),
+        Err(ProvisionError::DuplicateUser(_))
+    ));
+}
+
+#[test]
+fn test_diff() {
+    let old = BTreeMap::from([
+        (String::from("a"), String::from("1")),
+        (String::from("b"), String::from("2")),
+        (String::from("c"), String::from("3")),
+    ]);
+    let new = BTreeMap::from([
+        (String::from("b"), String::from("2")),
+        (String::from("c"), String::from("4")),
+        (String::from("d"), String::from("5")),
+    ]);
+
+    let changes = diff(&old, &new);
+
+    assert_eq!(
+        changes.iter().map(ToString::to_string).collect::<Vec<_>>(),
+        vec!["- a: 1", "~ c: 3 -> 4", "+ d: 5"]
+    );
+    assert!(diff(&new, &new).is_empty());
+}
+
+#[test]
+fn test_provision_is_idempotent() {
+    let store = MemoryStore::new();
+    let manifest = Manifest::parse(MANIFEST).unwrap();
+    let alice = &manifest.users[0];
+    let alice_secrets = secrets("password", "intermediate", &[("pin", "1234")]);
+
+    let plan = plan_user(&store, alice, &alice_secrets, &KdfParams::default()).unwrap();
+    assert_eq!(
+        keys(plan.changes()),
+        vec![
+            "auth.main_password",
+            "auth.secondary.pin",
+            "mounts.home.device",
+            "mounts.home.flags",
+            "mounts.home.fstype",
+            "mounts.pre./mnt/cache.device",
+            "mounts.pre./mnt/cache.fstype",
+            "session.args",
+            "session.command",
+            "session.env.XDG_CURRENT_DESKTOP",
+            "session.session_type",
+        ]
+    );
+    assert!(plan
+        .changes()
+        .iter()
+        .all(|change| change.old().is_none() && change.new_value().is_some()));
+    assert_eq!(
+        plan.mounts_to_authorize(),
+        Some(
+            alice
+                .mounts
+                .as_ref()
+                .unwrap()
+                .mount_points()
+                .hash()
+                .as_str()
+        )
+    );
+
+    // nothing is written until the plan is applied
+    assert!(load_user_auth_data(&store, "alice").unwrap().is_none());
+    plan.apply(&store, None, None).unwrap();
+
+    let auth_data = load_user_auth_data(&store, "alice").unwrap().unwrap();
+    assert!(auth_data.check_main(b"password").unwrap());
+    assert_eq!(
+        auth_data.main_by_auth(Some(b"1234")).unwrap().expose(),
+        b"password"
+    );
+    assert_eq!(
+        load_user_session_command(&store, "alice").unwrap().as_ref(),
+        alice.session.as_ref()
+    );
+
+    let again = plan_user(&store, alice, &alice_secrets, &KdfParams::default()).unwrap();
+    assert!(again.is_unchanged());
+    assert!(again.mounts_to_authorize().is_some());
+
+    // secrets left out of the manifest are not needed to find out nothing changed
+    let mut without_secrets = alice.clone();
+    without_secrets.main_password = None;
+    without_secrets.intermediate_key = None;
+    without_secrets.secondary.clear();
+    assert!(plan_user(
+        &store,
+        &without_secrets,
+        &UserSecrets::default(),
+        &KdfParams::default()
+    )
+    .unwrap()
+    .is_unchanged());
+}
+
+#[test]
+fn test_provision_changes() {
+    let store = MemoryStore::new();
+    let manifest = Manifest::parse(MANIFEST).unwrap();
+    let alice = &manifest.users[0];
+
+    plan_user(
+        &store,
+        alice,
+        &secrets("password", "intermediate", &[("pin", "1234")]),
+        &KdfParams::default(),
+    )
+    .unwrap()
+    .apply(&store, None, None)
+    .unwrap();
+
+    let mut changed = alice.clone();
+    let mut mounts = changed.mounts.clone().unwrap();
+    mounts.home.device = String::from("UUID=5678-EF01");
+    mounts.pre.clear();
+    changed.mounts = Some(mounts);
+    changed.session = Some(crate::command::SessionCommand::new(String::from("bash")));
+    changed.authorize_mounts = false;
+
+    let plan = plan_user(
+        &store,
+        &changed,
+        &secrets("new password", "intermediate", &[("pin", "4321")]),
+        &KdfParams::default(),
+    )
+    .unwrap();
+
+    assert_eq!(
+        plan.changes()
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>(),
+        vec![
+            "~ auth.main_password: set -> changed",
+            "~ auth.secondary.pin: password -> password (changed)",
+            "~ mounts.home.device: UUID=1234-ABCD -> UUID=5678-EF01",
+            "- mounts.pre./mnt/cache.device: LABEL=cache",
+            "- mounts.pre./mnt/cache.fstype: btrfs",
+            "- session.args: --unsupported-gpu",
+            "~ session.command: sway -> bash",
+            "- session.env.XDG_CURRENT_DESKTOP: sway",
+            "- session.session_type: wayland",
+        ]
+    );
+    assert_eq!(plan.mounts_to_authorize(), None);
+
+    plan.apply(&store, None, None).unwrap();
+
+    let auth_data = load_user_auth_data(&store, "alice").unwrap().unwrap();
+    assert!(auth_data.check_main(b"new password").unwrap());
+    assert_eq!(auth_data.secondary().count(), 1);
+    assert_eq!(
+        auth_data.main_by_auth(Some(b"4321")).unwrap().expose(),
+        b"new password"
+    );
+
+    // the intermediate key of an existing user cannot be replaced
+    assert!(matches!(
+        plan_user(
+            &store,
+            &changed,
+            &secrets("new password", "other", &[]),
+            &KdfParams::default()
+        ),
+        Err(ProvisionError::User(..))
+    ));
+
+    // the main password does not stand for the intermediate key
+    assert!(matches!(
+        plan_user(
+            &store,
+            &changed,
+            &secrets("new password", "new password", &[]),
+            &KdfParams::default()
+        ),
+        Err(ProvisionError::User(..))
+    ));
+
+    // changing secrets needs the intermediate key
+    let mut no_key = secrets("password", "", &[]);
+    no_key.intermediate_key = None;
+    assert!(matches!(
+        plan_user(&store, &changed, &no_key, &KdfParams::default()),
+        Err(ProvisionError::MissingIntermediateKey(..))
+    ));
+}
@@ -21,9 +21,9 @@
 
 #[test]
 fn test_autologin() {
-    let correct_main = "main password <3".to_string();
-    let intermediate = "intermediate_key".to_string();
-    let autologin = String::new();
+    let correct_main = b"main password <3".to_vec();
+    let intermediate = b"intermediate_key".to_vec();
+    let autologin = Vec::new();
 
     let mut user_cfg = crate::user::UserAuthData::new();
     user_cfg.set_main(&correct_main, &intermediate).unwrap();
@@ -33,16 +33,18 @@ fn test_autologin() {
 
     let secondary_password = Some(autologin);
     assert_eq!(
-        user_cfg.main_by_auth(&secondary_password).unwrap(),
+        user_cfg
+            .main_by_auth(secondary_password.as_deref())
+            .unwrap(),
         correct_main
     );
 }
 
 #[test]
 fn test_secondary() {
-    let correct_main = "main password <3".to_string();
-    let intermediate = "intermediate_key".to_string();
-    let secondary_passwords = ["daisujda".to_string(), "sfaffsss".to_string()];
+    let correct_main = b"main password <3".to_vec();
+    let intermediate = b"intermediate_key".to_vec();
+    let secondary_passwords = [b"daisujda".to_vec(), b"sfaffsss".to_vec()];
 
     let mut user_cfg = crate::user::UserAuthData::new();
     user_cfg.set_main(&correct_main, &intermediate).unwrap();
@@ -59,7 +61,9 @@ fn test_secondary() {
     for sp in secondary_passwords.iter() {
         let secondary_password = Some(sp.clone());
         assert_eq!(
-            user_cfg.main_by_auth(&secondary_password).unwrap(),
+            user_cfg
+                .main_by_auth(secondary_password.as_deref())
+                .unwrap(),
             correct_main
         );
         tested += 1;
@@ -67,3 +71,77 @@ fn test_secondary() {
 
     assert_eq!(tested, secondary_passwords.len());
 }
+
+#[test]
+fn test_rename_secondary() {
+    let correct_main = b"main password <3".to_vec();
+    let intermediate = b"intermediate_key".to_vec();
+
+    let mut user_cfg = crate::user::UserAuthData::new();
+    user_cfg.set_kdf(crate::kdf::KdfParams::argon2id(1024, 1, 1));
+    user_cfg.set_main(&correct_main, &intermediate).unwrap();
+    user_cfg
+        .add_secondary_password("phone", &intermediate, b"1234")
+        .unwrap();
+    user_cfg
+        .add_secondary_password("laptop", &intermediate, b"5678")
+        .unwrap();
+
+    assert!(matches!(
+        user_cfg.rename_secondary("tablet", "handheld"),
+        Err(crate::error::UserOperationError::User(
+            crate::user::UserAuthDataError::SecondaryNotFound
+        ))
+    ));
+    assert!(matches!(
+        user_cfg.rename_secondary("phone", "laptop"),
+        Err(crate::error::UserOperationError::User(
+            crate::user::UserAuthDataError::SecondaryNameTaken
+        ))
+    ));
+
+    user_cfg.rename_secondary("phone", "handheld").unwrap();
+    let names: Vec<String> = user_cfg.secondary().map(|auth| auth.name()).collect();
+    assert_eq!(
+        names,
+        vec![String::from("handheld"), String::from("laptop")]
+    );
+
+    // the renamed method still unlocks the main password
+    assert_eq!(
+        user_cfg.main_by_auth(Some(b"1234".as_slice())).unwrap(),
+        correct_main
+    );
+
+    // removing it leaves the other one working
+    assert!(user_cfg.remove_secondary("handheld"));
+    assert!(!user_cfg.remove_secondary("handheld"));
+    assert!(user_cfg.main_by_auth(Some(b"1234".as_slice())).is_err());
+    assert_eq!(
+        user_cfg.main_by_auth(Some(b"5678".as_slice())).unwrap(),
+        correct_main
+    );
+}
+
+#[test]
+fn test_add_secondary_with_main_password() {
+    let correct_main = b"main password <3".to_vec();
+    let intermediate = b"intermediate_key".to_vec();
+
+    let mut user_cfg = crate::user::UserAuthData::new();
+    user_cfg.set_main(&correct_main, &intermediate).unwrap();
+
+    // the main password is not an intermediate key: the entry could never authenticate
+    assert!(matches!(
+        user_cfg.add_secondary_password("phone", &correct_main, b"1234"),
+        Err(crate::error::UserOperationError::User(
+            crate::user::UserAuthDataError::WrongIntermediateKey
+        ))
+    ));
+    assert!(user_cfg.secondary().next().is_none());
+
+    user_cfg
+        .add_secondary_password("phone", &intermediate, b"1234")
+        .unwrap();
+    assert_eq!(user_cfg.main_by_auth(Some(b"1234")).unwrap(), correct_main);
+}
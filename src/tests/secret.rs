@@ -0,0 +1,128 @@
+/*
+    pam_polyauth: A pam module written in rust that supports multiple
+    authentication modes (including autologin).
+
+    Copyright (C) 2024-2025  Denis Benato
+
+    This program is free software; you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation; either version 2 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License along
+    with this program; if not, write to the Free Software Foundation, Inc.,
+    51 Franklin Street, Fifth Floor, Boston, MA 02110-1301 USA.
+*/
+
+use crate::secret::{
+    is_valid_password, is_valid_secret, read_secret, read_secret_line, SecretBytes, SecretString,
+    MAX_PASSWORD_LEN,
+};
+
+#[test]
+fn test_redacted() {
+    let secret = SecretString::from("hunter2");
+    assert!(!format!("{secret:?}").contains("hunter2"));
+
+    let secret = SecretBytes::from(b"hunter2".as_slice());
+    assert!(!format!("{secret:?}").contains("104"));
+}
+
+#[test]
+fn test_eq() {
+    let secret = SecretString::from("hunter2");
+
+    assert_eq!(secret, String::from("hunter2"));
+    assert_eq!(secret, "hunter2");
+    assert_ne!(secret, "hunter3");
+    assert_eq!(secret.clone(), secret);
+}
+
+#[test]
+fn test_constant_time_eq() {
+    assert!(crate::constant_time_eq(b"", b""));
+    assert!(crate::constant_time_eq(b"secret", b"secret"));
+    assert!(!crate::constant_time_eq(b"secret", b"secreT"));
+    assert!(!crate::constant_time_eq(b"secret", b"secret "));
+}
+
+#[test]
+fn test_into_bytes() {
+    let secret = SecretString::from("hunter2");
+    let bytes = SecretBytes::from(secret);
+
+    assert_eq!(bytes, b"hunter2".as_slice());
+    assert_eq!(bytes.len(), 7);
+}
+
+#[test]
+fn test_is_valid_password() {
+    assert!(is_valid_password("hunter2"));
+    assert!(is_valid_password("pass word ✓"));
+    assert!(is_valid_password(&"a".repeat(MAX_PASSWORD_LEN)));
+
+    assert!(!is_valid_password(""));
+    assert!(!is_valid_password(&"a".repeat(MAX_PASSWORD_LEN + 1)));
+    assert!(!is_valid_password("hunter2\n"));
+    assert!(!is_valid_password("hunter\r2"));
+    assert!(!is_valid_password("hunter\0two"));
+}
+
+#[test]
+fn test_is_valid_secret() {
+    assert!(is_valid_secret(b"hunter2"));
+    assert!(is_valid_secret(b"hunter\0two\n"));
+    assert!(is_valid_secret(&[0xff, 0xfe, 0x00, 0x80]));
+    assert!(is_valid_secret(&[0xff; MAX_PASSWORD_LEN]));
+
+    assert!(!is_valid_secret(b""));
+    assert!(!is_valid_secret(&[0xff; MAX_PASSWORD_LEN + 1]));
+}
+
+#[test]
+fn test_read_secret() {
+    assert_eq!(
+        read_secret(b"hunter2".as_slice()).unwrap(),
+        b"hunter2".as_slice()
+    );
+    assert_eq!(
+        read_secret(b"hunter2\n".as_slice()).unwrap(),
+        b"hunter2".as_slice()
+    );
+    assert_eq!(
+        read_secret(b"hunter2\r\n".as_slice()).unwrap(),
+        b"hunter2".as_slice()
+    );
+    assert_eq!(
+        read_secret(b"hunter2\n\n".as_slice()).unwrap(),
+        b"hunter2\n".as_slice()
+    );
+
+    // key files are taken as they are, even when they are not UTF-8
+    let key = [0xff, 0x00, 0xc3, 0x28, 0x0a, 0x80];
+    assert_eq!(read_secret(key.as_slice()).unwrap(), key.as_slice());
+
+    // oversized secrets are cut short, but never to a valid length
+    let oversized = read_secret("a".repeat(10 * MAX_PASSWORD_LEN).as_bytes()).unwrap();
+    assert!(!is_valid_secret(&oversized));
+
+    let mut lines = b"first\nsecond\r\n\xff\x00last".as_slice();
+    assert_eq!(
+        read_secret_line(&mut lines).unwrap().unwrap(),
+        b"first".as_slice()
+    );
+    assert_eq!(
+        read_secret_line(&mut lines).unwrap().unwrap(),
+        b"second".as_slice()
+    );
+    assert_eq!(
+        read_secret_line(&mut lines).unwrap().unwrap(),
+        b"\xff\x00last".as_slice()
+    );
+    assert!(read_secret_line(&mut lines).unwrap().is_none());
+}
@@ -21,15 +21,15 @@
 
 #[test]
 fn test_main_password_serialization() {
-    let first_main = "main password <3".to_string();
-    let intermediate = "intermediate_key".to_string();
+    let first_main = b"main password <3".to_vec();
+    let intermediate = b"intermediate_key".to_vec();
 
     let provided_password = Some(first_main.clone());
 
     let dir_name = "test1";
     let file_path = std::path::PathBuf::from(dir_name).join("config.json");
 
-    let source = crate::storage::StorageSource::File(file_path.clone());
+    let store = crate::storage::store::JsonStore::file(file_path.clone());
 
     // Clean up any existing test directory
     let _ = std::fs::remove_dir_all(dir_name);
@@ -41,22 +41,22 @@ fn test_main_password_serialization() {
         user_cfg.set_main(&first_main, &intermediate).unwrap();
 
         assert_eq!(
-            user_cfg.main_by_auth(&provided_password).unwrap(),
+            user_cfg.main_by_auth(provided_password.as_deref()).unwrap(),
             first_main
         );
 
         std::fs::create_dir(dir_name).unwrap();
-        crate::storage::store_user_auth_data(&user_cfg, &source, None, None).unwrap();
+        crate::storage::store_user_auth_data(&user_cfg, &store, "user", None, None).unwrap();
     }
 
-    match crate::storage::load_user_auth_data(&source) {
+    match crate::storage::load_user_auth_data(&store, "user") {
         Ok(reloaded) => {
             std::fs::remove_dir_all(dir_name).unwrap();
             assert_eq!(
                 reloaded
                     .as_ref()
                     .unwrap()
-                    .main_by_auth(&provided_password)
+                    .main_by_auth(provided_password.as_deref())
                     .unwrap(),
                 first_main
             )
@@ -72,13 +72,13 @@ fn test_main_password_serialization() {
 
 #[test]
 fn test_secondary_password_serialization() {
-    let correct_main = "main password <3".to_string();
-    let intermediate = "intermediate_key".to_string();
-    let secondary_passwords = ["daisujda".to_string(), "sfaffsss".to_string()];
+    let correct_main = b"main password <3".to_vec();
+    let intermediate = b"intermediate_key".to_vec();
+    let secondary_passwords = [b"daisujda".to_vec(), b"sfaffsss".to_vec()];
 
     let dir_name = "test2";
     let file_path = std::path::PathBuf::from(dir_name).join("config.json");
-    let source = crate::storage::StorageSource::File(file_path.clone());
+    let store = crate::storage::store::JsonStore::file(file_path.clone());
 
     // Clean up any existing test directory
     let _ = std::fs::remove_dir_all(dir_name);
@@ -95,11 +95,11 @@ fn test_secondary_password_serialization() {
         }
 
         std::fs::create_dir(dir_name).unwrap();
-        crate::storage::store_user_auth_data(&user_cfg, &source, None, None).unwrap();
+        crate::storage::store_user_auth_data(&user_cfg, &store, "user", None, None).unwrap();
     }
 
     let mut tested: usize = 0;
-    match crate::storage::load_user_auth_data(&source) {
+    match crate::storage::load_user_auth_data(&store, "user") {
         Ok(reloaded) => {
             std::fs::remove_dir_all(dir_name).unwrap();
 
@@ -111,7 +111,7 @@ fn test_secondary_password_serialization() {
                     reloaded
                         .as_ref()
                         .unwrap()
-                        .main_by_auth(&secondary_password)
+                        .main_by_auth(secondary_password.as_deref())
                         .unwrap(),
                     correct_main
                 );
@@ -127,3 +127,988 @@ fn test_secondary_password_serialization() {
 
     assert_eq!(tested, secondary_passwords.len());
 }
+
+#[test]
+fn test_legacy_format_migration() {
+    let main = b"main password <3".to_vec();
+    let intermediate = b"intermediate_key".to_vec();
+    let secondary = b"daisujda".to_vec();
+
+    let dir_name = "test3";
+    let file_path = std::path::PathBuf::from(dir_name).join("config.json");
+    let store = crate::storage::store::JsonStore::file(file_path.clone());
+
+    // Clean up any existing test directory
+    let _ = std::fs::remove_dir_all(dir_name);
+
+    {
+        let mut user_cfg = crate::user::UserAuthData::new();
+
+        // entries in the format that stores bcrypt hashes
+        user_cfg.push_main(crate::user::StoredMainPassword::Legacy(
+            crate::storage::legacy::MainPasswordV0::new(&main, &intermediate).unwrap(),
+        ));
+        user_cfg.push_secondary(crate::auth::SecondaryAuth::new(
+            "test0",
+            None,
+            crate::auth::SecondaryAuthMethod::LegacyPassword(
+                crate::storage::legacy::SecondaryPasswordV0::new(&intermediate, &secondary)
+                    .unwrap(),
+            ),
+        ));
+
+        std::fs::create_dir(dir_name).unwrap();
+        crate::storage::store_user_auth_data(&user_cfg, &store, "user", None, None).unwrap();
+    }
+
+    let formats = || {
+        let contents = std::fs::read_to_string(&file_path).unwrap();
+        let config: serde_json::Value = serde_json::from_str(&contents).unwrap();
+        (
+            config["auth_data"]["main_format"].as_u64().unwrap(),
+            config["auth_data"]["secondary"][0]["format"]
+                .as_u64()
+                .unwrap(),
+        )
+    };
+
+    // legacy entries are stored back in their own format until upgraded
+    assert_eq!(formats(), (0, 0));
+
+    let mut reloaded = crate::storage::load_user_auth_data(&store, "user")
+        .unwrap()
+        .unwrap();
+    assert!(reloaded.needs_upgrade());
+    assert!(reloaded.check_main(&main).unwrap());
+    assert!(!reloaded.check_main(&secondary).unwrap());
+    assert_eq!(reloaded.main_by_auth(Some(&main)).unwrap(), main);
+    assert_eq!(reloaded.main_by_auth(Some(&secondary)).unwrap(), main);
+
+    // the secondary password unlocks both entries
+    assert!(reloaded.upgrade(Some(&secondary)).unwrap());
+    assert!(!reloaded.needs_upgrade());
+    crate::storage::store_user_auth_data(&reloaded, &store, "user", None, None).unwrap();
+    assert_eq!(formats(), (1, 1));
+
+    let upgraded = crate::storage::load_user_auth_data(&store, "user")
+        .unwrap()
+        .unwrap();
+    std::fs::remove_dir_all(dir_name).unwrap();
+
+    assert!(!upgraded.needs_upgrade());
+    assert_eq!(upgraded.main_by_auth(Some(&main)).unwrap(), main);
+    assert_eq!(upgraded.main_by_auth(Some(&secondary)).unwrap(), main);
+    assert_eq!(upgraded.main(&intermediate).unwrap(), main);
+    assert!(upgraded
+        .main_by_auth(Some(b"wrong password".as_slice()))
+        .is_err());
+}
+
+/// Sets the memory cost of an encoded entry written with argon2id(1024, 1, 1) to u32::MAX
+fn raise_m_cost(encoded: &serde_json::Value) -> serde_json::Value {
+    use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+
+    let mut bytes = BASE64.decode(encoded.as_str().unwrap()).unwrap();
+    let costs = |encode: fn(u32) -> [u8; 4]| [encode(1024), encode(1), encode(1)].concat();
+    let (costs, huge) = match bytes.windows(12).any(|w| w == costs(u32::to_be_bytes)) {
+        true => (costs(u32::to_be_bytes), u32::MAX.to_be_bytes()),
+        false => (costs(u32::to_le_bytes), u32::MAX.to_le_bytes()),
+    };
+
+    let at = bytes.windows(12).position(|w| w == costs).unwrap();
+    bytes[at..at + 4].copy_from_slice(&huge);
+
+    serde_json::json!(BASE64.encode(&bytes))
+}
+
+#[test]
+fn test_kdf_costs_out_of_range() {
+    let dir_name = "test_kdf_costs";
+    let file_path = std::path::PathBuf::from(dir_name).join("config.json");
+    let store = crate::storage::store::JsonStore::file(file_path.clone());
+
+    // Clean up any existing test directory
+    let _ = std::fs::remove_dir_all(dir_name);
+
+    let mut auth_data = crate::user::UserAuthData::new();
+    auth_data.set_kdf(crate::kdf::KdfParams::argon2id(1024, 1, 1));
+    auth_data
+        .set_main(b"main password <3", b"intermediate_key")
+        .unwrap();
+    auth_data
+        .add_secondary_password("pin", b"intermediate_key", b"1234")
+        .unwrap();
+    crate::storage::store_user_auth_data(&auth_data, &store, "user", None, None).unwrap();
+
+    let original: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&file_path).unwrap()).unwrap();
+    let load_tampered = |tamper: &dyn Fn(&mut serde_json::Value)| {
+        let mut config = original.clone();
+        tamper(&mut config);
+        std::fs::write(&file_path, serde_json::to_string(&config).unwrap()).unwrap();
+        crate::storage::load_user_auth_data(&store, "user")
+    };
+
+    let untouched = load_tampered(&|_| {});
+    let huge_main = load_tampered(&|config| {
+        config["auth_data"]["main"] = raise_m_cost(&config["auth_data"]["main"]);
+    });
+    let huge_secondary = load_tampered(&|config| {
+        config["auth_data"]["secondary"][0]["password"] =
+            raise_m_cost(&config["auth_data"]["secondary"][0]["password"]);
+    });
+
+    std::fs::remove_dir_all(dir_name).unwrap();
+
+    assert!(untouched.unwrap().is_some());
+    assert!(matches!(
+        huge_main,
+        Err(crate::storage::StorageError::DeserializationError)
+    ));
+    assert!(matches!(
+        huge_secondary,
+        Err(crate::storage::StorageError::DeserializationError)
+    ));
+}
+
+#[test]
+fn test_autologin_flag() {
+    let dir_name = "test_autologin_flag";
+    let file_path = std::path::PathBuf::from(dir_name).join("config.json");
+    let store = crate::storage::store::JsonStore::file(file_path.clone());
+
+    // Clean up any existing test directory
+    let _ = std::fs::remove_dir_all(dir_name);
+
+    let mut auth_data = crate::user::UserAuthData::new();
+    auth_data.set_kdf(crate::kdf::KdfParams::argon2id(1024, 1, 1));
+    auth_data.set_main(b"main", b"intermediate_key").unwrap();
+    auth_data
+        .add_secondary_password("pin", b"intermediate_key", b"1234")
+        .unwrap();
+    auth_data
+        .add_secondary_password("autologin", b"intermediate_key", b"")
+        .unwrap();
+    crate::storage::store_user_auth_data(&auth_data, &store, "user", None, None).unwrap();
+
+    let stored = crate::storage::load_user_auth_data(&store, "user")
+        .unwrap()
+        .unwrap();
+
+    // entries written before the flag was recorded
+    let mut config: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&file_path).unwrap()).unwrap();
+    for item in config["auth_data"]["secondary"].as_array_mut().unwrap() {
+        item.as_object_mut().unwrap().remove("autologin");
+    }
+    std::fs::write(&file_path, serde_json::to_string(&config).unwrap()).unwrap();
+    let unflagged = crate::storage::load_user_auth_data(&store, "user")
+        .unwrap()
+        .unwrap();
+
+    std::fs::remove_dir_all(dir_name).unwrap();
+
+    let flags = stored
+        .secondary()
+        .map(|auth| auth.autologin())
+        .collect::<Vec<_>>();
+    assert_eq!(flags, vec![Some(false), Some(true)]);
+    assert!(stored.autologin());
+
+    assert!(unflagged.secondary().all(|auth| auth.autologin().is_none()));
+    assert!(unflagged.autologin());
+}
+
+#[test]
+fn test_store_keeps_backup() {
+    let dir_name = "test4";
+    let file_path = std::path::PathBuf::from(dir_name).join("config.json");
+    let store = crate::storage::store::JsonStore::file(file_path.clone());
+
+    // Clean up any existing test directory
+    let _ = std::fs::remove_dir_all(dir_name);
+
+    let first = crate::command::SessionCommand::new(String::from("first"));
+    let second = crate::command::SessionCommand::new(String::from("second"));
+
+    crate::storage::store_user_session_command(&first, &store, "user", None, None).unwrap();
+    let first_backup = crate::storage::load_user_session_command(
+        &crate::storage::store::JsonStore::file(file_path.with_file_name("config.json.bak")),
+        "user",
+    )
+    .unwrap()
+    .unwrap();
+
+    crate::storage::store_user_session_command(&second, &store, "user", None, None).unwrap();
+
+    let backup =
+        crate::storage::store::JsonStore::file(file_path.with_file_name("config.json.bak"));
+    let backed_up = crate::storage::load_user_session_command(&backup, "user")
+        .unwrap()
+        .unwrap();
+    let current = crate::storage::load_user_session_command(&store, "user")
+        .unwrap()
+        .unwrap();
+
+    let leftovers = std::fs::read_dir(dir_name)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .filter(|name| name.contains(".tmp"))
+        .count();
+
+    crate::storage::remove_user_data(&store, "user").unwrap();
+    let removed = !file_path.exists();
+    let removed_backup = crate::storage::load_user_session_command(&backup, "user")
+        .unwrap()
+        .unwrap();
+
+    std::fs::remove_dir_all(dir_name).unwrap();
+
+    assert_eq!(first_backup.command(), first.command());
+    assert_eq!(backed_up.command(), first.command());
+    assert_eq!(current.command(), second.command());
+    assert_eq!(leftovers, 0);
+    assert!(removed);
+    assert_eq!(removed_backup.command(), second.command());
+}
+
+#[test]
+fn test_concurrent_stores() {
+    let dir_name = "test5";
+    let file_path = std::path::PathBuf::from(dir_name).join("config.json");
+
+    // Clean up any existing test directory
+    let _ = std::fs::remove_dir_all(dir_name);
+
+    let session_path = file_path.clone();
+    let session_writer = std::thread::spawn(move || {
+        let store = crate::storage::store::JsonStore::file(session_path);
+        for i in 0..50 {
+            let command = crate::command::SessionCommand::new(format!("session{i}"));
+            crate::storage::store_user_session_command(&command, &store, "user", None, None)
+                .unwrap();
+        }
+    });
+
+    let mount_path = file_path.clone();
+    let mount_writer = std::thread::spawn(move || {
+        let store = crate::storage::store::JsonStore::file(mount_path);
+        for i in 0..50 {
+            let mounts = crate::mount::MountPoints::new(
+                crate::mount::MountParams::new(
+                    format!("/dev/disk{i}"),
+                    String::from("ext4"),
+                    vec![],
+                ),
+                std::collections::HashMap::new(),
+            );
+            crate::storage::store_user_mountpoints(Some(mounts), &store, "user", None, None)
+                .unwrap();
+        }
+    });
+
+    session_writer.join().unwrap();
+    mount_writer.join().unwrap();
+
+    let store = crate::storage::store::JsonStore::file(file_path);
+    let command = crate::storage::load_user_session_command(&store, "user").unwrap();
+    let mounts = crate::storage::load_user_mountpoints(&store, "user").unwrap();
+
+    std::fs::remove_dir_all(dir_name).unwrap();
+
+    // neither writer dropped the other's last update
+    assert_eq!(command.unwrap().command(), "session49");
+    assert_eq!(mounts.unwrap().mount().device(), "/dev/disk49");
+}
+
+#[test]
+fn test_busy_lock() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir_name = "test_busy_lock";
+    let file_path = std::path::PathBuf::from(dir_name).join("config.json");
+    let store = std::sync::Arc::new(crate::storage::store::JsonStore::file(file_path.clone()));
+
+    // Clean up any existing test directory
+    let _ = std::fs::remove_dir_all(dir_name);
+
+    let command = crate::command::SessionCommand::new(String::from("sway"));
+    crate::storage::store_user_session_command(&command, store.as_ref(), "user", None, None)
+        .unwrap();
+
+    // a writer holding the lock until told to go on
+    let (locked_tx, locked_rx) = std::sync::mpsc::channel();
+    let (release_tx, release_rx) = std::sync::mpsc::channel::<()>();
+    let holder = {
+        let store = store.clone();
+        std::thread::spawn(move || {
+            crate::storage::store::UserStore::update(
+                store.as_ref(),
+                "user",
+                None,
+                None,
+                &mut |_| {
+                    locked_tx.send(()).unwrap();
+                    release_rx.recv().unwrap();
+                    Ok(())
+                },
+            )
+            .unwrap()
+        })
+    };
+    locked_rx.recv().unwrap();
+
+    let started = std::time::Instant::now();
+    let skipped = crate::storage::without_waiting_for_locks(|| {
+        crate::storage::store_user_session_command(&command, store.as_ref(), "user", None, None)
+    });
+    let gave_up_at_once = started.elapsed() < std::time::Duration::from_secs(1);
+
+    release_tx.send(()).unwrap();
+    holder.join().unwrap();
+    let after_release =
+        crate::storage::store_user_session_command(&command, store.as_ref(), "user", None, None);
+
+    let lock_mode = std::fs::metadata(file_path.with_file_name("config.json.lock"))
+        .unwrap()
+        .permissions()
+        .mode();
+
+    std::fs::remove_dir_all(dir_name).unwrap();
+
+    assert!(crate::storage::is_locked_error(&skipped.unwrap_err()));
+    assert!(gave_up_at_once);
+    assert!(after_release.is_ok());
+    assert_eq!(lock_mode & 0o777, 0o600);
+}
+
+#[test]
+fn test_owner_write_in_read_only_dir() {
+    use std::os::unix::fs::PermissionsExt;
+
+    // the owner of the configuration is impersonated with the filesystem uid of a thread
+    if users::get_current_uid() != 0 {
+        return;
+    }
+
+    let dir_name = "test_owner_write";
+    let file_path = std::path::PathBuf::from(dir_name).join("config.json");
+    let store = crate::storage::store::JsonStore::file(file_path.clone());
+    let nobody = 65534;
+
+    // Clean up any existing test directory
+    let _ = std::fs::remove_dir_all(dir_name);
+
+    // set up by root for the user, in a directory the user cannot create files in
+    let first = crate::command::SessionCommand::new(String::from("first"));
+    crate::storage::store_user_session_command(&first, &store, "user", Some(nobody), Some(nobody))
+        .unwrap();
+    std::fs::set_permissions(dir_name, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+    let second = crate::command::SessionCommand::new(String::from("second"));
+    let written = std::thread::spawn(move || {
+        unsafe {
+            libc::setfsgid(nobody);
+            libc::setfsuid(nobody);
+        }
+        crate::storage::store_user_session_command(&second, &store, "user", None, None)
+    })
+    .join()
+    .unwrap();
+
+    let store = crate::storage::store::JsonStore::file(file_path.clone());
+    let current = crate::storage::load_user_session_command(&store, "user").unwrap();
+
+    // an interrupted rewrite is read back from the backup
+    std::fs::write(&file_path, "{\"version\": 0, \"session_com").unwrap();
+    let interrupted = crate::storage::load_user_session_command(&store, "user").unwrap();
+
+    let leftovers = std::fs::read_dir(dir_name)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .filter(|name| name.contains(".tmp"))
+        .count();
+
+    std::fs::remove_dir_all(dir_name).unwrap();
+
+    written.unwrap();
+    assert_eq!(current.unwrap().command(), "second");
+    assert_eq!(interrupted.unwrap().command(), "first");
+    assert_eq!(leftovers, 0);
+}
+
+#[test]
+fn test_baseline_config() {
+    let dir_name = "test6";
+    let file_path = std::path::PathBuf::from(dir_name).join("config.json");
+    let store = crate::storage::store::JsonStore::file(file_path.clone());
+
+    // Clean up any existing test directory
+    let _ = std::fs::remove_dir_all(dir_name);
+
+    // a file written by the first release, before blob formats and session arguments
+    let original = r#"{
+  "version": 0,
+  "session_command": {
+    "command": "startplasma-wayland"
+  },
+  "auth_data": {
+    "secondary": [
+      {
+        "name": "pin",
+        "creation_date": 0,
+        "auth_type": 0,
+        "password": ""
+      }
+    ]
+  },
+  "mountpoints": {
+    "home": {
+      "fstype": "ext4",
+      "device": "/dev/sda2",
+      "directory": "",
+      "args": []
+    }
+  }
+}"#;
+    std::fs::create_dir(dir_name).unwrap();
+    std::fs::write(&file_path, original).unwrap();
+
+    let report = crate::storage::migrate_user_config(&store, "user", false)
+        .unwrap()
+        .unwrap();
+    let untouched = std::fs::read_to_string(&file_path).unwrap();
+
+    let command = crate::storage::load_user_session_command(&store, "user").unwrap();
+    let mounts = crate::storage::load_user_mountpoints(&store, "user").unwrap();
+
+    std::fs::remove_dir_all(dir_name).unwrap();
+
+    // nothing has to be migrated: missing fields default to what the first release meant
+    assert!(!report.is_needed());
+    assert!(report.steps().is_empty());
+    assert_eq!(untouched, original);
+
+    let command = command.unwrap();
+    assert_eq!(command.command(), "startplasma-wayland");
+    assert!(command.args().is_empty());
+    assert_eq!(mounts.unwrap().mount().device(), "/dev/sda2");
+}
+
+/// Synthetic step used to exercise the migration machinery: moves `legacy_command` to `session_command`
+fn rename_legacy_command(
+    config: &mut serde_json::Value,
+) -> Result<(), crate::storage::StorageError> {
+    let object = config
+        .as_object_mut()
+        .ok_or(crate::storage::StorageError::DeserializationError)?;
+    if let Some(command) = object.remove("legacy_command") {
+        object.insert(String::from("session_command"), command);
+    }
+
+    Ok(())
+}
+
+const SYNTHETIC_MIGRATIONS: &[crate::storage::migration::Migration] =
+    &[crate::storage::migration::Migration {
+        from: 0,
+        description: "rename legacy_command to session_command",
+        apply: rename_legacy_command,
+    }];
+
+#[test]
+fn test_migration_steps() {
+    let dir_name = "test_migration_steps";
+    let file_path = std::path::PathBuf::from(dir_name).join("config.json");
+
+    // Clean up any existing test directory
+    let _ = std::fs::remove_dir_all(dir_name);
+
+    let original = r#"{ "legacy_command": { "command": "sway" } }"#;
+    std::fs::create_dir(dir_name).unwrap();
+    std::fs::write(&file_path, original).unwrap();
+
+    let migrate = |config: &mut serde_json::Value| {
+        crate::storage::migration::migrate_value(config, SYNTHETIC_MIGRATIONS, 1)
+    };
+
+    let planned = crate::storage::migrate_config_at_path_with(&file_path, true, migrate).unwrap();
+    let after_dry_run = std::fs::read_to_string(&file_path).unwrap();
+
+    let applied = crate::storage::migrate_config_at_path_with(&file_path, false, migrate).unwrap();
+    let migrated: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&file_path).unwrap()).unwrap();
+
+    // a version without a registered step is refused
+    let mut unknown = serde_json::json!({ "version": 1 });
+    let missing_step =
+        crate::storage::migration::migrate_value(&mut unknown, SYNTHETIC_MIGRATIONS, 2);
+
+    std::fs::remove_dir_all(dir_name).unwrap();
+
+    let planned = planned.unwrap();
+    assert!(planned.is_needed());
+    assert_eq!(planned.from_version(), 0);
+    assert_eq!(planned.to_version(), 1);
+    assert_eq!(
+        planned.steps(),
+        &["rename legacy_command to session_command"]
+    );
+    assert_eq!(after_dry_run, original);
+
+    assert_eq!(applied.unwrap(), planned);
+    assert_eq!(migrated["version"], 1);
+    assert_eq!(migrated["session_command"]["command"], "sway");
+    assert!(migrated.get("legacy_command").is_none());
+
+    assert!(matches!(
+        missing_step,
+        Err(crate::storage::StorageError::DeserializationError)
+    ));
+}
+
+#[test]
+fn test_future_config_version() {
+    let dir_name = "test7";
+    let file_path = std::path::PathBuf::from(dir_name).join("config.json");
+    let store = crate::storage::store::JsonStore::file(file_path.clone());
+
+    // Clean up any existing test directory
+    let _ = std::fs::remove_dir_all(dir_name);
+
+    let future = format!(
+        r#"{{ "version": {}, "session_command": {{ "command": "sway" }} }}"#,
+        crate::storage::migration::CURRENT_CONFIG_VERSION + 1
+    );
+    std::fs::create_dir(dir_name).unwrap();
+    std::fs::write(&file_path, &future).unwrap();
+
+    let loaded = crate::storage::load_user_session_command(&store, "user");
+    let command = crate::command::SessionCommand::new(String::from("bash"));
+    let stored = crate::storage::store_user_session_command(&command, &store, "user", None, None);
+    let migrated = crate::storage::migrate_user_config(&store, "user", false);
+    let contents = std::fs::read_to_string(&file_path).unwrap();
+
+    std::fs::remove_dir_all(dir_name).unwrap();
+
+    assert!(matches!(
+        loaded,
+        Err(crate::storage::StorageError::FutureConfigVersion(_))
+    ));
+    assert!(stored.is_err());
+    assert!(migrated.is_err());
+
+    // a newer file is never overwritten
+    assert_eq!(contents, future);
+}
+
+#[test]
+fn test_config_signature() {
+    use crate::storage::integrity::{ConfigIntegrity, ConfigSigningKey};
+
+    let dir_name = "test8";
+    let file_path = std::path::PathBuf::from(dir_name).join("config.json");
+    let store = crate::storage::store::JsonStore::file(file_path.clone());
+
+    // Clean up any existing test directory
+    let _ = std::fs::remove_dir_all(dir_name);
+
+    let key = ConfigSigningKey::generate();
+    let mounts = || {
+        let mut additional = std::collections::HashMap::new();
+        for dir in ["/a", "/b", "/c", "/d"] {
+            additional.insert(
+                String::from(dir),
+                crate::mount::MountParams::new(
+                    String::from("tmpfs"),
+                    String::from("tmpfs"),
+                    vec![],
+                ),
+            );
+        }
+
+        crate::mount::MountPoints::new(
+            crate::mount::MountParams::new(
+                String::from("/dev/sda1"),
+                String::from("ext4"),
+                vec![String::from("rw")],
+            ),
+            additional,
+        )
+    };
+    let command = crate::command::SessionCommand::new(String::from("sway"));
+
+    crate::storage::store_user_mountpoints(Some(mounts()), &store, "user", None, None).unwrap();
+    crate::storage::store_user_session_command(&command, &store, "user", None, None).unwrap();
+    let unsigned = crate::storage::verify_user_config(&store, "user", &key).unwrap();
+
+    crate::storage::sign_user_config(&store, "user", &key).unwrap();
+    let signed = crate::storage::verify_user_config(&store, "user", &key).unwrap();
+    let other_user = crate::storage::verify_user_config(&store, "other", &key).unwrap();
+    let other_key =
+        crate::storage::verify_user_config(&store, "user", &ConfigSigningKey::generate()).unwrap();
+
+    // storing the same data again, or changing authentication data, keeps the signature valid
+    crate::storage::store_user_mountpoints(Some(mounts()), &store, "user", None, None).unwrap();
+    let mut auth_data = crate::user::UserAuthData::new();
+    auth_data.set_main(b"main", b"intermediate").unwrap();
+    crate::storage::store_user_auth_data(&auth_data, &store, "user", None, None).unwrap();
+    let (restored, loaded_mounts) =
+        crate::storage::load_verified_user_mountpoints(&store, "user", &key).unwrap();
+
+    let changed = crate::command::SessionCommand::new(String::from("bash"));
+    crate::storage::store_user_session_command(&changed, &store, "user", None, None).unwrap();
+    let tampered = crate::storage::verify_user_config(&store, "user", &key).unwrap();
+
+    std::fs::remove_dir_all(dir_name).unwrap();
+
+    assert_eq!(unsigned, ConfigIntegrity::Unsigned);
+    assert_eq!(signed, ConfigIntegrity::Signed);
+    assert_eq!(other_user, ConfigIntegrity::Tampered);
+    assert_eq!(other_key, ConfigIntegrity::Tampered);
+    assert_eq!(restored, ConfigIntegrity::Signed);
+    assert_eq!(loaded_mounts.unwrap(), mounts());
+    assert_eq!(tampered, ConfigIntegrity::Tampered);
+}
+
+#[test]
+fn test_signing_key_encoding() {
+    use crate::storage::integrity::ConfigSigningKey;
+
+    let key = ConfigSigningKey::generate();
+    let encoded = key.to_base64();
+
+    assert_eq!(
+        ConfigSigningKey::from_base64(&format!("{encoded}\n"))
+            .unwrap()
+            .to_base64(),
+        encoded
+    );
+    assert!(ConfigSigningKey::from_base64("c2hvcnQ=").is_err());
+    assert!(ConfigSigningKey::from_base64("not base64!").is_err());
+}
+
+#[test]
+fn test_memory_store() {
+    use crate::storage::store::{MemoryStore, UserStore};
+
+    let store = MemoryStore::new();
+    let command = crate::command::SessionCommand::new(String::from("sway"));
+
+    assert!(crate::storage::load_user_session_command(&store, "user")
+        .unwrap()
+        .is_none());
+
+    crate::storage::store_user_session_command(&command, &store, "user", None, None).unwrap();
+    assert_eq!(
+        crate::storage::load_user_session_command(&store, "user")
+            .unwrap()
+            .unwrap()
+            .command(),
+        "sway"
+    );
+    assert!(crate::storage::load_user_session_command(&store, "other")
+        .unwrap()
+        .is_none());
+
+    let report = store.migrate("user", false).unwrap().unwrap();
+    assert!(!report.is_needed());
+
+    crate::storage::remove_user_data(&store, "user").unwrap();
+    assert!(store.read("user").unwrap().is_none());
+}
+
+#[test]
+fn test_split_store() {
+    use crate::storage::store::{SplitStore, UserStore};
+
+    let dir_name = "test9";
+    let store = SplitStore::new(dir_name);
+    let user_dir = std::path::PathBuf::from(dir_name).join("user");
+
+    // Clean up any existing test directory
+    let _ = std::fs::remove_dir_all(dir_name);
+
+    let main = b"main password <3".to_vec();
+    let mut auth_data = crate::user::UserAuthData::new();
+    auth_data.set_main(&main, b"intermediate_key").unwrap();
+    let mounts = crate::mount::MountPoints::new(
+        crate::mount::MountParams::new(String::from("/dev/sda1"), String::from("ext4"), vec![]),
+        std::collections::HashMap::new(),
+    );
+    let command = crate::command::SessionCommand::new(String::from("sway"));
+
+    crate::storage::store_user_auth_data(&auth_data, &store, "user", None, None).unwrap();
+    crate::storage::store_user_mountpoints(Some(mounts.clone()), &store, "user", None, None)
+        .unwrap();
+    crate::storage::store_user_session_command(&command, &store, "user", None, None).unwrap();
+
+    let files = ["auth.json", "mounts.json", "session.json"].map(|f| user_dir.join(f).exists());
+    let mounts_only: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(user_dir.join("mounts.json")).unwrap())
+            .unwrap();
+    let mounts_mode = std::os::unix::fs::PermissionsExt::mode(
+        &std::fs::metadata(user_dir.join("mounts.json"))
+            .unwrap()
+            .permissions(),
+    );
+
+    let reloaded = crate::storage::load_user_auth_data(&store, "user")
+        .unwrap()
+        .unwrap();
+    let reloaded_mounts = crate::storage::load_user_mountpoints(&store, "user").unwrap();
+    let reloaded_command = crate::storage::load_user_session_command(&store, "user").unwrap();
+
+    crate::storage::store_user_mountpoints(None, &store, "user", None, None).unwrap();
+    let mounts_removed = !user_dir.join("mounts.json").exists();
+    let auth_kept = store.read("user").unwrap().is_some();
+
+    crate::storage::remove_user_data(&store, "user").unwrap();
+    let removed = store.read("user").unwrap().is_none();
+
+    std::fs::remove_dir_all(dir_name).unwrap();
+
+    assert_eq!(files, [true, true, true]);
+    assert!(mounts_only.get("auth_data").is_none());
+    assert!(mounts_only.get("mountpoints").is_some());
+    assert_eq!(mounts_mode & 0o777, 0o644);
+    assert_eq!(reloaded.main_by_auth(Some(&main)).unwrap(), main);
+    assert_eq!(reloaded_mounts.unwrap(), mounts);
+    assert_eq!(reloaded_command.unwrap().command(), "sway");
+    assert!(mounts_removed);
+    assert!(auth_kept);
+    assert!(removed);
+}
+
+#[test]
+fn test_invalid_usernames() {
+    use crate::storage::store::{JsonStore, LayoutStore, SplitStore, StoreLayout, UserStore};
+    use crate::storage::StorageError;
+
+    let dir_name = "test_invalid_usernames";
+
+    // Clean up any existing test directory
+    let _ = std::fs::remove_dir_all(dir_name);
+
+    let stores: [Box<dyn UserStore>; 3] = [
+        Box::new(JsonStore::directory(dir_name)),
+        Box::new(SplitStore::new(dir_name)),
+        Box::new(LayoutStore::new(dir_name, StoreLayout::Detect)),
+    ];
+    let command = crate::command::SessionCommand::new(String::from("sway"));
+
+    let mut refused = vec![];
+    for store in stores.iter() {
+        for username in ["", ".hidden", "..", "../root", "a/b", "nul\0"] {
+            refused.push(matches!(
+                store.read(username),
+                Err(StorageError::InvalidUsername(_))
+            ));
+            refused.push(matches!(
+                crate::storage::store_user_session_command(
+                    &command,
+                    store.as_ref(),
+                    username,
+                    None,
+                    None
+                ),
+                Err(StorageError::InvalidUsername(_))
+            ));
+            refused.push(store.remove(username).is_err());
+            refused.push(store.read_signature(username).is_err());
+        }
+    }
+
+    let accepted = crate::storage::store_user_session_command(
+        &command,
+        stores[0].as_ref(),
+        "john.doe",
+        None,
+        None,
+    );
+    let nothing_outside = !std::path::Path::new("root.json").exists();
+
+    let _ = std::fs::remove_dir_all(dir_name);
+
+    assert!(refused.into_iter().all(|refused| refused));
+    assert!(accepted.is_ok());
+    assert!(nothing_outside);
+}
+
+#[test]
+fn test_overlay_store() {
+    use crate::storage::store::{DefaultsStore, JsonStore, OverlayStore, ReadOnlyStore};
+
+    let dir_name = "test10";
+    let defaults_path = std::path::PathBuf::from(dir_name).join("defaults.json");
+    let config_dir = std::path::PathBuf::from(dir_name).join("config");
+
+    // Clean up any existing test directory
+    let _ = std::fs::remove_dir_all(dir_name);
+
+    let vendor_command = crate::command::SessionCommand::new(String::from("gamescope"));
+    crate::storage::store_user_session_command(
+        &vendor_command,
+        &JsonStore::file(&defaults_path),
+        "vendor",
+        None,
+        None,
+    )
+    .unwrap();
+    let mut vendor_auth = crate::user::UserAuthData::new();
+    vendor_auth
+        .set_main(b"vendor password", b"intermediate_key")
+        .unwrap();
+    crate::storage::store_user_auth_data(
+        &vendor_auth,
+        &JsonStore::file(&defaults_path),
+        "vendor",
+        None,
+        None,
+    )
+    .unwrap();
+
+    let store = OverlayStore::new(
+        JsonStore::directory(&config_dir),
+        DefaultsStore::new(&defaults_path),
+    );
+
+    let unconfigured = crate::storage::load_user_session_command(&store, "user").unwrap();
+
+    let mounts = crate::mount::MountPoints::new(
+        crate::mount::MountParams::new(String::from("/dev/sda1"), String::from("ext4"), vec![]),
+        std::collections::HashMap::new(),
+    );
+    crate::storage::store_user_mountpoints(Some(mounts.clone()), &store, "user", None, None)
+        .unwrap();
+    crate::storage::store_user_mountpoints(Some(mounts.clone()), &store, "other", None, None)
+        .unwrap();
+    let merged_command = crate::storage::load_user_session_command(&store, "user").unwrap();
+    let other_command = crate::storage::load_user_session_command(&store, "other").unwrap();
+    let merged_mounts = crate::storage::load_user_mountpoints(&store, "user").unwrap();
+    let inherited_auth = crate::storage::load_user_auth_data(&store, "user").unwrap();
+
+    let user_command = crate::command::SessionCommand::new(String::from("sway"));
+    crate::storage::store_user_session_command(&user_command, &store, "user", None, None).unwrap();
+    let overridden = crate::storage::load_user_session_command(&store, "user").unwrap();
+    let other_untouched = crate::storage::load_user_session_command(&store, "other").unwrap();
+    let vendor_untouched =
+        crate::storage::load_user_session_command(&JsonStore::file(&defaults_path), "user")
+            .unwrap();
+
+    let defaults_read_only = crate::storage::store_user_session_command(
+        &user_command,
+        &DefaultsStore::new(&defaults_path),
+        "user",
+        None,
+        None,
+    );
+    let read_only = crate::storage::store_user_session_command(
+        &user_command,
+        &ReadOnlyStore::new(JsonStore::file(&defaults_path)),
+        "user",
+        None,
+        None,
+    );
+
+    std::fs::remove_dir_all(dir_name).unwrap();
+
+    assert!(unconfigured.is_none());
+    assert_eq!(merged_command.unwrap().command(), "gamescope");
+    assert_eq!(other_command.unwrap().command(), "gamescope");
+    assert_eq!(merged_mounts.unwrap(), mounts);
+    assert!(inherited_auth.is_none());
+    assert_eq!(overridden.unwrap().command(), "sway");
+    assert_eq!(other_untouched.unwrap().command(), "gamescope");
+    assert_eq!(vendor_untouched.unwrap().command(), "gamescope");
+    assert!(matches!(
+        defaults_read_only,
+        Err(crate::storage::StorageError::ReadOnly)
+    ));
+    assert!(matches!(
+        read_only,
+        Err(crate::storage::StorageError::ReadOnly)
+    ));
+}
+
+#[test]
+fn test_export_import() {
+    use crate::storage::export::{decrypt_user_config, export_user_config, import_user_config};
+    use crate::storage::store::MemoryStore;
+    use crate::storage::StorageError;
+
+    let kdf = crate::kdf::KdfParams::argon2id(1024, 1, 1);
+    let source = MemoryStore::new();
+
+    let main = b"main password <3".to_vec();
+    let mut auth_data = crate::user::UserAuthData::new();
+    auth_data.set_kdf(kdf);
+    auth_data.set_main(&main, b"intermediate_key").unwrap();
+    auth_data
+        .add_secondary_password("pin", b"intermediate_key", b"1234")
+        .unwrap();
+    let mounts = crate::mount::MountPoints::new(
+        crate::mount::MountParams::new(String::from("/dev/sda1"), String::from("ext4"), vec![]),
+        std::collections::HashMap::new(),
+    );
+    let command = crate::command::SessionCommand::new(String::from("sway"));
+
+    crate::storage::store_user_auth_data(&auth_data, &source, "user", None, None).unwrap();
+    crate::storage::store_user_mountpoints(Some(mounts.clone()), &source, "user", None, None)
+        .unwrap();
+    crate::storage::store_user_session_command(&command, &source, "user", None, None).unwrap();
+
+    let exported = export_user_config(&source, "user", b"passphrase", &kdf).unwrap();
+
+    let wrong_passphrase = decrypt_user_config(&exported, b"wrong passphrase");
+
+    // changing the KDF cost in the cleartext header must be detected
+    let mut weakened: serde_json::Value = serde_json::from_slice(&exported).unwrap();
+    weakened["header"]["m_cost"] = serde_json::json!(2048);
+    let weakened = decrypt_user_config(&serde_json::to_vec(&weakened).unwrap(), b"passphrase");
+
+    // costs out of bounds are refused before deriving anything
+    let mut unbounded: serde_json::Value = serde_json::from_slice(&exported).unwrap();
+    unbounded["header"]["m_cost"] = serde_json::json!(u32::MAX);
+    let unbounded = decrypt_user_config(&serde_json::to_vec(&unbounded).unwrap(), b"passphrase");
+
+    let mut future: serde_json::Value = serde_json::from_slice(&exported).unwrap();
+    future["header"]["version"] = serde_json::json!(99);
+    let future = decrypt_user_config(&serde_json::to_vec(&future).unwrap(), b"passphrase");
+
+    let export = decrypt_user_config(&exported, b"passphrase").unwrap();
+    let exported_auth = export.auth_data().unwrap().unwrap();
+
+    let target = MemoryStore::new();
+    import_user_config(&export, &target, "other", None, None).unwrap();
+    let imported = crate::storage::load_user_auth_data(&target, "other")
+        .unwrap()
+        .unwrap();
+
+    assert!(matches!(
+        wrong_passphrase,
+        Err(StorageError::ExportDecryption)
+    ));
+    assert!(matches!(weakened, Err(StorageError::ExportDecryption)));
+    assert!(matches!(unbounded, Err(StorageError::InvalidExport)));
+    assert!(matches!(
+        future,
+        Err(StorageError::UnsupportedExportVersion(99))
+    ));
+    assert!(decrypt_user_config(b"not an export", b"passphrase").is_err());
+    assert_eq!(export.username(), "user");
+    assert!(exported_auth.main(b"intermediate_key").is_ok());
+    assert!(exported_auth.main(b"wrong_key").is_err());
+    assert_eq!(export.mountpoints().unwrap(), mounts);
+    assert_eq!(export.session_command().unwrap().command(), "sway");
+    assert_eq!(imported.main_by_auth(Some(b"1234")).unwrap(), main);
+    assert_eq!(
+        crate::storage::load_user_mountpoints(&target, "other")
+            .unwrap()
+            .unwrap(),
+        mounts
+    );
+    assert!(crate::storage::load_user_auth_data(&target, "user")
+        .unwrap()
+        .is_none());
+}
@@ -21,9 +21,9 @@
 
 #[test]
 fn test_main_password_change() {
-    let first_main = "main password <3".to_string();
-    let second_main = "2nd main password :B".to_string();
-    let intermediate = "intermediate_key".to_string();
+    let first_main = b"main password <3".to_vec();
+    let second_main = b"2nd main password :B".to_vec();
+    let intermediate = b"intermediate_key".to_vec();
 
     let mut user_cfg = crate::user::UserAuthData::new();
 
@@ -36,8 +36,8 @@ fn test_main_password_change() {
 
 #[test]
 fn test_main_password_auth() {
-    let first_main = "main password <3".to_string();
-    let intermediate = "intermediate_key".to_string();
+    let first_main = b"main password <3".to_vec();
+    let intermediate = b"intermediate_key".to_vec();
 
     let mut user_cfg = crate::user::UserAuthData::new();
 
@@ -46,7 +46,130 @@ fn test_main_password_auth() {
 
     let provided_password = Some(first_main.clone());
     assert_eq!(
-        user_cfg.main_by_auth(&provided_password).unwrap(),
+        user_cfg.main_by_auth(provided_password.as_deref()).unwrap(),
         first_main
     );
 }
+
+#[test]
+fn test_kdf_upgrade() {
+    let main = b"main password <3".to_vec();
+    let intermediate = b"intermediate_key".to_vec();
+    let secondary_passwords = [b"daisujda".to_vec(), b"sfaffsss".to_vec()];
+
+    let mut user_cfg = crate::user::UserAuthData::new();
+
+    // create entries the way they were before Argon2id was introduced
+    user_cfg.set_kdf(crate::kdf::KdfParams::legacy());
+    user_cfg.set_main(&main, &intermediate).unwrap();
+    for (idx, sp) in secondary_passwords.iter().enumerate() {
+        user_cfg
+            .add_secondary_password(format!("test{}", idx).as_str(), &intermediate, sp)
+            .unwrap();
+    }
+
+    user_cfg.set_kdf(crate::kdf::KdfParams::argon2id(1024, 1, 1));
+    assert!(user_cfg.needs_upgrade());
+
+    // the main password cannot re-encrypt anything
+    assert!(!user_cfg.upgrade(Some(&main)).unwrap());
+
+    // the first secondary password upgrades its own entry and the main password
+    let first = Some(secondary_passwords[0].clone());
+    assert!(user_cfg.upgrade(first.as_deref()).unwrap());
+    assert!(!user_cfg.upgrade(first.as_deref()).unwrap());
+    assert!(user_cfg.needs_upgrade());
+
+    let second = Some(secondary_passwords[1].clone());
+    assert!(user_cfg.upgrade(second.as_deref()).unwrap());
+    assert!(!user_cfg.needs_upgrade());
+
+    // every password still works afterwards
+    assert_eq!(user_cfg.main_by_auth(first.as_deref()).unwrap(), main);
+    assert_eq!(user_cfg.main_by_auth(second.as_deref()).unwrap(), main);
+    assert_eq!(user_cfg.main_by_auth(Some(&main)).unwrap(), main);
+    assert_eq!(user_cfg.main(&intermediate).unwrap(), main);
+}
+
+#[test]
+fn test_check_main() {
+    let main = b"main password <3".to_vec();
+    let intermediate = b"intermediate_key".to_vec();
+
+    let mut user_cfg = crate::user::UserAuthData::new();
+    user_cfg.set_kdf(crate::kdf::KdfParams::argon2id(1024, 1, 1));
+
+    assert!(user_cfg.check_main(&main).is_err());
+
+    user_cfg.set_main(&main, &intermediate).unwrap();
+
+    assert!(user_cfg.check_main(&main).unwrap());
+    assert!(!user_cfg.check_main(&intermediate).unwrap());
+    assert!(!user_cfg.check_main(b"main password").unwrap());
+}
+
+#[test]
+fn test_is_intermediate_key() {
+    let main = b"main password <3".to_vec();
+    let intermediate = b"intermediate_key".to_vec();
+
+    let mut user_cfg = crate::user::UserAuthData::new();
+    user_cfg.set_kdf(crate::kdf::KdfParams::argon2id(1024, 1, 1));
+
+    assert!(user_cfg.is_intermediate_key(&intermediate).is_err());
+
+    user_cfg.set_main(&main, &intermediate).unwrap();
+
+    assert!(user_cfg.is_intermediate_key(&intermediate).unwrap());
+    assert!(!user_cfg.is_intermediate_key(&main).unwrap());
+    assert!(!user_cfg.is_intermediate_key(b"intermediate").unwrap());
+}
+
+#[test]
+fn test_autologin() {
+    let intermediate = b"intermediate_key".to_vec();
+
+    let mut user_cfg = crate::user::UserAuthData::new();
+    user_cfg.set_kdf(crate::kdf::KdfParams::argon2id(1024, 1, 1));
+
+    assert!(!user_cfg.autologin());
+
+    user_cfg.set_main(b"main", &intermediate).unwrap();
+    user_cfg
+        .add_secondary_password("pin", &intermediate, b"1234")
+        .unwrap();
+    let with_pin = user_cfg.autologin();
+
+    user_cfg
+        .add_secondary_password("autologin", &intermediate, b"")
+        .unwrap();
+
+    assert!(!with_pin);
+    assert!(user_cfg.autologin());
+    assert!(user_cfg.main_by_auth(Some(b"")).is_ok());
+}
+
+#[test]
+fn test_binary_passwords() {
+    // not valid UTF-8, with NUL bytes: e.g. from a key file
+    let main = vec![0xff, 0x00, b'm', 0xc3, 0x00];
+    let intermediate = vec![0x00, 0x01, 0x02, 0x80];
+    let secondary = vec![0xfe, 0x00, 0x00];
+
+    let mut user_cfg = crate::user::UserAuthData::new();
+    user_cfg.set_kdf(crate::kdf::KdfParams::argon2id(1024, 1, 1));
+    user_cfg.set_main(&main, &intermediate).unwrap();
+    user_cfg
+        .add_secondary_password("binary", &intermediate, &secondary)
+        .unwrap();
+
+    assert_eq!(user_cfg.main_by_auth(Some(&main)).unwrap(), main);
+    assert_eq!(user_cfg.main_by_auth(Some(&secondary)).unwrap(), main);
+    assert_eq!(user_cfg.main(&intermediate).unwrap(), main);
+
+    // a lossy UTF-8 conversion of the secondary password must not be accepted
+    let lossy = String::from_utf8_lossy(&secondary)
+        .into_owned()
+        .into_bytes();
+    assert!(user_cfg.main_by_auth(Some(&lossy)).is_err());
+}
@@ -22,17 +22,17 @@
 use bytevec2::*;
 
 use aes_gcm::{
-    aead::{Aead, AeadCore, KeyInit, OsRng},
+    aead::{Aead, KeyInit},
     Aes256Gcm, Key, Nonce,
 };
 
-extern crate bcrypt;
-use bcrypt::{hash, verify, DEFAULT_COST};
-
 use thiserror::Error;
 
 use crate::auth::*;
 use crate::error::*;
+use crate::kdf::KdfParams;
+use crate::secret::SecretBytes;
+use crate::storage::legacy::MainPasswordV0;
 
 #[derive(Debug, Copy, Clone, Error)]
 pub enum UserAuthDataError {
@@ -44,8 +44,10 @@ pub enum UserAuthDataError {
     CouldNotAuthenticate,
     #[error("Authentication method unsupported")]
     MatchingAuthNotProvided,
-    #[error("Invalid password (probably contains invalid characters)")]
-    InvalidPassword,
+    #[error("No authentication method with that name")]
+    SecondaryNotFound,
+    #[error("An authentication method with that name already exists")]
+    SecondaryNameTaken,
 }
 
 bytevec_decl! {
@@ -182,103 +184,172 @@ impl From<[u8; 32]> for AuthDataSalt {
     }
 }
 
+/// Plaintext of the main password verifier: the main password is never encrypted with itself
+const MAIN_VERIFIER_PLAINTEXT: &[u8] = b"polyauth main password verifier";
+
 bytevec_decl! {
     #[derive(PartialEq, Eq, Debug, Clone)]
     pub struct MainPassword {
-        main_hash: String,
-        enc_main: Vec<u8>,
+        enc_main: Vec<u8>, // this is encrypted with the (intermediate key, intermediate_key_salt)
         enc_main_nonce: AuthDataNonce,
-
         intermediate_key_salt: AuthDataSalt,
-        intermediate_key_hash: String
+
+        main_verifier: Vec<u8>, // MAIN_VERIFIER_PLAINTEXT encrypted with the (main password, main_salt)
+        main_verifier_nonce: AuthDataNonce,
+        main_salt: AuthDataSalt,
+
+        kdf: KdfParams
     }
 }
 
 impl MainPassword {
     pub fn new(
-        main: &Vec<u8>,
-        intermediate_key: &String,
+        main: &[u8],
+        intermediate_key: &[u8],
         intermediate_salt: &[u8; 32],
+        kdf: &KdfParams,
     ) -> Result<Self, UserOperationError> {
-        let main_hash = hash(main, DEFAULT_COST).map_err(UserOperationError::HashingError)?;
+        let (enc_main, enc_main_nonce) =
+            crate::encrypt_with_password(kdf, intermediate_key, intermediate_salt, main)?;
+
+        let main_salt = crate::random_salt();
+        let (main_verifier, main_verifier_nonce) =
+            crate::encrypt_with_password(kdf, main, &main_salt, MAIN_VERIFIER_PLAINTEXT)?;
+
+        Ok(Self {
+            enc_main,
+            enc_main_nonce: AuthDataNonce::from(enc_main_nonce),
+            intermediate_key_salt: AuthDataSalt::from(*intermediate_salt),
+            main_verifier,
+            main_verifier_nonce: AuthDataNonce::from(main_verifier_nonce),
+            main_salt: AuthDataSalt::from(main_salt),
+            kdf: *kdf,
+        })
+    }
 
-        let intermediate_key_hash =
-            hash(intermediate_key, DEFAULT_COST).map_err(UserOperationError::HashingError)?;
+    pub fn plain(&self, ik_or_main: &[u8]) -> Result<SecretBytes, UserOperationError> {
+        if self.check(ik_or_main)? {
+            return Ok(SecretBytes::from(ik_or_main));
+        }
 
-        let intermediate_derived_key =
-            crate::derive_key(intermediate_key.as_str(), intermediate_salt);
+        // provided data was not the main password itself: threat it as the intermediate key
+        self.by_intermediate_key(ik_or_main)
+    }
 
-        let key = Key::<Aes256Gcm>::from(intermediate_derived_key);
+    /// Decrypt the main password with the intermediate key protecting it
+    pub fn by_intermediate_key(
+        &self,
+        intermediate_key: &[u8],
+    ) -> Result<SecretBytes, UserOperationError> {
+        crate::decrypt_with_password(
+            &self.kdf,
+            intermediate_key,
+            &self.intermediate_key_salt.into(),
+            &self.enc_main_nonce.into(),
+            self.enc_main.as_ref(),
+        )
+        .map_err(|_| UserOperationError::User(UserAuthDataError::WrongIntermediateKey))
+    }
 
-        let main_cipher = Aes256Gcm::new(&key);
-        let main_nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    pub fn kdf(&self) -> &KdfParams {
+        &self.kdf
+    }
 
-        let temp: [u8; 12] = main_nonce.into();
+    pub fn intermediate_key_salt(&self) -> [u8; 32] {
+        self.intermediate_key_salt.into()
+    }
 
-        match main_cipher.encrypt(&main_nonce, main.as_ref()) {
-            Ok(enc_main) => Ok(Self {
-                main_hash,
-                enc_main,
-                enc_main_nonce: AuthDataNonce::from(temp),
-                intermediate_key_salt: AuthDataSalt::from(*intermediate_salt),
-                intermediate_key_hash,
-            }),
-            Err(err) => Err(UserOperationError::EncryptionError(err)),
+    /// Check if the given string is the intermediate key protecting the main password
+    pub fn is_intermediate_key(&self, intermediate_key: &[u8]) -> Result<bool, UserOperationError> {
+        match self.by_intermediate_key(intermediate_key) {
+            Ok(_) => Ok(true),
+            Err(UserOperationError::User(UserAuthDataError::WrongIntermediateKey)) => Ok(false),
+            Err(err) => Err(err),
         }
     }
 
-    pub fn plain(&self, ik_or_main: &String) -> Result<Vec<u8>, UserOperationError> {
-        if verify(ik_or_main, self.main_hash.as_str()).map_err(UserOperationError::HashingError)? {
-            return Ok(crate::password_to_vec(ik_or_main));
-        }
+    /// Check if the given string is the main password
+    pub fn check(&self, main_password: &[u8]) -> Result<bool, UserOperationError> {
+        let salt: [u8; 32] = self.main_salt.into();
+        let key = self.kdf.derive(main_password, &salt)?;
 
-        // provided data was not the main password itself: threat it as the intermediate key
-        let intermediate_key = ik_or_main;
+        let cipher = Aes256Gcm::new(&Key::<Aes256Gcm>::from(key));
+        let nonce: [u8; 12] = self.main_verifier_nonce.into();
 
-        if !verify(intermediate_key, self.intermediate_key_hash.as_str())
-            .map_err(UserOperationError::HashingError)?
-        {
-            return Err(UserOperationError::User(
-                UserAuthDataError::WrongIntermediateKey,
-            ));
-        }
+        Ok(cipher
+            .decrypt(&Nonce::from(nonce), self.main_verifier.as_ref())
+            .is_ok_and(|plaintext| crate::constant_time_eq(&plaintext, MAIN_VERIFIER_PLAINTEXT)))
+    }
+}
 
-        let temp: [u8; 32] = self.intermediate_key_salt.into();
-        let intermediate_derived_key = crate::derive_key(intermediate_key.as_str(), &temp);
+/// Main password entry as loaded from storage
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub(crate) enum StoredMainPassword {
+    Current(MainPassword),
 
-        let key = Key::<Aes256Gcm>::from(intermediate_derived_key);
+    /// An entry still in a bcrypt-verified format: replaced on the next upgrade
+    Legacy(MainPasswordV0),
+}
 
-        let main_cipher = Aes256Gcm::new(&key);
-        let temp: [u8; 12] = self.enc_main_nonce.into();
-        let main_nonce = Nonce::from(temp);
+impl StoredMainPassword {
+    fn plain(&self, ik_or_main: &[u8]) -> Result<SecretBytes, UserOperationError> {
+        match self {
+            Self::Current(main) => main.plain(ik_or_main),
+            Self::Legacy(main) => match main.check(ik_or_main)? {
+                true => Ok(SecretBytes::from(ik_or_main)),
+                false => main.by_intermediate_key(ik_or_main),
+            },
+        }
+    }
 
-        let decrypted_main = main_cipher
-            .decrypt(&main_nonce, self.enc_main.as_ref())
-            .map_err(UserOperationError::EncryptionError)?;
+    fn by_intermediate_key(
+        &self,
+        intermediate_key: &[u8],
+    ) -> Result<SecretBytes, UserOperationError> {
+        match self {
+            Self::Current(main) => main.by_intermediate_key(intermediate_key),
+            Self::Legacy(main) => main.by_intermediate_key(intermediate_key),
+        }
+    }
 
-        if !verify(decrypted_main.as_slice(), &self.main_hash)
-            .map_err(UserOperationError::HashingError)?
-        {
-            return Err(UserOperationError::User(
-                UserAuthDataError::WrongIntermediateKey,
-            ));
+    fn is_intermediate_key(&self, intermediate_key: &[u8]) -> Result<bool, UserOperationError> {
+        match self {
+            Self::Current(main) => main.is_intermediate_key(intermediate_key),
+            Self::Legacy(main) => main.is_intermediate_key(intermediate_key),
         }
+    }
 
-        Ok(decrypted_main)
+    fn check(&self, main_password: &[u8]) -> Result<bool, UserOperationError> {
+        match self {
+            Self::Current(main) => main.check(main_password),
+            Self::Legacy(main) => main.check(main_password),
+        }
     }
 
-    pub fn check(&self, main_password: &String) -> Result<bool, UserOperationError> {
-        let main_password_hash =
-            hash(main_password, DEFAULT_COST).map_err(UserOperationError::HashingError)?;
+    fn kdf(&self) -> &KdfParams {
+        match self {
+            Self::Current(main) => main.kdf(),
+            Self::Legacy(main) => main.kdf(),
+        }
+    }
+
+    fn intermediate_key_salt(&self) -> [u8; 32] {
+        match self {
+            Self::Current(main) => main.intermediate_key_salt(),
+            Self::Legacy(main) => main.intermediate_key_salt(),
+        }
+    }
 
-        Ok(self.main_hash == main_password_hash)
+    fn is_legacy(&self) -> bool {
+        matches!(self, Self::Legacy(_))
     }
 }
 
 #[derive(Debug, Clone, Default)]
 pub struct UserAuthData {
-    main: Option<MainPassword>,
+    main: Option<StoredMainPassword>,
     auth: Vec<SecondaryAuth>,
+    kdf: KdfParams,
 }
 
 impl UserAuthData {
@@ -286,27 +357,142 @@ impl UserAuthData {
         Self {
             main: None,
             auth: vec![],
+            kdf: KdfParams::default(),
         }
     }
 
+    /// KDF parameters used for every newly encrypted entry
+    pub fn kdf(&self) -> &KdfParams {
+        &self.kdf
+    }
+
+    /// Change the KDF parameters used for every newly encrypted entry
+    pub fn set_kdf(&mut self, kdf: KdfParams) {
+        self.kdf = kdf;
+    }
+
+    /// Check if any stored entry is in a legacy format or has been encrypted
+    /// with a weaker KDF than the current one
+    pub fn needs_upgrade(&self) -> bool {
+        self.main
+            .iter()
+            .any(|main| main.is_legacy() || main.kdf().is_weaker_than(&self.kdf))
+            || self
+                .auth
+                .iter()
+                .any(|sec_auth| sec_auth.is_legacy() || sec_auth.kdf().is_weaker_than(&self.kdf))
+    }
+
+    /// Re-encrypt in the current format and with the current KDF every entry that the given password can unlock:
+    /// the entry of the matching secondary authentication method and the main password.
+    ///
+    /// Entries of other secondary authentication methods are left untouched as their
+    /// password is not known: those will be upgraded when used.
+    ///
+    /// Returns true if anything has changed (and therefore has to be stored again).
+    pub fn upgrade(&mut self, password: Option<&[u8]>) -> Result<bool, UserOperationError> {
+        let Some(main) = &self.main else {
+            return Err(UserOperationError::User(
+                UserAuthDataError::MainPasswordNotSet,
+            ));
+        };
+
+        let mut upgraded = false;
+
+        let mut intermediate = None;
+        for sec_auth in self.auth.iter_mut() {
+            if let Ok(ik) = sec_auth.intermediate(password) {
+                if sec_auth.is_legacy() || sec_auth.kdf().is_weaker_than(&self.kdf) {
+                    sec_auth.rekey(&ik, password, &self.kdf)?;
+                    upgraded = true;
+                }
+
+                intermediate = Some(ik);
+                break;
+            }
+        }
+
+        // the given password might also be the intermediate key itself
+        if intermediate.is_none() {
+            if let Some(provided_pw) = password {
+                if main.is_intermediate_key(provided_pw)? {
+                    intermediate = Some(SecretBytes::from(provided_pw));
+                }
+            }
+        }
+
+        // the main password alone cannot be used to re-encrypt itself
+        if let Some(intermediate_key) = intermediate {
+            if main.is_legacy() || main.kdf().is_weaker_than(&self.kdf) {
+                let main_pw = main.by_intermediate_key(&intermediate_key)?;
+
+                self.main = Some(StoredMainPassword::Current(MainPassword::new(
+                    &main_pw,
+                    &intermediate_key,
+                    &crate::random_salt(),
+                    &self.kdf,
+                )?));
+                upgraded = true;
+            }
+        }
+
+        Ok(upgraded)
+    }
+
     pub fn add_secondary_password(
         &mut self,
         name: &str,
-        intermediate: &String,
-        secondary_password: &String,
+        intermediate: &[u8],
+        secondary_password: &[u8],
+    ) -> Result<(), UserOperationError> {
+        // check the intermediate key the same way main_by_auth will use it:
+        // the main password is accepted by main() but cannot unlock the main password entry
+        let main = self.main.as_ref().ok_or(UserOperationError::User(
+            UserAuthDataError::MainPasswordNotSet,
+        ))?;
+        let _ = main.by_intermediate_key(intermediate)?;
+
+        self.auth.push(
+            SecondaryAuth::new_password(
+                name,
+                None,
+                SecondaryPassword::new(intermediate, secondary_password, &self.kdf)?,
+            )
+            .with_autologin(Some(secondary_password.is_empty())),
+        );
+
+        Ok(())
+    }
+
+    /// Removes the secondary authentication methods with the given name, returning whether there was any
+    pub fn remove_secondary(&mut self, name: &str) -> bool {
+        let count = self.auth.len();
+        self.auth.retain(|auth| auth.name() != name);
+        self.auth.len() != count
+    }
+
+    /// Gives a new name to the secondary authentication methods with the given name:
+    /// the name is not part of the encrypted data, so nothing has to be unlocked.
+    pub fn rename_secondary(
+        &mut self,
+        name: &str,
+        new_name: &str,
     ) -> Result<(), UserOperationError> {
-        if !crate::is_valid_password(secondary_password) {
-            return Err(UserOperationError::User(UserAuthDataError::InvalidPassword));
+        if !self.auth.iter().any(|auth| auth.name() == name) {
+            return Err(UserOperationError::User(
+                UserAuthDataError::SecondaryNotFound,
+            ));
         }
 
-        // this makes the check about correctness of the intermediate key
-        let _ = self.main(intermediate)?;
+        if name != new_name && self.auth.iter().any(|auth| auth.name() == new_name) {
+            return Err(UserOperationError::User(
+                UserAuthDataError::SecondaryNameTaken,
+            ));
+        }
 
-        self.auth.push(SecondaryAuth::new_password(
-            name,
-            None,
-            SecondaryPassword::new(intermediate, secondary_password)?,
-        ));
+        for auth in self.auth.iter_mut().filter(|auth| auth.name() == name) {
+            auth.set_name(new_name);
+        }
 
         Ok(())
     }
@@ -316,8 +502,9 @@ impl UserAuthData {
     }
 
     /// Check if the given main passowrd is the same as the stored one
-    /// NOTE: this is NOT the same as a PAM authentication
-    pub fn check_main(&self, main_password: &String) -> Result<bool, UserOperationError> {
+    /// NOTE: this is NOT the same as a PAM authentication: it does not involve the intermediate key
+    /// and will never unlock any secondary authentication method
+    pub fn check_main(&self, main_password: &[u8]) -> Result<bool, UserOperationError> {
         let Some(stored_main) = &self.main else {
             return Err(UserOperationError::User(
                 UserAuthDataError::MainPasswordNotSet,
@@ -327,28 +514,62 @@ impl UserAuthData {
         stored_main.check(main_password)
     }
 
+    /// Check if the given key is the intermediate key protecting the main password
+    /// NOTE: unlike main() the main password itself is NOT accepted
+    pub fn is_intermediate_key(&self, intermediate_key: &[u8]) -> Result<bool, UserOperationError> {
+        let Some(stored_main) = &self.main else {
+            return Err(UserOperationError::User(
+                UserAuthDataError::MainPasswordNotSet,
+            ));
+        };
+
+        stored_main.is_intermediate_key(intermediate_key)
+    }
+
+    /// Check if a secondary authentication method accepts an empty password, so that the user
+    /// is logged in automatically.
+    ///
+    /// Entries record this when they are written: only the ones stored before are tried with
+    /// an empty password, which costs a key derivation each.
+    pub fn autologin(&self) -> bool {
+        let Some(main) = &self.main else {
+            return false;
+        };
+
+        if self.auth.iter().any(|auth| auth.autologin() == Some(true)) {
+            return true;
+        }
+
+        self.auth
+            .iter()
+            .filter(|auth| auth.autologin().is_none())
+            .any(|auth| {
+                auth.intermediate(Some(b""))
+                    .and_then(|intermediate| main.by_intermediate_key(&intermediate))
+                    .is_ok()
+            })
+    }
+
     /// Function to get the main password from a secondary password.
     /// NOTE: the main password always returns the main password
     pub fn main_by_auth(
         &self,
-        secondary_password: &Option<String>,
-    ) -> Result<String, UserOperationError> {
+        secondary_password: Option<&[u8]>,
+    ) -> Result<SecretBytes, UserOperationError> {
         let main = self.main.as_ref().ok_or(UserOperationError::User(
             UserAuthDataError::MainPasswordNotSet,
         ))?;
 
         if let Some(provided_pw) = secondary_password {
-            if !crate::is_valid_password(provided_pw) {
-                return Err(UserOperationError::User(UserAuthDataError::InvalidPassword));
-            } else if let Ok(main_pw) = main.plain(provided_pw) {
-                return Ok(crate::vec_to_password(&main_pw));
+            if let Ok(main_pw) = main.plain(provided_pw) {
+                return Ok(main_pw);
             }
         }
 
         for sec_auth in self.auth.iter() {
             if let Ok(intermediate) = sec_auth.intermediate(secondary_password) {
-                if let Ok(main_pw_as_vec) = main.plain(&intermediate) {
-                    return Ok(crate::vec_to_password(&main_pw_as_vec));
+                if let Ok(main_pw) = main.by_intermediate_key(&intermediate) {
+                    return Ok(main_pw);
                 }
             }
         }
@@ -358,15 +579,9 @@ impl UserAuthData {
         ))
     }
 
-    pub fn main(&self, intermediate_key: &String) -> Result<String, UserOperationError> {
-        if !crate::is_valid_password(intermediate_key) {
-            return Err(UserOperationError::User(UserAuthDataError::InvalidPassword));
-        }
-
+    pub fn main(&self, intermediate_key: &[u8]) -> Result<SecretBytes, UserOperationError> {
         match &self.main {
-            Some(main) => Ok(crate::vec_to_password(
-                (main.plain(intermediate_key)?).as_ref(),
-            )),
+            Some(main) => main.plain(intermediate_key),
             None => Err(UserOperationError::User(
                 UserAuthDataError::MainPasswordNotSet,
             )),
@@ -375,52 +590,31 @@ impl UserAuthData {
 
     pub fn set_main(
         &mut self,
-        main: &String,
-        intermediate_key: &String,
+        main: &[u8],
+        intermediate_key: &[u8],
     ) -> Result<(), UserOperationError> {
-        if !crate::is_valid_password(main) {
-            return Err(UserOperationError::User(UserAuthDataError::InvalidPassword));
-        }
-
-        if !crate::is_valid_password(intermediate_key) {
-            return Err(UserOperationError::User(UserAuthDataError::InvalidPassword));
-        }
-
-        match &self.main {
+        // an existing intermediate key salt is kept: the intermediate key MUST match
+        let intermediate_salt = match &self.main {
             Some(m) => {
-                if !verify(intermediate_key, &m.intermediate_key_hash)
-                    .map_err(UserOperationError::HashingError)?
-                {
+                if !m.is_intermediate_key(intermediate_key)? {
                     return Err(UserOperationError::User(
                         UserAuthDataError::WrongIntermediateKey,
                     ));
                 }
 
-                let temp: [u8; 32] = m.intermediate_key_salt.into();
-                let mp = MainPassword::new(&crate::password_to_vec(main), intermediate_key, &temp)?;
+                m.intermediate_key_salt()
+            }
+            None => crate::random_salt(),
+        };
 
-                self.main = Some(mp);
+        let mp = MainPassword::new(main, intermediate_key, &intermediate_salt, &self.kdf)?;
 
-                Ok(())
-            }
-            None => match MainPassword::new(
-                &crate::password_to_vec(main),
-                intermediate_key,
-                // generate a new random salt using the aes-gcm library (it will create a 32 bytes key)
-                &<[u8; 32]>::try_from(Aes256Gcm::generate_key(&mut OsRng).to_vec().as_slice())
-                    .unwrap(),
-            ) {
-                Ok(mp) => {
-                    self.main = Some(mp);
-
-                    Ok(())
-                }
-                Err(err) => Err(err),
-            },
-        }
+        self.main = Some(StoredMainPassword::Current(mp));
+
+        Ok(())
     }
 
-    pub(crate) fn main_password(&self) -> &Option<MainPassword> {
+    pub(crate) fn main_password(&self) -> &Option<StoredMainPassword> {
         &self.main
     }
 
@@ -428,7 +622,7 @@ impl UserAuthData {
         self.auth.iter()
     }
 
-    pub(crate) fn push_main(&mut self, value: MainPassword) {
+    pub(crate) fn push_main(&mut self, value: StoredMainPassword) {
         self.main = Some(value);
     }
 